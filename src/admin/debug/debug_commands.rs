@@ -188,6 +188,17 @@ pub(crate) async fn get_remote_pdu(
 					},
 				};
 
+				if services()
+					.rooms
+					.event_handler
+					.acl_check(&server, &room_id)
+					.is_err()
+				{
+					return Ok(RoomMessageEventContent::text_plain(format!(
+						"{server} is denied by the server ACL of {room_id}, not handling its PDU."
+					)));
+				}
+
 				vec![(event_id, value, room_id)]
 			};
 
@@ -384,7 +395,7 @@ pub(crate) async fn sign_json(body: Vec<&str>) -> Result<RoomMessageEventContent
 			Ok(mut value) => {
 				ruma::signatures::sign_json(
 					services().globals.server_name().as_str(),
-					services().globals.keypair(),
+					&*services().globals.keypair(),
 					&mut value,
 				)
 				.expect("our request json is what ruma expects");
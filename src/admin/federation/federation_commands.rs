@@ -1,6 +1,8 @@
-use std::fmt::Write;
+use std::{fmt::Write, time::Duration};
 
+use conduit::utils;
 use ruma::{events::room::message::RoomMessageEventContent, OwnedRoomId, RoomId, ServerName, UserId};
+use service::sending::Destination;
 
 use crate::{escape_html, get_room_info, services, utils::HtmlEscape, Result};
 
@@ -15,14 +17,58 @@ pub(crate) async fn enable_room(_body: Vec<&str>, room_id: Box<RoomId>) -> Resul
 }
 
 pub(crate) async fn incoming_federeation(_body: Vec<&str>) -> Result<RoomMessageEventContent> {
-	let map = services().globals.roomid_federationhandletime.read().await;
-	let mut msg = format!("Handling {} incoming pdus:\n", map.len());
+	let handling = services().globals.roomid_federationhandletime.read().await;
+	let queues = services().rooms.pdu_queue.queues();
+
+	let queued: usize = queues.values().map(|queue| queue.queued).sum();
+	let mut msg = format!(
+		"{queued} incoming pdus queued in {} rooms, {} of {} workers busy:\n",
+		queues.values().filter(|queue| queue.queued > 0).count(),
+		services().rooms.pdu_queue.busy_workers(),
+		services().globals.config.incoming_pdu_workers,
+	);
 
-	for (r, (e, i)) in map.iter() {
-		let elapsed = i.elapsed();
-		writeln!(msg, "{} {}: {}m{}s", r, e, elapsed.as_secs() / 60, elapsed.as_secs() % 60,)
+	for (room_id, queue) in &queues {
+		write!(
+			msg,
+			"{room_id}: {} queued, {} handled, {} failed, {} refused, {}s busy",
+			queue.queued,
+			queue.handled,
+			queue.failed,
+			queue.rejected,
+			queue.busy.as_secs(),
+		)
+		.expect("should be able to write to string buffer");
+
+		if let Some((event_id, started)) = handling.get(room_id) {
+			let elapsed = started.elapsed();
+			write!(
+				msg,
+				", handling {event_id} for {}m{}s",
+				elapsed.as_secs() / 60,
+				elapsed.as_secs() % 60
+			)
 			.expect("should be able to write to string buffer");
+		}
+
+		writeln!(msg).expect("should be able to write to string buffer");
 	}
+
+	// PDUs of joins, leaves and knocks are handled without going through a queue
+	for (room_id, (event_id, started)) in handling
+		.iter()
+		.filter(|(room_id, _)| !queues.contains_key(*room_id))
+	{
+		let elapsed = started.elapsed();
+		writeln!(
+			msg,
+			"{room_id}: handling {event_id} for {}m{}s",
+			elapsed.as_secs() / 60,
+			elapsed.as_secs() % 60
+		)
+		.expect("should be able to write to string buffer");
+	}
+
 	Ok(RoomMessageEventContent::text_plain(&msg))
 }
 
@@ -128,3 +174,66 @@ pub(crate) async fn remote_user_in_rooms(_body: Vec<&str>, user_id: Box<UserId>)
 
 	Ok(RoomMessageEventContent::text_html(output_plain, output_html))
 }
+
+pub(crate) async fn unhealthy_destinations(_body: Vec<&str>) -> Result<RoomMessageEventContent> {
+	let mut destinations = services().sending.unhealthy_destinations()?;
+	if destinations.is_empty() {
+		return Ok(RoomMessageEventContent::text_plain("All destinations are healthy."));
+	}
+
+	destinations.sort_by_key(|(_, health)| std::cmp::Reverse(health.failures));
+
+	let now = utils::millis_since_unix_epoch();
+	let ago = |millis: Option<u64>| {
+		millis.map_or_else(
+			|| "never".to_owned(),
+			|millis| {
+				format!(
+					"{} ago",
+					cyborgtime::format_duration(Duration::from_secs(now.saturating_sub(millis) / 1000))
+				)
+			},
+		)
+	};
+
+	let mut msg = format!("{} destination(s) failing:\n", destinations.len());
+	for (dest, health) in &destinations {
+		let dest = match dest {
+			Destination::Normal(server_name) => server_name.to_string(),
+			Destination::Appservice(id) => format!("appservice {id}"),
+			Destination::Push(user_id, _) => format!("push gateway of {user_id}"),
+		};
+		let next_retry = if health.next_retry > now {
+			format!(
+				"in {}",
+				cyborgtime::format_duration(Duration::from_secs((health.next_retry - now) / 1000))
+			)
+		} else {
+			"now".to_owned()
+		};
+
+		writeln!(
+			msg,
+			"{dest}: {} failure(s), last success {}, next retry {next_retry}, last error: {}",
+			health.failures,
+			ago(health.last_success),
+			health.last_error.as_deref().unwrap_or("none"),
+		)
+		.expect("should be able to write to string buffer");
+	}
+
+	Ok(RoomMessageEventContent::text_plain(msg))
+}
+
+pub(crate) async fn reset_backoff(_body: Vec<&str>, server_name: Box<ServerName>) -> Result<RoomMessageEventContent> {
+	if services()
+		.sending
+		.reset_backoff(&Destination::Normal(server_name.clone().into()))?
+	{
+		Ok(RoomMessageEventContent::text_plain(format!(
+			"Reset the backoff of {server_name}, retrying now."
+		)))
+	} else {
+		Ok(RoomMessageEventContent::text_plain(format!("{server_name} is not failing.")))
+	}
+}
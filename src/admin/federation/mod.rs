@@ -2,7 +2,8 @@ use clap::Subcommand;
 use ruma::{events::room::message::RoomMessageEventContent, RoomId, ServerName, UserId};
 
 use self::federation_commands::{
-	disable_room, enable_room, fetch_support_well_known, incoming_federeation, remote_user_in_rooms,
+	disable_room, enable_room, fetch_support_well_known, incoming_federeation, remote_user_in_rooms, reset_backoff,
+	unhealthy_destinations,
 };
 use crate::Result;
 
@@ -11,7 +12,7 @@ pub(crate) mod federation_commands;
 #[cfg_attr(test, derive(Debug))]
 #[derive(Subcommand)]
 pub(crate) enum FederationCommand {
-	/// - List all rooms we are currently handling an incoming pdu from
+	/// - List the queues of incoming pdus per room and what they are handling
 	IncomingFederation,
 
 	/// - Disables incoming federation handling for a room.
@@ -41,6 +42,17 @@ pub(crate) enum FederationCommand {
 	RemoteUserInRooms {
 		user_id: Box<UserId>,
 	},
+
+	/// - List destinations whose last transaction failed
+	///
+	/// Shows how often sending to them failed in a row, when it last worked,
+	/// when it is retried next and the last error.
+	UnhealthyDestinations,
+
+	/// - Reset the backoff of a failing server and retry sending to it now
+	ResetBackoff {
+		server_name: Box<ServerName>,
+	},
 }
 
 pub(crate) async fn process(command: FederationCommand, body: Vec<&str>) -> Result<RoomMessageEventContent> {
@@ -58,5 +70,9 @@ pub(crate) async fn process(command: FederationCommand, body: Vec<&str>) -> Resu
 		FederationCommand::RemoteUserInRooms {
 			user_id,
 		} => remote_user_in_rooms(body, user_id).await?,
+		FederationCommand::UnhealthyDestinations => unhealthy_destinations(body).await?,
+		FederationCommand::ResetBackoff {
+			server_name,
+		} => reset_backoff(body, server_name).await?,
 	})
 }
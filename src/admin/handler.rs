@@ -23,8 +23,9 @@ use service::{admin::HandlerResult, pdu::PduBuilder};
 use self::{fsck::FsckCommand, tester::TesterCommands};
 use crate::{
 	appservice, appservice::AppserviceCommand, debug, debug::DebugCommand, escape_html, federation,
-	federation::FederationCommand, fsck, media, media::MediaCommand, query, query::QueryCommand, room,
-	room::RoomCommand, server, server::ServerCommand, services, tester, user, user::UserCommand,
+	federation::FederationCommand, fsck, media, media::MediaCommand, query, query::QueryCommand, registration_token,
+	registration_token::RegistrationTokenCommand, room, room::RoomCommand, server, server::ServerCommand, services,
+	tester, user, user::UserCommand,
 };
 pub(crate) const PAGE_SIZE: usize = 100;
 
@@ -40,6 +41,10 @@ pub(crate) enum AdminCommand {
 	/// - Commands for managing local users
 	Users(UserCommand),
 
+	#[command(subcommand)]
+	/// - Commands for managing registration tokens
+	RegistrationTokens(RegistrationTokenCommand),
+
 	#[command(subcommand)]
 	/// - Commands for managing rooms
 	Rooms(RoomCommand),
@@ -221,6 +226,7 @@ async fn process_admin_command(command: AdminCommand, body: Vec<&str>) -> Result
 		AdminCommand::Appservices(command) => appservice::process(command, body).await?,
 		AdminCommand::Media(command) => media::process(command, body).await?,
 		AdminCommand::Users(command) => user::process(command, body).await?,
+		AdminCommand::RegistrationTokens(command) => registration_token::process(command, body).await?,
 		AdminCommand::Rooms(command) => room::process(command, body).await?,
 		AdminCommand::Federation(command) => federation::process(command, body).await?,
 		AdminCommand::Server(command) => server::process(command, body).await?,
@@ -5,6 +5,7 @@ pub(crate) mod fsck;
 pub(crate) mod handler;
 pub(crate) mod media;
 pub(crate) mod query;
+pub(crate) mod registration_token;
 pub(crate) mod room;
 pub(crate) mod server;
 pub(crate) mod tester;
@@ -0,0 +1,80 @@
+pub(crate) mod registration_token_commands;
+
+use clap::Subcommand;
+use ruma::events::room::message::RoomMessageEventContent;
+
+use self::registration_token_commands::{create, list, revoke, update};
+use crate::Result;
+
+#[cfg_attr(test, derive(Debug))]
+#[derive(Subcommand)]
+pub(crate) enum RegistrationTokenCommand {
+	/// - Create a new registration token
+	///
+	/// Requires `registration_requires_token` (or a static
+	/// `registration_token`) to be set for registrations to ask for tokens.
+	Create {
+		/// The token, if unspecified a random one is generated
+		#[arg(long)]
+		token: Option<String>,
+
+		/// How many users may register with this token, unlimited if
+		/// unspecified
+		#[arg(long)]
+		uses_allowed: Option<u64>,
+
+		/// How long the token stays valid, e.g. "7d", forever if unspecified
+		#[arg(long)]
+		expires_in: Option<String>,
+	},
+
+	/// - List all registration tokens and their usage
+	List,
+
+	/// - Change the limits of a registration token
+	Update {
+		token: String,
+
+		/// How many users may register with this token in total
+		#[arg(long, conflicts_with = "unlimited_uses")]
+		uses_allowed: Option<u64>,
+
+		/// Remove the usage limit
+		#[arg(long)]
+		unlimited_uses: bool,
+
+		/// How long the token stays valid from now on, e.g. "7d"
+		#[arg(long, conflicts_with = "no_expiry")]
+		expires_in: Option<String>,
+
+		/// Remove the expiry time
+		#[arg(long)]
+		no_expiry: bool,
+	},
+
+	/// - Revoke a registration token so it can no longer be used
+	Revoke {
+		token: String,
+	},
+}
+
+pub(crate) async fn process(command: RegistrationTokenCommand, body: Vec<&str>) -> Result<RoomMessageEventContent> {
+	Ok(match command {
+		RegistrationTokenCommand::Create {
+			token,
+			uses_allowed,
+			expires_in,
+		} => create(body, token, uses_allowed, expires_in).await?,
+		RegistrationTokenCommand::List => list(body).await?,
+		RegistrationTokenCommand::Update {
+			token,
+			uses_allowed,
+			unlimited_uses,
+			expires_in,
+			no_expiry,
+		} => update(body, token, uses_allowed, unlimited_uses, expires_in, no_expiry).await?,
+		RegistrationTokenCommand::Revoke {
+			token,
+		} => revoke(body, token).await?,
+	})
+}
@@ -0,0 +1,121 @@
+use std::{fmt::Write as _, time::Duration};
+
+use conduit::utils;
+use ruma::events::room::message::RoomMessageEventContent;
+use service::registration_tokens::RegistrationToken;
+
+use crate::{services, Result};
+
+pub(crate) async fn create(
+	_body: Vec<&str>, token: Option<String>, uses_allowed: Option<u64>, expires_in: Option<String>,
+) -> Result<RoomMessageEventContent> {
+	let expiry_time = match expires_in.as_deref().map(expiry_time_from).transpose() {
+		Ok(expiry_time) => expiry_time,
+		Err(e) => return Ok(RoomMessageEventContent::text_plain(e)),
+	};
+
+	match services()
+		.registration_tokens
+		.create(token, uses_allowed, expiry_time)
+	{
+		Ok(token) => Ok(RoomMessageEventContent::text_plain(format!(
+			"Created registration token {}",
+			describe(&token)
+		))),
+		Err(e) => Ok(RoomMessageEventContent::text_plain(format!(
+			"Failed to create registration token: {e}"
+		))),
+	}
+}
+
+pub(crate) async fn list(_body: Vec<&str>) -> Result<RoomMessageEventContent> {
+	let tokens = services()
+		.registration_tokens
+		.iter()
+		.collect::<Result<Vec<_>>>()?;
+
+	let mut msg = format!("Found {} registration token(s):\n", tokens.len());
+	for token in &tokens {
+		writeln!(msg, "{}", describe(token)).expect("should be able to write to string buffer");
+	}
+
+	Ok(RoomMessageEventContent::text_plain(msg))
+}
+
+pub(crate) async fn update(
+	_body: Vec<&str>, token: String, uses_allowed: Option<u64>, unlimited_uses: bool, expires_in: Option<String>,
+	no_expiry: bool,
+) -> Result<RoomMessageEventContent> {
+	let uses_allowed = if unlimited_uses {
+		Some(None)
+	} else {
+		uses_allowed.map(Some)
+	};
+
+	let expiry_time = if no_expiry {
+		Some(None)
+	} else {
+		match expires_in.as_deref().map(expiry_time_from).transpose() {
+			Ok(expiry_time) => expiry_time.map(Some),
+			Err(e) => return Ok(RoomMessageEventContent::text_plain(e)),
+		}
+	};
+
+	match services()
+		.registration_tokens
+		.update(&token, uses_allowed, expiry_time)?
+	{
+		Some(token) => Ok(RoomMessageEventContent::text_plain(format!(
+			"Updated registration token {}",
+			describe(&token)
+		))),
+		None => Ok(RoomMessageEventContent::text_plain(
+			"The specified registration token does not exist.",
+		)),
+	}
+}
+
+pub(crate) async fn revoke(_body: Vec<&str>, token: String) -> Result<RoomMessageEventContent> {
+	if services().registration_tokens.revoke(&token)? {
+		Ok(RoomMessageEventContent::text_plain(format!(
+			"Revoked registration token `{token}`."
+		)))
+	} else {
+		Ok(RoomMessageEventContent::text_plain(
+			"The specified registration token does not exist.",
+		))
+	}
+}
+
+fn expiry_time_from(expires_in: &str) -> Result<u64, String> {
+	let duration =
+		cyborgtime::parse_duration(expires_in).map_err(|e| format!("Failed to parse duration {expires_in:?}: {e}"))?;
+
+	Ok(utils::millis_since_unix_epoch().saturating_add(duration.as_millis().try_into().unwrap_or(u64::MAX)))
+}
+
+fn describe(token: &RegistrationToken) -> String {
+	let uses_allowed = token
+		.uses_allowed
+		.map_or_else(|| "unlimited".to_owned(), |uses_allowed| uses_allowed.to_string());
+
+	let expiry = match token.expiry_time {
+		None => "never expires".to_owned(),
+		Some(expiry_time) => {
+			let now = utils::millis_since_unix_epoch();
+			if expiry_time > now {
+				format!(
+					"expires in {}",
+					cyborgtime::format_duration(Duration::from_secs((expiry_time - now) / 1000))
+				)
+			} else {
+				"expired".to_owned()
+			}
+		},
+	};
+
+	format!(
+		"`{}`: {} of {uses_allowed} uses completed, {} pending, {expiry}",
+		token.token, token.completed, token.pending
+	)
+}
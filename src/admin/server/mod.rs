@@ -5,7 +5,7 @@ use ruma::events::room::message::RoomMessageEventContent;
 
 use self::server_commands::{
 	backup_database, clear_database_caches, clear_service_caches, list_backups, list_database_files, memory_usage,
-	show_config, uptime,
+	notice, rotate_signing_key, show_config, uptime,
 };
 use crate::Result;
 
@@ -42,6 +42,26 @@ pub(crate) enum ServerCommand {
 
 	/// - List database files
 	ListDatabaseFiles,
+
+	/// - Send a server notice to local users
+	///
+	/// The message goes into a code block below the command. Each user gets it
+	/// in their own server notices room, sent by the server notices user
+	/// (`server_notices_localpart`). Deactivated users are skipped.
+	Notice {
+		/// Send the notice to all local users
+		#[arg(long)]
+		all: bool,
+		/// Users to send the notice to
+		user_ids: Vec<String>,
+	},
+
+	/// - Generate a new signing key and use it for all new signatures
+	///
+	/// The current key is published as an old verify key expired now, so
+	/// events signed with it can still be verified. Use this when the key may
+	/// have leaked.
+	RotateSigningKey,
 }
 
 pub(crate) async fn process(command: ServerCommand, body: Vec<&str>) -> Result<RoomMessageEventContent> {
@@ -58,5 +78,10 @@ pub(crate) async fn process(command: ServerCommand, body: Vec<&str>) -> Result<R
 		ServerCommand::ListBackups => list_backups(body).await?,
 		ServerCommand::BackupDatabase => backup_database(body).await?,
 		ServerCommand::ListDatabaseFiles => list_database_files(body).await?,
+		ServerCommand::Notice {
+			all,
+			user_ids,
+		} => notice(body, all, user_ids).await?,
+		ServerCommand::RotateSigningKey => rotate_signing_key(body).await?,
 	})
 }
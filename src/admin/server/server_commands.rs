@@ -1,6 +1,7 @@
-use ruma::events::room::message::RoomMessageEventContent;
+use ruma::{events::room::message::RoomMessageEventContent, OwnedUserId, UserId};
+use tracing::{info, warn};
 
-use crate::{services, Result};
+use crate::{services, user_is_local, Result};
 
 pub(crate) async fn uptime(_body: Vec<&str>) -> Result<RoomMessageEventContent> {
 	let seconds = services()
@@ -96,3 +97,102 @@ pub(crate) async fn list_database_files(_body: Vec<&str>) -> Result<RoomMessageE
 	let result = services().globals.db.file_list()?;
 	Ok(RoomMessageEventContent::notice_html(String::new(), result))
 }
+
+pub(crate) async fn notice(body: Vec<&str>, all: bool, user_ids: Vec<String>) -> Result<RoomMessageEventContent> {
+	if body.len() < 3 || !body[0].trim().starts_with("```") || body.last().unwrap_or(&"").trim() != "```" {
+		return Ok(RoomMessageEventContent::text_plain(
+			"Expected code block in command body. Add --help for details.",
+		));
+	}
+	let message = body[1..body.len() - 1].join("\n");
+
+	let recipients = if all {
+		if !user_ids.is_empty() {
+			return Ok(RoomMessageEventContent::text_plain("Either pass user IDs or --all, not both."));
+		}
+
+		let conduit_user =
+			UserId::parse_with_server_name("conduit", services().globals.server_name()).expect("conduit user exists");
+		services()
+			.users
+			.list_local_users()?
+			.into_iter()
+			.filter_map(|user_id| OwnedUserId::try_from(user_id).ok())
+			.filter(|user_id| *user_id != conduit_user && !services().server_notices.is_notices_user(user_id))
+			.collect::<Vec<_>>()
+	} else {
+		if user_ids.is_empty() {
+			return Ok(RoomMessageEventContent::text_plain(
+				"Pass the user IDs to send the notice to, or --all.",
+			));
+		}
+
+		let mut recipients = Vec::with_capacity(user_ids.len());
+		for user_id in &user_ids {
+			let user_id = match UserId::parse_with_server_name(user_id.to_lowercase(), services().globals.server_name())
+			{
+				Ok(user_id) => user_id,
+				Err(e) => {
+					return Ok(RoomMessageEventContent::text_plain(format!(
+						"{user_id} is not a valid user ID: {e}"
+					)))
+				},
+			};
+
+			if !user_is_local(&user_id) || !services().users.exists(&user_id)? {
+				return Ok(RoomMessageEventContent::text_plain(format!(
+					"{user_id} is not a user of this server."
+				)));
+			}
+
+			recipients.push(user_id);
+		}
+		recipients
+	};
+
+	let content = RoomMessageEventContent::text_plain(message);
+	let mut sent: usize = 0;
+	let mut failed = Vec::new();
+	for user_id in &recipients {
+		if services().users.is_deactivated(user_id).unwrap_or(true) {
+			continue;
+		}
+
+		match services()
+			.server_notices
+			.send_notice(user_id, &content)
+			.await
+		{
+			Ok(_) => sent = sent.saturating_add(1),
+			Err(e) => {
+				warn!("Failed to send server notice to {user_id}: {e}");
+				failed.push(user_id.as_str());
+			},
+		}
+	}
+
+	info!("Sent server notice to {sent} user(s)");
+
+	if failed.is_empty() {
+		Ok(RoomMessageEventContent::text_plain(format!(
+			"Sent the server notice to {sent} user(s)."
+		)))
+	} else {
+		Ok(RoomMessageEventContent::text_plain(format!(
+			"Sent the server notice to {sent} user(s). Failed to send it to: {}",
+			failed.join(", ")
+		)))
+	}
+}
+
+pub(crate) async fn rotate_signing_key(_body: Vec<&str>) -> Result<RoomMessageEventContent> {
+	let old_key_id = format!("ed25519:{}", services().globals.keypair().version());
+	let keypair = services().globals.rotate_keypair()?;
+	let new_key_id = format!("ed25519:{}", keypair.version());
+
+	warn!("Rotated the server signing key from {old_key_id} to {new_key_id}");
+
+	Ok(RoomMessageEventContent::text_plain(format!(
+		"Rotated the signing key. New signatures use {new_key_id}, {old_key_id} is now listed as an old verify key."
+	)))
+}
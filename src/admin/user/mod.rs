@@ -3,7 +3,10 @@ pub(crate) mod user_commands;
 use clap::Subcommand;
 use ruma::events::room::message::RoomMessageEventContent;
 
-use self::user_commands::{create, deactivate, deactivate_all, list, list_joined_rooms, reset_password};
+use self::user_commands::{
+	create, deactivate, deactivate_all, export_data, import_password_hash, list, list_joined_rooms, list_sessions,
+	lock, login_as, reset_password, shadow_ban, suspend, unlock, unshadow_ban, unsuspend,
+};
 use crate::Result;
 
 #[cfg_attr(test, derive(Debug))]
@@ -21,15 +24,35 @@ pub(crate) enum UserCommand {
 	ResetPassword {
 		/// Username of the user for whom the password should be reset
 		username: String,
+		/// New password for the user, if unspecified one is generated
+		password: Option<String>,
+	},
+
+	/// - Import a password hash from another homeserver
+	///
+	/// Only bcrypt hashes (e.g. from Synapse) are supported. The password is
+	/// re-hashed with Argon2 the next time the user logs in. Requires building
+	/// with the "bcrypt" feature.
+	ImportPasswordHash {
+		/// Username of the user
+		username: String,
+		/// The bcrypt hash, e.g. `$2b$12$...`
+		hash: String,
 	},
 
 	/// - Deactivate a user
 	///
 	/// User will not be removed from all rooms by default.
 	/// Use --leave-rooms to force the user to leave all rooms
+	///
+	/// Use --erase to also clear their profile, delete their media and serve
+	/// their past events redacted to anyone joining later, which implies
+	/// --leave-rooms.
 	Deactivate {
 		#[arg(short, long)]
 		leave_rooms: bool,
+		#[arg(long)]
+		erase: bool,
 		user_id: String,
 	},
 
@@ -54,6 +77,78 @@ pub(crate) enum UserCommand {
 		force: bool,
 	},
 
+	/// - Suspend a user
+	///
+	/// Suspended users can still log in, sync and read, but cannot send
+	/// events, join rooms, upload media or change their profile. Reversible
+	/// with unsuspend.
+	Suspend {
+		user_id: String,
+	},
+
+	/// - Lift the suspension of a user
+	Unsuspend {
+		user_id: String,
+	},
+
+	/// - Lock a user
+	///
+	/// All requests of a locked user fail until they are unlocked. Their
+	/// devices stay logged in, so nothing is lost unlike with deactivation.
+	Lock {
+		user_id: String,
+	},
+
+	/// - Unlock a locked user
+	Unlock {
+		user_id: String,
+	},
+
+	/// - Shadow-ban a user
+	///
+	/// Requests of shadow-banned users appear to succeed, but their messages,
+	/// state events, invites, joins, profile changes and typing notifications
+	/// are silently dropped and never federated.
+	ShadowBan {
+		user_id: String,
+	},
+
+	/// - Lift the shadow-ban of a user
+	UnshadowBan {
+		user_id: String,
+	},
+
+	/// - Log in as a user
+	///
+	/// Creates a temporary session for the user, e.g. to reproduce issues they
+	/// reported. Its access token is written to the "impersonation" folder
+	/// next to the database, not to the admin room. The session is removed
+	/// once its access token expired.
+	LoginAs {
+		user_id: String,
+		/// How long the access token is valid, e.g. "30m", defaults to one hour
+		#[arg(long)]
+		expires_in: Option<String>,
+	},
+
+	/// - List the sessions (devices) of a user
+	///
+	/// Shows when and from which IP address and user agent each session was
+	/// last used.
+	ListSessions {
+		user_id: String,
+	},
+
+	/// - Export everything stored about a local user
+	///
+	/// Writes a gzipped tar archive to the "exports" folder next to the
+	/// database, containing the profile, devices, account data, room
+	/// memberships, events sent, read receipts, pushers, key backup metadata
+	/// and uploaded media of the user.
+	ExportData {
+		user_id: String,
+	},
+
 	/// - List local users in the database
 	List,
 
@@ -73,15 +168,49 @@ pub(crate) async fn process(command: UserCommand, body: Vec<&str>) -> Result<Roo
 		} => create(body, username, password).await?,
 		UserCommand::Deactivate {
 			leave_rooms,
+			erase,
 			user_id,
-		} => deactivate(body, leave_rooms, user_id).await?,
+		} => deactivate(body, leave_rooms, erase, user_id).await?,
 		UserCommand::ResetPassword {
 			username,
-		} => reset_password(body, username).await?,
+			password,
+		} => reset_password(body, username, password).await?,
+		UserCommand::ImportPasswordHash {
+			username,
+			hash,
+		} => import_password_hash(body, username, hash).await?,
 		UserCommand::DeactivateAll {
 			leave_rooms,
 			force,
 		} => deactivate_all(body, leave_rooms, force).await?,
+		UserCommand::Suspend {
+			user_id,
+		} => suspend(body, user_id).await?,
+		UserCommand::Unsuspend {
+			user_id,
+		} => unsuspend(body, user_id).await?,
+		UserCommand::Lock {
+			user_id,
+		} => lock(body, user_id).await?,
+		UserCommand::Unlock {
+			user_id,
+		} => unlock(body, user_id).await?,
+		UserCommand::ShadowBan {
+			user_id,
+		} => shadow_ban(body, user_id).await?,
+		UserCommand::UnshadowBan {
+			user_id,
+		} => unshadow_ban(body, user_id).await?,
+		UserCommand::LoginAs {
+			user_id,
+			expires_in,
+		} => login_as(body, user_id, expires_in).await?,
+		UserCommand::ListSessions {
+			user_id,
+		} => list_sessions(body, user_id).await?,
+		UserCommand::ExportData {
+			user_id,
+		} => export_data(body, user_id).await?,
 		UserCommand::ListJoinedRooms {
 			user_id,
 		} => list_joined_rooms(body, user_id).await?,
@@ -1,8 +1,19 @@
-use std::{fmt::Write as _, sync::Arc};
+use std::{
+	collections::BTreeMap,
+	fmt::Write as _,
+	fs::{self, File},
+	io::Write as _,
+	path::Path,
+	sync::Arc,
+	time::Duration,
+};
 
 use api::client::{join_room_by_id_helper, leave_all_rooms};
-use conduit::utils;
-use ruma::{events::room::message::RoomMessageEventContent, OwnedRoomId, UserId};
+use conduit::{utils, Error, PduCount};
+use flate2::{write::GzEncoder, Compression};
+use ruma::{events::room::message::RoomMessageEventContent, OwnedRoomId, OwnedUserId, UserId};
+use serde_json::{json, Value};
+use service::users::IMPERSONATION_TOKEN_TTL;
 use tracing::{error, info, warn};
 
 use crate::{escape_html, get_room_info, services, user_is_local, Result};
@@ -23,8 +34,6 @@ pub(crate) async fn list(_body: Vec<&str>) -> Result<RoomMessageEventContent> {
 pub(crate) async fn create(
 	_body: Vec<&str>, username: String, password: Option<String>,
 ) -> Result<RoomMessageEventContent> {
-	let password = password.unwrap_or_else(|| utils::random_string(AUTO_GEN_PASSWORD_LENGTH));
-
 	// Validate user id
 	let user_id =
 		match UserId::parse_with_server_name(username.as_str().to_lowercase(), services().globals.server_name()) {
@@ -51,6 +60,20 @@ pub(crate) async fn create(
 	if services().users.exists(&user_id)? {
 		return Ok(RoomMessageEventContent::text_plain(format!("Userid {user_id} already exists")));
 	}
+
+	// generated passwords are random and not subject to the password policy
+	let password = if let Some(password) = password {
+		if let Err(e) = services().users.check_password_policy(&user_id, &password) {
+			return Ok(RoomMessageEventContent::text_plain(format!(
+				"The password does not satisfy the password policy: {e}"
+			)));
+		}
+
+		password
+	} else {
+		utils::random_string(AUTO_GEN_PASSWORD_LENGTH)
+	};
+
 	// Create user
 	services().users.create(&user_id, Some(password.as_str()))?;
 
@@ -131,7 +154,7 @@ pub(crate) async fn create(
 }
 
 pub(crate) async fn deactivate(
-	_body: Vec<&str>, leave_rooms: bool, user_id: String,
+	_body: Vec<&str>, leave_rooms: bool, erase: bool, user_id: String,
 ) -> Result<RoomMessageEventContent> {
 	// Validate user id
 	let user_id =
@@ -165,10 +188,19 @@ pub(crate) async fn deactivate(
 
 		services().users.deactivate_account(&user_id)?;
 
-		if leave_rooms {
+		if leave_rooms || erase {
 			leave_all_rooms(&user_id).await;
 		}
 
+		if erase {
+			services().users.erase_account(&user_id).await?;
+			info!("Erased user {user_id}");
+
+			return Ok(RoomMessageEventContent::text_plain(format!(
+				"User {user_id} has been deactivated and erased"
+			)));
+		}
+
 		Ok(RoomMessageEventContent::text_plain(format!(
 			"User {user_id} has been deactivated"
 		)))
@@ -179,7 +211,9 @@ pub(crate) async fn deactivate(
 	}
 }
 
-pub(crate) async fn reset_password(_body: Vec<&str>, username: String) -> Result<RoomMessageEventContent> {
+pub(crate) async fn reset_password(
+	_body: Vec<&str>, username: String, password: Option<String>,
+) -> Result<RoomMessageEventContent> {
 	// Validate user id
 	let user_id =
 		match UserId::parse_with_server_name(username.as_str().to_lowercase(), services().globals.server_name()) {
@@ -206,7 +240,18 @@ pub(crate) async fn reset_password(_body: Vec<&str>, username: String) -> Result
 		return Ok(RoomMessageEventContent::text_plain("The specified user does not exist!"));
 	}
 
-	let new_password = utils::random_string(AUTO_GEN_PASSWORD_LENGTH);
+	// generated passwords are random and not subject to the password policy
+	let new_password = if let Some(password) = password {
+		if let Err(e) = services().users.check_password_policy(&user_id, &password) {
+			return Ok(RoomMessageEventContent::text_plain(format!(
+				"The password does not satisfy the password policy: {e}"
+			)));
+		}
+
+		password
+	} else {
+		utils::random_string(AUTO_GEN_PASSWORD_LENGTH)
+	};
 
 	match services()
 		.users
@@ -221,6 +266,47 @@ pub(crate) async fn reset_password(_body: Vec<&str>, username: String) -> Result
 	}
 }
 
+pub(crate) async fn import_password_hash(
+	_body: Vec<&str>, username: String, hash: String,
+) -> Result<RoomMessageEventContent> {
+	// Validate user id
+	let user_id =
+		match UserId::parse_with_server_name(username.as_str().to_lowercase(), services().globals.server_name()) {
+			Ok(id) => id,
+			Err(e) => {
+				return Ok(RoomMessageEventContent::text_plain(format!(
+					"The supplied username is not a valid username: {e}"
+				)))
+			},
+		};
+
+	if !user_is_local(&user_id) {
+		return Ok(RoomMessageEventContent::text_plain(format!(
+			"User {user_id} does not belong to our server."
+		)));
+	}
+
+	if !services().users.exists(&user_id)? {
+		return Ok(RoomMessageEventContent::text_plain("The specified user does not exist!"));
+	}
+
+	if !cfg!(feature = "bcrypt") {
+		return Ok(RoomMessageEventContent::text_plain(
+			"Importing password hashes requires conduwuit to be built with the \"bcrypt\" feature.",
+		));
+	}
+
+	if !service::users::auth::is_bcrypt_hash(&hash) {
+		return Ok(RoomMessageEventContent::text_plain("The supplied hash is not a bcrypt hash."));
+	}
+
+	services().users.set_password_hash(&user_id, &hash)?;
+
+	Ok(RoomMessageEventContent::text_plain(format!(
+		"Imported the password hash for user {user_id}, it will be re-hashed on their next login."
+	)))
+}
+
 pub(crate) async fn deactivate_all(body: Vec<&str>, leave_rooms: bool, force: bool) -> Result<RoomMessageEventContent> {
 	if body.len() > 2 && body[0].trim().starts_with("```") && body.last().unwrap().trim() == "```" {
 		let usernames = body.clone().drain(1..body.len() - 1).collect::<Vec<_>>();
@@ -303,6 +389,519 @@ pub(crate) async fn deactivate_all(body: Vec<&str>, leave_rooms: bool, force: bo
 	}
 }
 
+pub(crate) async fn suspend(_body: Vec<&str>, user_id: String) -> Result<RoomMessageEventContent> {
+	let user_id = match restrictable_user(&user_id)? {
+		Ok(user_id) => user_id,
+		Err(message) => return Ok(message),
+	};
+
+	if services().users.is_suspended(&user_id)? {
+		return Ok(RoomMessageEventContent::text_plain(format!(
+			"User {user_id} is already suspended."
+		)));
+	}
+
+	services().users.set_suspended(&user_id, true)?;
+	info!("Suspended user {user_id}");
+
+	Ok(RoomMessageEventContent::text_plain(format!(
+		"User {user_id} has been suspended, they can only read until unsuspended."
+	)))
+}
+
+pub(crate) async fn unsuspend(_body: Vec<&str>, user_id: String) -> Result<RoomMessageEventContent> {
+	let user_id = match restrictable_user(&user_id)? {
+		Ok(user_id) => user_id,
+		Err(message) => return Ok(message),
+	};
+
+	if !services().users.is_suspended(&user_id)? {
+		return Ok(RoomMessageEventContent::text_plain(format!("User {user_id} is not suspended.")));
+	}
+
+	services().users.set_suspended(&user_id, false)?;
+	info!("Unsuspended user {user_id}");
+
+	Ok(RoomMessageEventContent::text_plain(format!(
+		"User {user_id} is no longer suspended."
+	)))
+}
+
+pub(crate) async fn lock(_body: Vec<&str>, user_id: String) -> Result<RoomMessageEventContent> {
+	let user_id = match restrictable_user(&user_id)? {
+		Ok(user_id) => user_id,
+		Err(message) => return Ok(message),
+	};
+
+	if services().users.is_locked(&user_id)? {
+		return Ok(RoomMessageEventContent::text_plain(format!(
+			"User {user_id} is already locked."
+		)));
+	}
+
+	services().users.set_locked(&user_id, true)?;
+	info!("Locked user {user_id}");
+
+	Ok(RoomMessageEventContent::text_plain(format!(
+		"User {user_id} has been locked, all of their requests fail until unlocked."
+	)))
+}
+
+pub(crate) async fn unlock(_body: Vec<&str>, user_id: String) -> Result<RoomMessageEventContent> {
+	let user_id = match restrictable_user(&user_id)? {
+		Ok(user_id) => user_id,
+		Err(message) => return Ok(message),
+	};
+
+	if !services().users.is_locked(&user_id)? {
+		return Ok(RoomMessageEventContent::text_plain(format!("User {user_id} is not locked.")));
+	}
+
+	services().users.set_locked(&user_id, false)?;
+	info!("Unlocked user {user_id}");
+
+	Ok(RoomMessageEventContent::text_plain(format!(
+		"User {user_id} has been unlocked."
+	)))
+}
+
+pub(crate) async fn shadow_ban(_body: Vec<&str>, user_id: String) -> Result<RoomMessageEventContent> {
+	let user_id = match restrictable_user(&user_id)? {
+		Ok(user_id) => user_id,
+		Err(message) => return Ok(message),
+	};
+
+	if services().users.is_shadow_banned(&user_id)? {
+		return Ok(RoomMessageEventContent::text_plain(format!(
+			"User {user_id} is already shadow-banned."
+		)));
+	}
+
+	if services().users.is_admin(&user_id)? {
+		return Ok(RoomMessageEventContent::text_plain(format!(
+			"User {user_id} is an admin, revoke their admin privileges first."
+		)));
+	}
+
+	services().users.set_shadow_banned(&user_id, true)?;
+	info!("Shadow-banned user {user_id}");
+
+	Ok(RoomMessageEventContent::text_plain(format!(
+		"User {user_id} has been shadow-banned, their requests will appear to succeed but have no effect."
+	)))
+}
+
+pub(crate) async fn unshadow_ban(_body: Vec<&str>, user_id: String) -> Result<RoomMessageEventContent> {
+	let user_id = match restrictable_user(&user_id)? {
+		Ok(user_id) => user_id,
+		Err(message) => return Ok(message),
+	};
+
+	if !services().users.is_shadow_banned(&user_id)? {
+		return Ok(RoomMessageEventContent::text_plain(format!(
+			"User {user_id} is not shadow-banned."
+		)));
+	}
+
+	services().users.set_shadow_banned(&user_id, false)?;
+	info!("Lifted the shadow-ban of user {user_id}");
+
+	Ok(RoomMessageEventContent::text_plain(format!(
+		"User {user_id} is no longer shadow-banned."
+	)))
+}
+
+pub(crate) async fn login_as(
+	_body: Vec<&str>, user_id: String, expires_in: Option<String>,
+) -> Result<RoomMessageEventContent> {
+	let user_id = match restrictable_user(&user_id)? {
+		Ok(user_id) => user_id,
+		Err(message) => return Ok(message),
+	};
+
+	if services().users.is_deactivated(&user_id)? {
+		return Ok(RoomMessageEventContent::text_plain(format!("User {user_id} is deactivated.")));
+	}
+
+	let ttl = match expires_in {
+		Some(expires_in) => match cyborgtime::parse_duration(&expires_in) {
+			Ok(duration) => duration.as_millis().try_into().unwrap_or(u64::MAX),
+			Err(e) => {
+				return Ok(RoomMessageEventContent::text_plain(format!(
+					"Failed to parse duration {expires_in:?}: {e}"
+				)))
+			},
+		},
+		None => IMPERSONATION_TOKEN_TTL,
+	};
+
+	let (device_id, token, _) = services()
+		.users
+		.create_impersonation_device(&user_id, ttl)?;
+	warn!("Created impersonation session {device_id} for {user_id} through an admin command");
+
+	// The admin room history is kept, so the access token is only written to a
+	// file next to the database
+	let mut path = services().globals.config.database_path.clone();
+	path.push("impersonation");
+	path.push(device_id.as_str());
+	if let Err(e) = write_secret(&path, &token) {
+		error!("Failed to write the access token of impersonation session {device_id}: {e}");
+		services().users.remove_device(&user_id, &device_id)?;
+		return Ok(RoomMessageEventContent::text_plain(format!(
+			"Failed to write the access token to {}: {e}",
+			path.display()
+		)));
+	}
+
+	Ok(RoomMessageEventContent::text_plain(format!(
+		"Created impersonation session {device_id} for {user_id}, expiring in {}. The access token was written to {}.",
+		cyborgtime::format_duration(Duration::from_millis(ttl)),
+		path.display()
+	)))
+}
+
+/// Writes a secret to a new file only the user running the server can read.
+fn write_secret(path: &Path, secret: &str) -> std::io::Result<()> {
+	if let Some(dir) = path.parent() {
+		fs::create_dir_all(dir)?;
+	}
+
+	let mut options = fs::OpenOptions::new();
+	options.write(true).create_new(true);
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::OpenOptionsExt;
+		options.mode(0o600);
+	}
+
+	options.open(path)?.write_all(secret.as_bytes())
+}
+
+pub(crate) async fn list_sessions(_body: Vec<&str>, user_id: String) -> Result<RoomMessageEventContent> {
+	let user_id = match UserId::parse_with_server_name(user_id.to_lowercase(), services().globals.server_name()) {
+		Ok(id) => id,
+		Err(e) => {
+			return Ok(RoomMessageEventContent::text_plain(format!(
+				"The supplied username is not a valid username: {e}"
+			)))
+		},
+	};
+
+	if !user_is_local(&user_id) {
+		return Ok(RoomMessageEventContent::text_plain("User does not belong to our server."));
+	}
+
+	let mut devices = services()
+		.users
+		.all_devices_metadata(&user_id)
+		.collect::<Result<Vec<_>>>()?;
+
+	if devices.is_empty() {
+		return Ok(RoomMessageEventContent::text_plain(format!("{user_id} has no sessions.")));
+	}
+
+	// most recently seen first
+	devices.sort_by_key(|device| std::cmp::Reverse(device.last_seen_ts));
+
+	let now = utils::millis_since_unix_epoch();
+	let mut msg = format!("{user_id} has {} session(s):\n", devices.len());
+	for device in &devices {
+		let last_seen = device.last_seen_ts.map_or_else(
+			|| "never".to_owned(),
+			|ts| {
+				let ago = now.saturating_sub(ts.get().into()) / 1000;
+				format!("{} ago", cyborgtime::format_duration(Duration::from_secs(ago)))
+			},
+		);
+		let user_agent = services()
+			.users
+			.device_user_agent(&user_id, &device.device_id)?;
+
+		writeln!(
+			msg,
+			"`{}` ({}): last seen {last_seen} from {}, user agent: {}",
+			device.device_id,
+			device.display_name.as_deref().unwrap_or("no name"),
+			device.last_seen_ip.as_deref().unwrap_or("unknown IP"),
+			user_agent.as_deref().unwrap_or("unknown"),
+		)
+		.expect("should be able to write to string buffer");
+	}
+
+	Ok(RoomMessageEventContent::text_plain(msg))
+}
+
+pub(crate) async fn export_data(_body: Vec<&str>, user_id: String) -> Result<RoomMessageEventContent> {
+	let user_id = match UserId::parse_with_server_name(user_id.to_lowercase(), services().globals.server_name()) {
+		Ok(id) => id,
+		Err(e) => {
+			return Ok(RoomMessageEventContent::text_plain(format!(
+				"The supplied username is not a valid username: {e}"
+			)))
+		},
+	};
+
+	if !user_is_local(&user_id) {
+		return Ok(RoomMessageEventContent::text_plain("User does not belong to our server."));
+	}
+
+	if !services().users.exists(&user_id)? {
+		return Ok(RoomMessageEventContent::text_plain("User does not exist on this server."));
+	}
+
+	let mut path = services().globals.config.database_path.clone();
+	path.push("exports");
+	path.push(format!("{}-{}.tar.gz", user_id.localpart(), utils::millis_since_unix_epoch()));
+
+	// Files are written to the archive one at a time while they are collected
+	let (sender, receiver) = loole::bounded::<(String, Vec<u8>)>(1);
+	let archive_path = path.clone();
+	let writer = services()
+		.server
+		.runtime()
+		.spawn_blocking(move || -> std::io::Result<usize> {
+			if let Some(dir) = archive_path.parent() {
+				std::fs::create_dir_all(dir)?;
+			}
+
+			let mut archive = tar::Builder::new(GzEncoder::new(File::create(&archive_path)?, Compression::default()));
+			let mut count: usize = 0;
+			while let Ok((name, data)) = receiver.recv() {
+				let mut header = tar::Header::new_gnu();
+				header.set_size(data.len() as u64);
+				header.set_mode(0o600);
+				header.set_mtime(utils::millis_since_unix_epoch() / 1000);
+				archive.append_data(&mut header, name, data.as_slice())?;
+				count = count.saturating_add(1);
+			}
+			archive.into_inner()?.finish()?;
+
+			Ok(count)
+		});
+
+	let collected = write_user_data(&user_id, &ExportArchive(sender)).await;
+	let written = writer.await.expect("export task panicked");
+	if written.is_err() || collected.is_err() {
+		_ = std::fs::remove_file(&path);
+	}
+
+	// The writer stopping makes collecting fail, so its error comes first
+	let written = match (written, collected) {
+		(Err(e), _) => Err(e),
+		(Ok(_), Err(e)) => return Err(e),
+		(Ok(count), Ok(())) => Ok(count),
+	};
+
+	match written {
+		Ok(count) => {
+			info!("Exported the data of {user_id} to {}", path.display());
+			Ok(RoomMessageEventContent::text_plain(format!(
+				"Exported {count} files with the data of {user_id} to {}",
+				path.display()
+			)))
+		},
+		Err(e) => {
+			error!("Failed to export the data of {user_id}: {e}");
+			Ok(RoomMessageEventContent::text_plain(format!(
+				"Failed to write the export archive: {e}"
+			)))
+		},
+	}
+}
+
+/// Sends the files of a data export to the task writing the archive.
+struct ExportArchive(loole::Sender<(String, Vec<u8>)>);
+
+impl ExportArchive {
+	async fn add(&self, name: String, data: Vec<u8>) -> Result<()> {
+		self.0
+			.send_async((name, data))
+			.await
+			.map_err(|_| Error::AdminCommand("Writing the export archive failed."))
+	}
+
+	async fn add_json(&self, name: &str, value: &Value) -> Result<()> {
+		self.add(
+			name.to_owned(),
+			serde_json::to_vec_pretty(value).expect("JSON values always serialize"),
+		)
+		.await
+	}
+}
+
+/// Collects everything stored about a local user and adds it to the export
+/// archive file by file.
+async fn write_user_data(user_id: &UserId, archive: &ExportArchive) -> Result<()> {
+	let users = &services().users;
+	let threepids = services()
+		.threepid
+		.threepids(user_id)
+		.collect::<Result<Vec<_>>>()?;
+	archive
+		.add_json(
+			"profile.json",
+			&json!({
+				"user_id": user_id,
+				"displayname": users.displayname(user_id)?,
+				"avatar_url": users.avatar_url(user_id)?,
+				"blurhash": users.blurhash(user_id)?,
+				"deactivated": users.is_deactivated(user_id)?,
+				"threepids": threepids,
+				"accepted_terms_version": users.accepted_terms_version(user_id)?,
+			}),
+		)
+		.await?;
+
+	let devices = users
+		.all_devices_metadata(user_id)
+		.collect::<Result<Vec<_>>>()?;
+	archive.add_json("devices.json", &json!(devices)).await?;
+
+	let state_cache = &services().rooms.state_cache;
+	let joined = state_cache
+		.rooms_joined(user_id)
+		.collect::<Result<Vec<_>>>()?;
+	let invited = state_cache
+		.rooms_invited(user_id)
+		.map(|r| r.map(|(room_id, _)| room_id))
+		.collect::<Result<Vec<_>>>()?;
+	let left = state_cache
+		.rooms_left(user_id)
+		.map(|r| r.map(|(room_id, _)| room_id))
+		.collect::<Result<Vec<_>>>()?;
+	archive
+		.add_json(
+			"memberships.json",
+			&json!({
+				"joined": joined,
+				"invited": invited,
+				"left": left,
+			}),
+		)
+		.await?;
+
+	let account_data = &services().account_data;
+	let read_receipt = &services().rooms.read_receipt;
+	let mut room_account_data = BTreeMap::new();
+	let mut receipts = BTreeMap::new();
+	for room_id in joined.iter().chain(&left) {
+		let data = account_data.changes_since(Some(room_id), user_id, 0)?;
+		if !data.is_empty() {
+			room_account_data.insert(room_id.clone(), data.into_values().collect::<Vec<_>>());
+		}
+
+		let public = read_receipt
+			.readreceipts_since(room_id, 0)
+			.filter_map(Result::ok)
+			.filter(|(receipt_user, ..)| receipt_user == user_id)
+			.map(|(_, _, event)| event)
+			.collect::<Vec<_>>();
+		let private = read_receipt.private_read_get(room_id, user_id)?;
+		if !public.is_empty() || private.is_some() {
+			receipts.insert(
+				room_id.clone(),
+				json!({
+					"receipts": public,
+					"private_read_count": private,
+				}),
+			);
+		}
+
+		let sent = services()
+			.rooms
+			.timeline
+			.pdus_after(user_id, room_id, PduCount::min())?
+			.filter_map(Result::ok)
+			.filter(|(_, pdu)| pdu.sender == user_id)
+			.map(|(_, pdu)| pdu.to_room_event())
+			.collect::<Vec<_>>();
+		if !sent.is_empty() {
+			archive
+				.add_json(&format!("events/{room_id}.json"), &json!(sent))
+				.await?;
+		}
+	}
+
+	archive
+		.add_json(
+			"account_data.json",
+			&json!({
+				"global": account_data.changes_since(None, user_id, 0)?.into_values().collect::<Vec<_>>(),
+				"rooms": room_account_data,
+			}),
+		)
+		.await?;
+	archive
+		.add_json("read_receipts.json", &json!(receipts))
+		.await?;
+	archive
+		.add_json("pushers.json", &json!(services().pusher.get_pushers(user_id)?))
+		.await?;
+
+	let key_backups = &services().key_backups;
+	let key_backup = match key_backups.get_latest_backup(user_id)? {
+		Some((version, algorithm)) => json!({
+			"version": version,
+			"algorithm": algorithm,
+			"count": key_backups.count_keys(user_id, &version)?,
+			"etag": key_backups.get_etag(user_id, &version)?,
+		}),
+		None => Value::Null,
+	};
+	archive.add_json("key_backup.json", &key_backup).await?;
+
+	let mut media = BTreeMap::new();
+	for mxc in services().media.get_all_user_mxcs(user_id) {
+		let Some(file) = services().media.get(mxc.clone()).await? else {
+			continue;
+		};
+
+		let name = format!("media/{}", mxc.trim_start_matches("mxc://"));
+		media.insert(
+			mxc,
+			json!({
+				"file": name,
+				"content_type": file.content_type,
+				"content_disposition": file.content_disposition,
+			}),
+		);
+		archive.add(name, file.file).await?;
+	}
+	archive.add_json("media.json", &json!(media)).await
+}
+
+/// Parses the user of a suspend/lock/shadow-ban/login-as command, which has to
+/// be an existing local user other than the server account.
+fn restrictable_user(user_id: &str) -> Result<Result<OwnedUserId, RoomMessageEventContent>> {
+	let user_id = match UserId::parse_with_server_name(user_id.to_lowercase(), services().globals.server_name()) {
+		Ok(id) => id,
+		Err(e) => {
+			return Ok(Err(RoomMessageEventContent::text_plain(format!(
+				"The supplied username is not a valid username: {e}"
+			))))
+		},
+	};
+
+	if !user_is_local(&user_id) {
+		return Ok(Err(RoomMessageEventContent::text_plain("User does not belong to our server.")));
+	}
+
+	if !services().users.exists(&user_id)? {
+		return Ok(Err(RoomMessageEventContent::text_plain("User does not exist on this server.")));
+	}
+
+	if user_id
+		== UserId::parse_with_server_name("conduit", services().globals.server_name()).expect("conduit user exists")
+	{
+		return Ok(Err(RoomMessageEventContent::text_plain(
+			"Not allowed to restrict the Conduit service account.",
+		)));
+	}
+
+	Ok(Ok(user_id))
+}
+
 pub(crate) async fn list_joined_rooms(_body: Vec<&str>, user_id: String) -> Result<RoomMessageEventContent> {
 	// Validate user id
 	let user_id =
@@ -1,26 +1,37 @@
 use std::fmt::Write;
 
-use conduit::debug_info;
+use axum::{
+	extract::Query,
+	response::{Html, IntoResponse, Redirect, Response},
+	Form, Json,
+};
+use conduit::{debug_info, utils::HtmlEscape};
+use http::StatusCode;
 use register::RegistrationKind;
 use ruma::{
 	api::client::{
 		account::{
-			change_password, check_registration_token_validity, deactivate, get_3pids, get_username_availability,
+			add_3pid, change_password, check_registration_token_validity, deactivate, delete_3pid, get_3pids,
+			get_username_availability,
 			register::{self, LoginType},
-			request_3pid_management_token_via_email, request_3pid_management_token_via_msisdn, whoami,
+			request_3pid_management_token_via_email, request_3pid_management_token_via_msisdn,
+			request_password_change_token_via_email, request_registration_token_via_email, whoami,
 			ThirdPartyIdRemovalStatus,
 		},
 		error::ErrorKind,
-		uiaa::{AuthFlow, AuthType, UiaaInfo},
+		uiaa::{AuthData, AuthFlow, AuthType, EmailIdentity, UiaaInfo},
 	},
 	events::{room::message::RoomMessageEventContent, GlobalAccountDataEventType},
-	push, UserId,
+	push,
+	thirdparty::Medium,
+	OwnedUserId, UserId,
 };
+use serde::Deserialize;
 use tracing::{error, info, warn};
 
 use super::{join_room_by_id_helper, DEVICE_ID_LENGTH, SESSION_ID_LENGTH, TOKEN_LENGTH};
 use crate::{
-	service::user_is_local,
+	service::{threepid, user_is_local, users},
 	services,
 	utils::{self},
 	Error, Result, Ruma,
@@ -49,7 +60,7 @@ pub(crate) async fn get_register_available_route(
 		.ok_or(Error::BadRequest(ErrorKind::InvalidUsername, "Username is invalid."))?;
 
 	// Check if username is creative enough
-	if services().users.exists(&user_id)? {
+	if services().users.exists(&user_id)? || services().server_notices.is_notices_user(&user_id) {
 		return Err(Error::BadRequest(ErrorKind::UserInUse, "Desired user ID is already taken."));
 	}
 
@@ -80,7 +91,9 @@ pub(crate) async fn get_register_available_route(
 /// - Only works if registration is enabled
 /// - If type is guest: ignores all parameters except
 ///   initial_device_display_name
-/// - If sender is not appservice: Requires UIAA (but we only use a dummy stage)
+/// - If sender is not appservice: Requires UIAA (a dummy stage, unless
+///   registration tokens, email addresses or terms of service are required) and
+///   the password to satisfy the password policy
 /// - If type is not guest and no username is given: Always fails after UIAA
 ///   check
 /// - Creates a new account and populates it with default account data
@@ -99,9 +112,11 @@ pub(crate) async fn register_route(body: Ruma<register::v3::Request>) -> Result<
 
 	let is_guest = body.kind == RegistrationKind::Guest;
 
+	users::check_client_device_id(body.device_id.as_deref())?;
+
 	if is_guest
 		&& (!services().globals.allow_guest_registration()
-			|| (services().globals.allow_registration() && services().globals.config.registration_token.is_some()))
+			|| (services().globals.allow_registration() && services().registration_tokens.required()))
 	{
 		info!(
 			"Guest registration disabled / registration enabled with token configured, rejecting guest registration, \
@@ -133,7 +148,9 @@ pub(crate) async fn register_route(body: Ruma<register::v3::Request>) -> Result<
 					.filter(|user_id| !user_id.is_historical() && user_is_local(user_id))
 					.ok_or(Error::BadRequest(ErrorKind::InvalidUsername, "Username is invalid."))?;
 
-			if services().users.exists(&proposed_user_id)? {
+			if services().users.exists(&proposed_user_id)?
+				|| services().server_notices.is_notices_user(&proposed_user_id)
+			{
 				return Err(Error::BadRequest(ErrorKind::UserInUse, "Desired user ID is already taken."));
 			}
 
@@ -171,46 +188,68 @@ pub(crate) async fn register_route(body: Ruma<register::v3::Request>) -> Result<
 		return Err(Error::BadRequest(ErrorKind::Exclusive, "User ID reserved by appservice."));
 	}
 
+	if body.appservice_info.is_none() {
+		if let Some(password) = &body.password {
+			services().users.check_password_policy(&user_id, password)?;
+		}
+	}
+
 	// UIAA
-	let mut uiaainfo;
-	let skip_auth = if services().globals.config.registration_token.is_some() {
-		// Registration token required
-		uiaainfo = UiaaInfo {
-			flows: vec![AuthFlow {
-				stages: vec![AuthType::RegistrationToken],
-			}],
-			completed: Vec::new(),
-			params: Box::default(),
-			session: None,
-			auth_error: None,
-		};
-		body.appservice_info.is_some()
-	} else {
-		// No registration token necessary, but clients must still go through the flow
-		uiaainfo = UiaaInfo {
-			flows: vec![AuthFlow {
-				stages: vec![AuthType::Dummy],
-			}],
-			completed: Vec::new(),
-			params: Box::default(),
-			session: None,
-			auth_error: None,
-		};
-		body.appservice_info.is_some() || is_guest
+	let mut stages = Vec::new();
+	if services().registration_tokens.required() {
+		stages.push(AuthType::RegistrationToken);
+	}
+	let terms_params = services().uiaa.terms_params();
+	if terms_params.is_some() {
+		stages.push(AuthType::Terms);
+	}
+
+	let mut flows = Vec::new();
+	if services().threepid.email_enabled() {
+		let mut email_stages = stages.clone();
+		email_stages.push(AuthType::EmailIdentity);
+		flows.push(AuthFlow {
+			stages: email_stages,
+		});
+	}
+
+	if !services().globals.config.smtp.registration_requires_email {
+		// Clients must still go through the flow even if nothing is required
+		if stages.is_empty() {
+			stages.push(AuthType::Dummy);
+		}
+		flows.push(AuthFlow {
+			stages,
+		});
+	}
+
+	let mut uiaainfo = UiaaInfo {
+		flows,
+		completed: Vec::new(),
+		params: terms_params.unwrap_or_default(),
+		session: None,
+		auth_error: None,
 	};
+	let skip_auth = body.appservice_info.is_some() || (is_guest && !services().registration_tokens.required());
 
+	let mut uiaa_session = None;
 	if !skip_auth {
 		if let Some(auth) = &body.auth {
-			let (worked, uiaainfo) = services().uiaa.try_auth(
-				&UserId::parse_with_server_name("", services().globals.server_name()).expect("we know this is valid"),
-				"".into(),
-				auth,
-				&uiaainfo,
-			)?;
+			let (worked, uiaainfo) = services()
+				.uiaa
+				.try_auth(
+					&UserId::parse_with_server_name("", services().globals.server_name())
+						.expect("we know this is valid"),
+					"".into(),
+					auth,
+					&uiaainfo,
+				)
+				.await?;
 			if !worked {
 				return Err(Error::Uiaa(uiaainfo));
 			}
-		// Success!
+			// Success!
+			uiaa_session = uiaainfo.session;
 		} else if let Some(json) = body.json_body {
 			uiaainfo.session = Some(utils::random_string(SESSION_ID_LENGTH));
 			services().uiaa.create(
@@ -231,8 +270,59 @@ pub(crate) async fn register_route(body: Ruma<register::v3::Request>) -> Result<
 		body.password.as_deref()
 	};
 
+	// The email address validated during registration
+	let threepid = match &uiaa_session {
+		Some(session) => services().threepid.take_uiaa_session(session)?,
+		None => None,
+	};
+	if threepid.is_none()
+		&& !is_guest
+		&& body.appservice_info.is_none()
+		&& services().globals.config.smtp.registration_requires_email
+	{
+		return Err(Error::BadRequest(
+			ErrorKind::ThreepidAuthFailed,
+			"Registration requires a validated email address.",
+		));
+	}
+
+	if let Some(session) = &uiaa_session {
+		// Attribute the registration to the registration token it used, before
+		// creating the user as this fails if the reserved use of the token expired.
+		// This is undone below if the user cannot be created.
+		services()
+			.registration_tokens
+			.complete_registration(session, &user_id)?;
+	}
+
+	// Bind the email address before creating the user, so that registration
+	// fails if another account took it in the meantime
+	if let Some(threepid) = &threepid {
+		if let Err(e) = services().threepid.add(&user_id, threepid) {
+			services()
+				.registration_tokens
+				.abort_registration(&user_id)?;
+			return Err(e);
+		}
+	}
+
 	// Create user
-	services().users.create(&user_id, password)?;
+	if let Err(e) = services().users.create(&user_id, password) {
+		if let Some(threepid) = &threepid {
+			services()
+				.threepid
+				.remove(&user_id, &threepid.medium, &threepid.address)?;
+		}
+		services()
+			.registration_tokens
+			.abort_registration(&user_id)?;
+		return Err(e);
+	}
+
+	if uiaa_session.is_some() {
+		// Every flow includes the terms of service stage if there are terms
+		services().users.accept_terms(&user_id)?;
+	}
 
 	// Default to pretty displayname
 	let mut displayname = user_id.localpart().to_owned();
@@ -285,9 +375,22 @@ pub(crate) async fn register_route(body: Ruma<register::v3::Request>) -> Result<
 	let token = utils::random_string(TOKEN_LENGTH);
 
 	// Create device for this account
-	services()
-		.users
-		.create_device(&user_id, &device_id, &token, body.initial_device_display_name.clone())?;
+	services().users.create_device(
+		&user_id,
+		&device_id,
+		&token,
+		body.initial_device_display_name.clone(),
+		body.client_ip,
+		body.user_agent.as_deref(),
+	)?;
+
+	let refresh_token = body
+		.refresh_token
+		.then(|| utils::random_string(TOKEN_LENGTH));
+	let (expires_in, refresh_token) =
+		services()
+			.users
+			.set_token_expiry(&user_id, &device_id, &token, refresh_token.as_deref())?;
 
 	debug_info!(%user_id, %device_id, "User account was created");
 
@@ -394,8 +497,8 @@ pub(crate) async fn register_route(body: Ruma<register::v3::Request>) -> Result<
 		access_token: Some(token),
 		user_id,
 		device_id: Some(device_id),
-		refresh_token: None,
-		expires_in: None,
+		refresh_token,
+		expires_in,
 	})
 }
 
@@ -403,7 +506,11 @@ pub(crate) async fn register_route(body: Ruma<register::v3::Request>) -> Result<
 ///
 /// Changes the password of this account.
 ///
-/// - Requires UIAA to verify user password
+/// - Requires UIAA to verify user password, or an email address validated with
+///   [`POST /_matrix/client/v3/account/password/email/requestToken`](fn.
+///   request_password_change_token_via_email_route.html) when resetting a
+///   forgotten password without an access token
+/// - Requires the new password to satisfy the password policy
 /// - Changes the password of the sender user
 /// - The password hash is calculated using argon2 with 32 character salt, the
 ///   plain password is
@@ -419,9 +526,15 @@ pub(crate) async fn register_route(body: Ruma<register::v3::Request>) -> Result<
 pub(crate) async fn change_password_route(
 	body: Ruma<change_password::v3::Request>,
 ) -> Result<change_password::v3::Response> {
-	let sender_user = body.sender_user.as_ref().expect("user is authenticated");
+	let Some(sender_user) = body.sender_user.as_ref() else {
+		return reset_password(body).await;
+	};
 	let sender_device = body.sender_device.as_ref().expect("user is authenticated");
 
+	services()
+		.users
+		.check_password_policy(sender_user, &body.new_password)?;
+
 	let mut uiaainfo = UiaaInfo {
 		flows: vec![AuthFlow {
 			stages: vec![AuthType::Password],
@@ -435,7 +548,8 @@ pub(crate) async fn change_password_route(
 	if let Some(auth) = &body.auth {
 		let (worked, uiaainfo) = services()
 			.uiaa
-			.try_auth(sender_user, sender_device, auth, &uiaainfo)?;
+			.try_auth(sender_user, sender_device, auth, &uiaainfo)
+			.await?;
 		if !worked {
 			return Err(Error::Uiaa(uiaainfo));
 		}
@@ -477,6 +591,111 @@ pub(crate) async fn change_password_route(
 	Ok(change_password::v3::Response {})
 }
 
+/// Resets the password of the account an email address validated through UIAA
+/// is bound to, for users who are not logged in.
+async fn reset_password(body: Ruma<change_password::v3::Request>) -> Result<change_password::v3::Response> {
+	if !services().threepid.email_enabled() {
+		return Err(Error::BadRequest(ErrorKind::MissingToken, "Missing access token."));
+	}
+
+	let mut uiaainfo = UiaaInfo {
+		flows: vec![AuthFlow {
+			stages: vec![AuthType::EmailIdentity],
+		}],
+		completed: Vec::new(),
+		params: Box::default(),
+		session: None,
+		auth_error: None,
+	};
+
+	// Nobody is logged in, so the session is not bound to a user like during
+	// registration
+	let anonymous =
+		UserId::parse_with_server_name("", services().globals.server_name()).expect("we know this is valid");
+
+	// Check the password policy before the email validation is used up
+	if let Some(AuthData::EmailIdentity(EmailIdentity {
+		thirdparty_id_creds,
+		..
+	})) = &body.auth
+	{
+		if let Some(threepid) = services()
+			.threepid
+			.validated_session(thirdparty_id_creds.sid.as_str(), thirdparty_id_creds.client_secret.as_str())?
+		{
+			if let Some(user_id) = services()
+				.threepid
+				.find_user(&threepid.medium, &threepid.address)?
+			{
+				services()
+					.users
+					.check_password_policy(&user_id, &body.new_password)?;
+			}
+		}
+	}
+
+	let uiaa_session = if let Some(auth) = &body.auth {
+		let (worked, uiaainfo) = services()
+			.uiaa
+			.try_auth(&anonymous, "".into(), auth, &uiaainfo)
+			.await?;
+		if !worked {
+			return Err(Error::Uiaa(uiaainfo));
+		}
+
+		uiaainfo.session.expect("session is always set")
+	} else if let Some(json) = body.json_body {
+		uiaainfo.session = Some(utils::random_string(SESSION_ID_LENGTH));
+		services()
+			.uiaa
+			.create(&anonymous, "".into(), &uiaainfo, &json)?;
+		return Err(Error::Uiaa(uiaainfo));
+	} else {
+		return Err(Error::BadRequest(ErrorKind::NotJson, "Not json."));
+	};
+
+	let Some(threepid) = services().threepid.take_uiaa_session(&uiaa_session)? else {
+		return Err(Error::BadRequest(
+			ErrorKind::ThreepidAuthFailed,
+			"Email address has not been validated.",
+		));
+	};
+
+	let Some(user_id) = services()
+		.threepid
+		.find_user(&threepid.medium, &threepid.address)?
+	else {
+		return Err(Error::BadRequest(
+			ErrorKind::ThreepidNotFound,
+			"Email address is not bound to an account.",
+		));
+	};
+
+	services()
+		.users
+		.set_password(&user_id, Some(&body.new_password))?;
+
+	if body.logout_devices {
+		for id in services()
+			.users
+			.all_device_ids(&user_id)
+			.filter_map(Result::ok)
+		{
+			services().users.remove_device(&user_id, &id)?;
+		}
+	}
+
+	info!("User {user_id} reset their password using their email address.");
+	services()
+		.admin
+		.send_message(RoomMessageEventContent::notice_plain(format!(
+			"User {user_id} reset their password using their email address."
+		)))
+		.await;
+
+	Ok(change_password::v3::Response {})
+}
+
 /// # `GET _matrix/client/r0/account/whoami`
 ///
 /// Get `user_id` of the sender user.
@@ -521,7 +740,8 @@ pub(crate) async fn deactivate_route(body: Ruma<deactivate::v3::Request>) -> Res
 	if let Some(auth) = &body.auth {
 		let (worked, uiaainfo) = services()
 			.uiaa
-			.try_auth(sender_user, sender_device, auth, &uiaainfo)?;
+			.try_auth(sender_user, sender_device, auth, &uiaainfo)
+			.await?;
 		if !worked {
 			return Err(Error::Uiaa(uiaainfo));
 		}
@@ -542,11 +762,20 @@ pub(crate) async fn deactivate_route(body: Ruma<deactivate::v3::Request>) -> Res
 	// Remove devices and mark account as deactivated
 	services().users.deactivate_account(sender_user)?;
 
-	info!("User {} deactivated their account.", sender_user);
+	if body.erase {
+		services().users.erase_account(sender_user).await?;
+	}
+
+	let erased = if body.erase {
+		" and asked to be erased"
+	} else {
+		""
+	};
+	info!("User {sender_user} deactivated their account{erased}.");
 	services()
 		.admin
 		.send_message(RoomMessageEventContent::notice_plain(format!(
-			"User {sender_user} deactivated their account."
+			"User {sender_user} deactivated their account{erased}."
 		)))
 		.await;
 
@@ -558,12 +787,106 @@ pub(crate) async fn deactivate_route(body: Ruma<deactivate::v3::Request>) -> Res
 /// # `GET _matrix/client/v3/account/3pid`
 ///
 /// Get a list of third party identifiers associated with this account.
-///
-/// - Currently always returns empty list
 pub(crate) async fn third_party_route(body: Ruma<get_3pids::v3::Request>) -> Result<get_3pids::v3::Response> {
-	let _sender_user = body.sender_user.as_ref().expect("user is authenticated");
+	let sender_user = body.sender_user.as_ref().expect("user is authenticated");
+
+	let threepids = services()
+		.threepid
+		.threepids(sender_user)
+		.collect::<Result<Vec<_>>>()?;
+
+	Ok(get_3pids::v3::Response::new(threepids))
+}
+
+/// # `POST /_matrix/client/v3/account/3pid/add`
+///
+/// Binds an email address validated with [`POST
+/// /_matrix/client/v3/account/3pid/email/requestToken`](fn.
+/// request_3pid_management_token_via_email_route.html) to this account.
+///
+/// - Requires UIAA to verify user password
+pub(crate) async fn add_3pid_route(body: Ruma<add_3pid::v3::Request>) -> Result<add_3pid::v3::Response> {
+	let sender_user = body.sender_user.as_ref().expect("user is authenticated");
+	let sender_device = body.sender_device.as_ref().expect("user is authenticated");
+
+	let mut uiaainfo = UiaaInfo {
+		flows: vec![AuthFlow {
+			stages: vec![AuthType::Password],
+		}],
+		completed: Vec::new(),
+		params: Box::default(),
+		session: None,
+		auth_error: None,
+	};
+
+	if let Some(auth) = &body.auth {
+		let (worked, uiaainfo) = services()
+			.uiaa
+			.try_auth(sender_user, sender_device, auth, &uiaainfo)
+			.await?;
+		if !worked {
+			return Err(Error::Uiaa(uiaainfo));
+		}
+	// Success!
+	} else if let Some(json) = body.json_body {
+		uiaainfo.session = Some(utils::random_string(SESSION_ID_LENGTH));
+		services()
+			.uiaa
+			.create(sender_user, sender_device, &uiaainfo, &json)?;
+		return Err(Error::Uiaa(uiaainfo));
+	} else {
+		return Err(Error::BadRequest(ErrorKind::NotJson, "Not json."));
+	}
+
+	let Some(threepid) = services()
+		.threepid
+		.validated_session(body.sid.as_str(), body.client_secret.as_str())?
+	else {
+		return Err(Error::BadRequest(
+			ErrorKind::ThreepidAuthFailed,
+			"Third party identifier has not been validated.",
+		));
+	};
+
+	services().threepid.add(sender_user, &threepid)?;
 
-	Ok(get_3pids::v3::Response::new(Vec::new()))
+	info!(
+		"User {sender_user} added {} {} to their account.",
+		threepid.medium, threepid.address
+	);
+
+	Ok(add_3pid::v3::Response {})
+}
+
+/// # `POST /_matrix/client/v3/account/3pid/delete`
+///
+/// Removes a third party identifier from this account.
+///
+/// - Identity servers are not supported, so nothing is ever unbound from one
+pub(crate) async fn delete_3pid_route(body: Ruma<delete_3pid::v3::Request>) -> Result<delete_3pid::v3::Response> {
+	let sender_user = body.sender_user.as_ref().expect("user is authenticated");
+
+	let address = if body.medium == Medium::Email {
+		body.address.trim().to_lowercase()
+	} else {
+		body.address.clone()
+	};
+
+	if !services()
+		.threepid
+		.remove(sender_user, &body.medium, &address)?
+	{
+		return Err(Error::BadRequest(
+			ErrorKind::ThreepidNotFound,
+			"Third party identifier is not bound to your account.",
+		));
+	}
+
+	info!("User {sender_user} removed {} {address} from their account.", body.medium);
+
+	Ok(delete_3pid::v3::Response {
+		id_server_unbind_result: ThirdPartyIdRemovalStatus::NoSupport,
+	})
 }
 
 /// # `POST /_matrix/client/v3/account/3pid/email/requestToken`
@@ -573,13 +896,196 @@ pub(crate) async fn third_party_route(body: Ruma<get_3pids::v3::Request>) -> Res
 ///
 /// - 403 signals that The homeserver does not allow the third party identifier
 ///   as a contact option.
+/// - Sends the validation token through the SMTP relay from the `smtp` config
 pub(crate) async fn request_3pid_management_token_via_email_route(
-	_body: Ruma<request_3pid_management_token_via_email::v3::Request>,
+	body: Ruma<request_3pid_management_token_via_email::v3::Request>,
 ) -> Result<request_3pid_management_token_via_email::v3::Response> {
-	Err(Error::BadRequest(
-		ErrorKind::ThreepidDenied,
-		"Third party identifier is not allowed",
+	let sid = services()
+		.threepid
+		.request_email_token(
+			threepid::Purpose::AddThreepid,
+			body.client_secret.as_str(),
+			&body.email,
+			body.send_attempt,
+			body.next_link.as_deref(),
+			body.client_ip,
+		)
+		.await?;
+
+	let mut response = request_3pid_management_token_via_email::v3::Response::new(
+		sid.try_into().expect("generated session ids are valid"),
+	);
+	response.submit_url = Some(threepid::submit_url().to_string());
+
+	Ok(response)
+}
+
+/// # `POST /_matrix/client/v3/register/email/requestToken`
+///
+/// Sends a validation token to an email address that is not in use yet, for
+/// the `m.login.email.identity` registration stage.
+pub(crate) async fn request_registration_token_via_email_route(
+	body: Ruma<request_registration_token_via_email::v3::Request>,
+) -> Result<request_registration_token_via_email::v3::Response> {
+	if !services().globals.allow_registration() {
+		return Err(Error::BadRequest(ErrorKind::forbidden(), "Registration has been disabled."));
+	}
+
+	let sid = services()
+		.threepid
+		.request_email_token(
+			threepid::Purpose::Register,
+			body.client_secret.as_str(),
+			&body.email,
+			body.send_attempt,
+			body.next_link.as_deref(),
+			body.client_ip,
+		)
+		.await?;
+
+	let mut response = request_registration_token_via_email::v3::Response::new(
+		sid.try_into().expect("generated session ids are valid"),
+	);
+	response.submit_url = Some(threepid::submit_url().to_string());
+
+	Ok(response)
+}
+
+/// # `POST /_matrix/client/v3/account/password/email/requestToken`
+///
+/// Sends a validation token to an email address bound to an account, which
+/// lets a user who forgot their password reset it with [`POST
+/// /_matrix/client/r0/account/password`](fn.change_password_route.html).
+pub(crate) async fn request_password_change_token_via_email_route(
+	body: Ruma<request_password_change_token_via_email::v3::Request>,
+) -> Result<request_password_change_token_via_email::v3::Response> {
+	let sid = services()
+		.threepid
+		.request_email_token(
+			threepid::Purpose::PasswordReset,
+			body.client_secret.as_str(),
+			&body.email,
+			body.send_attempt,
+			body.next_link.as_deref(),
+			body.client_ip,
+		)
+		.await?;
+
+	let mut response = request_password_change_token_via_email::v3::Response::new(
+		sid.try_into().expect("generated session ids are valid"),
+	);
+	response.submit_url = Some(threepid::submit_url().to_string());
+
+	Ok(response)
+}
+
+#[derive(Deserialize)]
+pub(crate) struct SubmitToken {
+	sid: String,
+	client_secret: String,
+	token: String,
+}
+
+/// # `GET /_conduwuit/client/email/submit_token`
+///
+/// Target of the link in validation emails. Validates the session and
+/// redirects to the `next_link` the client gave, if any.
+pub(crate) async fn submit_email_token_link_route(Query(query): Query<SubmitToken>) -> Response {
+	match services()
+		.threepid
+		.submit_token(&query.sid, &query.client_secret, &query.token)
+	{
+		Ok(Some(next_link)) if next_link.starts_with("https://") || next_link.starts_with("http://") => {
+			Redirect::to(&next_link).into_response()
+		},
+		Ok(_) => "Your email address has been validated, you can now return to your client.".into_response(),
+		Err(e) => (StatusCode::BAD_REQUEST, format!("Failed to validate your email address: {e}")).into_response(),
+	}
+}
+
+/// # `POST /_conduwuit/client/email/submit_token`
+///
+/// The `submit_url` returned from `requestToken`, for clients that let the
+/// user enter the validation token themselves.
+pub(crate) async fn submit_email_token_route(Json(body): Json<SubmitToken>) -> Result<impl IntoResponse> {
+	services()
+		.threepid
+		.submit_token(&body.sid, &body.client_secret, &body.token)?;
+
+	Ok(Json(serde_json::json!({
+		"success": true,
+	})))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct Consent {
+	u: String,
+	t: String,
+}
+
+/// # `GET /_conduwuit/consent`
+///
+/// Target of the `consent_uri` in `M_CONSENT_NOT_GIVEN` errors. Links the
+/// current terms of service and asks the user to accept them.
+pub(crate) async fn consent_form_route(Query(query): Query<Consent>) -> Response {
+	let terms = &services().globals.config.terms;
+	let (Some(version), Some(url)) = (&terms.version, &terms.url) else {
+		return (StatusCode::NOT_FOUND, "This server has no terms of service.").into_response();
+	};
+
+	if let Err(e) = consent_user(&query) {
+		return (StatusCode::FORBIDDEN, e.to_string()).into_response();
+	}
+
+	Html(format!(
+		"<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{name}</title></head><body>\n<p>Please read the \
+		 <a href=\"{url}\">{name}</a> (version {version}) of {server_name}.</p>\n<form method=\"post\">\n<input \
+		 type=\"hidden\" name=\"u\" value=\"{user_id}\">\n<input type=\"hidden\" name=\"t\" \
+		 value=\"{token}\">\n<button type=\"submit\">I have read and agree to the \
+		 {name}</button>\n</form>\n</body></html>\n",
+		name = HtmlEscape(&terms.name),
+		url = HtmlEscape(url.as_str()),
+		version = HtmlEscape(version),
+		server_name = HtmlEscape(services().globals.server_name().as_str()),
+		user_id = HtmlEscape(&query.u),
+		token = HtmlEscape(&query.t),
 	))
+	.into_response()
+}
+
+/// # `POST /_conduwuit/consent`
+///
+/// Records that the user accepted the current terms of service.
+pub(crate) async fn consent_route(Form(form): Form<Consent>) -> Response {
+	if !services().globals.config.terms.enabled() {
+		return (StatusCode::NOT_FOUND, "This server has no terms of service.").into_response();
+	}
+
+	let user_id = match consent_user(&form) {
+		Ok(user_id) => user_id,
+		Err(e) => return (StatusCode::FORBIDDEN, e.to_string()).into_response(),
+	};
+
+	if let Err(e) = services().users.accept_terms(&user_id) {
+		error!("Failed to record that {user_id} accepted the terms of service: {e}");
+		return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to record your consent.").into_response();
+	}
+
+	info!("{user_id} accepted the terms of service");
+
+	"Thank you, you can now return to your client.".into_response()
+}
+
+/// Authenticates the user on the consent page by the token in their link.
+fn consent_user(consent: &Consent) -> Result<OwnedUserId> {
+	let user_id = UserId::parse(consent.u.as_str())
+		.map_err(|_| Error::BadRequest(ErrorKind::InvalidParam, "Invalid user ID."))?;
+
+	if !services().users.check_consent_token(&user_id, &consent.t)? {
+		return Err(Error::BadRequest(ErrorKind::forbidden(), "Invalid consent link."));
+	}
+
+	Ok(user_id)
 }
 
 /// # `POST /_matrix/client/v3/account/3pid/msisdn/requestToken`
@@ -600,21 +1106,22 @@ pub(crate) async fn request_3pid_management_token_via_msisdn_route(
 
 /// # `GET /_matrix/client/v1/register/m.login.registration_token/validity`
 ///
-/// Checks if the provided registration token is valid at the time of checking
+/// Checks if the provided registration token is valid at the time of checking,
+/// either the static token from the config or a token created by an admin that
+/// has not expired or run out of uses.
 ///
-/// Currently does not have any ratelimiting, and this isn't very practical as
-/// there is only one registration token allowed.
+/// Currently does not have any ratelimiting.
 pub(crate) async fn check_registration_token_validity(
 	body: Ruma<check_registration_token_validity::v1::Request>,
 ) -> Result<check_registration_token_validity::v1::Response> {
-	let Some(reg_token) = services().globals.config.registration_token.clone() else {
+	if !services().registration_tokens.required() {
 		return Err(Error::BadRequest(
 			ErrorKind::forbidden(),
 			"Server does not allow token registration.",
 		));
-	};
+	}
 
 	Ok(check_registration_token_validity::v1::Response {
-		valid: reg_token == body.token,
+		valid: services().registration_tokens.is_valid(&body.token)?,
 	})
 }
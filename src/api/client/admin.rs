@@ -0,0 +1,173 @@
+//! conduwuit-specific HTTP admin API, for automating what the admin room
+//! commands do. All endpoints require the access token of a server admin.
+
+use axum::{extract::Path, response::IntoResponse, Json};
+use ruma::{api::client::error::ErrorKind, events::room::message::RoomMessageEventContent, UserId};
+use serde::{de::IgnoredAny, Deserialize, Deserializer};
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::{
+	service::{user_is_local, users::IMPERSONATION_TOKEN_TTL},
+	services, AdminRequest, Error, Result,
+};
+
+#[derive(Deserialize)]
+pub(crate) struct CreateRegistrationToken {
+	token: Option<String>,
+	uses_allowed: Option<u64>,
+	/// Milliseconds since the unix epoch
+	expiry_time: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct LoginAs {
+	/// How long the access token is valid, defaults to one hour
+	expires_in_ms: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct UpdateRegistrationToken {
+	/// Absent leaves the limit unchanged, `null` removes it
+	#[serde(default, deserialize_with = "double_option")]
+	uses_allowed: Option<Option<u64>>,
+	#[serde(default, deserialize_with = "double_option")]
+	expiry_time: Option<Option<u64>>,
+}
+
+/// # `GET /_conduwuit/admin/v1/registration_tokens`
+///
+/// Lists all registration tokens and their usage.
+pub(crate) async fn admin_list_registration_tokens_route(_: AdminRequest<IgnoredAny>) -> Result<impl IntoResponse> {
+	let registration_tokens = services()
+		.registration_tokens
+		.iter()
+		.collect::<Result<Vec<_>>>()?;
+
+	Ok(Json(json!({
+		"registration_tokens": registration_tokens,
+	})))
+}
+
+/// # `POST /_conduwuit/admin/v1/registration_tokens`
+///
+/// Creates a registration token, generating a random one if no token is given.
+pub(crate) async fn admin_create_registration_token_route(
+	request: AdminRequest<CreateRegistrationToken>,
+) -> Result<impl IntoResponse> {
+	let body = request.body;
+	let token = services()
+		.registration_tokens
+		.create(body.token, body.uses_allowed, body.expiry_time)?;
+
+	info!(
+		"{} created registration token {} via the admin API",
+		request.sender_user, token.token
+	);
+
+	Ok(Json(token))
+}
+
+/// # `GET /_conduwuit/admin/v1/registration_tokens/{token}`
+///
+/// Returns a registration token and its usage.
+pub(crate) async fn admin_get_registration_token_route(
+	Path(token): Path<String>, _: AdminRequest<IgnoredAny>,
+) -> Result<impl IntoResponse> {
+	let token = services()
+		.registration_tokens
+		.get(&token)?
+		.ok_or(Error::BadRequest(ErrorKind::NotFound, "Registration token not found."))?;
+
+	Ok(Json(token))
+}
+
+/// # `PUT /_conduwuit/admin/v1/registration_tokens/{token}`
+///
+/// Changes the usage limit and expiry time of a registration token.
+pub(crate) async fn admin_update_registration_token_route(
+	Path(token): Path<String>, request: AdminRequest<UpdateRegistrationToken>,
+) -> Result<impl IntoResponse> {
+	let body = request.body;
+	let token = services()
+		.registration_tokens
+		.update(&token, body.uses_allowed, body.expiry_time)?
+		.ok_or(Error::BadRequest(ErrorKind::NotFound, "Registration token not found."))?;
+
+	Ok(Json(token))
+}
+
+/// # `DELETE /_conduwuit/admin/v1/registration_tokens/{token}`
+///
+/// Revokes a registration token.
+pub(crate) async fn admin_revoke_registration_token_route(
+	Path(token): Path<String>, request: AdminRequest<IgnoredAny>,
+) -> Result<impl IntoResponse> {
+	if !services().registration_tokens.revoke(&token)? {
+		return Err(Error::BadRequest(ErrorKind::NotFound, "Registration token not found."));
+	}
+
+	info!("{} revoked registration token {token} via the admin API", request.sender_user);
+
+	Ok(Json(json!({})))
+}
+
+/// # `POST /_conduwuit/admin/v1/users/{user_id}/login`
+///
+/// Creates a temporary session for a local user and returns its access token.
+/// The session is announced in the admin room and removed once its access
+/// token expired.
+pub(crate) async fn admin_login_as_route(
+	Path(user_id): Path<String>, request: AdminRequest<LoginAs>,
+) -> Result<impl IntoResponse> {
+	let admin_id = request.sender_user;
+	let body = request.body;
+
+	let user_id = UserId::parse_with_server_name(user_id, services().globals.server_name())
+		.map_err(|_| Error::BadRequest(ErrorKind::InvalidParam, "Invalid user ID."))?;
+
+	if !user_is_local(&user_id) || !services().users.exists(&user_id)? {
+		return Err(Error::BadRequest(ErrorKind::NotFound, "User not found."));
+	}
+
+	if user_id
+		== UserId::parse_with_server_name("conduit", services().globals.server_name()).expect("conduit user exists")
+	{
+		return Err(Error::BadRequest(
+			ErrorKind::forbidden(),
+			"Cannot log in as the server account.",
+		));
+	}
+
+	if services().users.is_deactivated(&user_id)? {
+		return Err(Error::BadRequest(ErrorKind::UserDeactivated, "User is deactivated."));
+	}
+
+	let ttl = body.expires_in_ms.unwrap_or(IMPERSONATION_TOKEN_TTL);
+	let (device_id, access_token, expires_at) = services()
+		.users
+		.create_impersonation_device(&user_id, ttl)?;
+
+	warn!("{admin_id} created impersonation session {device_id} for {user_id} via the admin API");
+	services()
+		.admin
+		.send_message(RoomMessageEventContent::notice_plain(format!(
+			"{admin_id} created impersonation session {device_id} for {user_id} via the admin API."
+		)))
+		.await;
+
+	Ok(Json(json!({
+		"user_id": user_id,
+		"device_id": device_id,
+		"access_token": access_token,
+		"expires_at": expires_at,
+	})))
+}
+
+fn double_option<'de, T, D>(deserializer: D) -> std::result::Result<Option<Option<T>>, D::Error>
+where
+	T: Deserialize<'de>,
+	D: Deserializer<'de>,
+{
+	Option::<T>::deserialize(deserializer).map(Some)
+}
@@ -1,8 +1,10 @@
 use std::collections::BTreeMap;
 
 use ruma::api::client::discovery::get_capabilities::{
-	self, Capabilities, RoomVersionStability, RoomVersionsCapability, ThirdPartyIdChangesCapability,
+	self, Capabilities, GetLoginTokenCapability, RoomVersionStability, RoomVersionsCapability,
+	ThirdPartyIdChangesCapability,
 };
+use serde_json::json;
 
 use crate::{services, Result, Ruma};
 
@@ -27,11 +29,29 @@ pub(crate) async fn get_capabilities_route(
 		available,
 	};
 
-	// conduit does not implement 3PID stuff
+	// email addresses can only be changed if they can be validated
 	capabilities.thirdparty_id_changes = ThirdPartyIdChangesCapability {
-		enabled: false,
+		enabled: services().threepid.email_enabled(),
 	};
 
+	capabilities.get_login_token = GetLoginTokenCapability {
+		enabled: services().globals.config.login_via_existing_session,
+	};
+
+	let config = &services().globals.config;
+	capabilities
+		.set(
+			"m.password_policy",
+			json!({
+				"m.minimum_length": config.password_min_length,
+				"m.require_digit": config.password_require_digit,
+				"m.require_lowercase": config.password_require_lowercase,
+				"m.require_uppercase": config.password_require_uppercase,
+				"m.require_symbol": config.password_require_symbol,
+			}),
+		)
+		.expect("password policy capability is valid json");
+
 	Ok(get_capabilities::v3::Response {
 		capabilities,
 	})
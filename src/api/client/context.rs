@@ -66,6 +66,11 @@ pub(crate) async fn get_context_route(body: Ruma<get_context::v3::Request>) -> R
 	// Use limit or else 10, with maximum 100
 	let limit = usize::try_from(body.limit).unwrap_or(10).min(100);
 
+	let mut base_event = (*base_event).clone();
+	services()
+		.rooms
+		.state_accessor
+		.redact_erased_for_user(sender_user, &mut base_event)?;
 	let base_event = base_event.to_room_event();
 
 	let events_before: Vec<_> = services()
@@ -101,8 +106,14 @@ pub(crate) async fn get_context_route(body: Ruma<get_context::v3::Request>) -> R
 
 	let events_before: Vec<_> = events_before
 		.into_iter()
-		.map(|(_, pdu)| pdu.to_room_event())
-		.collect();
+		.map(|(_, mut pdu)| {
+			services()
+				.rooms
+				.state_accessor
+				.redact_erased_for_user(sender_user, &mut pdu)?;
+			Ok(pdu.to_room_event())
+		})
+		.collect::<Result<_>>()?;
 
 	let events_after: Vec<_> = services()
 		.rooms
@@ -160,8 +171,14 @@ pub(crate) async fn get_context_route(body: Ruma<get_context::v3::Request>) -> R
 
 	let events_after: Vec<_> = events_after
 		.into_iter()
-		.map(|(_, pdu)| pdu.to_room_event())
-		.collect();
+		.map(|(_, mut pdu)| {
+			services()
+				.rooms
+				.state_accessor
+				.redact_erased_for_user(sender_user, &mut pdu)?;
+			Ok(pdu.to_room_event())
+		})
+		.collect::<Result<_>>()?;
 
 	let mut state = Vec::with_capacity(state_ids.len());
 
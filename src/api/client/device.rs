@@ -88,7 +88,8 @@ pub(crate) async fn delete_device_route(body: Ruma<delete_device::v3::Request>)
 	if let Some(auth) = &body.auth {
 		let (worked, uiaainfo) = services()
 			.uiaa
-			.try_auth(sender_user, sender_device, auth, &uiaainfo)?;
+			.try_auth(sender_user, sender_device, auth, &uiaainfo)
+			.await?;
 		if !worked {
 			return Err(Error::Uiaa(uiaainfo));
 		}
@@ -142,7 +143,8 @@ pub(crate) async fn delete_devices_route(
 	if let Some(auth) = &body.auth {
 		let (worked, uiaainfo) = services()
 			.uiaa
-			.try_auth(sender_user, sender_device, auth, &uiaainfo)?;
+			.try_auth(sender_user, sender_device, auth, &uiaainfo)
+			.await?;
 		if !worked {
 			return Err(Error::Uiaa(uiaainfo));
 		}
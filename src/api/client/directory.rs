@@ -38,6 +38,7 @@ pub(crate) async fn get_public_rooms_filtered_route(
 			.globals
 			.forbidden_remote_room_directory_server_names()
 			.contains(server)
+			|| !services().globals.server_is_allowed(server)
 		{
 			return Err(Error::BadRequest(
 				ErrorKind::forbidden(),
@@ -75,6 +76,7 @@ pub(crate) async fn get_public_rooms_route(
 			.globals
 			.forbidden_remote_room_directory_server_names()
 			.contains(server)
+			|| !services().globals.server_is_allowed(server)
 		{
 			return Err(Error::BadRequest(
 				ErrorKind::forbidden(),
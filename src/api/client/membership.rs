@@ -9,18 +9,23 @@ use ruma::{
 	api::{
 		client::{
 			error::ErrorKind,
+			knock::knock_room,
 			membership::{
 				ban_user, forget_room, get_member_events, invite_user, join_room_by_id, join_room_by_id_or_alias,
-				joined_members, joined_rooms, kick_user, leave_room, unban_user, ThirdPartySigned,
+				joined_members, joined_rooms, kick_user, leave_room, unban_user, Invite3pid, ThirdPartySigned,
 			},
 		},
-		federation::{self, membership::create_invite},
+		federation::{
+			self,
+			knock::{create_knock_event_template, send_knock},
+			membership::create_invite,
+		},
 	},
 	canonical_json::to_canonical_value,
 	events::{
 		room::{
 			join_rules::{AllowRule, JoinRule, RoomJoinRulesEventContent},
-			member::{MembershipState, RoomMemberEventContent},
+			member::{MembershipState, RoomMemberEventContent, ThirdPartyInvite},
 			message::RoomMessageEventContent,
 		},
 		StateEventType, TimelineEventType,
@@ -37,6 +42,7 @@ use super::get_alias_helper;
 use crate::{
 	service::{
 		pdu::{gen_event_id_canonical_json, PduBuilder},
+		rooms::partial_state::PartialState,
 		server_is_ours, user_is_local,
 	},
 	services,
@@ -275,6 +281,49 @@ pub(crate) async fn join_room_by_id_or_alias_route(
 	})
 }
 
+/// # `POST /_matrix/client/v3/knock/{roomIdOrAlias}`
+///
+/// Tries to knock on a room to ask for an invite.
+///
+/// - If the server knowns about this room: creates the knock event and does
+///   auth rules locally
+/// - If the server does not know about the room: asks the servers in the
+///   `server_name` query param, the alias server and the room ID server over
+///   federation
+pub(crate) async fn knock_room_route(body: Ruma<knock_room::v3::Request>) -> Result<knock_room::v3::Response> {
+	let sender_user = body.sender_user.as_deref().expect("user is authenticated");
+	let body = body.body;
+
+	let (servers, room_id) = match OwnedRoomId::try_from(body.room_id_or_alias) {
+		Ok(room_id) => {
+			banned_room_check(sender_user, Some(&room_id), room_id.server_name()).await?;
+
+			let mut servers = body.server_name;
+			if let Some(server) = room_id.server_name() {
+				servers.push(server.to_owned());
+			}
+
+			(servers, room_id)
+		},
+		Err(room_alias) => {
+			let response = get_alias_helper(room_alias.clone(), Some(body.server_name.clone())).await?;
+
+			banned_room_check(sender_user, Some(&response.room_id), Some(room_alias.server_name())).await?;
+
+			let mut servers = body.server_name;
+			servers.extend(response.servers);
+
+			(servers, response.room_id)
+		},
+	};
+
+	knock_room_helper(sender_user, &room_id, body.reason, &servers).await?;
+
+	Ok(knock_room::v3::Response {
+		room_id,
+	})
+}
+
 /// # `POST /_matrix/client/v3/rooms/{roomId}/leave`
 ///
 /// Tries to leave the sender user from a room.
@@ -307,15 +356,16 @@ pub(crate) async fn invite_user_route(body: Ruma<invite_user::v3::Request>) -> R
 
 	banned_room_check(sender_user, Some(&body.room_id), body.room_id.server_name()).await?;
 
-	if let invite_user::v3::InvitationRecipient::UserId {
-		user_id,
-	} = &body.recipient
-	{
-		invite_helper(sender_user, user_id, &body.room_id, body.reason.clone(), false).await?;
-		Ok(invite_user::v3::Response {})
-	} else {
-		Err(Error::BadRequest(ErrorKind::NotFound, "User not found."))
+	match &body.recipient {
+		invite_user::v3::InvitationRecipient::UserId {
+			user_id,
+		} => invite_helper(sender_user, user_id, &body.room_id, body.reason.clone(), false, None).await?,
+		invite_user::v3::InvitationRecipient::ThirdPartyId(invite) => {
+			invite_3pid_helper(sender_user, &body.room_id, invite).await?;
+		},
 	}
+
+	Ok(invite_user::v3::Response {})
 }
 
 /// # `POST /_matrix/client/r0/rooms/{roomId}/kick`
@@ -566,6 +616,13 @@ pub(crate) async fn get_member_events_route(
 		));
 	}
 
+	// The members are only known once the room has its full state
+	services()
+		.rooms
+		.partial_state
+		.wait_for_full_state(&body.room_id)
+		.await?;
+
 	Ok(get_member_events::v3::Response {
 		chunk: services()
 			.rooms
@@ -601,6 +658,13 @@ pub(crate) async fn joined_members_route(
 		));
 	}
 
+	// The members are only known once the room has its full state
+	services()
+		.rooms
+		.partial_state
+		.wait_for_full_state(&body.room_id)
+		.await?;
+
 	let mut joined = BTreeMap::new();
 	for user_id in services()
 		.rooms
@@ -631,6 +695,14 @@ pub async fn join_room_by_id_helper(
 ) -> Result<join_room_by_id::v3::Response> {
 	let sender_user = sender_user.expect("user is authenticated");
 
+	// Pretend shadow-banned users joined, without asking a remote server
+	if services().users.is_shadow_banned(sender_user)? {
+		info!("Shadow-banned user {sender_user} attempted to join {room_id}");
+		return Ok(join_room_by_id::v3::Response {
+			room_id: room_id.into(),
+		});
+	}
+
 	if matches!(services().rooms.state_cache.is_joined(sender_user, room_id), Ok(true)) {
 		info!("{sender_user} is already joined in {room_id}");
 		return Ok(join_room_by_id::v3::Response {
@@ -736,7 +808,7 @@ async fn join_room_by_id_helper_remote(
 	// to be present
 	ruma::signatures::hash_and_sign_event(
 		services().globals.server_name().as_str(),
-		services().globals.keypair(),
+		&*services().globals.keypair(),
 		&mut join_event_stub,
 		&room_version_id,
 	)
@@ -765,7 +837,7 @@ async fn join_room_by_id_helper_remote(
 				room_id: room_id.to_owned(),
 				event_id: event_id.to_owned(),
 				pdu: PduEvent::convert_to_outgoing_federation_event(join_event.clone()),
-				omit_members: false,
+				omit_members: services().globals.config.allow_partial_state_joins,
 			},
 		)
 		.await?;
@@ -917,7 +989,7 @@ async fn join_room_by_id_helper_remote(
 	let auth_check = state_res::event_auth::auth_check(
 		&state_res::RoomVersion::new(&room_version_id).expect("room version is supported"),
 		&parsed_join_pdu,
-		None::<PduEvent>, // TODO: third party invite
+		None::<PduEvent>, // join events never carry a third party invite
 		|k, s| {
 			services()
 				.rooms
@@ -993,6 +1065,28 @@ async fn join_room_by_id_helper_remote(
 		.state
 		.set_room_state(room_id, statehash_after_join, &state_lock)?;
 
+	if send_join_response.room_state.members_omitted {
+		info!("Joined {room_id} with partial state, resyncing the full state in the background");
+		let mut servers: Vec<OwnedServerName> = send_join_response
+			.room_state
+			.servers_in_room
+			.iter()
+			.flatten()
+			.filter_map(|server| OwnedServerName::try_from(server.as_str()).ok())
+			.collect();
+		if !servers.contains(&remote_server) {
+			servers.insert(0, remote_server);
+		}
+
+		services().rooms.partial_state.mark_partial(
+			room_id,
+			&PartialState {
+				join_event_id: event_id.to_owned(),
+				servers,
+			},
+		)?;
+	}
+
 	Ok(join_room_by_id::v3::Response::new(room_id.to_owned()))
 }
 
@@ -1166,7 +1260,7 @@ async fn join_room_by_id_helper_local(
 		// to be present
 		ruma::signatures::hash_and_sign_event(
 			services().globals.server_name().as_str(),
-			services().globals.keypair(),
+			&*services().globals.keypair(),
 			&mut join_event_stub,
 			&room_version_id,
 		)
@@ -1310,6 +1404,224 @@ async fn make_join_request(
 	make_join_response_and_server
 }
 
+async fn knock_room_helper(
+	sender_user: &UserId, room_id: &RoomId, reason: Option<String>, servers: &[OwnedServerName],
+) -> Result<()> {
+	// Pretend shadow-banned users knocked, without asking a remote server
+	if services().users.is_shadow_banned(sender_user)? {
+		info!("Shadow-banned user {sender_user} attempted to knock on {room_id}");
+		return Ok(());
+	}
+
+	if services()
+		.rooms
+		.state_cache
+		.is_joined(sender_user, room_id)?
+	{
+		return Err(Error::BadRequest(
+			ErrorKind::forbidden(),
+			"You are already joined to this room.",
+		));
+	}
+
+	if services()
+		.rooms
+		.state_cache
+		.is_invited(sender_user, room_id)?
+	{
+		return Err(Error::BadRequest(
+			ErrorKind::forbidden(),
+			"You are already invited to this room, join it instead.",
+		));
+	}
+
+	let mutex_state = Arc::clone(
+		services()
+			.globals
+			.roomid_mutex_state
+			.write()
+			.await
+			.entry(room_id.to_owned())
+			.or_default(),
+	);
+	let state_lock = mutex_state.lock().await;
+
+	// Ask a remote server if we are not participating in this room
+	if !services()
+		.rooms
+		.state_cache
+		.server_in_room(services().globals.server_name(), room_id)?
+	{
+		drop(state_lock);
+		return knock_room_helper_remote(sender_user, room_id, reason, servers).await;
+	}
+
+	services()
+		.rooms
+		.timeline
+		.build_and_append_pdu(
+			PduBuilder {
+				event_type: TimelineEventType::RoomMember,
+				content: to_raw_value(&RoomMemberEventContent {
+					membership: MembershipState::Knock,
+					displayname: services().users.displayname(sender_user)?,
+					avatar_url: services().users.avatar_url(sender_user)?,
+					is_direct: None,
+					third_party_invite: None,
+					blurhash: services().users.blurhash(sender_user)?,
+					reason,
+					join_authorized_via_users_server: None,
+				})
+				.expect("event is valid, we just created it"),
+				unsigned: None,
+				state_key: Some(sender_user.to_string()),
+				redacts: None,
+			},
+			sender_user,
+			room_id,
+			&state_lock,
+		)
+		.await?;
+
+	Ok(())
+}
+
+async fn knock_room_helper_remote(
+	sender_user: &UserId, room_id: &RoomId, reason: Option<String>, servers: &[OwnedServerName],
+) -> Result<()> {
+	info!("Knocking on {room_id} over federation.");
+
+	let (make_knock_response, remote_server) = make_knock_request(sender_user, room_id, servers).await?;
+
+	let room_version_id = make_knock_response.room_version;
+	if !services()
+		.globals
+		.supported_room_versions()
+		.contains(&room_version_id)
+	{
+		return Err(Error::BadServerResponse("Room version is not supported"));
+	}
+
+	let mut knock_event_stub: CanonicalJsonObject = serde_json::from_str(make_knock_response.event.get())
+		.map_err(|_| Error::BadServerResponse("Invalid make_knock event json received from server."))?;
+
+	knock_event_stub.insert(
+		"origin".to_owned(),
+		CanonicalJsonValue::String(services().globals.server_name().as_str().to_owned()),
+	);
+	knock_event_stub.insert(
+		"origin_server_ts".to_owned(),
+		CanonicalJsonValue::Integer(
+			utils::millis_since_unix_epoch()
+				.try_into()
+				.expect("Timestamp is valid js_int value"),
+		),
+	);
+	knock_event_stub.insert(
+		"content".to_owned(),
+		to_canonical_value(RoomMemberEventContent {
+			membership: MembershipState::Knock,
+			displayname: services().users.displayname(sender_user)?,
+			avatar_url: services().users.avatar_url(sender_user)?,
+			is_direct: None,
+			third_party_invite: None,
+			blurhash: services().users.blurhash(sender_user)?,
+			reason,
+			join_authorized_via_users_server: None,
+		})
+		.expect("event is valid, we just created it"),
+	);
+
+	// Knocking only exists since room version 7, which has no "event_id" field
+	knock_event_stub.remove("event_id");
+
+	// In order to create a compatible ref hash (EventID) the `hashes` field needs
+	// to be present
+	ruma::signatures::hash_and_sign_event(
+		services().globals.server_name().as_str(),
+		&*services().globals.keypair(),
+		&mut knock_event_stub,
+		&room_version_id,
+	)
+	.expect("event is valid, we just created it");
+
+	// Generate event id
+	let event_id = EventId::parse(format!(
+		"${}",
+		ruma::signatures::reference_hash(&knock_event_stub, &room_version_id)
+			.expect("ruma can calculate reference hashes")
+	))
+	.expect("ruma's reference hashes are valid event ids");
+
+	// Add event_id back
+	knock_event_stub.insert("event_id".to_owned(), CanonicalJsonValue::String(event_id.as_str().to_owned()));
+
+	info!("Asking {remote_server} for send_knock in room {room_id}");
+	let send_knock_response = services()
+		.sending
+		.send_federation_request(
+			&remote_server,
+			send_knock::v1::Request {
+				room_id: room_id.to_owned(),
+				event_id,
+				pdu: PduEvent::convert_to_outgoing_federation_event(knock_event_stub),
+			},
+		)
+		.await?;
+
+	info!("send_knock finished");
+
+	// We are not in the room, so the stripped state of the response is all we
+	// can show the user until the knock is answered
+	services().rooms.state_cache.update_membership(
+		room_id,
+		sender_user,
+		RoomMemberEventContent::new(MembershipState::Knock),
+		sender_user,
+		Some(send_knock_response.knock_room_state),
+		None,
+		false,
+	)?;
+
+	Ok(())
+}
+
+async fn make_knock_request(
+	sender_user: &UserId, room_id: &RoomId, servers: &[OwnedServerName],
+) -> Result<(create_knock_event_template::v1::Response, OwnedServerName)> {
+	let mut make_knock_response_and_server =
+		Err(Error::BadServerResponse("No server available to assist in knocking."));
+
+	for remote_server in servers {
+		if server_is_ours(remote_server) {
+			continue;
+		}
+
+		info!("Asking {remote_server} for make_knock");
+		let make_knock_response = services()
+			.sending
+			.send_federation_request(
+				remote_server,
+				create_knock_event_template::v1::Request {
+					room_id: room_id.to_owned(),
+					user_id: sender_user.to_owned(),
+					ver: services().globals.supported_room_versions(),
+				},
+			)
+			.await;
+
+		trace!("make_knock response: {:?}", make_knock_response);
+
+		make_knock_response_and_server = make_knock_response.map(|r| (r, remote_server.clone()));
+
+		if make_knock_response_and_server.is_ok() {
+			break;
+		}
+	}
+
+	make_knock_response_and_server
+}
+
 async fn validate_and_add_event_id(
 	pdu: &RawJsonValue, room_version: &RoomVersionId, pub_key_map: &RwLock<BTreeMap<String, BTreeMap<String, Base64>>>,
 ) -> Result<(OwnedEventId, CanonicalJsonObject)> {
@@ -1370,7 +1682,14 @@ async fn validate_and_add_event_id(
 
 pub(crate) async fn invite_helper(
 	sender_user: &UserId, user_id: &UserId, room_id: &RoomId, reason: Option<String>, is_direct: bool,
+	third_party_invite: Option<ThirdPartyInvite>,
 ) -> Result<()> {
+	// Invites of shadow-banned users are silently dropped
+	if services().users.is_shadow_banned(sender_user)? {
+		info!("Shadow-banned user {sender_user} attempted to invite {user_id} to {room_id}");
+		return Ok(());
+	}
+
 	if !services().users.is_admin(user_id)? && services().globals.block_non_admin_invites() {
 		info!("User {sender_user} is not an admin and attempted to send an invite to room {room_id}");
 		return Err(Error::BadRequest(
@@ -1397,7 +1716,7 @@ pub(crate) async fn invite_helper(
 				displayname: None,
 				is_direct: Some(is_direct),
 				membership: MembershipState::Invite,
-				third_party_invite: None,
+				third_party_invite,
 				blurhash: None,
 				reason,
 				join_authorized_via_users_server: None,
@@ -1525,7 +1844,7 @@ pub(crate) async fn invite_helper(
 					displayname: services().users.displayname(user_id)?,
 					avatar_url: services().users.avatar_url(user_id)?,
 					is_direct: Some(is_direct),
-					third_party_invite: None,
+					third_party_invite,
 					blurhash: services().users.blurhash(user_id)?,
 					reason,
 					join_authorized_via_users_server: None,
@@ -1546,6 +1865,81 @@ pub(crate) async fn invite_helper(
 	Ok(())
 }
 
+/// Invites a third party identifier. Users the identifier is bound to on the
+/// identity server are invited directly, otherwise the identity server stores
+/// the invite until the identifier is bound and the invite is sent as an
+/// `m.room.third_party_invite` event.
+pub(crate) async fn invite_3pid_helper(sender_user: &UserId, room_id: &RoomId, invite: &Invite3pid) -> Result<()> {
+	// Invites of shadow-banned users are silently dropped
+	if services().users.is_shadow_banned(sender_user)? {
+		info!("Shadow-banned user {sender_user} attempted to invite a third party identifier to {room_id}");
+		return Ok(());
+	}
+
+	if let Some(user_id) = services()
+		.threepid
+		.identity_lookup(&invite.id_server, &invite.id_access_token, &invite.medium, &invite.address)
+		.await?
+	{
+		return invite_helper(sender_user, &user_id, room_id, None, false, None).await;
+	}
+
+	if !services()
+		.rooms
+		.state_cache
+		.is_joined(sender_user, room_id)?
+	{
+		return Err(Error::BadRequest(
+			ErrorKind::forbidden(),
+			"You don't have permission to view this room.",
+		));
+	}
+
+	let (token, content) = services()
+		.threepid
+		.store_invite(
+			&invite.id_server,
+			&invite.id_access_token,
+			&invite.medium,
+			&invite.address,
+			room_id,
+			sender_user,
+		)
+		.await?;
+
+	let mutex_state = Arc::clone(
+		services()
+			.globals
+			.roomid_mutex_state
+			.write()
+			.await
+			.entry(room_id.to_owned())
+			.or_default(),
+	);
+	let state_lock = mutex_state.lock().await;
+
+	services()
+		.rooms
+		.timeline
+		.build_and_append_pdu(
+			PduBuilder {
+				event_type: TimelineEventType::RoomThirdPartyInvite,
+				content: to_raw_value(&content).expect("event is valid, we just created it"),
+				unsigned: None,
+				state_key: Some(token),
+				redacts: None,
+			},
+			sender_user,
+			room_id,
+			&state_lock,
+		)
+		.await?;
+
+	drop(state_lock);
+
+	Ok(())
+}
+
 // Make a user leave all their joined rooms, forgets all rooms, and ignores
 // errors
 pub async fn leave_all_rooms(user_id: &UserId) {
@@ -1593,9 +1987,10 @@ pub async fn leave_room(user_id: &UserId, room_id: &RoomId, reason: Option<Strin
 			.rooms
 			.state_cache
 			.invite_state(user_id, room_id)?
+			.map_or_else(|| services().rooms.state_cache.knock_state(user_id, room_id), |s| Ok(Some(s)))?
 			.map_or_else(|| services().rooms.state_cache.left_state(user_id, room_id), |s| Ok(Some(s)))?;
 
-		// We always drop the invite, we can't rely on other servers
+		// We always drop the invite or knock, we can't rely on other servers
 		services().rooms.state_cache.update_membership(
 			room_id,
 			user_id,
@@ -1674,13 +2069,16 @@ pub async fn leave_room(user_id: &UserId, room_id: &RoomId, reason: Option<Strin
 async fn remote_leave_room(user_id: &UserId, room_id: &RoomId) -> Result<()> {
 	let mut make_leave_response_and_server = Err(Error::BadServerResponse("No server available to assist in leaving."));
 
+	// Retracting a knock is a leave as well, asking the servers which sent us the
+	// stripped state of the room
 	let invite_state = services()
 		.rooms
 		.state_cache
 		.invite_state(user_id, room_id)?
-		.ok_or(Error::BadRequest(ErrorKind::BadState, "User is not invited."))?;
+		.map_or_else(|| services().rooms.state_cache.knock_state(user_id, room_id), |s| Ok(Some(s)))?
+		.ok_or(Error::BadRequest(ErrorKind::BadState, "User is not invited or knocking."))?;
 
-	let servers: HashSet<OwnedServerName> = services()
+	let mut servers: HashSet<OwnedServerName> = services()
 		.rooms
 		.state_cache
 		.servers_invite_via(room_id)?
@@ -1695,6 +2093,9 @@ async fn remote_leave_room(user_id: &UserId, room_id: &RoomId) -> Result<()> {
 				.collect::<HashSet<OwnedServerName>>(),
 			HashSet::from_iter,
 		);
+	if let Some(server) = room_id.server_name() {
+		servers.insert(server.to_owned());
+	}
 
 	debug!("servers in remote_leave_room: {servers:?}");
 
@@ -1760,7 +2161,7 @@ async fn remote_leave_room(user_id: &UserId, room_id: &RoomId) -> Result<()> {
 	// to be present
 	ruma::signatures::hash_and_sign_event(
 		services().globals.server_name().as_str(),
-		services().globals.keypair(),
+		&*services().globals.keypair(),
 		&mut leave_event_stub,
 		&room_version_id,
 	)
@@ -9,6 +9,7 @@ use ruma::{
 		error::ErrorKind,
 		filter::{RoomEventFilter, UrlFilter},
 		message::{get_message_events, send_message_event},
+		room::get_event_by_timestamp,
 	},
 	events::{MessageLikeEventType, StateEventType},
 	RoomId, UserId,
@@ -186,8 +187,14 @@ pub(crate) async fn get_message_events_route(
 
 			let events_after: Vec<_> = events_after
 				.into_iter()
-				.map(|(_, pdu)| pdu.to_room_event())
-				.collect();
+				.map(|(_, mut pdu)| {
+					services()
+						.rooms
+						.state_accessor
+						.redact_erased_for_user(sender_user, &mut pdu)?;
+					Ok(pdu.to_room_event())
+				})
+				.collect::<Result<_>>()?;
 
 			resp.start = from.stringify();
 			resp.end = next_token.map(|count| count.stringify());
@@ -231,8 +238,14 @@ pub(crate) async fn get_message_events_route(
 
 			let events_before: Vec<_> = events_before
 				.into_iter()
-				.map(|(_, pdu)| pdu.to_room_event())
-				.collect();
+				.map(|(_, mut pdu)| {
+					services()
+						.rooms
+						.state_accessor
+						.redact_erased_for_user(sender_user, &mut pdu)?;
+					Ok(pdu.to_room_event())
+				})
+				.collect::<Result<_>>()?;
 
 			resp.start = from.stringify();
 			resp.end = next_token.map(|count| count.stringify());
@@ -265,6 +278,41 @@ pub(crate) async fn get_message_events_route(
 	Ok(resp)
 }
 
+/// # `GET /_matrix/client/v1/rooms/{roomId}/timestamp_to_event`
+///
+/// Finds the event closest to the given timestamp in the given direction.
+///
+/// - Asks other servers in the room if our history has a gap around the
+///   timestamp
+pub(crate) async fn get_event_by_timestamp_route(
+	body: Ruma<get_event_by_timestamp::v1::Request>,
+) -> Result<get_event_by_timestamp::v1::Response> {
+	let sender_user = body.sender_user.as_ref().expect("user is authenticated");
+
+	if !services()
+		.rooms
+		.state_accessor
+		.user_can_see_state_events(sender_user, &body.room_id)?
+	{
+		return Err(Error::BadRequest(
+			ErrorKind::forbidden(),
+			"You don't have permission to view this room.",
+		));
+	}
+
+	let (event_id, origin_server_ts) = services()
+		.rooms
+		.timeline
+		.timestamp_to_event(&body.room_id, body.ts, body.dir)
+		.await?
+		.ok_or(Error::BadRequest(
+			ErrorKind::NotFound,
+			"No event found in the given direction of the timestamp.",
+		))?;
+
+	Ok(get_event_by_timestamp::v1::Response::new(event_id, origin_server_ts))
+}
+
 fn visibility_filter(pdu: &PduEvent, user_id: &UserId, room_id: &RoomId) -> bool {
 	services()
 		.rooms
@@ -1,4 +1,5 @@
 pub(super) mod account;
+pub(super) mod admin;
 pub(super) mod alias;
 pub(super) mod backup;
 pub(super) mod capabilities;
@@ -35,6 +36,7 @@ pub(super) mod user_directory;
 pub(super) mod voip;
 
 pub(super) use account::*;
+pub(super) use admin::*;
 pub use alias::get_alias_helper;
 pub(super) use alias::*;
 pub(super) use backup::*;
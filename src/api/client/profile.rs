@@ -29,6 +29,11 @@ pub(crate) async fn set_displayname_route(
 ) -> Result<set_display_name::v3::Response> {
 	let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
+	// Profile changes of shadow-banned users are silently dropped
+	if services().users.is_shadow_banned(sender_user)? {
+		return Ok(set_display_name::v3::Response {});
+	}
+
 	services()
 		.users
 		.set_displayname(sender_user, body.displayname.clone())
@@ -169,6 +174,11 @@ pub(crate) async fn set_avatar_url_route(
 ) -> Result<set_avatar_url::v3::Response> {
 	let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
+	// Profile changes of shadow-banned users are silently dropped
+	if services().users.is_shadow_banned(sender_user)? {
+		return Ok(set_avatar_url::v3::Response {});
+	}
+
 	services()
 		.users
 		.set_avatar_url(sender_user, body.avatar_url.clone())
@@ -28,7 +28,7 @@ use ruma::{
 use serde_json::{json, value::to_raw_value};
 use tracing::{error, info, warn};
 
-use super::invite_helper;
+use super::{invite_3pid_helper, invite_helper};
 use crate::{
 	service::{appservice::RegistrationInfo, pdu::PduBuilder},
 	services, Error, Result, Ruma,
@@ -457,13 +457,18 @@ pub(crate) async fn create_room_route(body: Ruma<create_room::v3::Request>) -> R
 			.await?;
 	}
 
-	// 8. Events implied by invite (and TODO: invite_3pid)
+	// 8. Events implied by invite and invite_3pid
 	drop(state_lock);
 	for user_id in &body.invite {
-		if let Err(e) = invite_helper(sender_user, user_id, &room_id, None, body.is_direct).await {
+		if let Err(e) = invite_helper(sender_user, user_id, &room_id, None, body.is_direct, None).await {
 			warn!(%e, "Failed to send invite");
 		}
 	}
+	for invite in &body.invite_3pid {
+		if let Err(e) = invite_3pid_helper(sender_user, &room_id, invite).await {
+			warn!(%e, "Failed to send third party invite");
+		}
+	}
 
 	// Homeserver specific stuff
 	if let Some(alias) = alias {
@@ -511,6 +516,10 @@ pub(crate) async fn get_room_event_route(
 	}
 
 	let mut event = (*event).clone();
+	services()
+		.rooms
+		.state_accessor
+		.redact_erased_for_user(sender_user, &mut event)?;
 	event.add_age()?;
 
 	Ok(get_room_event::v3::Response {
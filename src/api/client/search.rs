@@ -12,7 +12,7 @@ use ruma::{
 	serde::Raw,
 	uint, OwnedRoomId,
 };
-use tracing::debug;
+use tracing::{debug, error};
 
 use crate::{services, Error, Result, Ruma};
 
@@ -146,7 +146,17 @@ pub(crate) async fn search_events_route(body: Ruma<search_events::v3::Request>)
 						.user_can_see_event(sender_user, &pdu.room_id, &pdu.event_id)
 						.unwrap_or(false)
 				})
-				.map(|pdu| pdu.to_room_event())
+				.and_then(|mut pdu| {
+					if let Err(e) = services()
+						.rooms
+						.state_accessor
+						.redact_erased_for_user(sender_user, &mut pdu)
+					{
+						error!("Failed to redact event {} of an erased user: {e}", pdu.event_id);
+						return None;
+					}
+					Some(pdu.to_room_event())
+				})
 		})
 		.map(|result| {
 			Ok::<_, Error>(SearchResult {
@@ -1,26 +1,29 @@
+use std::time::Duration;
+
 use ruma::{
 	api::client::{
 		error::ErrorKind,
 		session::{
+			get_login_token,
 			get_login_types::{
 				self,
-				v3::{ApplicationServiceLoginType, PasswordLoginType},
+				v3::{ApplicationServiceLoginType, PasswordLoginType, TokenLoginType},
 			},
 			login::{
 				self,
 				v3::{DiscoveryInfo, HomeserverInfo},
 			},
-			logout, logout_all,
+			logout, logout_all, refresh_token,
 		},
-		uiaa::UserIdentifier,
+		uiaa::{AuthFlow, AuthType, UiaaInfo, UserIdentifier},
 	},
 	UserId,
 };
 use serde::Deserialize;
 use tracing::{debug, info, warn};
 
-use super::{DEVICE_ID_LENGTH, TOKEN_LENGTH};
-use crate::{services, utils, utils::hash, Error, Result, Ruma};
+use super::{DEVICE_ID_LENGTH, SESSION_ID_LENGTH, TOKEN_LENGTH};
+use crate::{service::users, services, utils, Error, Result, Ruma};
 
 #[derive(Debug, Deserialize)]
 struct Claims {
@@ -35,10 +38,18 @@ struct Claims {
 pub(crate) async fn get_login_types_route(
 	_body: Ruma<get_login_types::v3::Request>,
 ) -> Result<get_login_types::v3::Response> {
-	Ok(get_login_types::v3::Response::new(vec![
+	let mut flows = vec![
 		get_login_types::v3::LoginType::Password(PasswordLoginType::default()),
 		get_login_types::v3::LoginType::ApplicationService(ApplicationServiceLoginType::default()),
-	]))
+	];
+
+	if services().globals.config.login_via_existing_session || services().globals.jwt_decoding_key().is_some() {
+		flows.push(get_login_types::v3::LoginType::Token(TokenLoginType {
+			get_login_token: services().globals.config.login_via_existing_session,
+		}));
+	}
+
+	Ok(get_login_types::v3::Response::new(flows))
 }
 
 /// # `POST /_matrix/client/v3/login`
@@ -46,8 +57,9 @@ pub(crate) async fn get_login_types_route(
 /// Authenticates the user and returns an access token it can use in subsequent
 /// requests.
 ///
-/// - The user needs to authenticate using their password (or if enabled using a
-///   json web token)
+/// - The user needs to authenticate using their password (checked by the
+///   configured password providers), a login token issued by `login/get_token`
+///   (or if enabled using a json web token)
 /// - If `device_id` is known: invalidates old access token of that device
 /// - If `device_id` is unknown: creates a new device
 /// - Returns access token that is associated with the user and device
@@ -56,6 +68,8 @@ pub(crate) async fn get_login_types_route(
 /// /_matrix/client/r0/login`](fn.get_supported_versions_route.html) to see
 /// supported login types.
 pub(crate) async fn login_route(body: Ruma<login::v3::Request>) -> Result<login::v3::Response> {
+	users::check_client_device_id(body.device_id.as_deref())?;
+
 	// Validate login method
 	// TODO: Other login methods
 	let user_id = match &body.login_info {
@@ -77,16 +91,11 @@ pub(crate) async fn login_route(body: Ruma<login::v3::Request>) -> Result<login:
 			}
 			.map_err(|_| Error::BadRequest(ErrorKind::InvalidUsername, "Username is invalid."))?;
 
-			let hash = services()
-				.users
-				.password_hash(&user_id)?
-				.ok_or(Error::BadRequest(ErrorKind::forbidden(), "Wrong username or password."))?;
-
-			if hash.is_empty() {
+			if services().users.exists(&user_id)? && services().users.is_deactivated(&user_id)? {
 				return Err(Error::BadRequest(ErrorKind::UserDeactivated, "The user has been deactivated"));
 			}
 
-			if hash::verify_password(password, &hash).is_err() {
+			if !services().users.check_password(&user_id, password).await? {
 				return Err(Error::BadRequest(ErrorKind::forbidden(), "Wrong username or password."));
 			}
 
@@ -96,7 +105,13 @@ pub(crate) async fn login_route(body: Ruma<login::v3::Request>) -> Result<login:
 			token,
 		}) => {
 			debug!("Got token login type");
-			if let Some(jwt_decoding_key) = services().globals.jwt_decoding_key() {
+			if let Some(user_id) = services().users.find_from_login_token(token)? {
+				if services().users.is_deactivated(&user_id)? {
+					return Err(Error::BadRequest(ErrorKind::UserDeactivated, "The user has been deactivated"));
+				}
+
+				user_id
+			} else if let Some(jwt_decoding_key) = services().globals.jwt_decoding_key() {
 				let token =
 					jsonwebtoken::decode::<Claims>(token, jwt_decoding_key, &jsonwebtoken::Validation::default())
 						.map_err(|e| {
@@ -111,10 +126,7 @@ pub(crate) async fn login_route(body: Ruma<login::v3::Request>) -> Result<login:
 					Error::BadRequest(ErrorKind::InvalidUsername, "Username is invalid.")
 				})?
 			} else {
-				return Err(Error::BadRequest(
-					ErrorKind::Unknown,
-					"Token login is not supported (server has no jwt decoding key).",
-				));
+				return Err(Error::BadRequest(ErrorKind::forbidden(), "Invalid or expired login token."));
 			}
 		},
 		#[allow(deprecated)]
@@ -153,6 +165,10 @@ pub(crate) async fn login_route(body: Ruma<login::v3::Request>) -> Result<login:
 		},
 	};
 
+	if services().users.is_locked(&user_id)? {
+		return Err(Error::BadRequest(ErrorKind::UserLocked, "This account has been locked."));
+	}
+
 	// Generate new device id if the user didn't specify one
 	let device_id = body
 		.device_id
@@ -173,10 +189,23 @@ pub(crate) async fn login_route(body: Ruma<login::v3::Request>) -> Result<login:
 	if device_exists {
 		services().users.set_token(&user_id, &device_id, &token)?;
 	} else {
+		services().users.create_device(
+			&user_id,
+			&device_id,
+			&token,
+			body.initial_device_display_name.clone(),
+			body.client_ip,
+			body.user_agent.as_deref(),
+		)?;
+	}
+
+	let refresh_token = body
+		.refresh_token
+		.then(|| utils::random_string(TOKEN_LENGTH));
+	let (expires_in, refresh_token) =
 		services()
 			.users
-			.create_device(&user_id, &device_id, &token, body.initial_device_display_name.clone())?;
-	}
+			.set_token_expiry(&user_id, &device_id, &token, refresh_token.as_deref())?;
 
 	// send client well-known if specified so the client knows to reconfigure itself
 	let client_discovery_info: Option<DiscoveryInfo> = services()
@@ -196,9 +225,126 @@ pub(crate) async fn login_route(body: Ruma<login::v3::Request>) -> Result<login:
 		access_token: token,
 		device_id,
 		well_known: client_discovery_info,
-		expires_in: None,
+		expires_in,
 		home_server: Some(services().globals.server_name().to_owned()),
-		refresh_token: None,
+		refresh_token,
+	})
+}
+
+/// # `POST /_matrix/client/v3/refresh`
+///
+/// Exchanges a refresh token for a new access token and refresh token.
+///
+/// - Invalidates the old access token of the device
+/// - Rotates the refresh token; the old refresh token can no longer be used
+/// - If an already rotated refresh token is used again, the device is logged
+///   out as the refresh token was likely leaked
+pub(crate) async fn refresh_token_route(body: Ruma<refresh_token::v3::Request>) -> Result<refresh_token::v3::Response> {
+	let refresh_token = utils::random_string(TOKEN_LENGTH);
+	let Some((user_id, device_id, is_current)) = services()
+		.users
+		.rotate_refresh_token(&body.refresh_token, &refresh_token)?
+	else {
+		return Err(Error::BadRequest(
+			ErrorKind::UnknownToken {
+				soft_logout: false,
+			},
+			"Unknown refresh token.",
+		));
+	};
+
+	if !is_current {
+		warn!("Reuse of a rotated refresh token detected for {user_id} device {device_id}, logging out the device");
+		services().users.remove_device(&user_id, &device_id)?;
+		services().users.mark_device_key_update(&user_id)?;
+
+		return Err(Error::BadRequest(
+			ErrorKind::UnknownToken {
+				soft_logout: false,
+			},
+			"Refresh token has already been used.",
+		));
+	}
+
+	if services().users.is_locked(&user_id)? {
+		return Err(Error::BadRequest(ErrorKind::UserLocked, "This account has been locked."));
+	}
+
+	let access_token = utils::random_string(TOKEN_LENGTH);
+	services()
+		.users
+		.set_token(&user_id, &device_id, &access_token)?;
+
+	let expires_in_ms = services().users.expire_token(&access_token)?;
+
+	debug!("{user_id} refreshed the access token of device {device_id}");
+
+	Ok(refresh_token::v3::Response {
+		access_token,
+		refresh_token: Some(refresh_token),
+		expires_in_ms: Some(expires_in_ms),
+	})
+}
+
+/// # `POST /_matrix/client/v1/login/get_token`
+///
+/// Issues a short-lived, single-use login token for the sender user, which can
+/// be used to log in a new device with the `m.login.token` login type.
+///
+/// - Requires UIAA to verify user password
+/// - Only available if `login_via_existing_session` is enabled
+pub(crate) async fn login_token_route(
+	body: Ruma<get_login_token::v1::Request>,
+) -> Result<get_login_token::v1::Response> {
+	if !services().globals.config.login_via_existing_session {
+		return Err(Error::BadRequest(
+			ErrorKind::forbidden(),
+			"Login via an existing session is not enabled on this server.",
+		));
+	}
+
+	let sender_user = body.sender_user.as_ref().expect("user is authenticated");
+	let sender_device = body.sender_device.as_ref().expect("user is authenticated");
+
+	let mut uiaainfo = UiaaInfo {
+		flows: vec![AuthFlow {
+			stages: vec![AuthType::Password],
+		}],
+		completed: Vec::new(),
+		params: Box::default(),
+		session: None,
+		auth_error: None,
+	};
+
+	if let Some(auth) = &body.auth {
+		let (worked, uiaainfo) = services()
+			.uiaa
+			.try_auth(sender_user, sender_device, auth, &uiaainfo)
+			.await?;
+		if !worked {
+			return Err(Error::Uiaa(uiaainfo));
+		}
+	// Success!
+	} else if let Some(json) = body.json_body {
+		uiaainfo.session = Some(utils::random_string(SESSION_ID_LENGTH));
+		services()
+			.uiaa
+			.create(sender_user, sender_device, &uiaainfo, &json)?;
+		return Err(Error::Uiaa(uiaainfo));
+	} else {
+		return Err(Error::BadRequest(ErrorKind::NotJson, "Not json."));
+	}
+
+	let login_token = utils::random_string(TOKEN_LENGTH);
+	let expires_in = services()
+		.users
+		.create_login_token(sender_user, &login_token)?;
+
+	info!("{sender_user} requested a login token from device {sender_device}");
+
+	Ok(get_login_token::v1::Response {
+		expires_in: Duration::from_millis(expires_in),
+		login_token,
 	})
 }
 
@@ -12,8 +12,8 @@ use ruma::{
 		sync::sync_events::{
 			self,
 			v3::{
-				Ephemeral, Filter, GlobalAccountData, InviteState, InvitedRoom, JoinedRoom, LeftRoom, Presence,
-				RoomAccountData, RoomSummary, Rooms, State, Timeline, ToDevice,
+				Ephemeral, Filter, GlobalAccountData, InviteState, InvitedRoom, JoinedRoom, KnockState, KnockedRoom,
+				LeftRoom, Presence, RoomAccountData, RoomSummary, Rooms, State, Timeline, ToDevice,
 			},
 			v4::SlidingOp,
 			DeviceLists, UnreadNotificationsCount,
@@ -232,6 +232,50 @@ pub(crate) async fn sync_events_route(
 		);
 	}
 
+	let mut knocked_rooms = BTreeMap::new();
+	let all_knocked_rooms: Vec<_> = services()
+		.rooms
+		.state_cache
+		.rooms_knocked(&sender_user)
+		.collect();
+	for result in all_knocked_rooms {
+		let (room_id, knock_state_events) = result?;
+
+		{
+			// Get and drop the lock to wait for remaining operations to finish
+			let mutex_insert = Arc::clone(
+				services()
+					.globals
+					.roomid_mutex_insert
+					.write()
+					.await
+					.entry(room_id.clone())
+					.or_default(),
+			);
+			let insert_lock = mutex_insert.lock().await;
+			drop(insert_lock);
+		};
+
+		let knock_count = services()
+			.rooms
+			.state_cache
+			.get_knock_count(&room_id, &sender_user)?;
+
+		// Knocked before last sync
+		if Some(since) >= knock_count {
+			continue;
+		}
+
+		knocked_rooms.insert(
+			room_id.clone(),
+			KnockedRoom {
+				knock_state: KnockState {
+					events: knock_state_events,
+				},
+			},
+		);
+	}
+
 	for user_id in left_encrypted_users {
 		let dont_share_encrypted_room = services()
 			.rooms
@@ -267,7 +311,7 @@ pub(crate) async fn sync_events_route(
 			leave: left_rooms,
 			join: joined_rooms,
 			invite: invited_rooms,
-			knock: BTreeMap::new(), // TODO
+			knock: knocked_rooms,
 		},
 		presence: Presence {
 			events: presence_updates
@@ -1041,7 +1085,7 @@ async fn load_joined_room(
 fn load_timeline(
 	sender_user: &UserId, room_id: &RoomId, roomsincecount: PduCount, limit: u64,
 ) -> Result<(Vec<(PduCount, PduEvent)>, bool), Error> {
-	let timeline_pdus;
+	let mut timeline_pdus;
 	let limited = if services()
 		.rooms
 		.timeline
@@ -1077,6 +1121,14 @@ fn load_timeline(
 		timeline_pdus = Vec::new();
 		false
 	};
+
+	for (_, pdu) in &mut timeline_pdus {
+		services()
+			.rooms
+			.state_accessor
+			.redact_erased_for_user(sender_user, pdu)?;
+	}
+
 	Ok((timeline_pdus, limited))
 }
 
@@ -1588,9 +1640,43 @@ pub(crate) async fn sync_events_v4_route(
 		);
 	}
 
+	// Knocked rooms are not part of any list as we cannot show their timeline, so
+	// only their stripped state is sent once after each knock. Sliding sync has no
+	// field for knocks, so it goes in `invite_state`.
+	for result in services().rooms.state_cache.rooms_knocked(&sender_user) {
+		let (room_id, knock_state) = result?;
+		let knock_count = services()
+			.rooms
+			.state_cache
+			.get_knock_count(&room_id, &sender_user)?;
+		if Some(globalsince) >= knock_count {
+			continue;
+		}
+
+		rooms.insert(
+			room_id.clone(),
+			sync_events::v4::SlidingSyncRoom {
+				name: None,
+				avatar: ruma::JsOption::Undefined,
+				initial: Some(true),
+				is_dm: None,
+				invite_state: Some(knock_state),
+				unread_notifications: UnreadNotificationsCount::default(),
+				timeline: Vec::new(),
+				required_state: Vec::new(),
+				prev_batch: None,
+				limited: false,
+				joined_count: None,
+				invited_count: None,
+				num_live: None,
+				timestamp: None,
+			},
+		);
+	}
+
 	if rooms
 		.iter()
-		.all(|(_, r)| r.timeline.is_empty() && r.required_state.is_empty())
+		.all(|(_, r)| r.timeline.is_empty() && r.required_state.is_empty() && r.invite_state.is_none())
 	{
 		// Hang a few seconds so requests are not spammed
 		// Stop hanging if new info arrives
@@ -44,8 +44,14 @@ pub(crate) async fn get_threads_route(body: Ruma<get_threads::v1::Request>) -> R
 	Ok(get_threads::v1::Response {
 		chunk: threads
 			.into_iter()
-			.map(|(_, pdu)| pdu.to_room_event())
-			.collect(),
+			.map(|(_, mut pdu)| {
+				services()
+					.rooms
+					.state_accessor
+					.redact_erased_for_user(sender_user, &mut pdu)?;
+				Ok(pdu.to_room_event())
+			})
+			.collect::<Result<_>>()?,
 		next_batch,
 	})
 }
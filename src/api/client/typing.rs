@@ -12,6 +12,11 @@ pub(crate) async fn create_typing_event_route(
 
 	let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
+	// Typing notifications of shadow-banned users are silently dropped
+	if services().users.is_shadow_banned(sender_user)? {
+		return Ok(create_typing_event::v3::Response {});
+	}
+
 	if !services()
 		.rooms
 		.state_cache
@@ -3,7 +3,7 @@ use std::collections::BTreeMap;
 use axum::{response::IntoResponse, Json};
 use ruma::api::client::{
 	discovery::{
-		discover_homeserver::{self, HomeserverInfo, SlidingSyncProxyInfo},
+		discover_homeserver::{self, HomeserverInfo, IdentityServerInfo, SlidingSyncProxyInfo},
 		discover_support::{self, Contact},
 		get_supported_versions,
 	},
@@ -73,7 +73,14 @@ pub(crate) async fn well_known_client(
 		homeserver: HomeserverInfo {
 			base_url: client_url.clone(),
 		},
-		identity_server: None,
+		identity_server: services()
+			.globals
+			.config
+			.identity_server
+			.as_ref()
+			.map(|identity_server| IdentityServerInfo {
+				base_url: identity_server.to_string(),
+			}),
 		sliding_sync_proxy: Some(SlidingSyncProxyInfo {
 			url: client_url,
 		}),
@@ -7,7 +7,7 @@ extern crate conduit_core as conduit;
 extern crate conduit_service as service;
 
 pub(crate) use conduit::{debug_info, debug_warn, utils, Error, Result};
-pub(crate) use ruma_wrapper::{Ruma, RumaResponse};
+pub(crate) use ruma_wrapper::{AdminRequest, Ruma, RumaResponse};
 pub(crate) use service::{pdu::PduEvent, services, user_is_local};
 
 conduit::mod_ctor! {}
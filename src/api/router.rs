@@ -17,13 +17,19 @@ pub fn build(router: Router, server: &Server) -> Router {
 		.ruma_route(client::register_route)
 		.ruma_route(client::get_login_types_route)
 		.ruma_route(client::login_route)
+		.ruma_route(client::login_token_route)
+		.ruma_route(client::refresh_token_route)
 		.ruma_route(client::whoami_route)
 		.ruma_route(client::logout_route)
 		.ruma_route(client::logout_all_route)
 		.ruma_route(client::change_password_route)
 		.ruma_route(client::deactivate_route)
 		.ruma_route(client::third_party_route)
+		.ruma_route(client::add_3pid_route)
+		.ruma_route(client::delete_3pid_route)
 		.ruma_route(client::request_3pid_management_token_via_email_route)
+		.ruma_route(client::request_registration_token_via_email_route)
+		.ruma_route(client::request_password_change_token_via_email_route)
 		.ruma_route(client::request_3pid_management_token_via_msisdn_route)
 		.ruma_route(client::check_registration_token_validity)
 		.ruma_route(client::get_capabilities_route)
@@ -78,6 +84,7 @@ pub fn build(router: Router, server: &Server) -> Router {
 		.ruma_route(client::get_alias_route)
 		.ruma_route(client::join_room_by_id_route)
 		.ruma_route(client::join_room_by_id_or_alias_route)
+		.ruma_route(client::knock_room_route)
 		.ruma_route(client::joined_members_route)
 		.ruma_route(client::leave_room_route)
 		.ruma_route(client::forget_room_route)
@@ -124,6 +131,7 @@ pub fn build(router: Router, server: &Server) -> Router {
 		.ruma_route(client::sync_events_v4_route)
 		.ruma_route(client::get_context_route)
 		.ruma_route(client::get_message_events_route)
+		.ruma_route(client::get_event_by_timestamp_route)
 		.ruma_route(client::search_events_route)
 		.ruma_route(client::turn_server_route)
 		.ruma_route(client::send_event_to_device_route)
@@ -182,6 +190,25 @@ pub fn build(router: Router, server: &Server) -> Router {
         .ruma_route(client::well_known_support)
         .ruma_route(client::well_known_client)
         .route("/_conduwuit/server_version", get(client::conduwuit_server_version))
+		.route(
+			"/_conduwuit/admin/v1/registration_tokens",
+			get(client::admin_list_registration_tokens_route).post(client::admin_create_registration_token_route),
+		)
+		.route(
+			"/_conduwuit/admin/v1/registration_tokens/:token",
+			get(client::admin_get_registration_token_route)
+				.put(client::admin_update_registration_token_route)
+				.delete(client::admin_revoke_registration_token_route),
+		)
+		.route("/_conduwuit/admin/v1/users/:user_id/login", post(client::admin_login_as_route))
+		.route(
+			"/_conduwuit/consent",
+			get(client::consent_form_route).post(client::consent_route),
+		)
+		.route(
+			"/_conduwuit/client/email/submit_token",
+			get(client::submit_email_token_link_route).post(client::submit_email_token_route),
+		)
 		.route("/_matrix/client/r0/rooms/:room_id/initialSync", get(initial_sync))
 		.route("/_matrix/client/v3/rooms/:room_id/initialSync", get(initial_sync))
 		.route("/client/server.json", get(client::syncv3_client_server_json));
@@ -191,11 +218,14 @@ pub fn build(router: Router, server: &Server) -> Router {
 			.ruma_route(server::get_server_version_route)
 			.route("/_matrix/key/v2/server", get(server::get_server_keys_route))
 			.route("/_matrix/key/v2/server/:key_id", get(server::get_server_keys_deprecated_route))
+			.ruma_route(server::get_remote_server_keys_batch_route)
+			.ruma_route(server::get_remote_server_keys_route)
 			.ruma_route(server::get_public_rooms_route)
 			.ruma_route(server::get_public_rooms_filtered_route)
 			.ruma_route(server::send_transaction_message_route)
 			.ruma_route(server::get_event_route)
 			.ruma_route(server::get_backfill_route)
+			.ruma_route(server::get_event_by_timestamp_route)
 			.ruma_route(server::get_missing_events_route)
 			.ruma_route(server::get_event_authorization_route)
 			.ruma_route(server::get_room_state_route)
@@ -206,7 +236,11 @@ pub fn build(router: Router, server: &Server) -> Router {
 			.ruma_route(server::create_join_event_template_route)
 			.ruma_route(server::create_join_event_v1_route)
 			.ruma_route(server::create_join_event_v2_route)
+			.ruma_route(server::create_knock_event_template_route)
+			.ruma_route(server::create_knock_event_v1_route)
 			.ruma_route(server::create_invite_route)
+			.ruma_route(server::exchange_third_party_invite_route)
+			.ruma_route(server::third_party_invite_onbind_route)
 			.ruma_route(server::get_devices_route)
 			.ruma_route(server::get_room_information_route)
 			.ruma_route(server::get_profile_information_route)
@@ -6,19 +6,49 @@ use axum_extra::{
 	typed_header::TypedHeaderRejectionReason,
 	TypedHeader,
 };
-use http::uri::PathAndQuery;
+use http::{uri::PathAndQuery, Method};
 use ruma::{
-	api::{client::error::ErrorKind, AuthScheme, Metadata},
+	api::{
+		client::{
+			account::{change_password, deactivate},
+			backup::{
+				add_backup_keys, add_backup_keys_for_room, add_backup_keys_for_session, create_backup_version,
+				delete_backup_keys, delete_backup_keys_for_room, delete_backup_keys_for_session, delete_backup_version,
+				update_backup_version,
+			},
+			config::{set_global_account_data, set_room_account_data},
+			device::{delete_device, delete_devices, update_device},
+			directory::get_public_rooms_filtered,
+			error::ErrorKind,
+			filter::create_filter,
+			keys::{claim_keys, get_keys, upload_keys, upload_signatures, upload_signing_keys},
+			membership::{forget_room, leave_room},
+			push::{delete_pushrule, set_pusher, set_pushrule, set_pushrule_actions, set_pushrule_enabled},
+			read_marker::set_read_marker,
+			receipt::create_receipt,
+			search::search_events,
+			session::{logout, logout_all},
+			tag::{create_tag, delete_tag},
+			to_device::send_event_to_device,
+			user_directory::search_users,
+		},
+		AuthScheme, IncomingRequest, Metadata,
+	},
 	CanonicalJsonValue, OwnedDeviceId, OwnedServerName, OwnedUserId, UserId,
 };
 use tracing::warn;
 
 use super::{request::Request, xmatrix::XMatrix};
-use crate::{service::appservice::RegistrationInfo, services, Error, Result};
+use crate::{
+	debug_warn,
+	service::{appservice::RegistrationInfo, users},
+	services, Error, Result,
+};
 
 enum Token {
 	Appservice(Box<RegistrationInfo>),
 	User((OwnedUserId, OwnedDeviceId)),
+	Expired,
 	Invalid,
 	None,
 }
@@ -30,8 +60,64 @@ pub(super) struct Auth {
 	pub(super) appservice_info: Option<RegistrationInfo>,
 }
 
+/// Endpoints a user with a locked account may still use.
+const LOCKED_ALLOWED: &[Metadata] = &[logout::v3::Request::METADATA, logout_all::v3::Request::METADATA];
+
+/// Endpoints besides reads a user who did not accept the current terms of
+/// service may still use, which let them leave without agreeing.
+const CONSENT_ALLOWED: &[Metadata] = &[
+	logout::v3::Request::METADATA,
+	logout_all::v3::Request::METADATA,
+	deactivate::v3::Request::METADATA,
+	leave_room::v3::Request::METADATA,
+	forget_room::v3::Request::METADATA,
+	create_filter::v3::Request::METADATA,
+	get_keys::v3::Request::METADATA,
+	search_events::v3::Request::METADATA,
+	get_public_rooms_filtered::v3::Request::METADATA,
+	search_users::v3::Request::METADATA,
+];
+
+/// Endpoints besides reads and [`CONSENT_ALLOWED`] a suspended user may still
+/// use. Suspended accounts keep whatever clients need to keep working with
+/// existing data, like end-to-end encryption keys and account data.
+const SUSPENDED_ALLOWED: &[Metadata] = &[
+	change_password::v3::Request::METADATA,
+	delete_device::v3::Request::METADATA,
+	delete_devices::v3::Request::METADATA,
+	update_device::v3::Request::METADATA,
+	set_read_marker::v3::Request::METADATA,
+	create_receipt::v3::Request::METADATA,
+	claim_keys::v3::Request::METADATA,
+	upload_keys::v3::Request::METADATA,
+	upload_signing_keys::v3::Request::METADATA,
+	upload_signatures::v3::Request::METADATA,
+	send_event_to_device::v3::Request::METADATA,
+	set_global_account_data::v3::Request::METADATA,
+	set_room_account_data::v3::Request::METADATA,
+	create_tag::v3::Request::METADATA,
+	delete_tag::v3::Request::METADATA,
+	set_pusher::v3::Request::METADATA,
+	set_pushrule::v3::Request::METADATA,
+	delete_pushrule::v3::Request::METADATA,
+	set_pushrule_enabled::v3::Request::METADATA,
+	set_pushrule_actions::v3::Request::METADATA,
+	create_backup_version::v3::Request::METADATA,
+	update_backup_version::v3::Request::METADATA,
+	delete_backup_version::v3::Request::METADATA,
+	add_backup_keys::v3::Request::METADATA,
+	add_backup_keys_for_room::v3::Request::METADATA,
+	add_backup_keys_for_session::v3::Request::METADATA,
+	delete_backup_keys::v3::Request::METADATA,
+	delete_backup_keys_for_room::v3::Request::METADATA,
+	delete_backup_keys_for_session::v3::Request::METADATA,
+];
+
+/// Authenticates a request. `metadata` describes the endpoint, it is `None`
+/// for the endpoints which are not ruma endpoints.
 pub(super) async fn auth(
-	request: &mut Request, json_body: &Option<CanonicalJsonValue>, metadata: &Metadata,
+	request: &mut Request, json_body: &Option<CanonicalJsonValue>, authentication: AuthScheme,
+	metadata: Option<&Metadata>,
 ) -> Result<Auth> {
 	let bearer: Option<TypedHeader<Authorization<Bearer>>> = request.parts.extract().await?;
 	let token = match &bearer {
@@ -43,7 +129,19 @@ pub(super) async fn auth(
 		if let Some(reg_info) = services().appservice.find_from_token(token).await {
 			Token::Appservice(Box::new(reg_info))
 		} else if let Some((user_id, device_id)) = services().users.find_from_token(token)? {
-			Token::User((user_id, OwnedDeviceId::from(device_id)))
+			if services().users.is_token_expired(token)? {
+				if users::is_impersonation_device(&device_id) {
+					// impersonation sessions end for good once expired
+					services()
+						.users
+						.remove_device(&user_id, device_id.as_str().into())?;
+					Token::Invalid
+				} else {
+					Token::Expired
+				}
+			} else {
+				Token::User((user_id, OwnedDeviceId::from(device_id)))
+			}
 		} else {
 			Token::Invalid
 		}
@@ -51,7 +149,7 @@ pub(super) async fn auth(
 		Token::None
 	};
 
-	if metadata.authentication == AuthScheme::None {
+	if authentication == AuthScheme::None {
 		match request.parts.uri.path() {
 			// TODO: can we check this better?
 			"/_matrix/client/v3/publicRooms" | "/_matrix/client/r0/publicRooms" => {
@@ -65,7 +163,7 @@ pub(super) async fn auth(
 							// we should have validated the token above
 							// already
 						},
-						Token::None | Token::Invalid => {
+						Token::None | Token::Expired | Token::Invalid => {
 							return Err(Error::BadRequest(ErrorKind::MissingToken, "Missing or invalid access token."));
 						},
 					}
@@ -75,7 +173,21 @@ pub(super) async fn auth(
 		};
 	}
 
-	match (metadata.authentication, token) {
+	match (authentication, token) {
+		// Endpoints without authentication (such as /refresh) must stay usable with an
+		// expired access token still attached by the client.
+		(AuthScheme::None, Token::Expired) => Ok(Auth {
+			origin: None,
+			sender_user: None,
+			sender_device: None,
+			appservice_info: None,
+		}),
+		(_, Token::Expired) => Err(Error::BadRequest(
+			ErrorKind::UnknownToken {
+				soft_logout: true,
+			},
+			"Access token has expired.",
+		)),
 		(_, Token::Invalid) => Err(Error::BadRequest(
 			ErrorKind::UnknownToken {
 				soft_logout: false,
@@ -105,17 +217,28 @@ pub(super) async fn auth(
 					Err(Error::BadRequest(ErrorKind::MissingToken, "Missing access token."))
 				}
 			},
+			// password resets through a validated email address
+			"/_matrix/client/v3/account/password" | "/_matrix/client/r0/account/password" => Ok(Auth {
+				origin: None,
+				sender_user: None,
+				sender_device: None,
+				appservice_info: None,
+			}),
 			_ => Err(Error::BadRequest(ErrorKind::MissingToken, "Missing access token.")),
 		},
 		(
 			AuthScheme::AccessToken | AuthScheme::AccessTokenOptional | AuthScheme::None,
 			Token::User((user_id, device_id)),
-		) => Ok(Auth {
-			origin: None,
-			sender_user: Some(user_id),
-			sender_device: Some(device_id),
-			appservice_info: None,
-		}),
+		) => {
+			check_account_restrictions(&request.parts.method, metadata, &user_id)?;
+
+			Ok(Auth {
+				origin: None,
+				sender_user: Some(user_id),
+				sender_device: Some(device_id),
+				appservice_info: None,
+			})
+		},
 		(AuthScheme::ServerSignatures, Token::None) => Ok(auth_server(request, json_body).await?),
 		(AuthScheme::None | AuthScheme::AppserviceToken | AuthScheme::AccessTokenOptional, Token::None) => Ok(Auth {
 			sender_user: None,
@@ -134,6 +257,59 @@ pub(super) async fn auth(
 	}
 }
 
+/// Enforces account locking (MSC3939), suspension (MSC3823) and consent to the
+/// terms of service on requests authenticated with a user's access token.
+fn check_account_restrictions(method: &Method, metadata: Option<&Metadata>, user_id: &UserId) -> Result<()> {
+	if services().users.is_locked(user_id)? && !is_any_endpoint(metadata, LOCKED_ALLOWED) {
+		return Err(Error::BadRequest(ErrorKind::UserLocked, "This account has been locked."));
+	}
+
+	if services().users.is_suspended(user_id)? && !allowed_while_suspended(method, metadata) {
+		return Err(Error::user_suspended("This account has been suspended and can only read."));
+	}
+
+	if services().users.needs_consent(user_id)? && !allowed_without_consent(method, metadata) {
+		let consent_uri = services().users.consent_url(user_id)?;
+		return Err(Error::consent_not_given(
+			consent_uri.as_str(),
+			"You have to accept the current terms of service to continue.",
+		));
+	}
+
+	Ok(())
+}
+
+/// Whether a suspended user may make a request. Suspended accounts keep read
+/// access and whatever clients need to keep working with existing data, like
+/// end-to-end encryption keys, account data and leaving rooms.
+fn allowed_while_suspended(method: &Method, metadata: Option<&Metadata>) -> bool {
+	allowed_without_consent(method, metadata) || is_any_endpoint(metadata, SUSPENDED_ALLOWED)
+}
+
+/// Whether a user who did not accept the current terms of service may make a
+/// request. They can only read, and leave.
+fn allowed_without_consent(method: &Method, metadata: Option<&Metadata>) -> bool {
+	method == Method::GET
+		|| method == Method::HEAD
+		|| method == Method::OPTIONS
+		|| is_any_endpoint(metadata, CONSENT_ALLOWED)
+}
+
+/// Whether the endpoint is one of `endpoints`. Endpoints are told apart by
+/// their method and path templates rather than the request path, whose last
+/// segment is often chosen by the client.
+fn is_any_endpoint(metadata: Option<&Metadata>, endpoints: &[Metadata]) -> bool {
+	metadata.is_some_and(|metadata| {
+		endpoints.iter().any(|endpoint| {
+			metadata.method == endpoint.method
+				&& metadata
+					.history
+					.all_paths()
+					.eq(endpoint.history.all_paths())
+		})
+	})
+}
+
 fn auth_appservice(request: &Request, info: Box<RegistrationInfo>) -> Result<Auth> {
 	let user_id = request
 		.query
@@ -188,6 +364,14 @@ async fn auth_server(request: &mut Request, json_body: &Option<CanonicalJsonValu
 		})?;
 
 	let origin = &x_matrix.origin;
+	if !services().globals.server_is_allowed(origin) {
+		debug_warn!("Refusing request from {origin}, it is not in allowed_remote_server_names");
+		return Err(Error::BadRequest(
+			ErrorKind::forbidden(),
+			"Federation with this server is not allowed.",
+		));
+	}
+
 	let signatures = BTreeMap::from_iter([(x_matrix.key.clone(), CanonicalJsonValue::String(x_matrix.sig))]);
 	let signatures = BTreeMap::from_iter([(origin.as_str().to_owned(), CanonicalJsonValue::Object(signatures))]);
 
@@ -259,3 +443,53 @@ async fn auth_server(request: &mut Request, json_body: &Option<CanonicalJsonValu
 		},
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use http::Method;
+	use ruma::api::{
+		client::{
+			keys::upload_keys, membership::leave_room, message::send_message_event, push::set_pusher, session::logout,
+			state::send_state_event,
+		},
+		IncomingRequest,
+	};
+
+	use super::{allowed_while_suspended, allowed_without_consent, is_any_endpoint, LOCKED_ALLOWED};
+
+	#[test]
+	fn client_chosen_path_segments_do_not_allow_writes() {
+		// e.g. `PUT /rooms/{roomId}/send/m.room.message/leave`
+		let send = Some(&send_message_event::v3::Request::METADATA);
+		let state = Some(&send_state_event::v3::Request::METADATA);
+
+		assert!(!allowed_while_suspended(&Method::PUT, send));
+		assert!(!allowed_while_suspended(&Method::PUT, state));
+		assert!(!allowed_without_consent(&Method::PUT, send));
+		assert!(!allowed_without_consent(&Method::PUT, state));
+		assert!(!is_any_endpoint(send, LOCKED_ALLOWED));
+	}
+
+	#[test]
+	fn consent_allows_less_than_suspension() {
+		let leave = Some(&leave_room::v3::Request::METADATA);
+		let upload_keys = Some(&upload_keys::v3::Request::METADATA);
+		let set_pusher = Some(&set_pusher::v3::Request::METADATA);
+
+		assert!(allowed_while_suspended(&Method::POST, leave));
+		assert!(allowed_without_consent(&Method::POST, leave));
+		assert!(allowed_while_suspended(&Method::POST, upload_keys));
+		assert!(!allowed_without_consent(&Method::POST, upload_keys));
+		assert!(allowed_while_suspended(&Method::POST, set_pusher));
+		assert!(!allowed_without_consent(&Method::POST, set_pusher));
+	}
+
+	#[test]
+	fn reads_are_allowed_but_not_for_locked_accounts() {
+		assert!(allowed_while_suspended(&Method::GET, None));
+		assert!(allowed_without_consent(&Method::GET, None));
+		assert!(!allowed_while_suspended(&Method::POST, None));
+		assert!(!is_any_endpoint(None, LOCKED_ALLOWED));
+		assert!(is_any_endpoint(Some(&logout::v3::Request::METADATA), LOCKED_ALLOWED));
+	}
+}
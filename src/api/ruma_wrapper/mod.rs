@@ -3,16 +3,17 @@ mod handler;
 mod request;
 mod xmatrix;
 
-use std::{mem, ops::Deref};
+use std::{mem, net::IpAddr, ops::Deref};
 
 use axum::{async_trait, body::Body, extract::FromRequest};
 use bytes::{BufMut, BytesMut};
 pub(super) use conduit::error::RumaResponse;
 use conduit::{debug, debug_warn, trace, warn};
 use ruma::{
-	api::{client::error::ErrorKind, IncomingRequest},
+	api::{client::error::ErrorKind, AuthScheme, IncomingRequest},
 	CanonicalJsonValue, OwnedDeviceId, OwnedServerName, OwnedUserId, UserId,
 };
+use serde::de::DeserializeOwned;
 
 pub(super) use self::handler::RouterExt;
 use self::{auth::Auth, request::Request};
@@ -42,6 +43,12 @@ pub(crate) struct Ruma<T> {
 	/// Parsed JSON content.
 	/// None when body is not a valid string
 	pub(crate) json_body: Option<CanonicalJsonValue>,
+
+	/// Address of the client, see the `ip_source` config option.
+	pub(crate) client_ip: Option<IpAddr>,
+
+	/// User-Agent header of the request.
+	pub(crate) user_agent: Option<String>,
 }
 
 #[async_trait]
@@ -54,7 +61,19 @@ where
 	async fn from_request(request: hyper::Request<Body>, _: &S) -> Result<Self, Self::Rejection> {
 		let mut request = request::from(request).await?;
 		let mut json_body = serde_json::from_slice::<CanonicalJsonValue>(&request.body).ok();
-		let auth = auth::auth(&mut request, &json_body, &T::METADATA).await?;
+		let auth = auth::auth(&mut request, &json_body, T::METADATA.authentication, Some(&T::METADATA)).await?;
+		let user_agent = request.user_agent().map(ToOwned::to_owned);
+
+		if let (Some(user_id), Some(device_id)) = (&auth.sender_user, &auth.sender_device) {
+			if let Err(e) =
+				services()
+					.users
+					.update_device_last_seen(user_id, device_id, request.client_ip, user_agent.as_deref())
+			{
+				warn!("Failed to update last seen info of device {device_id} of {user_id}: {e}");
+			}
+		}
+
 		Ok(Self {
 			body: make_body::<T>(&mut request, &mut json_body, &auth)?,
 			origin: auth.origin,
@@ -62,6 +81,53 @@ where
 			sender_device: auth.sender_device,
 			appservice_info: auth.appservice_info,
 			json_body,
+			client_ip: request.client_ip,
+			user_agent,
+		})
+	}
+}
+
+/// Extractor for the conduwuit admin API. Its endpoints are not ruma endpoints,
+/// but are authenticated like client endpoints with the access token of a user,
+/// who has to be a server admin.
+pub(crate) struct AdminRequest<T> {
+	/// Request JSON body
+	pub(crate) body: T,
+
+	/// The server admin making the request.
+	pub(crate) sender_user: OwnedUserId,
+}
+
+#[async_trait]
+impl<T, S> FromRequest<S, Body> for AdminRequest<T>
+where
+	T: DeserializeOwned,
+{
+	type Rejection = Error;
+
+	async fn from_request(request: hyper::Request<Body>, _: &S) -> Result<Self, Self::Rejection> {
+		let mut request = request::from(request).await?;
+		let json_body = serde_json::from_slice::<CanonicalJsonValue>(&request.body).ok();
+		let auth = auth::auth(&mut request, &json_body, AuthScheme::AccessToken, None).await?;
+
+		let Some(sender_user) = auth.sender_user else {
+			return Err(Error::BadRequest(ErrorKind::MissingToken, "Missing access token."));
+		};
+
+		if !services().users.is_admin(&sender_user)? {
+			return Err(Error::BadRequest(ErrorKind::forbidden(), "You are not a server admin."));
+		}
+
+		let body = if request.body.is_empty() {
+			serde_json::from_slice(b"{}")
+		} else {
+			serde_json::from_slice(&request.body)
+		}
+		.map_err(|_| Error::BadRequest(ErrorKind::BadJson, "Failed to deserialize request."))?;
+
+		Ok(Self {
+			body,
+			sender_user,
 		})
 	}
 }
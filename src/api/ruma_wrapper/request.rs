@@ -1,8 +1,16 @@
-use std::str;
+use std::{
+	net::{IpAddr, SocketAddr},
+	str,
+};
 
-use axum::{extract::Path, RequestExt, RequestPartsExt};
+use axum::{
+	extract::{ConnectInfo, Path},
+	RequestExt, RequestPartsExt,
+};
+use axum_client_ip::{SecureClientIp, SecureClientIpSource};
 use bytes::Bytes;
-use http::request::Parts;
+use http::{header, request::Parts};
+use ipaddress::IPAddress;
 use ruma::api::client::error::ErrorKind;
 use serde::Deserialize;
 
@@ -19,6 +27,7 @@ pub(super) struct Request {
 	pub(super) query: QueryParams,
 	pub(super) body: Bytes,
 	pub(super) parts: Parts,
+	pub(super) client_ip: Option<IpAddr>,
 }
 
 pub(super) async fn from(request: hyper::Request<axum::body::Body>) -> Result<Request> {
@@ -26,6 +35,7 @@ pub(super) async fn from(request: hyper::Request<axum::body::Body>) -> Result<Re
 	let (mut parts, body) = limited.into_parts();
 
 	let path: Path<Vec<String>> = parts.extract().await?;
+	let client_ip = client_ip(&mut parts).await;
 	let query = serde_html_form::from_str(parts.uri.query().unwrap_or_default())
 		.map_err(|_| Error::BadRequest(ErrorKind::Unknown, "Failed to read query parameters"))?;
 
@@ -45,5 +55,42 @@ pub(super) async fn from(request: hyper::Request<axum::body::Body>) -> Result<Re
 		query,
 		body,
 		parts,
+		client_ip,
 	})
 }
+
+impl Request {
+	pub(super) fn user_agent(&self) -> Option<&str> {
+		self.parts
+			.headers
+			.get(header::USER_AGENT)
+			.and_then(|user_agent| user_agent.to_str().ok())
+	}
+}
+
+/// Address of the client. The header configured as `ip_source` is only
+/// trusted on requests from a trusted proxy or over a UNIX socket.
+async fn client_ip(parts: &mut Parts) -> Option<IpAddr> {
+	let peer = parts
+		.extensions
+		.get::<ConnectInfo<SocketAddr>>()
+		.map(|ConnectInfo(addr)| addr.ip());
+
+	if matches!(services().globals.config.ip_source, SecureClientIpSource::ConnectInfo) {
+		return peer;
+	}
+
+	let trusted = peer.map_or(true, |peer| {
+		IPAddress::parse(peer.to_string()).is_ok_and(|ip| services().globals.is_trusted_proxy(&ip))
+	});
+	if !trusted {
+		return peer;
+	}
+
+	parts
+		.extract::<SecureClientIp>()
+		.await
+		.ok()
+		.map(|SecureClientIp(ip)| ip)
+		.or(peer)
+}
@@ -60,8 +60,19 @@ pub(crate) async fn get_backfill_route(body: Ruma<get_backfill::v1::Request>) ->
 				Ok(true),
 			)
 		})
-		.map(|(_, pdu)| services().rooms.timeline.get_pdu_json(&pdu.event_id))
-		.filter_map(|r| r.ok().flatten())
+		.filter_map(|(_, pdu)| {
+			let pdu_json = services()
+				.rooms
+				.timeline
+				.get_pdu_json(&pdu.event_id)
+				.ok()
+				.flatten()?;
+			services()
+				.rooms
+				.state_accessor
+				.redact_erased_for_server(origin, &pdu.room_id, &pdu.event_id, pdu_json)
+				.ok()
+		})
 		.map(PduEvent::convert_to_outgoing_federation_event)
 		.collect();
 
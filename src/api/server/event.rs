@@ -45,6 +45,12 @@ pub(crate) async fn get_event_route(body: Ruma<get_event::v1::Request>) -> Resul
 		return Err(Error::BadRequest(ErrorKind::forbidden(), "Server is not allowed to see event."));
 	}
 
+	let room_id = room_id.to_owned();
+	let event = services()
+		.rooms
+		.state_accessor
+		.redact_erased_for_server(origin, &room_id, &body.event_id, event)?;
+
 	Ok(get_event::v1::Response {
 		origin: services().globals.server_name().to_owned(),
 		origin_server_ts: MilliSecondsSinceUnixEpoch::now(),
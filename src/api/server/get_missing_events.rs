@@ -79,6 +79,12 @@ pub(crate) async fn get_missing_events_route(
 				)
 				.map_err(|_| Error::bad_database("Invalid prev_events in event in database."))?,
 			);
+			let pdu = services().rooms.state_accessor.redact_erased_for_server(
+				origin,
+				&body.room_id,
+				&queued_events[i],
+				pdu,
+			)?;
 			events.push(PduEvent::convert_to_outgoing_federation_event(pdu));
 		}
 		i = i.saturating_add(1);
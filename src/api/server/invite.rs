@@ -101,7 +101,7 @@ pub(crate) async fn create_invite_route(body: Ruma<create_invite::v2::Request>)
 
 	ruma::signatures::hash_and_sign_event(
 		services().globals.server_name().as_str(),
-		services().globals.keypair(),
+		&*services().globals.keypair(),
 		&mut signed_event,
 		&body.room_version,
 	)
@@ -1,54 +1,36 @@
-use std::{
-	collections::BTreeMap,
-	time::{Duration, SystemTime},
-};
-
 use axum::{response::IntoResponse, Json};
+use futures_util::{stream::FuturesUnordered, StreamExt};
 use ruma::{
 	api::{
-		federation::discovery::{get_server_keys, ServerSigningKeys, VerifyKey},
+		client::error::ErrorKind,
+		federation::discovery::{
+			get_remote_server_keys, get_remote_server_keys_batch, get_server_keys, ServerSigningKeys,
+		},
 		OutgoingResponse,
 	},
-	serde::{Base64, Raw},
-	MilliSecondsSinceUnixEpoch, OwnedServerSigningKeyId,
+	serde::Raw,
+	CanonicalJsonObject, MilliSecondsSinceUnixEpoch,
 };
+use serde_json::value::to_raw_value;
+use tracing::debug;
+
+use crate::{services, Error, Result, Ruma};
 
-use crate::{services, Result};
+/// Maximum number of servers whose keys can be queried in one batch request
+const MAX_BATCH_SERVERS: usize = 32;
 
 /// # `GET /_matrix/key/v2/server`
 ///
 /// Gets the public signing keys of this server.
 ///
-/// - Matrix does not support invalidating public keys, so the key returned by
-///   this will be valid
-/// forever.
+/// - Keys retired by rotating the signing key are listed in `old_verify_keys`
+/// - `valid_until_ts` is `signing_key_validity_period_s` from now
 // Response type for this endpoint is Json because we need to calculate a
 // signature for the response
 pub(crate) async fn get_server_keys_route() -> Result<impl IntoResponse> {
-	let verify_keys: BTreeMap<OwnedServerSigningKeyId, VerifyKey> = BTreeMap::from([(
-		format!("ed25519:{}", services().globals.keypair().version())
-			.try_into()
-			.expect("found invalid server signing keys in DB"),
-		VerifyKey {
-			key: Base64::new(services().globals.keypair().public_key().to_vec()),
-		},
-	)]);
-
 	let mut response = serde_json::from_slice(
 		get_server_keys::v2::Response {
-			server_key: Raw::new(&ServerSigningKeys {
-				server_name: services().globals.server_name().to_owned(),
-				verify_keys,
-				old_verify_keys: BTreeMap::new(),
-				signatures: BTreeMap::new(),
-				valid_until_ts: MilliSecondsSinceUnixEpoch::from_system_time(
-					SystemTime::now()
-						.checked_add(Duration::from_secs(86400 * 7))
-						.expect("valid_until_ts should not get this high"),
-				)
-				.expect("time is valid"),
-			})
-			.expect("static conversion, no errors"),
+			server_key: Raw::new(&services().globals.own_server_keys()?).expect("static conversion, no errors"),
 		}
 		.try_into_http_response::<Vec<u8>>()
 		.unwrap()
@@ -58,7 +40,7 @@ pub(crate) async fn get_server_keys_route() -> Result<impl IntoResponse> {
 
 	ruma::signatures::sign_json(
 		services().globals.server_name().as_str(),
-		services().globals.keypair(),
+		&*services().globals.keypair(),
 		&mut response,
 	)
 	.unwrap();
@@ -68,9 +50,98 @@ pub(crate) async fn get_server_keys_route() -> Result<impl IntoResponse> {
 
 /// # `GET /_matrix/key/v2/server/{keyId}`
 ///
-/// Gets the public signing keys of this server.
+/// Gets the public signing keys of this server, ignoring `keyId`.
 ///
-/// - Matrix does not support invalidating public keys, so the key returned by
-///   this will be valid
-/// forever.
+/// - Keys retired by rotating the signing key are listed in `old_verify_keys`
+/// - `valid_until_ts` is `signing_key_validity_period_s` from now
 pub(crate) async fn get_server_keys_deprecated_route() -> impl IntoResponse { get_server_keys_route().await }
+
+/// # `GET /_matrix/key/v2/query/{serverName}`
+///
+/// Gets the public signing keys of another server, acting as a notary.
+///
+/// - Keys are served from the cache, or fetched when missing or not valid until
+///   `minimum_valid_until_ts`
+/// - Only keys signed by the server itself are served
+/// - Every returned key response is additionally signed by this server
+pub(crate) async fn get_remote_server_keys_route(
+	body: Ruma<get_remote_server_keys::v2::Request>,
+) -> Result<get_remote_server_keys::v2::Response> {
+	let server_keys = services()
+		.rooms
+		.event_handler
+		.notary_server_keys(&body.server_name, Vec::new(), body.minimum_valid_until_ts)
+		.await?
+		.map(|keys| sign_server_keys(&keys))
+		.into_iter()
+		.collect();
+
+	Ok(get_remote_server_keys::v2::Response::new(server_keys))
+}
+
+/// # `POST /_matrix/key/v2/query`
+///
+/// Gets the public signing keys of several servers at once, acting as a
+/// notary.
+///
+/// - At most 32 servers can be queried at once, their keys are fetched
+///   concurrently
+/// - Servers whose keys could not be found are left out of the response
+pub(crate) async fn get_remote_server_keys_batch_route(
+	body: Ruma<get_remote_server_keys_batch::v2::Request>,
+) -> Result<get_remote_server_keys_batch::v2::Response> {
+	if body.server_keys.len() > MAX_BATCH_SERVERS {
+		return Err(Error::BadRequest(
+			ErrorKind::LimitExceeded {
+				retry_after: None,
+			},
+			"Too many servers in the key query.",
+		));
+	}
+
+	let mut futures: FuturesUnordered<_> = body
+		.server_keys
+		.iter()
+		.map(|(server_name, criteria)| async move {
+			let key_ids = criteria.keys().map(ToString::to_string).collect();
+			let minimum_valid_until_ts = criteria
+				.values()
+				.filter_map(|criteria| criteria.minimum_valid_until_ts)
+				.max()
+				.unwrap_or_else(MilliSecondsSinceUnixEpoch::now);
+
+			let keys = services()
+				.rooms
+				.event_handler
+				.notary_server_keys(server_name, key_ids, minimum_valid_until_ts)
+				.await;
+			(server_name, keys)
+		})
+		.collect();
+
+	let mut server_keys = Vec::with_capacity(body.server_keys.len());
+	while let Some((server_name, keys)) = futures.next().await {
+		match keys {
+			Ok(Some(keys)) => server_keys.push(sign_server_keys(&keys)),
+			Ok(None) => debug!("No signing keys found for {server_name}"),
+			Err(e) => debug!("Failed to get signing keys for {server_name}: {e}"),
+		}
+	}
+
+	Ok(get_remote_server_keys_batch::v2::Response::new(server_keys))
+}
+
+/// Adds our signature to a key response of another server.
+fn sign_server_keys(keys: &ServerSigningKeys) -> Raw<ServerSigningKeys> {
+	let mut object: CanonicalJsonObject =
+		serde_json::from_slice(&serde_json::to_vec(keys).expect("serversigningkeys can be serialized")).unwrap();
+
+	ruma::signatures::sign_json(
+		services().globals.server_name().as_str(),
+		&*services().globals.keypair(),
+		&mut object,
+	)
+	.unwrap();
+
+	Raw::from_json(to_raw_value(&object).expect("signed keys can be serialized"))
+}
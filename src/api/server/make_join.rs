@@ -74,6 +74,13 @@ pub(crate) async fn create_join_event_template_route(
 		}
 	}
 
+	// The state is only complete once a partial-state join has been resynced
+	services()
+		.rooms
+		.partial_state
+		.wait_for_full_state(&body.room_id)
+		.await?;
+
 	let mutex_state = Arc::clone(
 		services()
 			.globals
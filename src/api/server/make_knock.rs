@@ -0,0 +1,150 @@
+use std::sync::Arc;
+
+use ruma::{
+	api::{client::error::ErrorKind, federation::knock::create_knock_event_template},
+	events::{
+		room::{
+			join_rules::{JoinRule, RoomJoinRulesEventContent},
+			member::{MembershipState, RoomMemberEventContent},
+		},
+		StateEventType, TimelineEventType,
+	},
+	RoomId, RoomVersionId,
+};
+use serde_json::value::to_raw_value;
+use tracing::warn;
+
+use crate::{service::pdu::PduBuilder, services, Error, Result, Ruma};
+
+/// # `GET /_matrix/federation/v1/make_knock/{roomId}/{userId}`
+///
+/// Creates a knock template.
+pub(crate) async fn create_knock_event_template_route(
+	body: Ruma<create_knock_event_template::v1::Request>,
+) -> Result<create_knock_event_template::v1::Response> {
+	if !services().rooms.metadata.exists(&body.room_id)? {
+		return Err(Error::BadRequest(ErrorKind::NotFound, "Room is unknown to this server."));
+	}
+
+	let origin = body.origin.as_ref().expect("server is authenticated");
+	if body.user_id.server_name() != origin {
+		return Err(Error::BadRequest(
+			ErrorKind::InvalidParam,
+			"Not allowed to knock on behalf of another server/user",
+		));
+	}
+
+	// ACL check origin server
+	services()
+		.rooms
+		.event_handler
+		.acl_check(origin, &body.room_id)?;
+
+	if services()
+		.globals
+		.config
+		.forbidden_remote_server_names
+		.contains(origin)
+	{
+		warn!(
+			"Server {origin} for remote user {} tried knocking on room ID {} which has a server name that is globally \
+			 forbidden. Rejecting.",
+			&body.user_id, &body.room_id,
+		);
+		return Err(Error::BadRequest(
+			ErrorKind::forbidden(),
+			"Server is banned on this homeserver.",
+		));
+	}
+
+	let room_version_id = services().rooms.state.get_room_version(&body.room_id)?;
+	if !body.ver.contains(&room_version_id) {
+		return Err(Error::BadRequest(
+			ErrorKind::IncompatibleRoomVersion {
+				room_version: room_version_id,
+			},
+			"Room version not supported.",
+		));
+	}
+
+	check_knock_allowed(&body.room_id, &room_version_id)?;
+
+	let mutex_state = Arc::clone(
+		services()
+			.globals
+			.roomid_mutex_state
+			.write()
+			.await
+			.entry(body.room_id.clone())
+			.or_default(),
+	);
+	let state_lock = mutex_state.lock().await;
+
+	let content =
+		to_raw_value(&RoomMemberEventContent::new(MembershipState::Knock)).expect("member event is valid value");
+
+	let (_pdu, mut pdu_json) = services().rooms.timeline.create_hash_and_sign_event(
+		PduBuilder {
+			event_type: TimelineEventType::RoomMember,
+			content,
+			unsigned: None,
+			state_key: Some(body.user_id.to_string()),
+			redacts: None,
+		},
+		&body.user_id,
+		&body.room_id,
+		&state_lock,
+	)?;
+
+	drop(state_lock);
+
+	// Knocking rooms are all v7 or above, which removed the "event_id" field from
+	// the remote PDU format
+	pdu_json.remove("event_id");
+
+	Ok(create_knock_event_template::v1::Response {
+		room_version: room_version_id,
+		event: to_raw_value(&pdu_json).expect("CanonicalJson can be serialized to JSON"),
+	})
+}
+
+/// Checks that the room version supports knocking and the join rules of the
+/// room allow it.
+pub(crate) fn check_knock_allowed(room_id: &RoomId, room_version_id: &RoomVersionId) -> Result<()> {
+	if matches!(
+		room_version_id,
+		RoomVersionId::V1
+			| RoomVersionId::V2
+			| RoomVersionId::V3
+			| RoomVersionId::V4
+			| RoomVersionId::V5
+			| RoomVersionId::V6
+	) {
+		return Err(Error::BadRequest(
+			ErrorKind::IncompatibleRoomVersion {
+				room_version: room_version_id.clone(),
+			},
+			"Room version does not support knocking.",
+		));
+	}
+
+	let join_rule = services()
+		.rooms
+		.state_accessor
+		.room_state_get(room_id, &StateEventType::RoomJoinRules, "")?
+		.map(|join_rules_event| {
+			serde_json::from_str::<RoomJoinRulesEventContent>(join_rules_event.content.get())
+				.map_err(|_| Error::bad_database("Invalid join rules event in db."))
+		})
+		.transpose()?
+		.map(|content| content.join_rule);
+
+	if !matches!(join_rule, Some(JoinRule::Knock | JoinRule::KnockRestricted(_))) {
+		return Err(Error::BadRequest(
+			ErrorKind::forbidden(),
+			"The join rules of this room do not allow knocking.",
+		));
+	}
+
+	Ok(())
+}
@@ -6,14 +6,18 @@ pub(super) mod hierarchy;
 pub(super) mod invite;
 pub(super) mod key;
 pub(super) mod make_join;
+pub(super) mod make_knock;
 pub(super) mod make_leave;
 pub(super) mod publicrooms;
 pub(super) mod query;
 pub(super) mod send;
 pub(super) mod send_join;
+pub(super) mod send_knock;
 pub(super) mod send_leave;
 pub(super) mod state;
 pub(super) mod state_ids;
+pub(super) mod third_party_invite;
+pub(super) mod timestamp_to_event;
 pub(super) mod user;
 pub(super) mod version;
 pub(super) mod well_known;
@@ -26,14 +30,18 @@ pub(super) use hierarchy::*;
 pub(super) use invite::*;
 pub(super) use key::*;
 pub(super) use make_join::*;
+pub(super) use make_knock::*;
 pub(super) use make_leave::*;
 pub(super) use publicrooms::*;
 pub(super) use query::*;
 pub(super) use send::*;
 pub(super) use send_join::*;
+pub(super) use send_knock::*;
 pub(super) use send_leave::*;
 pub(super) use state::*;
 pub(super) use state_ids::*;
+pub(super) use third_party_invite::*;
+pub(super) use timestamp_to_event::*;
 pub(super) use user::*;
 pub(super) use version::*;
 pub(super) use well_known::*;
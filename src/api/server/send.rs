@@ -1,25 +1,38 @@
-use std::{collections::BTreeMap, sync::Arc, time::Instant};
+use std::{
+	collections::{BTreeMap, HashMap, HashSet},
+	sync::Arc,
+	time::Instant,
+};
 
 use conduit::debug_warn;
 use ruma::{
 	api::{
 		client::error::ErrorKind,
-		federation::transactions::{
-			edu::{DeviceListUpdateContent, DirectDeviceContent, Edu, SigningKeyUpdateContent},
-			send_transaction_message,
+		federation::{
+			event::get_event,
+			transactions::{
+				edu::{DeviceListUpdateContent, DirectDeviceContent, Edu, SigningKeyUpdateContent},
+				send_transaction_message,
+			},
 		},
 	},
-	events::receipt::{ReceiptEvent, ReceiptEventContent, ReceiptType},
+	events::{
+		receipt::{ReceiptEvent, ReceiptEventContent, ReceiptType},
+		room::member::{MembershipState, RoomMemberEventContent},
+		StateEventType, TimelineEventType,
+	},
 	to_device::DeviceIdOrAllDevices,
+	CanonicalJsonObject, CanonicalJsonValue, EventId, RoomId, RoomVersionId, ServerName, UserId,
 };
+use serde_json::value::RawValue as RawJsonValue;
 use tokio::sync::RwLock;
-use tracing::{debug, error, trace, warn};
+use tracing::{debug, error, info, trace};
 
 use crate::{
-	service::rooms::event_handler::parse_incoming_pdu,
+	service::{pdu::gen_event_id_canonical_json, rooms::event_handler::parse_incoming_pdu, user_is_local},
 	services,
 	utils::{self},
-	Error, Result, Ruma,
+	Error, PduEvent, Result, Ruma,
 };
 
 /// # `PUT /_matrix/federation/v1/send/{txnId}`
@@ -51,24 +64,17 @@ pub(crate) async fn send_transaction_message_route(
 		));
 	}
 
-	// This is all the auth_events that have been recursively fetched so they don't
-	// have to be deserialized over and over again.
-	// TODO: make this persist across requests but not in a DB Tree (in globals?)
-	// TODO: This could potentially also be some sort of trie (suffix tree) like
-	// structure so that once an auth event is known it would know (using indexes
-	// maybe) all of the auth events that it references.
-	// let mut auth_cache = EventMap::new();
-
 	let txn_start_time = Instant::now();
 	let mut parsed_pdus = Vec::with_capacity(body.pdus.len());
 	for pdu in &body.pdus {
-		parsed_pdus.push(match parse_incoming_pdu(pdu) {
-			Ok(t) => t,
-			Err(e) => {
-				debug_warn!("Could not parse PDU: {e}");
-				continue;
+		match parse_incoming_pdu(pdu) {
+			Ok((event_id, _value, room_id)) => parsed_pdus.push((event_id, room_id, &**pdu)),
+			Err(e) => match handle_knock_rejection(origin, pdu).await {
+				Ok(true) => {},
+				Ok(false) => debug_warn!("Could not parse PDU: {e}"),
+				Err(e) => debug_warn!("Could not apply knock rejection: {e}"),
 			},
-		});
+		}
 
 		// We do not add the event_id field to the pdu here because of signature
 		// and hashes checks
@@ -83,63 +89,28 @@ pub(crate) async fn send_transaction_message_route(
 		"Starting txn",
 	);
 
-	// We go through all the signatures we see on the PDUs and fetch the
-	// corresponding signing keys
-	let pub_key_map = RwLock::new(BTreeMap::new());
-	if !parsed_pdus.is_empty() {
-		services()
-			.rooms
-			.event_handler
-			.fetch_required_signing_keys(parsed_pdus.iter().map(|(_event_id, event, _room_id)| event), &pub_key_map)
-			.await
-			.unwrap_or_else(|e| {
-				warn!("Could not fetch all signatures for PDUs from {origin}: {:?}", e);
-			});
+	// The PDUs are handled by the queue workers of their rooms, so the response
+	// only tells which PDUs were refused because the queue of their room is full.
+	// The sending server does not retry those, they are fetched as missing
+	// prev_events once a later PDU of their room is accepted.
+	let refused_rooms = if parsed_pdus.is_empty() {
+		HashSet::new()
+	} else {
+		let refused_rooms = services().rooms.pdu_queue.queue_pdus(
+			origin,
+			&parsed_pdus
+				.iter()
+				.map(|(_, room_id, pdu)| (&**room_id, *pdu))
+				.collect::<Vec<_>>(),
+		)?;
 
 		debug!(
 			elapsed = ?txn_start_time.elapsed(),
-			"Fetched signing keys"
-		);
-	}
-
-	let mut resolved_map = BTreeMap::new();
-	for (event_id, value, room_id) in parsed_pdus {
-		let pdu_start_time = Instant::now();
-		let mutex = Arc::clone(
-			services()
-				.globals
-				.roomid_mutex_federation
-				.write()
-				.await
-				.entry(room_id.clone())
-				.or_default(),
-		);
-		let mutex_lock = mutex.lock().await;
-		resolved_map.insert(
-			event_id.clone(),
-			services()
-				.rooms
-				.event_handler
-				.handle_incoming_pdu(origin, &room_id, &event_id, value, true, &pub_key_map)
-				.await
-				.map(|_| ()),
-		);
-		drop(mutex_lock);
-
-		debug!(
-			pdu_elapsed = ?pdu_start_time.elapsed(),
-			txn_elapsed = ?txn_start_time.elapsed(),
-			"Finished PDU {event_id}",
+			"Queued PDUs"
 		);
-	}
 
-	for pdu in &resolved_map {
-		if let Err(e) = pdu.1 {
-			if matches!(e, Error::BadRequest(ErrorKind::NotFound, _)) {
-				warn!("Incoming PDU failed {:?}", pdu);
-			}
-		}
-	}
+		refused_rooms
+	};
 
 	for edu in body
 		.edus
@@ -371,9 +342,256 @@ pub(crate) async fn send_transaction_message_route(
 	);
 
 	Ok(send_transaction_message::v1::Response {
-		pdus: resolved_map
+		pdus: parsed_pdus
 			.into_iter()
-			.map(|(e, r)| (e, r.map_err(|e| e.sanitized_error())))
+			.map(|(event_id, room_id, _)| {
+				if refused_rooms.contains(&room_id) {
+					let e = Error::BadRequest(
+						ErrorKind::LimitExceeded {
+							retry_after: None,
+						},
+						"Too many PDUs are queued for this room.",
+					);
+					(event_id, Err(e.sanitized_error()))
+				} else {
+					(event_id, Ok(()))
+				}
+			})
 			.collect(),
 	})
 }
+
+/// Applies the rejection of a local user's knock on a room we do not
+/// participate in. It only reaches us as the server of the knocking user, so
+/// it cannot be handled like the PDUs of rooms we are in. Returns whether the
+/// PDU was such a rejection.
+async fn handle_knock_rejection(origin: &ServerName, pdu: &RawJsonValue) -> Result<bool> {
+	let Ok(value) = serde_json::from_str::<CanonicalJsonObject>(pdu.get()) else {
+		return Ok(false);
+	};
+
+	let get_str = |key: &str| value.get(key).and_then(CanonicalJsonValue::as_str);
+	let (Some(room_id), Some(user_id), Some(sender)) = (
+		get_str("room_id").and_then(|room_id| RoomId::parse(room_id).ok()),
+		get_str("state_key").and_then(|user_id| UserId::parse(user_id).ok()),
+		get_str("sender").and_then(|sender| UserId::parse(sender).ok()),
+	) else {
+		return Ok(false);
+	};
+
+	let membership = value
+		.get("content")
+		.and_then(CanonicalJsonValue::as_object)
+		.and_then(|content| content.get("membership"))
+		.and_then(CanonicalJsonValue::as_str);
+	if get_str("type") != Some("m.room.member")
+		|| !matches!(membership, Some("leave" | "ban"))
+		|| !user_is_local(&user_id)
+		|| sender.server_name() != origin
+		|| services()
+			.rooms
+			.state_cache
+			.server_in_room(services().globals.server_name(), &room_id)?
+	{
+		return Ok(false);
+	}
+
+	let Some(knock_state) = services()
+		.rooms
+		.state_cache
+		.knock_state(&user_id, &room_id)?
+	else {
+		return Ok(false);
+	};
+
+	// The stripped create event of the knock state is all we know about the room
+	let stripped_create = knock_state
+		.iter()
+		.filter_map(|event| serde_json::from_str::<serde_json::Value>(event.json().get()).ok())
+		.find(|event| event["type"] == "m.room.create")
+		.ok_or(Error::BadServerResponse("Knocked room has no known room version."))?;
+	let room_version_id: RoomVersionId = serde_json::from_value(stripped_create["content"]["room_version"].clone())
+		.map_err(|_| Error::BadServerResponse("Knocked room has no known room version."))?;
+
+	let Ok((event_id, value)) = gen_event_id_canonical_json(pdu, &room_version_id) else {
+		return Err(Error::BadRequest(
+			ErrorKind::InvalidParam,
+			"Could not convert event to canonical json.",
+		));
+	};
+
+	let pub_key_map = RwLock::new(BTreeMap::new());
+	services()
+		.rooms
+		.event_handler
+		.fetch_required_signing_keys([&value], &pub_key_map)
+		.await?;
+	ruma::signatures::verify_event(&*pub_key_map.read().await, &value, &room_version_id)
+		.map_err(|_| Error::BadRequest(ErrorKind::forbidden(), "Knock rejection failed signature verification."))?;
+
+	let rejection = PduEvent::from_id_val(&event_id, value)
+		.map_err(|_| Error::BadRequest(ErrorKind::InvalidParam, "Knock rejection is not a valid PDU."))?;
+	let Some(create_event) =
+		fetch_create_event(origin, &room_id, &room_version_id, &stripped_create, &rejection.auth_events).await
+	else {
+		return Err(Error::BadServerResponse(
+			"Knock rejection does not refer to the create event of the room.",
+		));
+	};
+
+	// The sender has to be in the room and allowed to kick according to the auth
+	// events, which are fetched and checked like outliers
+	let auth_events: HashMap<_, _> = services()
+		.rooms
+		.event_handler
+		.fetch_and_handle_outliers(
+			origin,
+			&rejection.auth_events,
+			&create_event,
+			&room_id,
+			&room_version_id,
+			&pub_key_map,
+		)
+		.await
+		.into_iter()
+		.filter_map(|(pdu, _)| Some(((StateEventType::from(pdu.kind.to_string()), pdu.state_key.clone()?), pdu)))
+		.collect();
+
+	if auth_events
+		.get(&(StateEventType::RoomCreate, String::new()))
+		.map(|pdu| &pdu.event_id)
+		!= Some(&create_event.event_id)
+		|| !services()
+			.rooms
+			.event_handler
+			.auth_check(&room_version_id, &rejection, |kind, state_key| {
+				auth_events
+					.get(&(kind.clone(), state_key.to_owned()))
+					.cloned()
+			})? {
+		return Err(Error::BadRequest(
+			ErrorKind::forbidden(),
+			"Sender of the knock rejection is not allowed to reject it.",
+		));
+	}
+
+	info!("Knock of {user_id} on {room_id} was rejected by {event_id}");
+	services().rooms.state_cache.update_membership(
+		&room_id,
+		&user_id,
+		RoomMemberEventContent::new(MembershipState::Leave),
+		&sender,
+		None,
+		None,
+		false,
+	)?;
+
+	Ok(true)
+}
+
+/// Fetches the create event among the auth events of a knock rejection from
+/// `origin`, if it matches the stripped create event of the knock.
+async fn fetch_create_event(
+	origin: &ServerName, room_id: &RoomId, room_version_id: &RoomVersionId, stripped_create: &serde_json::Value,
+	auth_events: &[Arc<EventId>],
+) -> Option<PduEvent> {
+	for event_id in auth_events {
+		let Ok(response) = services()
+			.sending
+			.send_federation_request(
+				origin,
+				get_event::v1::Request {
+					event_id: (**event_id).to_owned(),
+				},
+			)
+			.await
+		else {
+			continue;
+		};
+
+		let Ok((calculated_event_id, value)) = gen_event_id_canonical_json(&response.pdu, room_version_id) else {
+			continue;
+		};
+		if *calculated_event_id != **event_id {
+			continue;
+		}
+
+		let Ok(pdu) = PduEvent::from_id_val(event_id, value) else {
+			continue;
+		};
+		if pdu.kind == TimelineEventType::RoomCreate && pdu.state_key.as_deref() == Some("") {
+			return is_stripped_create(&pdu, room_id, stripped_create).then_some(pdu);
+		}
+	}
+
+	None
+}
+
+/// Whether a create event is the one the stripped create event of a knock was
+/// made from.
+fn is_stripped_create(pdu: &PduEvent, room_id: &RoomId, stripped_create: &serde_json::Value) -> bool {
+	*pdu.room_id == *room_id
+		&& stripped_create["sender"] == pdu.sender.as_str()
+		&& serde_json::from_str::<serde_json::Value>(pdu.content.get())
+			.is_ok_and(|content| content["room_version"] == stripped_create["content"]["room_version"])
+}
+
+#[cfg(test)]
+mod tests {
+	use ruma::{event_id, room_id, CanonicalJsonObject};
+	use serde_json::json;
+
+	use super::is_stripped_create;
+	use crate::PduEvent;
+
+	fn create_event(room_id: &str, sender: &str, room_version: &str) -> PduEvent {
+		let value: CanonicalJsonObject = serde_json::from_value(json!({
+			"room_id": room_id,
+			"sender": sender,
+			"origin_server_ts": 0,
+			"type": "m.room.create",
+			"state_key": "",
+			"content": { "room_version": room_version },
+			"prev_events": [],
+			"auth_events": [],
+			"depth": 1,
+			"hashes": { "sha256": "" },
+			"signatures": {},
+		}))
+		.unwrap();
+
+		PduEvent::from_id_val(event_id!("$create"), value).unwrap()
+	}
+
+	#[test]
+	fn create_event_must_match_the_knock_state() {
+		let room_id = room_id!("!knocked:remote.test");
+		let stripped_create = json!({
+			"type": "m.room.create",
+			"state_key": "",
+			"sender": "@creator:remote.test",
+			"content": { "room_version": "10" },
+		});
+
+		assert!(is_stripped_create(
+			&create_event("!knocked:remote.test", "@creator:remote.test", "10"),
+			room_id,
+			&stripped_create
+		));
+		assert!(!is_stripped_create(
+			&create_event("!other:remote.test", "@creator:remote.test", "10"),
+			room_id,
+			&stripped_create
+		));
+		assert!(!is_stripped_create(
+			&create_event("!knocked:remote.test", "@forger:evil.test", "10"),
+			room_id,
+			&stripped_create
+		));
+		assert!(!is_stripped_create(
+			&create_event("!knocked:remote.test", "@creator:remote.test", "9"),
+			room_id,
+			&stripped_create
+		));
+	}
+}
@@ -1,11 +1,14 @@
 #![allow(deprecated)]
 
-use std::{collections::BTreeMap, sync::Arc};
+use std::{
+	collections::{BTreeMap, HashSet},
+	sync::Arc,
+};
 
 use ruma::{
 	api::{client::error::ErrorKind, federation::membership::create_join_event},
 	events::{room::member::MembershipState, StateEventType},
-	CanonicalJsonValue, OwnedServerName, OwnedUserId, RoomId, ServerName,
+	CanonicalJsonValue, OwnedServerName, OwnedUserId, RoomId, ServerName, UserId,
 };
 use serde_json::value::{to_raw_value, RawValue as RawJsonValue};
 use tokio::sync::RwLock;
@@ -14,8 +17,12 @@ use tracing::warn;
 use crate::{service::pdu::gen_event_id_canonical_json, services, Error, PduEvent, Result, Ruma};
 
 /// helper method for /send_join v1 and v2
+///
+/// With `omit_members` (MSC3706) the state only contains the memberships of
+/// the joining server and those authorizing the join, and the auth chain
+/// leaves out events already in the state.
 async fn create_join_event(
-	origin: &ServerName, room_id: &RoomId, pdu: &RawJsonValue,
+	origin: &ServerName, room_id: &RoomId, pdu: &RawJsonValue, omit_members: bool,
 ) -> Result<create_join_event::v1::RoomState> {
 	if !services().rooms.metadata.exists(room_id)? {
 		return Err(Error::BadRequest(ErrorKind::NotFound, "Room is unknown to this server."));
@@ -24,6 +31,13 @@ async fn create_join_event(
 	// ACL check origin server
 	services().rooms.event_handler.acl_check(origin, room_id)?;
 
+	// We can only hand out the state once we have all of it ourselves
+	services()
+		.rooms
+		.partial_state
+		.wait_for_full_state(room_id)
+		.await?;
+
 	// We need to return the state prior to joining, let's keep a reference to that
 	// here
 	let shortstatehash = services()
@@ -126,7 +140,7 @@ async fn create_join_event(
 
 	ruma::signatures::hash_and_sign_event(
 		services().globals.server_name().as_str(),
-		services().globals.keypair(),
+		&*services().globals.keypair(),
 		&mut value,
 		&room_version_id,
 	)
@@ -166,16 +180,48 @@ async fn create_join_event(
 		.ok_or_else(|| Error::BadRequest(ErrorKind::InvalidParam, "Could not accept as timeline event."))?;
 	drop(mutex_lock);
 
-	let state_ids = services()
+	let mut state_ids = services()
 		.rooms
 		.state_accessor
 		.state_full_ids(shortstatehash)
 		.await?;
+	if omit_members {
+		let join_auth_events: HashSet<_> = value
+			.get("auth_events")
+			.and_then(CanonicalJsonValue::as_array)
+			.into_iter()
+			.flatten()
+			.filter_map(CanonicalJsonValue::as_str)
+			.map(ToOwned::to_owned)
+			.collect();
+
+		state_ids.retain(|&shortstatekey, event_id| {
+			match services()
+				.rooms
+				.short
+				.get_statekey_from_short(shortstatekey)
+			{
+				Ok((StateEventType::RoomMember, state_key)) => {
+					join_auth_events.contains(event_id.as_str())
+						|| UserId::parse(state_key).is_ok_and(|user_id| user_id.server_name() == sender.server_name())
+				},
+				Ok(_) => true,
+				Err(_) => false,
+			}
+		});
+	}
+
+	let omitted_from_auth_chain: HashSet<_> = if omit_members {
+		state_ids.values().cloned().collect()
+	} else {
+		HashSet::new()
+	};
 	let auth_chain_ids = services()
 		.rooms
 		.auth_chain
 		.event_ids_iter(room_id, state_ids.values().cloned().collect())
-		.await?;
+		.await?
+		.filter(|id| !omitted_from_auth_chain.contains(id));
 
 	services().sending.send_pdu_room(room_id, &pdu_id)?;
 
@@ -240,7 +286,7 @@ pub(crate) async fn create_join_event_v1_route(
 		}
 	}
 
-	let room_state = create_join_event(origin, &body.room_id, &body.pdu).await?;
+	let room_state = create_join_event(origin, &body.room_id, &body.pdu, false).await?;
 
 	Ok(create_join_event::v1::Response {
 		room_state,
@@ -285,13 +331,26 @@ pub(crate) async fn create_join_event_v2_route(
 		auth_chain,
 		state,
 		event,
-	} = create_join_event(origin, &body.room_id, &body.pdu).await?;
+	} = create_join_event(origin, &body.room_id, &body.pdu, body.omit_members).await?;
+
+	// Joining servers with partial state send to these until they have all
+	// memberships
+	let servers_in_room = body.omit_members.then(|| {
+		services()
+			.rooms
+			.state_cache
+			.room_servers(&body.room_id)
+			.filter_map(Result::ok)
+			.map(Into::into)
+			.collect()
+	});
+
 	let room_state = create_join_event::v2::RoomState {
-		members_omitted: false,
+		members_omitted: body.omit_members,
 		auth_chain,
 		state,
 		event,
-		servers_in_room: None,
+		servers_in_room,
 	};
 
 	Ok(create_join_event::v2::Response {
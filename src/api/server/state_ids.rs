@@ -30,6 +30,13 @@ pub(crate) async fn get_room_state_ids_route(
 		return Err(Error::BadRequest(ErrorKind::forbidden(), "Server is not in room."));
 	}
 
+	// The state is only complete once a partial-state join has been resynced
+	services()
+		.rooms
+		.partial_state
+		.wait_for_full_state(&body.room_id)
+		.await?;
+
 	let shortstatehash = services()
 		.rooms
 		.state_accessor
@@ -0,0 +1,155 @@
+use ruma::{
+	api::{
+		client::error::ErrorKind,
+		federation::thirdparty::{bind_callback, exchange_invite},
+	},
+	events::{
+		room::member::{SignedContent, ThirdPartyInvite},
+		StateEventType,
+	},
+	RoomId, UserId,
+};
+use tracing::{info, warn};
+
+use crate::{client::invite_helper, service::user_is_local, services, Error, Result, Ruma};
+
+/// Most third party invites exchanged for a single bind
+const MAX_ONBIND_INVITES: usize = 20;
+
+/// # `PUT /_matrix/federation/v1/3pid/onbind`
+///
+/// Called by the identity server when a third party identifier was bound to
+/// one of our users. The third party invites stored for it are turned into
+/// invites by the servers of the inviting users.
+pub(crate) async fn third_party_invite_onbind_route(
+	body: Ruma<bind_callback::v1::Request>,
+) -> Result<bind_callback::v1::Response> {
+	if !user_is_local(&body.mxid)
+		|| !services().users.exists(&body.mxid)?
+		|| services().users.is_deactivated(&body.mxid)?
+	{
+		return Err(Error::BadRequest(
+			ErrorKind::InvalidParam,
+			"User does not exist on this server.",
+		));
+	}
+
+	if body.invites.len() > MAX_ONBIND_INVITES {
+		return Err(Error::BadRequest(ErrorKind::InvalidParam, "Too many third party invites."));
+	}
+
+	// The request is not authenticated, so it must not make us send requests for
+	// others at any rate
+	services()
+		.threepid
+		.limit_onbind_rate(&body.mxid, body.client_ip)?;
+
+	let mxid = &body.mxid;
+	for invite in &body.invites {
+		// Converted through JSON, as the ruma type of `signed` does not follow the
+		// spec in every version
+		let Ok(signed) = serde_json::to_value(&invite.signed).and_then(serde_json::from_value::<SignedContent>) else {
+			warn!("Ignoring third party invite to {} without a valid signature", invite.room_id);
+			continue;
+		};
+
+		if invite.mxid != *mxid || signed.mxid != *mxid {
+			warn!("Ignoring third party invite to {} for another user than {mxid}", invite.room_id);
+			continue;
+		}
+
+		info!(
+			"{mxid} bound {} {}, exchanging the third party invite to {} by {}",
+			invite.medium, invite.address, invite.room_id, invite.sender
+		);
+
+		let third_party_invite = ThirdPartyInvite {
+			display_name: invite.address.clone(),
+			signed,
+		};
+
+		let result = if user_is_local(&invite.sender) {
+			exchange_third_party_invite(&invite.sender, mxid, &invite.room_id, third_party_invite).await
+		} else {
+			services()
+				.sending
+				.send_federation_request(
+					invite.sender.server_name(),
+					exchange_invite::v1::Request {
+						room_id: invite.room_id.clone(),
+						kind: StateEventType::RoomMember,
+						sender: invite.sender.clone(),
+						state_key: mxid.clone(),
+						content: third_party_invite,
+					},
+				)
+				.await
+				.map(|_| ())
+		};
+
+		if let Err(e) = result {
+			warn!("Failed to exchange the third party invite to {}: {e}", invite.room_id);
+		}
+	}
+
+	Ok(bind_callback::v1::Response {})
+}
+
+/// # `PUT /_matrix/federation/v1/exchange_third_party_invite/{roomId}`
+///
+/// Turns a third party invite by one of our users into an invite of the user
+/// the third party identifier was bound to.
+pub(crate) async fn exchange_third_party_invite_route(
+	body: Ruma<exchange_invite::v1::Request>,
+) -> Result<exchange_invite::v1::Response> {
+	let origin = body.origin.as_ref().expect("server is authenticated");
+
+	// ACL check origin
+	services()
+		.rooms
+		.event_handler
+		.acl_check(origin, &body.room_id)?;
+
+	if body.kind != StateEventType::RoomMember {
+		return Err(Error::BadRequest(
+			ErrorKind::InvalidParam,
+			"Not allowed to exchange a third party invite for a non-membership event.",
+		));
+	}
+
+	if body.state_key.server_name() != origin {
+		return Err(Error::BadRequest(
+			ErrorKind::InvalidParam,
+			"Not allowed to exchange a third party invite on behalf of another server.",
+		));
+	}
+
+	if !user_is_local(&body.sender) {
+		return Err(Error::BadRequest(
+			ErrorKind::InvalidParam,
+			"Sender of the third party invite does not belong to this server.",
+		));
+	}
+
+	let body = body.body;
+	exchange_third_party_invite(&body.sender, &body.state_key, &body.room_id, body.content).await?;
+
+	Ok(exchange_invite::v1::Response {})
+}
+
+/// Sends the invite of a local user which replaces their third party invite.
+/// The auth rules check the signature of the identity server against the
+/// `m.room.third_party_invite` event the invite refers to.
+async fn exchange_third_party_invite(
+	sender: &UserId, user_id: &UserId, room_id: &RoomId, third_party_invite: ThirdPartyInvite,
+) -> Result<()> {
+	if !services()
+		.rooms
+		.state_cache
+		.server_in_room(services().globals.server_name(), room_id)?
+	{
+		return Err(Error::BadRequest(ErrorKind::NotFound, "Room is unknown to this server."));
+	}
+
+	invite_helper(sender, user_id, room_id, None, false, Some(third_party_invite)).await
+}
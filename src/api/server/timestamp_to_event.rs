@@ -0,0 +1,47 @@
+use ruma::{
+	api::{client::error::ErrorKind, federation::event::get_event_by_timestamp},
+	MilliSecondsSinceUnixEpoch,
+};
+
+use crate::{services, Error, Result, Ruma};
+
+/// # `GET /_matrix/federation/v1/timestamp_to_event/{roomId}`
+///
+/// Finds the event closest to the given timestamp in the given direction in
+/// our local timeline.
+pub(crate) async fn get_event_by_timestamp_route(
+	body: Ruma<get_event_by_timestamp::v1::Request>,
+) -> Result<get_event_by_timestamp::v1::Response> {
+	let origin = body.origin.as_ref().expect("server is authenticated");
+
+	services()
+		.rooms
+		.event_handler
+		.acl_check(origin, &body.room_id)?;
+
+	if !services()
+		.rooms
+		.state_accessor
+		.is_world_readable(&body.room_id)?
+		&& !services()
+			.rooms
+			.state_cache
+			.server_in_room(origin, &body.room_id)?
+	{
+		return Err(Error::BadRequest(ErrorKind::forbidden(), "Server is not in room."));
+	}
+
+	let pdu = services()
+		.rooms
+		.timeline
+		.pdu_by_timestamp(&body.room_id, body.ts, body.dir)?
+		.ok_or(Error::BadRequest(
+			ErrorKind::NotFound,
+			"No event found in the given direction of the timestamp.",
+		))?;
+
+	Ok(get_event_by_timestamp::v1::Response::new(
+		pdu.event_id,
+		MilliSecondsSinceUnixEpoch(pdu.origin_server_ts),
+	))
+}
@@ -100,14 +100,46 @@ pub fn check(config: &Config) -> Result<(), Error> {
 		}
 	}
 
+	for cidr in &config.trusted_proxies {
+		if let Err(e) = ipaddress::IPAddress::parse(cidr) {
+			error!("Error parsing trusted proxy IP CIDR range from string: {e}");
+			return Err(Error::bad_config(
+				"Error parsing \"trusted_proxies\" IP CIDR ranges from strings",
+			));
+		}
+	}
+
+	if config.server_notices_localpart.is_empty() || config.server_notices_localpart == "conduit" {
+		return Err(Error::bad_config(
+			"\"server_notices_localpart\" must not be empty or the localpart of the server account (conduit)",
+		));
+	}
+
+	if !ruma::UserId::parse_with_server_name(config.server_notices_localpart.as_str(), &config.server_name)
+		.is_ok_and(|user_id| !user_id.is_historical())
+	{
+		return Err(Error::bad_config(
+			"\"server_notices_localpart\" is not a valid localpart, it may only contain lowercase letters, digits and \
+			 ._=-/",
+		));
+	}
+
+	if config.signing_key_validity_period_s < 60 * 60 {
+		return Err(Error::bad_config(
+			"\"signing_key_validity_period_s\" must be at least one hour, remote servers cache our keys for this long.",
+		));
+	}
+
 	if config.allow_registration
 		&& !config.yes_i_am_very_very_sure_i_want_an_open_registration_server_prone_to_abuse
 		&& config.registration_token.is_none()
+		&& !config.registration_requires_token
 	{
 		return Err(Error::bad_config(
 			"!! You have `allow_registration` enabled without a token configured in your config which means you are \
 			 allowing ANYONE to register on your conduwuit instance without any 2nd-step (e.g. registration token).\n
-If this is not the intended behaviour, please set a registration token with the `registration_token` config option.\n
+If this is not the intended behaviour, please set a registration token with the `registration_token` config option, or \
+			 set `registration_requires_token` and create tokens with `!admin registration-tokens create`.\n
 For security and safety reasons, conduwuit will shut down. If you are extra sure this is the desired behaviour you \
 			 want, please set the following config option to true:
 `yes_i_am_very_very_sure_i_want_an_open_registration_server_prone_to_abuse`",
@@ -117,6 +149,7 @@ For security and safety reasons, conduwuit will shut down. If you are extra sure
 	if config.allow_registration
 		&& config.yes_i_am_very_very_sure_i_want_an_open_registration_server_prone_to_abuse
 		&& config.registration_token.is_none()
+		&& !config.registration_requires_token
 	{
 		warn!(
 			"Open registration is enabled via setting \
@@ -126,6 +159,102 @@ For security and safety reasons, conduwuit will shut down. If you are extra sure
 		);
 	}
 
+	if config.access_token_ttl < 60_000 {
+		return Err(Error::bad_config(
+			"Access token TTL is less than a minute. Please increase \"access_token_ttl\".",
+		));
+	}
+
+	if config.ldap.enable {
+		if !cfg!(feature = "ldap") {
+			return Err(Error::bad_config(
+				"LDAP authentication is enabled but conduwuit was built without the \"ldap\" feature.",
+			));
+		}
+
+		if config.ldap.uri.is_none() {
+			return Err(Error::bad_config("LDAP authentication is enabled but \"ldap.uri\" is not set."));
+		}
+
+		let direct_bind = config
+			.ldap
+			.bind_dn
+			.as_ref()
+			.is_some_and(|bind_dn| bind_dn.contains("{username}"));
+
+		if !direct_bind && config.ldap.base_dn.is_empty() {
+			return Err(Error::bad_config(
+				"LDAP search-then-bind mode requires \"ldap.base_dn\" to be set, or set \"ldap.bind_dn\" to a DN \
+				 template containing {username} to bind as the user directly.",
+			));
+		}
+
+		if config.ldap.bind_password_file.is_some() && (config.ldap.bind_dn.is_none() || direct_bind) {
+			warn!("\"ldap.bind_password_file\" is only used with a search-then-bind service account \"ldap.bind_dn\".");
+		}
+	}
+
+	if config.smtp.enable {
+		if !cfg!(feature = "email") {
+			return Err(Error::bad_config(
+				"SMTP is enabled but conduwuit was built without the \"email\" feature.",
+			));
+		}
+
+		if config.smtp.url.is_none() {
+			return Err(Error::bad_config("SMTP is enabled but \"smtp.url\" is not set."));
+		}
+
+		if config.smtp.from.is_none() {
+			return Err(Error::bad_config("SMTP is enabled but \"smtp.from\" is not set."));
+		}
+
+		if config.smtp.password_file.is_some() && config.smtp.username.is_none() {
+			warn!("\"smtp.password_file\" is set without \"smtp.username\" and will not be used.");
+		}
+	} else if config.smtp.registration_requires_email {
+		return Err(Error::bad_config(
+			"\"smtp.registration_requires_email\" requires SMTP to be enabled to send validation emails.",
+		));
+	}
+
+	if config.identity_server.is_some() && !cfg!(feature = "email") {
+		return Err(Error::bad_config(
+			"\"identity_server\" is set but conduwuit was built without the \"email\" feature.",
+		));
+	}
+
+	if config.terms.version.is_some() != config.terms.url.is_some() {
+		return Err(Error::bad_config(
+			"Terms of service require both \"terms.version\" and \"terms.url\" to be set.",
+		));
+	}
+
+	if !config.allowed_remote_server_names.is_empty() {
+		for server in &config.trusted_servers {
+			if !config.allowed_remote_server_names.is_match(server.as_str()) {
+				warn!(
+					"Trusted key server {server} is not allowed by \"allowed_remote_server_names\", it will not be \
+					 asked for signing keys."
+				);
+			}
+		}
+	}
+
+	if config.incoming_pdu_queue_capacity < 50 {
+		return Err(Error::bad_config(
+			"A transaction may contain up to 50 PDUs for the same room. Please set \"incoming_pdu_queue_capacity\" to \
+			 at least 50.",
+		));
+	}
+
+	if config.incoming_pdu_workers == 0 {
+		return Err(Error::bad_config(
+			"At least one worker is required to handle incoming PDUs. Please set \"incoming_pdu_workers\" to a \
+			 positive number.",
+		));
+	}
+
 	if config.allow_outgoing_presence && !config.allow_local_presence {
 		return Err(Error::bad_config(
 			"Outgoing presence requires allowing local presence. Please enable \"allow_local_presence\".",
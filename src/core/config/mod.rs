@@ -5,6 +5,7 @@ use std::{
 	path::PathBuf,
 };
 
+use axum_client_ip::SecureClientIpSource;
 use either::{
 	Either,
 	Either::{Left, Right},
@@ -68,6 +69,8 @@ pub struct Config {
 	pub db_cache_capacity_mb: f64,
 	#[serde(default = "default_new_user_displayname_suffix")]
 	pub new_user_displayname_suffix: String,
+	#[serde(default = "default_server_notices_localpart")]
+	pub server_notices_localpart: String,
 	#[serde(default)]
 	pub allow_check_for_updates: bool,
 
@@ -117,6 +120,13 @@ pub struct Config {
 	pub max_request_size: u32,
 	#[serde(default = "default_max_fetch_prev_events")]
 	pub max_fetch_prev_events: u16,
+	/// Maximum number of incoming PDUs waiting to be handled per room. PDUs
+	/// which would exceed it are dropped with an error in the transaction
+	/// response, and only fetched again as missing events of a later PDU.
+	#[serde(default = "default_incoming_pdu_queue_capacity")]
+	pub incoming_pdu_queue_capacity: usize,
+	#[serde(default = "default_incoming_pdu_workers")]
+	pub incoming_pdu_workers: usize,
 
 	#[serde(default = "default_request_conn_timeout")]
 	pub request_conn_timeout: u64,
@@ -156,6 +166,8 @@ pub struct Config {
 	#[serde(default)]
 	pub yes_i_am_very_very_sure_i_want_an_open_registration_server_prone_to_abuse: bool,
 	pub registration_token: Option<String>,
+	#[serde(default)]
+	pub registration_requires_token: bool,
 	#[serde(default = "true_fn")]
 	pub allow_encryption: bool,
 	#[serde(default = "true_fn")]
@@ -181,6 +193,15 @@ pub struct Config {
 	#[serde(default)]
 	pub well_known: WellKnownConfig,
 	#[serde(default)]
+	pub ldap: LdapConfig,
+	#[serde(default)]
+	pub rest_auth: RestAuthConfig,
+	pub imported_password_pepper: Option<String>,
+	#[serde(default)]
+	pub smtp: SmtpConfig,
+	#[serde(default)]
+	pub terms: TermsConfig,
+	#[serde(default)]
 	#[cfg(feature = "perf_measurements")]
 	pub allow_jaeger: bool,
 	#[serde(default)]
@@ -195,10 +216,37 @@ pub struct Config {
 	#[serde(default)]
 	pub proxy: ProxyConfig,
 	pub jwt_secret: Option<String>,
+	#[serde(default)]
+	pub login_via_existing_session: bool,
+	#[serde(default = "default_login_token_ttl")]
+	pub login_token_ttl: u64,
+	#[serde(default = "default_access_token_ttl")]
+	pub access_token_ttl: u64,
+	#[serde(default = "true_fn")]
+	pub expire_access_tokens_without_refresh: bool,
+	#[serde(default = "default_password_min_length")]
+	pub password_min_length: usize,
+	#[serde(default)]
+	pub password_require_digit: bool,
+	#[serde(default)]
+	pub password_require_lowercase: bool,
+	#[serde(default)]
+	pub password_require_uppercase: bool,
+	#[serde(default)]
+	pub password_require_symbol: bool,
+	#[serde(default)]
+	pub password_forbid_localpart: bool,
+	#[serde(default)]
+	pub password_denylist: Vec<String>,
 	#[serde(default = "default_trusted_servers")]
 	pub trusted_servers: Vec<OwnedServerName>,
 	#[serde(default = "true_fn")]
 	pub query_trusted_key_servers_first: bool,
+	#[serde(default = "default_signing_key_validity_period_s")]
+	pub signing_key_validity_period_s: u64,
+	#[serde(default)]
+	pub allow_partial_state_joins: bool,
+	pub identity_server: Option<Url>,
 	#[serde(default = "default_log")]
 	pub log: String,
 	#[serde(default)]
@@ -306,10 +354,18 @@ pub struct Config {
 	pub forbidden_remote_server_names: Vec<OwnedServerName>,
 	#[serde(default = "Vec::new")]
 	pub forbidden_remote_room_directory_server_names: Vec<OwnedServerName>,
+	#[serde(default = "RegexSet::empty")]
+	#[serde(with = "serde_regex")]
+	pub allowed_remote_server_names: RegexSet,
 
 	#[serde(default = "default_ip_range_denylist")]
 	pub ip_range_denylist: Vec<String>,
 
+	#[serde(default = "default_ip_source")]
+	pub ip_source: SecureClientIpSource,
+	#[serde(default = "default_trusted_proxies")]
+	pub trusted_proxies: Vec<String>,
+
 	#[serde(default = "Vec::new")]
 	pub url_preview_domain_contains_allowlist: Vec<String>,
 	#[serde(default = "Vec::new")]
@@ -374,6 +430,111 @@ pub struct WellKnownConfig {
 	pub support_mxid: Option<OwnedUserId>,
 }
 
+#[derive(Clone, Debug, Deserialize, Default)]
+pub struct LdapConfig {
+	#[serde(default)]
+	pub enable: bool,
+	pub uri: Option<Url>,
+	#[serde(default)]
+	pub starttls: bool,
+	#[serde(default)]
+	pub base_dn: String,
+	/// Either a DN template containing `{username}` to bind as the user
+	/// directly, or the DN of a service account used to search for the user
+	/// before binding as them
+	pub bind_dn: Option<String>,
+	pub bind_password_file: Option<PathBuf>,
+	#[serde(default = "default_ldap_filter")]
+	pub filter: String,
+	#[serde(default = "default_ldap_uid_attribute")]
+	pub uid_attribute: String,
+	#[serde(default = "default_ldap_name_attribute")]
+	pub name_attribute: String,
+	pub admin_base_dn: Option<String>,
+	pub admin_filter: Option<String>,
+	#[serde(default = "true_fn")]
+	pub create_users: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct RestAuthConfig {
+	pub endpoint: Option<Url>,
+	#[serde(default = "true_fn")]
+	pub create_users: bool,
+}
+
+impl Default for RestAuthConfig {
+	fn default() -> Self {
+		Self {
+			endpoint: None,
+			create_users: true,
+		}
+	}
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SmtpConfig {
+	#[serde(default)]
+	pub enable: bool,
+	/// Relay URL, e.g. `smtp://localhost:1025` for a local mail sink,
+	/// `smtp://mail.example.com:587?tls=required` for STARTTLS or
+	/// `smtps://mail.example.com` for implicit TLS
+	pub url: Option<String>,
+	pub username: Option<String>,
+	pub password_file: Option<PathBuf>,
+	/// Sender of validation emails, e.g. `conduwuit <noreply@example.com>`
+	pub from: Option<String>,
+	#[serde(default = "default_email_validation_token_ttl")]
+	pub validation_token_ttl: u64,
+	#[serde(default)]
+	pub registration_requires_email: bool,
+}
+
+impl Default for SmtpConfig {
+	fn default() -> Self {
+		Self {
+			enable: false,
+			url: None,
+			username: None,
+			password_file: None,
+			from: None,
+			validation_token_ttl: default_email_validation_token_ttl(),
+			registration_requires_email: false,
+		}
+	}
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct TermsConfig {
+	/// Version of the current terms. Users who accepted another version have
+	/// to accept this one before they can continue using their account.
+	pub version: Option<String>,
+	/// Where the terms document is published
+	pub url: Option<Url>,
+	#[serde(default = "default_terms_name")]
+	pub name: String,
+	#[serde(default = "default_terms_language")]
+	pub language: String,
+}
+
+impl TermsConfig {
+	/// Whether users have to accept terms of service, which requires a version
+	/// and a document.
+	#[must_use]
+	pub fn enabled(&self) -> bool { self.version.is_some() && self.url.is_some() }
+}
+
+impl Default for TermsConfig {
+	fn default() -> Self {
+		Self {
+			version: None,
+			url: None,
+			name: default_terms_name(),
+			language: default_terms_language(),
+		}
+	}
+}
+
 const DEPRECATED_KEYS: &[&str] = &[
 	"cache_capacity",
 	"max_concurrent_requests",
@@ -547,6 +708,11 @@ impl fmt::Display for Config {
 			("DNS query over TCP only", &self.query_over_tcp_only.to_string()),
 			("Query all nameservers", &self.query_all_nameservers.to_string()),
 			("Maximum request size (bytes)", &self.max_request_size.to_string()),
+			(
+				"Incoming PDU queue capacity per room",
+				&self.incoming_pdu_queue_capacity.to_string(),
+			),
+			("Incoming PDU workers", &self.incoming_pdu_workers.to_string()),
 			("Sender retry backoff limit", &self.sender_retry_backoff_limit.to_string()),
 			("Request connect timeout", &self.request_conn_timeout.to_string()),
 			("Request timeout", &self.request_timeout.to_string()),
@@ -568,9 +734,13 @@ impl fmt::Display for Config {
 				"Registration token",
 				match self.registration_token {
 					Some(_) => "set",
-					None => "not set (open registration!)",
+					None => "not set",
 				},
 			),
+			(
+				"Registration requires a token",
+				&(self.registration_token.is_some() || self.registration_requires_token).to_string(),
+			),
 			(
 				"Allow guest registration (inherently false if allow registration is false)",
 				&self.allow_guest_registration.to_string(),
@@ -584,6 +754,7 @@ impl fmt::Display for Config {
 				&self.allow_guests_auto_join_rooms.to_string(),
 			),
 			("New user display name suffix", &self.new_user_displayname_suffix),
+			("Server notices user localpart", &self.server_notices_localpart),
 			("Allow encryption", &self.allow_encryption.to_string()),
 			("Allow federation", &self.allow_federation.to_string()),
 			(
@@ -648,6 +819,32 @@ impl fmt::Display for Config {
 					None => "not set",
 				},
 			),
+			(
+				"Allow login via existing session (m.login.token)",
+				&self.login_via_existing_session.to_string(),
+			),
+			("Login token TTL (ms)", &self.login_token_ttl.to_string()),
+			("Access token TTL (ms)", &self.access_token_ttl.to_string()),
+			(
+				"Expire access tokens of clients without refresh token support",
+				&self.expire_access_tokens_without_refresh.to_string(),
+			),
+			("Password minimum length", &self.password_min_length.to_string()),
+			("Password requires a digit", &self.password_require_digit.to_string()),
+			(
+				"Password requires a lowercase letter",
+				&self.password_require_lowercase.to_string(),
+			),
+			(
+				"Password requires an uppercase letter",
+				&self.password_require_uppercase.to_string(),
+			),
+			("Password requires a symbol", &self.password_require_symbol.to_string()),
+			(
+				"Password must not contain the username",
+				&self.password_forbid_localpart.to_string(),
+			),
+			("Denylisted passwords", &self.password_denylist.len().to_string()),
 			("Trusted key servers", {
 				let mut lst = vec![];
 				for server in &self.trusted_servers {
@@ -659,6 +856,19 @@ impl fmt::Display for Config {
 				"Query Trusted Key Servers First",
 				&self.query_trusted_key_servers_first.to_string(),
 			),
+			(
+				"Signing key validity period (seconds)",
+				&self.signing_key_validity_period_s.to_string(),
+			),
+			("Allow partial-state joins", &self.allow_partial_state_joins.to_string()),
+			(
+				"Identity server",
+				&if let Some(identity_server) = &self.identity_server {
+					identity_server.to_string()
+				} else {
+					"not set".to_owned()
+				},
+			),
 			(
 				"TURN username",
 				if self.turn_username.is_empty() {
@@ -776,6 +986,13 @@ impl fmt::Display for Config {
 				}
 				&lst.join(", ")
 			}),
+			("Allowed Remote Server Names (closed federation)", {
+				&self
+					.allowed_remote_server_names
+					.patterns()
+					.iter()
+					.join(", ")
+			}),
 			("Outbound Request IP Range Denylist", {
 				let mut lst = vec![];
 				for item in self.ip_range_denylist.iter().cloned().enumerate() {
@@ -784,6 +1001,8 @@ impl fmt::Display for Config {
 				}
 				&lst.join(", ")
 			}),
+			("Client IP source", &format!("{:?}", self.ip_source)),
+			("Trusted proxies", &self.trusted_proxies.join(", ")),
 			("Forbidden usernames", {
 				&self.forbidden_usernames.patterns().iter().join(", ")
 			}),
@@ -867,6 +1086,75 @@ impl fmt::Display for Config {
 					String::new()
 				},
 			),
+			("LDAP authentication", &self.ldap.enable.to_string()),
+			(
+				"LDAP URI",
+				&if let Some(uri) = &self.ldap.uri {
+					uri.to_string()
+				} else {
+					String::new()
+				},
+			),
+			("LDAP base DN", &self.ldap.base_dn),
+			(
+				"LDAP admin filter",
+				&if let Some(admin_filter) = &self.ldap.admin_filter {
+					admin_filter.clone()
+				} else {
+					String::new()
+				},
+			),
+			("LDAP create users on first login", &self.ldap.create_users.to_string()),
+			(
+				"REST auth endpoint",
+				&if let Some(endpoint) = &self.rest_auth.endpoint {
+					endpoint.to_string()
+				} else {
+					String::new()
+				},
+			),
+			(
+				"REST auth create users on first login",
+				&self.rest_auth.create_users.to_string(),
+			),
+			("SMTP email verification", &self.smtp.enable.to_string()),
+			(
+				"SMTP relay URL",
+				&if let Some(url) = &self.smtp.url {
+					url.clone()
+				} else {
+					String::new()
+				},
+			),
+			(
+				"SMTP sender address",
+				&if let Some(from) = &self.smtp.from {
+					from.clone()
+				} else {
+					String::new()
+				},
+			),
+			("Email validation token TTL", &self.smtp.validation_token_ttl.to_string()),
+			(
+				"Registration requires an email address",
+				&self.smtp.registration_requires_email.to_string(),
+			),
+			("Terms of service version", self.terms.version.as_deref().unwrap_or("not set")),
+			(
+				"Terms of service URL",
+				&if let Some(url) = &self.terms.url {
+					url.to_string()
+				} else {
+					String::new()
+				},
+			),
+			(
+				"Imported password pepper",
+				match self.imported_password_pepper {
+					Some(_) => "set",
+					None => "not set",
+				},
+			),
 		];
 
 		let mut msg: String = "Active config values:\n\n".to_owned();
@@ -949,6 +1237,12 @@ fn default_request_idle_timeout() -> u64 { 5 }
 
 fn default_request_idle_per_host() -> u16 { 1 }
 
+fn default_ldap_filter() -> String { "(objectClass=*)".to_owned() }
+
+fn default_ldap_uid_attribute() -> String { "uid".to_owned() }
+
+fn default_ldap_name_attribute() -> String { "displayName".to_owned() }
+
 fn default_well_known_conn_timeout() -> u64 { 6 }
 
 fn default_well_known_timeout() -> u64 { 10 }
@@ -973,6 +1267,10 @@ fn default_pusher_idle_timeout() -> u64 { 15 }
 
 fn default_max_fetch_prev_events() -> u16 { 100_u16 }
 
+fn default_incoming_pdu_queue_capacity() -> usize { 1000 }
+
+fn default_incoming_pdu_workers() -> usize { 8 }
+
 #[cfg(feature = "perf_measurements")]
 fn default_tracing_flame_filter() -> String { "trace,h2=off".to_owned() }
 
@@ -995,6 +1293,18 @@ fn default_notification_push_path() -> String { "/_matrix/push/v1/notify".to_own
 
 fn default_turn_ttl() -> u64 { 60 * 60 * 24 }
 
+fn default_login_token_ttl() -> u64 { 2 * 60 * 1000 }
+
+fn default_access_token_ttl() -> u64 { 60 * 60 * 1000 }
+
+fn default_password_min_length() -> usize { 8 }
+
+fn default_email_validation_token_ttl() -> u64 { 60 * 60 * 1000 }
+
+fn default_terms_name() -> String { "Terms of Service".to_owned() }
+
+fn default_terms_language() -> String { "en".to_owned() }
+
 fn default_presence_idle_timeout_s() -> u64 { 5 * 60 }
 
 fn default_presence_offline_timeout_s() -> u64 { 30 * 60 }
@@ -1038,6 +1348,10 @@ fn default_rocksdb_bottommost_compression_level() -> i32 { 32767 }
 #[must_use]
 pub fn default_default_room_version() -> RoomVersionId { RoomVersionId::V10 }
 
+fn default_ip_source() -> SecureClientIpSource { SecureClientIpSource::ConnectInfo }
+
+fn default_trusted_proxies() -> Vec<String> { vec!["127.0.0.0/8".to_owned(), "::1/128".to_owned()] }
+
 fn default_ip_range_denylist() -> Vec<String> {
 	vec![
 		"127.0.0.0/8".to_owned(),
@@ -1068,6 +1382,10 @@ fn default_url_preview_max_spider_size() -> usize {
 
 fn default_new_user_displayname_suffix() -> String { "🏳️‍⚧️".to_owned() }
 
+fn default_server_notices_localpart() -> String { "notices".to_owned() }
+
+fn default_signing_key_validity_period_s() -> u64 { 60 * 60 * 24 * 7 }
+
 fn default_sentry_endpoint() -> Option<Url> {
 	Url::parse("https://fe2eb4536aa04949e28eff3128d64757@o4506996327251968.ingest.us.sentry.io/4506996334657536")
 		.unwrap()
@@ -18,9 +18,15 @@ use thiserror::Error;
 use tracing::error;
 use ErrorKind::{
 	Forbidden, GuestAccessForbidden, LimitExceeded, MissingToken, NotFound, ThreepidAuthFailed, ThreepidDenied,
-	TooLarge, Unauthorized, Unknown, UnknownToken, Unrecognized, UserDeactivated, WrongRoomKeysVersion,
+	TooLarge, Unauthorized, Unknown, UnknownToken, Unrecognized, UserDeactivated, UserLocked, WrongRoomKeysVersion,
 };
 
+/// Error code of [`Error::user_suspended`]
+const USER_SUSPENDED: &str = "M_USER_SUSPENDED";
+
+/// Error code of [`Error::consent_not_given`]
+const CONSENT_NOT_GIVEN: &str = "M_CONSENT_NOT_GIVEN";
+
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 #[derive(Error)]
@@ -97,6 +103,26 @@ impl Error {
 		Self::BadConfig(message.to_owned())
 	}
 
+	/// `M_USER_SUSPENDED` from MSC3823, which ruma has no `ErrorKind` for yet.
+	pub fn user_suspended(message: &'static str) -> Self {
+		let kind = serde_json::from_value(serde_json::json!({ "errcode": USER_SUSPENDED }))
+			.expect("custom error codes are valid error kinds");
+
+		Self::BadRequest(kind, message)
+	}
+
+	/// `M_CONSENT_NOT_GIVEN`, pointing the user to where they can accept the
+	/// current terms of service.
+	pub fn consent_not_given(consent_uri: &str, message: &'static str) -> Self {
+		let kind = serde_json::from_value(serde_json::json!({
+			"errcode": CONSENT_NOT_GIVEN,
+			"consent_uri": consent_uri,
+		}))
+		.expect("custom error codes are valid error kinds");
+
+		Self::BadRequest(kind, message)
+	}
+
 	/// Returns the Matrix error code / error kind
 	pub fn error_code(&self) -> ErrorKind {
 		if let Self::Federation(_, error) = self {
@@ -183,12 +209,16 @@ impl Error {
 					| UnknownToken {
 						..
 					}
+					| UserLocked
 					| MissingToken => StatusCode::UNAUTHORIZED,
 					NotFound | Unrecognized => StatusCode::NOT_FOUND,
 					LimitExceeded {
 						..
 					} => StatusCode::TOO_MANY_REQUESTS,
 					TooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+					_ if kind.to_string() == USER_SUSPENDED || kind.to_string() == CONSENT_NOT_GIVEN => {
+						StatusCode::FORBIDDEN
+					},
 					_ => StatusCode::BAD_REQUEST,
 				},
 			),
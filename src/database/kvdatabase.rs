@@ -17,6 +17,7 @@ pub struct KeyValueDatabase {
 	//pub globals: globals::Globals,
 	pub global: Arc<dyn KvTree>,
 	pub server_signingkeys: Arc<dyn KvTree>,
+	pub server_signedkeys: Arc<dyn KvTree>,
 
 	pub roomid_inviteviaservers: Arc<dyn KvTree>,
 
@@ -25,10 +26,21 @@ pub struct KeyValueDatabase {
 	pub userid_displayname: Arc<dyn KvTree>,
 	pub userid_avatarurl: Arc<dyn KvTree>,
 	pub userid_blurhash: Arc<dyn KvTree>,
+	pub userid_suspendedsince: Arc<dyn KvTree>,    // SuspendedSince = u64
+	pub userid_lockedsince: Arc<dyn KvTree>,       // LockedSince = u64
+	pub userid_shadowbannedsince: Arc<dyn KvTree>, // ShadowBannedSince = u64
+	pub erased_users: Arc<dyn KvTree>,             // ErasedSince = u64
+	pub userid_termsversion: Arc<dyn KvTree>,      // Version of the terms of service the user accepted
+	pub userid_consenttoken: Arc<dyn KvTree>,      // Token authenticating the user on the consent page
 	pub userdeviceid_token: Arc<dyn KvTree>,
 	pub userdeviceid_metadata: Arc<dyn KvTree>, // This is also used to check if a device exists
+	pub userdeviceid_useragent: Arc<dyn KvTree>,
 	pub userid_devicelistversion: Arc<dyn KvTree>, // DevicelistVersion = u64
 	pub token_userdeviceid: Arc<dyn KvTree>,
+	pub logintoken_expiresatuserid: Arc<dyn KvTree>, // ExpiresAtUserId = ExpiresAt (u64) + UserId
+	pub token_expiresat: Arc<dyn KvTree>,            // ExpiresAt = u64, only for expiring access tokens
+	pub refreshtoken_userdeviceid: Arc<dyn KvTree>,
+	pub userdeviceid_refreshtoken: Arc<dyn KvTree>, // RefreshToken = Current + Previous (for reuse detection)
 
 	pub onetimekeyid_onetimekeys: Arc<dyn KvTree>, // OneTimeKeyId = UserId + DeviceKeyId
 	pub userid_lastonetimekeyupdate: Arc<dyn KvTree>, // LastOneTimeKeyUpdate = Count
@@ -47,6 +59,21 @@ pub struct KeyValueDatabase {
 	pub userdevicesessionid_uiaainfo: Arc<dyn KvTree>, // User-interactive authentication
 	pub userdevicesessionid_uiaarequest: RwLock<BTreeMap<(OwnedUserId, OwnedDeviceId, String), CanonicalJsonValue>>,
 
+	//pub registration_tokens: registration_tokens::RegistrationTokens,
+	pub registrationtoken_info: Arc<dyn KvTree>, // Info = RegistrationToken as json
+	pub uiaasessionid_registrationtoken: Arc<dyn KvTree>, // Token used in a not yet finished registration
+	pub userid_registrationtoken: Arc<dyn KvTree>, // Token the user registered with
+
+	//pub server_notices: server_notices::ServerNotices,
+	pub userid_servernoticesroomid: Arc<dyn KvTree>,
+
+	//pub threepid: threepid::Threepid,
+	pub threepidsid_session: Arc<dyn KvTree>, // Session = ValidationSession as json
+	pub clientsecretthreepid_sid: Arc<dyn KvTree>, // ClientSecretThreepid = ClientSecret + Medium + Address
+	pub uiaasessionid_threepidsid: Arc<dyn KvTree>, // Validation session used in a not yet finished UIAA session
+	pub useridthreepid_info: Arc<dyn KvTree>, // UserIdThreepid = UserId + Medium + Address
+	pub threepid_userid: Arc<dyn KvTree>,     // Threepid = Medium + Address
+
 	//pub edus: RoomEdus,
 	pub readreceiptid_readreceipt: Arc<dyn KvTree>, // ReadReceiptId = RoomId + Count + UserId
 	pub roomuserid_privateread: Arc<dyn KvTree>,    // RoomUserId = Room + User, PrivateRead = Count
@@ -55,6 +82,7 @@ pub struct KeyValueDatabase {
 	//pub rooms: rooms::Rooms,
 	pub pduid_pdu: Arc<dyn KvTree>, // PduId = ShortRoomId + Count
 	pub eventid_pduid: Arc<dyn KvTree>,
+	pub roomtimestamp_pduid: Arc<dyn KvTree>, // RoomTimestamp = ShortRoomId + Timestamp + PduIdCount
 	pub roomid_pduleaves: Arc<dyn KvTree>,
 	pub alias_roomid: Arc<dyn KvTree>,
 	pub aliasid_alias: Arc<dyn KvTree>, // AliasId = RoomId + Count
@@ -73,8 +101,10 @@ pub struct KeyValueDatabase {
 	pub roomid_joinedcount: Arc<dyn KvTree>,
 	pub roomid_invitedcount: Arc<dyn KvTree>,
 	pub roomuseroncejoinedids: Arc<dyn KvTree>,
-	pub userroomid_invitestate: Arc<dyn KvTree>, // InviteState = Vec<Raw<Pdu>>
-	pub roomuserid_invitecount: Arc<dyn KvTree>, // InviteCount = Count
+	pub userroomid_invitestate: Arc<dyn KvTree>,  // InviteState = Vec<Raw<Pdu>>
+	pub roomuserid_invitecount: Arc<dyn KvTree>,  // InviteCount = Count
+	pub userroomid_knockedstate: Arc<dyn KvTree>, // KnockedState = Vec<Raw<Pdu>>
+	pub roomuserid_knockedcount: Arc<dyn KvTree>, // KnockedCount = Count
 	pub userroomid_leftstate: Arc<dyn KvTree>,
 	pub roomuserid_leftcount: Arc<dyn KvTree>,
 
@@ -115,6 +145,13 @@ pub struct KeyValueDatabase {
 	pub eventid_outlierpdu: Arc<dyn KvTree>,
 	pub softfailedeventids: Arc<dyn KvTree>,
 
+	/// RoomId -> PartialState of a room joined without its full state.
+	pub roomid_partialstate: Arc<dyn KvTree>,
+	/// RoomId + EventId -> () for events accepted while the room had partial
+	/// state.
+	pub roomideventid_partialstate: Arc<dyn KvTree>,
+	pub roomqueuedpduid_pdu: Arc<dyn KvTree>, // RoomQueuedPduId = RoomId + Count, Pdu = Origin + Pdu
+
 	/// ShortEventId + ShortEventId -> ().
 	pub tofrom_relation: Arc<dyn KvTree>,
 	/// RoomId + EventId -> Parent PDU EventId.
@@ -142,6 +179,7 @@ pub struct KeyValueDatabase {
 	                                           * PduId / Id (for edus), Data = EDU content */
 	pub servercurrentevent_data: Arc<dyn KvTree>, /* ServerCurrentEvents = (+ / $)ServerName / UserId + PduId
 	                                               * / Id (for edus), Data = EDU content */
+	pub destination_health: Arc<dyn KvTree>, // Destination prefix => DestinationHealth as json
 
 	//pub appservice: appservice::Appservice,
 	pub id_appserviceregistrations: Arc<dyn KvTree>,
@@ -167,10 +205,21 @@ impl KeyValueDatabase {
 			userid_displayname: builder.open_tree("userid_displayname")?,
 			userid_avatarurl: builder.open_tree("userid_avatarurl")?,
 			userid_blurhash: builder.open_tree("userid_blurhash")?,
+			userid_suspendedsince: builder.open_tree("userid_suspendedsince")?,
+			userid_lockedsince: builder.open_tree("userid_lockedsince")?,
+			userid_shadowbannedsince: builder.open_tree("userid_shadowbannedsince")?,
+			erased_users: builder.open_tree("erased_users")?,
+			userid_termsversion: builder.open_tree("userid_termsversion")?,
+			userid_consenttoken: builder.open_tree("userid_consenttoken")?,
 			userdeviceid_token: builder.open_tree("userdeviceid_token")?,
 			userdeviceid_metadata: builder.open_tree("userdeviceid_metadata")?,
+			userdeviceid_useragent: builder.open_tree("userdeviceid_useragent")?,
 			userid_devicelistversion: builder.open_tree("userid_devicelistversion")?,
 			token_userdeviceid: builder.open_tree("token_userdeviceid")?,
+			logintoken_expiresatuserid: builder.open_tree("logintoken_expiresatuserid")?,
+			token_expiresat: builder.open_tree("token_expiresat")?,
+			refreshtoken_userdeviceid: builder.open_tree("refreshtoken_userdeviceid")?,
+			userdeviceid_refreshtoken: builder.open_tree("userdeviceid_refreshtoken")?,
 			onetimekeyid_onetimekeys: builder.open_tree("onetimekeyid_onetimekeys")?,
 			userid_lastonetimekeyupdate: builder.open_tree("userid_lastonetimekeyupdate")?,
 			keychangeid_userid: builder.open_tree("keychangeid_userid")?,
@@ -185,11 +234,21 @@ impl KeyValueDatabase {
 
 			userdevicesessionid_uiaainfo: builder.open_tree("userdevicesessionid_uiaainfo")?,
 			userdevicesessionid_uiaarequest: RwLock::new(BTreeMap::new()),
+			registrationtoken_info: builder.open_tree("registrationtoken_info")?,
+			uiaasessionid_registrationtoken: builder.open_tree("uiaasessionid_registrationtoken")?,
+			userid_registrationtoken: builder.open_tree("userid_registrationtoken")?,
+			userid_servernoticesroomid: builder.open_tree("userid_servernoticesroomid")?,
+			threepidsid_session: builder.open_tree("threepidsid_session")?,
+			clientsecretthreepid_sid: builder.open_tree("clientsecretthreepid_sid")?,
+			uiaasessionid_threepidsid: builder.open_tree("uiaasessionid_threepidsid")?,
+			useridthreepid_info: builder.open_tree("useridthreepid_info")?,
+			threepid_userid: builder.open_tree("threepid_userid")?,
 			readreceiptid_readreceipt: builder.open_tree("readreceiptid_readreceipt")?,
 			roomuserid_privateread: builder.open_tree("roomuserid_privateread")?, // "Private" read receipt
 			roomuserid_lastprivatereadupdate: builder.open_tree("roomuserid_lastprivatereadupdate")?,
 			pduid_pdu: builder.open_tree("pduid_pdu")?,
 			eventid_pduid: builder.open_tree("eventid_pduid")?,
+			roomtimestamp_pduid: builder.open_tree("roomtimestamp_pduid")?,
 			roomid_pduleaves: builder.open_tree("roomid_pduleaves")?,
 
 			alias_roomid: builder.open_tree("alias_roomid")?,
@@ -209,6 +268,8 @@ impl KeyValueDatabase {
 			roomuseroncejoinedids: builder.open_tree("roomuseroncejoinedids")?,
 			userroomid_invitestate: builder.open_tree("userroomid_invitestate")?,
 			roomuserid_invitecount: builder.open_tree("roomuserid_invitecount")?,
+			userroomid_knockedstate: builder.open_tree("userroomid_knockedstate")?,
+			roomuserid_knockedcount: builder.open_tree("roomuserid_knockedcount")?,
 			userroomid_leftstate: builder.open_tree("userroomid_leftstate")?,
 			roomuserid_leftcount: builder.open_tree("roomuserid_leftcount")?,
 
@@ -239,6 +300,9 @@ impl KeyValueDatabase {
 
 			eventid_outlierpdu: builder.open_tree("eventid_outlierpdu")?,
 			softfailedeventids: builder.open_tree("softfailedeventids")?,
+			roomid_partialstate: builder.open_tree("roomid_partialstate")?,
+			roomideventid_partialstate: builder.open_tree("roomideventid_partialstate")?,
+			roomqueuedpduid_pdu: builder.open_tree("roomqueuedpduid_pdu")?,
 
 			tofrom_relation: builder.open_tree("tofrom_relation")?,
 			referencedevents: builder.open_tree("referencedevents")?,
@@ -254,10 +318,12 @@ impl KeyValueDatabase {
 			servername_educount: builder.open_tree("servername_educount")?,
 			servernameevent_data: builder.open_tree("servernameevent_data")?,
 			servercurrentevent_data: builder.open_tree("servercurrentevent_data")?,
+			destination_health: builder.open_tree("destination_health")?,
 			id_appserviceregistrations: builder.open_tree("id_appserviceregistrations")?,
 			senderkey_pusher: builder.open_tree("senderkey_pusher")?,
 			global: builder.open_tree("global")?,
 			server_signingkeys: builder.open_tree("server_signingkeys")?,
+			server_signedkeys: builder.open_tree("server_signedkeys")?,
 
 			roomid_inviteviaservers: builder.open_tree("roomid_inviteviaservers")?,
 
@@ -22,7 +22,10 @@ use ruma::{
 	EventId, OwnedRoomAliasId, OwnedRoomId, OwnedUserId, RoomAliasId, RoomId, RoomVersionId, UserId,
 };
 use serde_json::value::to_raw_value;
-use tokio::{sync::Mutex, task::JoinHandle};
+use tokio::{
+	sync::{Mutex, MutexGuard},
+	task::JoinHandle,
+};
 use tracing::{error, warn};
 
 use crate::{pdu::PduBuilder, services};
@@ -166,179 +169,17 @@ impl Service {
 
 		services().users.create(&server_user, None)?;
 
-		let room_version = services().globals.default_room_version();
-		let mut content = match room_version {
-			RoomVersionId::V1
-			| RoomVersionId::V2
-			| RoomVersionId::V3
-			| RoomVersionId::V4
-			| RoomVersionId::V5
-			| RoomVersionId::V6
-			| RoomVersionId::V7
-			| RoomVersionId::V8
-			| RoomVersionId::V9
-			| RoomVersionId::V10 => RoomCreateEventContent::new_v1(server_user.clone()),
-			RoomVersionId::V11 => RoomCreateEventContent::new_v11(),
-			_ => {
-				warn!("Unexpected or unsupported room version {}", room_version);
-				return Err(Error::BadRequest(
-					ErrorKind::BadJson,
-					"Unexpected or unsupported room version found",
-				));
-			},
-		};
-
-		content.federate = true;
-		content.predecessor = None;
-		content.room_version = room_version;
-
-		// 1. The room create event
-		services()
-			.rooms
-			.timeline
-			.build_and_append_pdu(
-				PduBuilder {
-					event_type: TimelineEventType::RoomCreate,
-					content: to_raw_value(&content).expect("event is valid, we just created it"),
-					unsigned: None,
-					state_key: Some(String::new()),
-					redacts: None,
-				},
-				&server_user,
-				&room_id,
-				&state_lock,
-			)
-			.await?;
-
-		// 2. Make conduit bot join
-		services()
-			.rooms
-			.timeline
-			.build_and_append_pdu(
-				PduBuilder {
-					event_type: TimelineEventType::RoomMember,
-					content: to_raw_value(&RoomMemberEventContent {
-						membership: MembershipState::Join,
-						displayname: None,
-						avatar_url: None,
-						is_direct: None,
-						third_party_invite: None,
-						blurhash: None,
-						reason: None,
-						join_authorized_via_users_server: None,
-					})
-					.expect("event is valid, we just created it"),
-					unsigned: None,
-					state_key: Some(server_user.to_string()),
-					redacts: None,
-				},
-				&server_user,
-				&room_id,
-				&state_lock,
-			)
-			.await?;
-
-		// 3. Power levels
+		// 1.-5. The create event, the conduit bot's join, power levels, join rules,
+		// history visibility, guest access and name
 		let mut users = BTreeMap::new();
 		users.insert(server_user.clone(), 100.into());
+		let power_levels = RoomPowerLevelsEventContent {
+			users,
+			..Default::default()
+		};
 
-		services()
-			.rooms
-			.timeline
-			.build_and_append_pdu(
-				PduBuilder {
-					event_type: TimelineEventType::RoomPowerLevels,
-					content: to_raw_value(&RoomPowerLevelsEventContent {
-						users,
-						..Default::default()
-					})
-					.expect("event is valid, we just created it"),
-					unsigned: None,
-					state_key: Some(String::new()),
-					redacts: None,
-				},
-				&server_user,
-				&room_id,
-				&state_lock,
-			)
-			.await?;
-
-		// 4.1 Join Rules
-		services()
-			.rooms
-			.timeline
-			.build_and_append_pdu(
-				PduBuilder {
-					event_type: TimelineEventType::RoomJoinRules,
-					content: to_raw_value(&RoomJoinRulesEventContent::new(JoinRule::Invite))
-						.expect("event is valid, we just created it"),
-					unsigned: None,
-					state_key: Some(String::new()),
-					redacts: None,
-				},
-				&server_user,
-				&room_id,
-				&state_lock,
-			)
-			.await?;
-
-		// 4.2 History Visibility
-		services()
-			.rooms
-			.timeline
-			.build_and_append_pdu(
-				PduBuilder {
-					event_type: TimelineEventType::RoomHistoryVisibility,
-					content: to_raw_value(&RoomHistoryVisibilityEventContent::new(HistoryVisibility::Shared))
-						.expect("event is valid, we just created it"),
-					unsigned: None,
-					state_key: Some(String::new()),
-					redacts: None,
-				},
-				&server_user,
-				&room_id,
-				&state_lock,
-			)
-			.await?;
-
-		// 4.3 Guest Access
-		services()
-			.rooms
-			.timeline
-			.build_and_append_pdu(
-				PduBuilder {
-					event_type: TimelineEventType::RoomGuestAccess,
-					content: to_raw_value(&RoomGuestAccessEventContent::new(GuestAccess::Forbidden))
-						.expect("event is valid, we just created it"),
-					unsigned: None,
-					state_key: Some(String::new()),
-					redacts: None,
-				},
-				&server_user,
-				&room_id,
-				&state_lock,
-			)
-			.await?;
-
-		// 5. Events implied by name and topic
 		let room_name = format!("{} Admin Room", services().globals.server_name());
-		services()
-			.rooms
-			.timeline
-			.build_and_append_pdu(
-				PduBuilder {
-					event_type: TimelineEventType::RoomName,
-					content: to_raw_value(&RoomNameEventContent::new(room_name))
-						.expect("event is valid, we just created it"),
-					unsigned: None,
-					state_key: Some(String::new()),
-					redacts: None,
-				},
-				&server_user,
-				&room_id,
-				&state_lock,
-			)
-			.await?;
+		create_system_room(&room_id, &server_user, power_levels, room_name, &state_lock).await?;
 
 		services()
 			.rooms
@@ -533,4 +374,235 @@ impl Service {
 
 		Ok(())
 	}
+
+	/// Make the user leave the conduit admin room.
+	///
+	/// In conduit, this is equivalent to revoking admin privileges. The leave
+	/// is sent by the user themselves as admins share the server user's power
+	/// level and cannot be kicked.
+	pub async fn revoke_user_admin(&self, user_id: &UserId) -> Result<()> {
+		let Some(room_id) = Self::get_admin_room().await? else {
+			return Ok(());
+		};
+
+		if !services().rooms.state_cache.is_joined(user_id, &room_id)? {
+			return Ok(());
+		}
+
+		let mutex_state = Arc::clone(
+			services()
+				.globals
+				.roomid_mutex_state
+				.write()
+				.await
+				.entry(room_id.clone())
+				.or_default(),
+		);
+		let state_lock = mutex_state.lock().await;
+
+		services()
+			.rooms
+			.timeline
+			.build_and_append_pdu(
+				PduBuilder {
+					event_type: TimelineEventType::RoomMember,
+					content: to_raw_value(&RoomMemberEventContent {
+						membership: MembershipState::Leave,
+						displayname: None,
+						avatar_url: None,
+						is_direct: None,
+						third_party_invite: None,
+						blurhash: None,
+						reason: Some("Admin privileges revoked".to_owned()),
+						join_authorized_via_users_server: None,
+					})
+					.expect("event is valid, we just created it"),
+					unsigned: None,
+					state_key: Some(user_id.to_string()),
+					redacts: None,
+				},
+				user_id,
+				&room_id,
+				&state_lock,
+			)
+			.await?;
+
+		Ok(())
+	}
+}
+
+/// Creates the initial state of a private room owned by a server-local system
+/// user: the create event, the user's join, the given power levels, invite-only
+/// join rules, shared history visibility, forbidden guest access and the name.
+pub(crate) async fn create_system_room(
+	room_id: &RoomId, system_user: &UserId, power_levels: RoomPowerLevelsEventContent, room_name: String,
+	state_lock: &MutexGuard<'_, ()>,
+) -> Result<()> {
+	let room_version = services().globals.default_room_version();
+	let mut content = match room_version {
+		RoomVersionId::V1
+		| RoomVersionId::V2
+		| RoomVersionId::V3
+		| RoomVersionId::V4
+		| RoomVersionId::V5
+		| RoomVersionId::V6
+		| RoomVersionId::V7
+		| RoomVersionId::V8
+		| RoomVersionId::V9
+		| RoomVersionId::V10 => RoomCreateEventContent::new_v1(system_user.to_owned()),
+		RoomVersionId::V11 => RoomCreateEventContent::new_v11(),
+		_ => {
+			warn!("Unexpected or unsupported room version {}", room_version);
+			return Err(Error::BadRequest(
+				ErrorKind::BadJson,
+				"Unexpected or unsupported room version found",
+			));
+		},
+	};
+
+	content.federate = true;
+	content.predecessor = None;
+	content.room_version = room_version;
+
+	// 1. The room create event
+	services()
+		.rooms
+		.timeline
+		.build_and_append_pdu(
+			PduBuilder {
+				event_type: TimelineEventType::RoomCreate,
+				content: to_raw_value(&content).expect("event is valid, we just created it"),
+				unsigned: None,
+				state_key: Some(String::new()),
+				redacts: None,
+			},
+			system_user,
+			room_id,
+			state_lock,
+		)
+		.await?;
+
+	// 2. Make the system user join
+	services()
+		.rooms
+		.timeline
+		.build_and_append_pdu(
+			PduBuilder {
+				event_type: TimelineEventType::RoomMember,
+				content: to_raw_value(&RoomMemberEventContent {
+					membership: MembershipState::Join,
+					displayname: None,
+					avatar_url: None,
+					is_direct: None,
+					third_party_invite: None,
+					blurhash: None,
+					reason: None,
+					join_authorized_via_users_server: None,
+				})
+				.expect("event is valid, we just created it"),
+				unsigned: None,
+				state_key: Some(system_user.to_string()),
+				redacts: None,
+			},
+			system_user,
+			room_id,
+			state_lock,
+		)
+		.await?;
+
+	// 3. Power levels
+	services()
+		.rooms
+		.timeline
+		.build_and_append_pdu(
+			PduBuilder {
+				event_type: TimelineEventType::RoomPowerLevels,
+				content: to_raw_value(&power_levels).expect("event is valid, we just created it"),
+				unsigned: None,
+				state_key: Some(String::new()),
+				redacts: None,
+			},
+			system_user,
+			room_id,
+			state_lock,
+		)
+		.await?;
+
+	// 4.1 Join Rules
+	services()
+		.rooms
+		.timeline
+		.build_and_append_pdu(
+			PduBuilder {
+				event_type: TimelineEventType::RoomJoinRules,
+				content: to_raw_value(&RoomJoinRulesEventContent::new(JoinRule::Invite))
+					.expect("event is valid, we just created it"),
+				unsigned: None,
+				state_key: Some(String::new()),
+				redacts: None,
+			},
+			system_user,
+			room_id,
+			state_lock,
+		)
+		.await?;
+
+	// 4.2 History Visibility
+	services()
+		.rooms
+		.timeline
+		.build_and_append_pdu(
+			PduBuilder {
+				event_type: TimelineEventType::RoomHistoryVisibility,
+				content: to_raw_value(&RoomHistoryVisibilityEventContent::new(HistoryVisibility::Shared))
+					.expect("event is valid, we just created it"),
+				unsigned: None,
+				state_key: Some(String::new()),
+				redacts: None,
+			},
+			system_user,
+			room_id,
+			state_lock,
+		)
+		.await?;
+
+	// 4.3 Guest Access
+	services()
+		.rooms
+		.timeline
+		.build_and_append_pdu(
+			PduBuilder {
+				event_type: TimelineEventType::RoomGuestAccess,
+				content: to_raw_value(&RoomGuestAccessEventContent::new(GuestAccess::Forbidden))
+					.expect("event is valid, we just created it"),
+				unsigned: None,
+				state_key: Some(String::new()),
+				redacts: None,
+			},
+			system_user,
+			room_id,
+			state_lock,
+		)
+		.await?;
+
+	// 5. Events implied by name
+	services()
+		.rooms
+		.timeline
+		.build_and_append_pdu(
+			PduBuilder {
+				event_type: TimelineEventType::RoomName,
+				content: to_raw_value(&RoomNameEventContent::new(room_name))
+					.expect("event is valid, we just created it"),
+				unsigned: None,
+				state_key: Some(String::new()),
+				redacts: None,
+			},
+			system_user,
+			room_id,
+			state_lock,
+		)
+		.await?;
+
+	Ok(())
 }
@@ -4,7 +4,8 @@ use async_trait::async_trait;
 use futures_util::{stream::FuturesUnordered, StreamExt};
 use lru_cache::LruCache;
 use ruma::{
-	api::federation::discovery::{ServerSigningKeys, VerifyKey},
+	api::federation::discovery::{OldVerifyKey, ServerSigningKeys, VerifyKey},
+	serde::Base64,
 	signatures::Ed25519KeyPair,
 	DeviceId, MilliSecondsSinceUnixEpoch, OwnedServerSigningKeyId, ServerName, UserId,
 };
@@ -14,6 +15,7 @@ use crate::{database::Cork, services, utils, Error, KeyValueDatabase, Result};
 
 const COUNTER: &[u8] = b"c";
 const LAST_CHECK_FOR_UPDATES_COUNT: &[u8] = b"u";
+const OLD_VERIFY_KEYS: &[u8] = b"old_verify_keys";
 
 #[async_trait]
 pub trait Data: Send + Sync {
@@ -32,6 +34,14 @@ pub trait Data: Send + Sync {
 	fn clear_caches(&self, amount: u32);
 	fn load_keypair(&self) -> Result<Ed25519KeyPair>;
 	fn remove_keypair(&self) -> Result<()>;
+
+	/// Replaces the keypair with a newly generated one and returns it. The
+	/// public key of the previous keypair is kept as an old verify key expired
+	/// now.
+	fn rotate_keypair(&self) -> Result<Ed25519KeyPair>;
+
+	/// Public keys of our previous keypairs
+	fn old_verify_keys(&self) -> Result<BTreeMap<OwnedServerSigningKeyId, OldVerifyKey>>;
 	fn add_signing_key(
 		&self, origin: &ServerName, new_keys: ServerSigningKeys,
 	) -> Result<BTreeMap<OwnedServerSigningKeyId, VerifyKey>>;
@@ -39,6 +49,13 @@ pub trait Data: Send + Sync {
 	/// This returns an empty `Ok(BTreeMap<..>)` when there are no keys found
 	/// for the server.
 	fn signing_keys_for(&self, origin: &ServerName) -> Result<BTreeMap<OwnedServerSigningKeyId, VerifyKey>>;
+
+	/// The retired keys of a remote server with the time they expired.
+	fn old_signing_keys_for(&self, origin: &ServerName) -> Result<BTreeMap<OwnedServerSigningKeyId, OldVerifyKey>>;
+
+	/// The most recent key response signed by the server itself, as served to
+	/// others when acting as a notary.
+	fn signed_server_keys(&self, origin: &ServerName) -> Result<Option<ServerSigningKeys>>;
 	fn database_version(&self) -> Result<u64>;
 	fn bump_database_version(&self, new_version: u64) -> Result<()>;
 	fn backup(&self) -> Result<(), Box<dyn std::error::Error>> { unimplemented!() }
@@ -225,30 +242,41 @@ lasttimelinecount_cache: {lasttimelinecount_cache} / {max_lasttimelinecount_cach
 			Ok,
 		)?;
 
-		let mut parts = keypair_bytes.splitn(2, |&b| b == 0xFF);
-
-		utils::string_from_bytes(
-			// 1. version
-			parts
-				.next()
-				.expect("splitn always returns at least one element"),
-		)
-		.map_err(|_| Error::bad_database("Invalid version bytes in keypair."))
-		.and_then(|version| {
-			// 2. key
-			parts
-				.next()
-				.ok_or_else(|| Error::bad_database("Invalid keypair format in database."))
-				.map(|key| (version, key))
-		})
-		.and_then(|(version, key)| {
-			Ed25519KeyPair::from_der(key, version)
-				.map_err(|_| Error::bad_database("Private or public keys are invalid."))
-		})
+		parse_keypair(&keypair_bytes)
 	}
 
 	fn remove_keypair(&self) -> Result<()> { self.global.remove(b"keypair") }
 
+	fn rotate_keypair(&self) -> Result<Ed25519KeyPair> {
+		let old_keypair = self.load_keypair()?;
+		let mut old_verify_keys = self.old_verify_keys()?;
+		old_verify_keys.insert(
+			format!("ed25519:{}", old_keypair.version())
+				.try_into()
+				.map_err(|_| Error::bad_database("Invalid version in keypair."))?,
+			OldVerifyKey::new(
+				MilliSecondsSinceUnixEpoch::now(),
+				Base64::new(old_keypair.public_key().to_vec()),
+			),
+		);
+		self.global.insert(
+			OLD_VERIFY_KEYS,
+			&serde_json::to_vec(&old_verify_keys).expect("old verify keys can be serialized"),
+		)?;
+
+		let keypair = utils::generate_keypair();
+		self.global.insert(b"keypair", &keypair)?;
+
+		parse_keypair(&keypair)
+	}
+
+	fn old_verify_keys(&self) -> Result<BTreeMap<OwnedServerSigningKeyId, OldVerifyKey>> {
+		self.global.get(OLD_VERIFY_KEYS)?.map_or_else(
+			|| Ok(BTreeMap::new()),
+			|bytes| serde_json::from_slice(&bytes).map_err(|_| Error::bad_database("Invalid old verify keys in db.")),
+		)
+	}
+
 	fn add_signing_key(
 		&self, origin: &ServerName, new_keys: ServerSigningKeys,
 	) -> Result<BTreeMap<OwnedServerSigningKeyId, VerifyKey>> {
@@ -266,7 +294,20 @@ lasttimelinecount_cache: {lasttimelinecount_cache} / {max_lasttimelinecount_cach
 			verify_keys,
 			old_verify_keys,
 			..
-		} = new_keys;
+		} = new_keys.clone();
+
+		// Keep the latest response signed by the origin untouched, its signature
+		// does not cover the merged set of keys
+		if new_keys.signatures.contains_key(origin)
+			&& self
+				.signed_server_keys(origin)?
+				.map_or(true, |signed| signed.valid_until_ts <= new_keys.valid_until_ts)
+		{
+			self.server_signedkeys.insert(
+				origin.as_bytes(),
+				&serde_json::to_vec(&new_keys).expect("serversigningkeys can be serialized"),
+			)?;
+		}
 
 		keys.verify_keys.extend(verify_keys);
 		keys.old_verify_keys.extend(old_verify_keys);
@@ -306,6 +347,21 @@ lasttimelinecount_cache: {lasttimelinecount_cache} / {max_lasttimelinecount_cach
 		Ok(signingkeys)
 	}
 
+	fn old_signing_keys_for(&self, origin: &ServerName) -> Result<BTreeMap<OwnedServerSigningKeyId, OldVerifyKey>> {
+		Ok(self
+			.server_signingkeys
+			.get(origin.as_bytes())?
+			.and_then(|bytes| serde_json::from_slice(&bytes).ok())
+			.map_or_else(BTreeMap::new, |keys: ServerSigningKeys| keys.old_verify_keys))
+	}
+
+	fn signed_server_keys(&self, origin: &ServerName) -> Result<Option<ServerSigningKeys>> {
+		Ok(self
+			.server_signedkeys
+			.get(origin.as_bytes())?
+			.and_then(|bytes| serde_json::from_slice(&bytes).ok()))
+	}
+
 	fn database_version(&self) -> Result<u64> {
 		self.global.get(b"version")?.map_or(Ok(0), |version| {
 			utils::u64_from_bytes(&version).map_err(|_| Error::bad_database("Database version id is invalid."))
@@ -323,3 +379,26 @@ lasttimelinecount_cache: {lasttimelinecount_cache} / {max_lasttimelinecount_cach
 
 	fn file_list(&self) -> Result<String> { self.db.file_list() }
 }
+
+/// Parses a keypair stored as its version, 0xFF and the DER encoded key.
+fn parse_keypair(keypair_bytes: &[u8]) -> Result<Ed25519KeyPair> {
+	let mut parts = keypair_bytes.splitn(2, |&b| b == 0xFF);
+
+	utils::string_from_bytes(
+		// 1. version
+		parts
+			.next()
+			.expect("splitn always returns at least one element"),
+	)
+	.map_err(|_| Error::bad_database("Invalid version bytes in keypair."))
+	.and_then(|version| {
+		// 2. key
+		parts
+			.next()
+			.ok_or_else(|| Error::bad_database("Invalid keypair format in database."))
+			.map(|key| (version, key))
+	})
+	.and_then(|(version, key)| {
+		Ed25519KeyPair::from_der(key, version).map_err(|_| Error::bad_database("Private or public keys are invalid."))
+	})
+}
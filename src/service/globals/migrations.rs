@@ -14,9 +14,10 @@ use ruma::{
 	push::Ruleset,
 	EventId, OwnedRoomId, RoomId, UserId,
 };
+use serde::Deserialize;
 use tracing::{debug, error, info, warn};
 
-use crate::{services, utils, Config, Error, Result};
+use crate::{rooms::timeline::timestamp_key, services, utils, Config, Error, Result};
 
 pub(crate) async fn migrations(db: &KeyValueDatabase, config: &Config) -> Result<()> {
 	// Matrix resource ownership is based on the server name; changing it
@@ -118,6 +119,10 @@ pub(crate) async fn migrations(db: &KeyValueDatabase, config: &Config) -> Result
 			retroactively_fix_bad_data_from_roomuserid_joined(db, config).await?;
 		}
 
+		if db.global.get(b"index_timeline_by_timestamp")?.is_none() {
+			index_timeline_by_timestamp(db, config).await?;
+		}
+
 		assert_eq!(
 			services().globals.database_version().unwrap(),
 			latest_database_version,
@@ -189,6 +194,7 @@ pub(crate) async fn migrations(db: &KeyValueDatabase, config: &Config) -> Result
 			.insert(b"fix_bad_double_separator_in_state_cache", &[])?;
 		db.global
 			.insert(b"retroactively_fix_bad_data_from_roomuserid_joined", &[])?;
+		db.global.insert(b"index_timeline_by_timestamp", &[])?;
 
 		// Create the admin room and server user on first run
 		services().admin.create_admin_room().await?;
@@ -824,3 +830,36 @@ async fn retroactively_fix_bad_data_from_roomuserid_joined(db: &KeyValueDatabase
 	info!("Finished fixing");
 	Ok(())
 }
+
+async fn index_timeline_by_timestamp(db: &KeyValueDatabase, _config: &Config) -> Result<()> {
+	warn!("Indexing the timeline of all rooms by origin_server_ts");
+
+	#[derive(Deserialize)]
+	struct ExtractTimestamp {
+		origin_server_ts: u64,
+	}
+
+	let _cork = db.db.cork();
+
+	let mut iter_count: usize = 0;
+	for (pdu_id, pdu) in db.pduid_pdu.iter() {
+		let Ok(ExtractTimestamp {
+			origin_server_ts,
+		}) = serde_json::from_slice(&pdu)
+		else {
+			debug_warn!("Skipping pdu without origin_server_ts at {pdu_id:?}");
+			continue;
+		};
+
+		db.roomtimestamp_pduid
+			.insert(&timestamp_key(&pdu_id, origin_server_ts), &pdu_id)?;
+
+		iter_count = iter_count.saturating_add(1);
+	}
+
+	db.db.cleanup()?;
+	db.global.insert(b"index_timeline_by_timestamp", &[])?;
+
+	info!("Indexed {iter_count} pdus by timestamp");
+	Ok(())
+}
@@ -9,8 +9,8 @@ use std::{
 	collections::{BTreeMap, HashMap},
 	fs,
 	path::PathBuf,
-	sync::Arc,
-	time::Instant,
+	sync::{Arc, RwLock as StdRwLock},
+	time::{Duration, Instant, SystemTime},
 };
 
 use base64::{engine::general_purpose, Engine as _};
@@ -21,11 +21,11 @@ use regex::RegexSet;
 use ruma::{
 	api::{
 		client::discovery::discover_support::ContactRole,
-		federation::discovery::{ServerSigningKeys, VerifyKey},
+		federation::discovery::{OldVerifyKey, ServerSigningKeys, VerifyKey},
 	},
 	serde::Base64,
-	DeviceId, OwnedEventId, OwnedRoomId, OwnedServerName, OwnedServerSigningKeyId, OwnedUserId, RoomVersionId,
-	ServerName, UserId,
+	DeviceId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedRoomId, OwnedServerName, OwnedServerSigningKeyId,
+	OwnedUserId, RoomVersionId, ServerName, UserId,
 };
 use tokio::{
 	sync::{Mutex, RwLock},
@@ -43,7 +43,8 @@ pub struct Service {
 
 	pub config: Config,
 	pub cidr_range_denylist: Vec<IPAddress>,
-	keypair: Arc<ruma::signatures::Ed25519KeyPair>,
+	pub trusted_proxies: Vec<IPAddress>,
+	keypair: StdRwLock<Arc<ruma::signatures::Ed25519KeyPair>>,
 	jwt_decoding_key: Option<jsonwebtoken::DecodingKey>,
 	pub resolver: Arc<resolver::Resolver>,
 	pub client: client::Client,
@@ -99,11 +100,18 @@ impl Service {
 			cidr_range_denylist.push(cidr);
 		}
 
+		let trusted_proxies = config
+			.trusted_proxies
+			.iter()
+			.map(|cidr| IPAddress::parse(cidr.as_str()).expect("valid cidr range"))
+			.collect();
+
 		let mut s = Self {
 			db,
 			config: config.clone(),
 			cidr_range_denylist,
-			keypair: Arc::new(keypair),
+			trusted_proxies,
+			keypair: StdRwLock::new(Arc::new(keypair)),
 			resolver: resolver.clone(),
 			client: client::Client::new(config, &resolver),
 			jwt_decoding_key,
@@ -133,8 +141,25 @@ impl Service {
 		Ok(s)
 	}
 
-	/// Returns this server's keypair.
-	pub fn keypair(&self) -> &ruma::signatures::Ed25519KeyPair { &self.keypair }
+	/// Returns this server's current keypair.
+	pub fn keypair(&self) -> Arc<ruma::signatures::Ed25519KeyPair> {
+		Arc::clone(&*self.keypair.read().expect("locked for reading"))
+	}
+
+	/// Replaces this server's keypair with a newly generated one used for all
+	/// new signatures. The previous public key is published as an old verify
+	/// key from now on.
+	pub fn rotate_keypair(&self) -> Result<Arc<ruma::signatures::Ed25519KeyPair>> {
+		let mut keypair = self.keypair.write().expect("locked for writing");
+		*keypair = Arc::new(self.db.rotate_keypair()?);
+
+		Ok(Arc::clone(&*keypair))
+	}
+
+	/// Returns the public keys of this server's previous keypairs.
+	pub fn old_verify_keys(&self) -> Result<BTreeMap<OwnedServerSigningKeyId, OldVerifyKey>> {
+		self.db.old_verify_keys()
+	}
 
 	#[tracing::instrument(skip(self))]
 	pub fn next_count(&self) -> Result<u64> { self.db.next_count() }
@@ -252,6 +277,23 @@ impl Service {
 		&self.config.forbidden_remote_room_directory_server_names
 	}
 
+	/// Whether `allowed_remote_server_names` lets us federate with the server.
+	/// Every server is allowed when it is empty.
+	pub fn server_is_allowed(&self, server_name: &ServerName) -> bool {
+		allowed_by(&self.config.allowed_remote_server_names, self.server_name(), server_name)
+	}
+
+	/// Whether the server is banned by `forbidden_remote_server_names` or not
+	/// allowed by `allowed_remote_server_names`, so nothing is sent to it.
+	pub fn server_is_forbidden(&self, server_name: &ServerName) -> bool {
+		!self.server_is_allowed(server_name)
+			|| self
+				.config
+				.forbidden_remote_server_names
+				.iter()
+				.any(|forbidden| forbidden == server_name)
+	}
+
 	pub fn well_known_support_page(&self) -> &Option<Url> { &self.config.well_known.support_page }
 
 	pub fn well_known_support_role(&self) -> &Option<ContactRole> { &self.config.well_known.support_role }
@@ -289,19 +331,70 @@ impl Service {
 	pub fn signing_keys_for(&self, origin: &ServerName) -> Result<BTreeMap<OwnedServerSigningKeyId, VerifyKey>> {
 		let mut keys = self.db.signing_keys_for(origin)?;
 		if origin == self.server_name() {
-			keys.insert(
-				format!("ed25519:{}", self.keypair().version())
-					.try_into()
-					.expect("found invalid server signing keys in DB"),
-				VerifyKey {
-					key: Base64::new(self.keypair.public_key().to_vec()),
-				},
+			let ServerSigningKeys {
+				verify_keys,
+				old_verify_keys,
+				..
+			} = self.own_server_keys()?;
+
+			keys.extend(verify_keys);
+			keys.extend(
+				old_verify_keys
+					.into_iter()
+					.map(|(id, old)| (id, VerifyKey::new(old.key))),
 			);
 		}
 
 		Ok(keys)
 	}
 
+	/// The retired keys of a server with the time they expired, including our
+	/// own.
+	pub fn old_signing_keys_for(&self, origin: &ServerName) -> Result<BTreeMap<OwnedServerSigningKeyId, OldVerifyKey>> {
+		if origin == self.server_name() {
+			return self.old_verify_keys();
+		}
+
+		self.db.old_signing_keys_for(origin)
+	}
+
+	/// The latest key response of a remote server carrying its own signature,
+	/// or our own keys when `origin` is this server.
+	pub fn signed_server_keys(&self, origin: &ServerName) -> Result<Option<ServerSigningKeys>> {
+		if origin == self.server_name() {
+			return self.own_server_keys().map(Some);
+		}
+
+		self.db.signed_server_keys(origin)
+	}
+
+	/// The public signing keys of this server, including the retired ones,
+	/// valid for `signing_key_validity_period_s`.
+	pub fn own_server_keys(&self) -> Result<ServerSigningKeys> {
+		let keypair = self.keypair();
+		let verify_keys = BTreeMap::from([(
+			format!("ed25519:{}", keypair.version())
+				.try_into()
+				.expect("found invalid server signing keys in DB"),
+			VerifyKey {
+				key: Base64::new(keypair.public_key().to_vec()),
+			},
+		)]);
+
+		Ok(ServerSigningKeys {
+			server_name: self.server_name().to_owned(),
+			verify_keys,
+			old_verify_keys: self.old_verify_keys()?,
+			signatures: BTreeMap::new(),
+			valid_until_ts: MilliSecondsSinceUnixEpoch::from_system_time(
+				SystemTime::now()
+					.checked_add(Duration::from_secs(self.config.signing_key_validity_period_s))
+					.expect("valid_until_ts should not get this high"),
+			)
+			.expect("time is valid"),
+		})
+	}
+
 	pub fn database_version(&self) -> Result<u64> { self.db.database_version() }
 
 	pub fn bump_database_version(&self, new_version: u64) -> Result<()> { self.db.bump_database_version(new_version) }
@@ -352,6 +445,9 @@ impl Service {
 
 		true
 	}
+
+	/// Whether client IP headers of requests from this address are trusted.
+	pub fn is_trusted_proxy(&self, ip: &IPAddress) -> bool { self.trusted_proxies.iter().any(|cidr| cidr.includes(ip)) }
 }
 
 #[inline]
@@ -362,3 +458,50 @@ pub fn server_is_ours(server_name: &ServerName) -> bool { server_name == service
 #[inline]
 #[must_use]
 pub fn user_is_local(user_id: &UserId) -> bool { server_is_ours(user_id.server_name()) }
+
+/// Whether the `allowed` patterns match the server name anywhere. Our own
+/// server is always allowed, and every server when there are no patterns.
+fn allowed_by(allowed: &RegexSet, our_server_name: &ServerName, server_name: &ServerName) -> bool {
+	allowed.is_empty() || server_name == our_server_name || allowed.is_match(server_name.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+	use regex::RegexSet;
+	use ruma::server_name;
+
+	use super::allowed_by;
+
+	#[test]
+	fn every_server_is_allowed_without_patterns() {
+		assert!(allowed_by(
+			&RegexSet::empty(),
+			server_name!("ours.org"),
+			server_name!("example.org")
+		));
+	}
+
+	#[test]
+	fn only_matching_servers_and_ours_are_allowed() {
+		let allowed = RegexSet::new([r"^example\.org$", r"\.example\.com$"]).expect("valid patterns");
+		let ours = server_name!("ours.org");
+
+		assert!(allowed_by(&allowed, ours, server_name!("example.org")));
+		assert!(allowed_by(&allowed, ours, server_name!("matrix.example.com")));
+		assert!(allowed_by(&allowed, ours, ours));
+		assert!(!allowed_by(&allowed, ours, server_name!("evil-example.org")));
+		assert!(!allowed_by(&allowed, ours, server_name!("example.org.evil.net")));
+		assert!(!allowed_by(&allowed, ours, server_name!("example.com")));
+	}
+
+	#[test]
+	fn unanchored_patterns_match_anywhere() {
+		let allowed = RegexSet::new([r"example\.org"]).expect("valid pattern");
+
+		assert!(allowed_by(
+			&allowed,
+			server_name!("ours.org"),
+			server_name!("example.org.evil.net")
+		));
+	}
+}
@@ -1,5 +1,5 @@
 use conduit::debug_info;
-use ruma::api::client::error::ErrorKind;
+use ruma::{api::client::error::ErrorKind, UserId};
 use tracing::debug;
 
 use crate::{media::UrlPreviewData, utils::string_from_bytes, Error, KeyValueDatabase, Result};
@@ -21,6 +21,9 @@ pub(crate) trait Data: Send + Sync {
 
 	fn get_all_media_keys(&self) -> Vec<Vec<u8>>;
 
+	/// Returns the MXC URIs of all media uploaded by a user.
+	fn get_all_user_mxcs(&self, user_id: &UserId) -> Vec<String>;
+
 	// TODO: use this
 	#[allow(dead_code)]
 	fn remove_url_preview(&self, url: &str) -> Result<()>;
@@ -158,6 +161,14 @@ impl Data for KeyValueDatabase {
 	/// associated with it such as width, height, content-type, etc)
 	fn get_all_media_keys(&self) -> Vec<Vec<u8>> { self.mediaid_file.iter().map(|(key, _)| key).collect() }
 
+	fn get_all_user_mxcs(&self, user_id: &UserId) -> Vec<String> {
+		self.mediaid_user
+			.iter()
+			.filter(|(_, user)| user == user_id.as_bytes())
+			.filter_map(|(mxc, _)| string_from_bytes(&mxc).ok())
+			.collect()
+	}
+
 	fn remove_url_preview(&self, url: &str) -> Result<()> { self.url_previews.remove(url.as_bytes()) }
 
 	fn set_url_preview(&self, url: &str, data: &UrlPreviewData, timestamp: std::time::Duration) -> Result<()> {
@@ -3,7 +3,7 @@ use std::{collections::HashMap, io::Cursor, sync::Arc, time::SystemTime};
 
 use data::Data;
 use image::imageops::FilterType;
-use ruma::{OwnedMxcUri, OwnedUserId};
+use ruma::{OwnedMxcUri, OwnedUserId, UserId};
 use serde::Serialize;
 use tokio::{
 	fs::{self, File},
@@ -180,6 +180,9 @@ impl Service {
 		}
 	}
 
+	/// Returns the MXC URIs of all media a local user uploaded.
+	pub fn get_all_user_mxcs(&self, user_id: &UserId) -> Vec<String> { self.db.get_all_user_mxcs(user_id) }
+
 	/// Deletes all remote only media files in the given at or after
 	/// time/duration. Returns a u32 with the amount of media files deleted.
 	pub async fn delete_all_remote_media_at_after_time(&self, time: String, force: bool) -> Result<usize> {
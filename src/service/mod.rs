@@ -9,8 +9,11 @@ pub mod key_backups;
 pub mod media;
 pub mod presence;
 pub mod pusher;
+pub mod registration_tokens;
 pub mod rooms;
 pub mod sending;
+pub mod server_notices;
+pub mod threepid;
 pub mod transaction_ids;
 pub mod uiaa;
 pub mod users;
@@ -57,12 +57,7 @@ pub struct PduEvent {
 impl PduEvent {
 	#[tracing::instrument(skip(self))]
 	pub fn redact(&mut self, room_version_id: RoomVersionId, reason: &Self) -> crate::Result<()> {
-		self.unsigned = None;
-
-		let mut content = serde_json::from_str(self.content.get())
-			.map_err(|_| Error::bad_database("PDU in db has invalid content."))?;
-		redact_content_in_place(&mut content, &room_version_id, self.kind.to_string())
-			.map_err(|e| Error::Redaction(self.sender.server_name().to_owned(), e))?;
+		self.redact_content(&room_version_id)?;
 
 		self.unsigned = Some(
 			to_raw_value(&json!({
@@ -71,6 +66,19 @@ impl PduEvent {
 			.expect("to string always works"),
 		);
 
+		Ok(())
+	}
+
+	/// Strips the content and unsigned data of the event down to what a
+	/// redaction keeps, without pointing to a redaction event.
+	pub fn redact_content(&mut self, room_version_id: &RoomVersionId) -> crate::Result<()> {
+		self.unsigned = None;
+
+		let mut content = serde_json::from_str(self.content.get())
+			.map_err(|_| Error::bad_database("PDU in db has invalid content."))?;
+		redact_content_in_place(&mut content, room_version_id, self.kind.to_string())
+			.map_err(|e| Error::Redaction(self.sender.server_name().to_owned(), e))?;
+
 		self.content = to_raw_value(&content).expect("to string always works");
 
 		Ok(())
@@ -0,0 +1,139 @@
+use std::mem::size_of;
+
+use ruma::UserId;
+
+use super::RegistrationToken;
+use crate::{utils, Error, KeyValueDatabase, Result};
+
+pub(crate) trait Data: Send + Sync {
+	/// Returns a registration token and its usage.
+	fn registration_token(&self, token: &str) -> Result<Option<RegistrationToken>>;
+
+	/// Creates or updates a registration token.
+	fn set_registration_token(&self, token: &RegistrationToken) -> Result<()>;
+
+	/// Removes a registration token. Users who registered with it stay
+	/// attributed to it.
+	fn remove_registration_token(&self, token: &str) -> Result<()>;
+
+	/// Returns an iterator over all registration tokens.
+	fn registration_tokens<'a>(&'a self) -> Box<dyn Iterator<Item = Result<RegistrationToken>> + 'a>;
+
+	/// Remembers the token a UIAA session completed the registration token
+	/// stage with, and when it did so in milliseconds since the unix epoch.
+	fn set_session_registration_token(&self, session: &str, token: &str, reserved_at: u64) -> Result<()>;
+
+	/// Returns the token a UIAA session completed the registration token stage
+	/// with, and when it did so.
+	fn session_registration_token(&self, session: &str) -> Result<Option<(String, u64)>>;
+
+	/// Returns an iterator over all UIAA sessions which reserved a use of a
+	/// registration token, with the token and when the use was reserved.
+	fn session_registration_tokens<'a>(&'a self) -> Box<dyn Iterator<Item = Result<(String, String, u64)>> + 'a>;
+
+	fn remove_session_registration_token(&self, session: &str) -> Result<()>;
+
+	/// Attributes a registration to the token it used.
+	fn set_user_registration_token(&self, user_id: &UserId, token: &str) -> Result<()>;
+
+	/// Returns the token a user registered with.
+	fn user_registration_token(&self, user_id: &UserId) -> Result<Option<String>>;
+
+	fn remove_user_registration_token(&self, user_id: &UserId) -> Result<()>;
+}
+
+impl Data for KeyValueDatabase {
+	fn registration_token(&self, token: &str) -> Result<Option<RegistrationToken>> {
+		self.registrationtoken_info
+			.get(token.as_bytes())?
+			.map(|bytes| {
+				serde_json::from_slice(&bytes)
+					.map_err(|_| Error::bad_database("Invalid registration token info in db."))
+			})
+			.transpose()
+	}
+
+	fn set_registration_token(&self, token: &RegistrationToken) -> Result<()> {
+		self.registrationtoken_info.insert(
+			token.token.as_bytes(),
+			&serde_json::to_vec(token).expect("RegistrationToken::to_vec always works"),
+		)
+	}
+
+	fn remove_registration_token(&self, token: &str) -> Result<()> {
+		self.registrationtoken_info.remove(token.as_bytes())
+	}
+
+	fn registration_tokens<'a>(&'a self) -> Box<dyn Iterator<Item = Result<RegistrationToken>> + 'a> {
+		Box::new(self.registrationtoken_info.iter().map(|(_, bytes)| {
+			serde_json::from_slice(&bytes).map_err(|_| Error::bad_database("Invalid registration token info in db."))
+		}))
+	}
+
+	fn set_session_registration_token(&self, session: &str, token: &str, reserved_at: u64) -> Result<()> {
+		let mut value = reserved_at.to_be_bytes().to_vec();
+		value.extend_from_slice(token.as_bytes());
+
+		self.uiaasessionid_registrationtoken
+			.insert(session.as_bytes(), &value)
+	}
+
+	fn session_registration_token(&self, session: &str) -> Result<Option<(String, u64)>> {
+		self.uiaasessionid_registrationtoken
+			.get(session.as_bytes())?
+			.map(|bytes| parse_reservation(&bytes))
+			.transpose()
+	}
+
+	fn session_registration_tokens<'a>(&'a self) -> Box<dyn Iterator<Item = Result<(String, String, u64)>> + 'a> {
+		Box::new(
+			self.uiaasessionid_registrationtoken
+				.iter()
+				.map(|(session, bytes)| {
+					let session = utils::string_from_bytes(&session)
+						.map_err(|_| Error::bad_database("Session in uiaasessionid_registrationtoken is invalid."))?;
+					let (token, reserved_at) = parse_reservation(&bytes)?;
+
+					Ok((session, token, reserved_at))
+				}),
+		)
+	}
+
+	fn remove_session_registration_token(&self, session: &str) -> Result<()> {
+		self.uiaasessionid_registrationtoken
+			.remove(session.as_bytes())
+	}
+
+	fn set_user_registration_token(&self, user_id: &UserId, token: &str) -> Result<()> {
+		self.userid_registrationtoken
+			.insert(user_id.as_bytes(), token.as_bytes())
+	}
+
+	fn user_registration_token(&self, user_id: &UserId) -> Result<Option<String>> {
+		self.userid_registrationtoken
+			.get(user_id.as_bytes())?
+			.map(|bytes| {
+				utils::string_from_bytes(&bytes)
+					.map_err(|_| Error::bad_database("Registration token in userid_registrationtoken is invalid."))
+			})
+			.transpose()
+	}
+
+	fn remove_user_registration_token(&self, user_id: &UserId) -> Result<()> {
+		self.userid_registrationtoken.remove(user_id.as_bytes())
+	}
+}
+
+fn parse_reservation(bytes: &[u8]) -> Result<(String, u64)> {
+	if bytes.len() < size_of::<u64>() {
+		return Err(Error::bad_database("Invalid reservation in uiaasessionid_registrationtoken."));
+	}
+
+	let (reserved_at, token) = bytes.split_at(size_of::<u64>());
+	let reserved_at = utils::u64_from_bytes(reserved_at)
+		.map_err(|_| Error::bad_database("Invalid reservation time in uiaasessionid_registrationtoken."))?;
+	let token = utils::string_from_bytes(token)
+		.map_err(|_| Error::bad_database("Registration token in uiaasessionid_registrationtoken is invalid."))?;
+
+	Ok((token, reserved_at))
+}
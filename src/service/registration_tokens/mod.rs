@@ -0,0 +1,307 @@
+mod data;
+
+use std::sync::{Arc, Mutex};
+
+use conduit::{utils, Error, Result};
+use data::Data;
+use ruma::{api::client::error::ErrorKind, UserId};
+use serde::{Deserialize, Serialize};
+
+use crate::services;
+
+/// Length of generated registration tokens
+pub const TOKEN_LENGTH: usize = 16;
+
+/// How long a registration may take from completing the registration token
+/// stage to finishing, in milliseconds. The reserved use of the token is
+/// released afterwards, so abandoned registrations cannot exhaust a token.
+const RESERVATION_TTL: u64 = 30 * 60 * 1000;
+
+/// A registration token stored in the database, as opposed to the static
+/// `registration_token` from the config.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RegistrationToken {
+	pub token: String,
+	/// How many registrations may use this token, unlimited if `None`
+	pub uses_allowed: Option<u64>,
+	/// Registrations that completed the token stage but did not finish yet
+	pub pending: u64,
+	/// Registrations that finished using this token
+	pub completed: u64,
+	/// Milliseconds since the unix epoch after which the token is invalid
+	pub expiry_time: Option<u64>,
+}
+
+impl RegistrationToken {
+	/// Whether the token has uses left and has not expired.
+	#[must_use]
+	pub fn is_valid(&self) -> bool {
+		self.expiry_time
+			.map_or(true, |expiry_time| expiry_time > utils::millis_since_unix_epoch())
+			&& self
+				.uses_allowed
+				.map_or(true, |uses_allowed| self.pending.saturating_add(self.completed) < uses_allowed)
+	}
+}
+
+pub struct Service {
+	pub(super) db: Arc<dyn Data>,
+	/// Serializes read-modify-write cycles of the usage counters
+	pub(super) usage_lock: Mutex<()>,
+}
+
+impl Service {
+	/// Whether registrations have to complete the `m.login.registration_token`
+	/// stage.
+	pub fn required(&self) -> bool {
+		let config = &services().globals.config;
+		config.registration_token.is_some() || config.registration_requires_token
+	}
+
+	/// Creates a new registration token, generating a random one if `token`
+	/// is `None`.
+	pub fn create(
+		&self, token: Option<String>, uses_allowed: Option<u64>, expiry_time: Option<u64>,
+	) -> Result<RegistrationToken> {
+		let token = token.unwrap_or_else(|| utils::random_string(TOKEN_LENGTH));
+
+		if token.is_empty()
+			|| token.len() > 64
+			|| !token
+				.chars()
+				.all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '~' | '-'))
+		{
+			return Err(Error::BadRequest(
+				ErrorKind::InvalidParam,
+				"Registration tokens must be 1 to 64 characters of A-Z, a-z, 0-9, '.', '_', '~' or '-'.",
+			));
+		}
+
+		let _lock = self.usage_lock.lock().expect("locked");
+		if self.db.registration_token(&token)?.is_some() {
+			return Err(Error::BadRequest(ErrorKind::InvalidParam, "Registration token already exists."));
+		}
+
+		let token = RegistrationToken {
+			token,
+			uses_allowed,
+			pending: 0,
+			completed: 0,
+			expiry_time,
+		};
+		self.db.set_registration_token(&token)?;
+
+		Ok(token)
+	}
+
+	/// Returns a registration token and its usage.
+	pub fn get(&self, token: &str) -> Result<Option<RegistrationToken>> { self.db.registration_token(token) }
+
+	/// Returns an iterator over all registration tokens.
+	pub fn iter<'a>(&'a self) -> impl Iterator<Item = Result<RegistrationToken>> + 'a { self.db.registration_tokens() }
+
+	/// Updates the limits of a registration token. `None` leaves a limit
+	/// unchanged, `Some(None)` removes it.
+	pub fn update(
+		&self, token: &str, uses_allowed: Option<Option<u64>>, expiry_time: Option<Option<u64>>,
+	) -> Result<Option<RegistrationToken>> {
+		let _lock = self.usage_lock.lock().expect("locked");
+		let Some(mut info) = self.db.registration_token(token)? else {
+			return Ok(None);
+		};
+
+		if let Some(uses_allowed) = uses_allowed {
+			info.uses_allowed = uses_allowed;
+		}
+
+		if let Some(expiry_time) = expiry_time {
+			info.expiry_time = expiry_time;
+		}
+
+		self.db.set_registration_token(&info)?;
+
+		Ok(Some(info))
+	}
+
+	/// Revokes a registration token, returns false if it did not exist.
+	pub fn revoke(&self, token: &str) -> Result<bool> {
+		let _lock = self.usage_lock.lock().expect("locked");
+		if self.db.registration_token(token)?.is_none() {
+			return Ok(false);
+		}
+
+		self.db.remove_registration_token(token)?;
+
+		Ok(true)
+	}
+
+	/// Whether a token can currently be used to register, either the static
+	/// token from the config or a valid database token.
+	pub fn is_valid(&self, token: &str) -> Result<bool> {
+		if services().globals.config.registration_token.as_deref() == Some(token) {
+			return Ok(true);
+		}
+
+		let _lock = self.usage_lock.lock().expect("locked");
+		self.release_expired_reservations()?;
+
+		Ok(self
+			.db
+			.registration_token(token)?
+			.is_some_and(|info| info.is_valid()))
+	}
+
+	/// Validates the token a UIAA session submitted for the
+	/// `m.login.registration_token` stage and reserves one of its uses until
+	/// the registration finishes, or the reservation expires.
+	pub fn use_in_session(&self, token: &str, session: &str) -> Result<bool> {
+		let _lock = self.usage_lock.lock().expect("locked");
+		self.release_expired_reservations()?;
+
+		// the session already reserved a use
+		if self
+			.db
+			.session_registration_token(session)?
+			.is_some_and(|(reserved, _)| reserved == token)
+		{
+			return Ok(true);
+		}
+
+		if services().globals.config.registration_token.as_deref() != Some(token) {
+			let Some(mut info) = self.db.registration_token(token)? else {
+				return Ok(false);
+			};
+
+			if !info.is_valid() {
+				return Ok(false);
+			}
+
+			info.pending = info.pending.saturating_add(1);
+			self.db.set_registration_token(&info)?;
+		}
+
+		self.db
+			.set_session_registration_token(session, token, utils::millis_since_unix_epoch())?;
+
+		Ok(true)
+	}
+
+	/// Attributes a finished registration to the token its UIAA session used,
+	/// turning the reserved use into a completed one. Fails if a token is
+	/// required but the reservation of the session expired.
+	pub fn complete_registration(&self, session: &str, user_id: &UserId) -> Result<()> {
+		let _lock = self.usage_lock.lock().expect("locked");
+		self.release_expired_reservations()?;
+
+		let Some((token, _)) = self.db.session_registration_token(session)? else {
+			if self.required() {
+				return Err(Error::BadRequest(
+					ErrorKind::forbidden(),
+					"The registration took too long, please register again.",
+				));
+			}
+
+			return Ok(());
+		};
+
+		// the token might have been revoked in the meantime
+		if let Some(mut info) = self.db.registration_token(&token)? {
+			info.pending = info.pending.saturating_sub(1);
+			info.completed = info.completed.saturating_add(1);
+			self.db.set_registration_token(&info)?;
+		}
+
+		self.db.set_user_registration_token(user_id, &token)?;
+		self.db.remove_session_registration_token(session)
+	}
+
+	/// Undoes `complete_registration` for a registration which failed before
+	/// the user was created, giving the use back to the token.
+	pub fn abort_registration(&self, user_id: &UserId) -> Result<()> {
+		let _lock = self.usage_lock.lock().expect("locked");
+
+		let Some(token) = self.db.user_registration_token(user_id)? else {
+			return Ok(());
+		};
+
+		// the token might have been revoked in the meantime
+		if let Some(mut info) = self.db.registration_token(&token)? {
+			info.completed = info.completed.saturating_sub(1);
+			self.db.set_registration_token(&info)?;
+		}
+
+		self.db.remove_user_registration_token(user_id)
+	}
+
+	/// Returns the token a user registered with.
+	pub fn used_by(&self, user_id: &UserId) -> Result<Option<String>> { self.db.user_registration_token(user_id) }
+
+	/// Releases the uses reserved by UIAA sessions which did not finish their
+	/// registration in time. Must be called with the usage lock held.
+	fn release_expired_reservations(&self) -> Result<()> {
+		let now = utils::millis_since_unix_epoch();
+		let expired = self
+			.db
+			.session_registration_tokens()
+			.filter_map(Result::ok)
+			.filter(|(_, _, reserved_at)| reservation_expired(*reserved_at, now))
+			.collect::<Vec<_>>();
+
+		for (session, token, _) in expired {
+			// the token might have been revoked in the meantime
+			if let Some(mut info) = self.db.registration_token(&token)? {
+				info.pending = info.pending.saturating_sub(1);
+				self.db.set_registration_token(&info)?;
+			}
+
+			self.db.remove_session_registration_token(&session)?;
+		}
+
+		Ok(())
+	}
+}
+
+fn reservation_expired(reserved_at: u64, now: u64) -> bool { reserved_at.saturating_add(RESERVATION_TTL) < now }
+
+#[cfg(test)]
+mod tests {
+	use super::{reservation_expired, RegistrationToken, RESERVATION_TTL};
+
+	fn token(uses_allowed: Option<u64>, pending: u64, completed: u64) -> RegistrationToken {
+		RegistrationToken {
+			token: "token".to_owned(),
+			uses_allowed,
+			pending,
+			completed,
+			expiry_time: None,
+		}
+	}
+
+	#[test]
+	fn pending_uses_count_against_the_limit() {
+		assert!(token(None, 100, 100).is_valid());
+		assert!(token(Some(2), 1, 0).is_valid());
+		assert!(!token(Some(2), 1, 1).is_valid());
+		assert!(!token(Some(2), 2, 0).is_valid());
+		assert!(!token(Some(0), 0, 0).is_valid());
+	}
+
+	#[test]
+	fn expired_tokens_are_invalid() {
+		let mut expired = token(None, 0, 0);
+		expired.expiry_time = Some(1);
+		assert!(!expired.is_valid());
+
+		let mut valid = token(None, 0, 0);
+		valid.expiry_time = Some(u64::MAX);
+		assert!(valid.is_valid());
+	}
+
+	#[test]
+	fn reservations_expire() {
+		assert!(!reservation_expired(1_000, 1_000));
+		assert!(!reservation_expired(1_000, 1_000 + RESERVATION_TTL));
+		assert!(reservation_expired(1_000, 1_001 + RESERVATION_TTL));
+		assert!(!reservation_expired(u64::MAX, 0));
+	}
+}
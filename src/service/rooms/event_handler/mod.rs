@@ -1,5 +1,6 @@
 mod parse_incoming_pdu;
 mod signing_keys;
+mod third_party_invite;
 pub struct Service;
 
 use std::{
@@ -26,6 +27,7 @@ use ruma::{
 	state_res::{self, RoomVersion, StateMap},
 	uint, CanonicalJsonValue, EventId, MilliSecondsSinceUnixEpoch, OwnedUserId, RoomId, RoomVersionId, ServerName,
 };
+pub use third_party_invite::{verify_third_party_invite, with_token_as_public_key};
 use tokio::sync::RwLock;
 use tracing::{debug, error, info, trace, warn};
 
@@ -107,6 +109,17 @@ impl Service {
 
 		self.acl_check(sender.server_name(), room_id)?;
 
+		// 1.3.3 Check the origin and the sender's server are allowed to federate
+		// with us, as allowed servers may relay the events of other servers
+		if !services().globals.server_is_allowed(origin) || !services().globals.server_is_allowed(sender.server_name())
+		{
+			debug!("Refusing PDU {event_id} from {origin} sent by {sender}, the server is not allowed");
+			return Err(Error::BadRequest(
+				ErrorKind::forbidden(),
+				"Server is not allowed to federate with this homeserver.",
+			));
+		}
+
 		// Fetch create event
 		let create_event = services()
 			.rooms
@@ -305,6 +318,11 @@ impl Service {
 			// 3. check content hash, redact if doesn't match
 			let room_version_id = Self::get_room_version_id(create_event)?;
 
+			if let Err(e) = self.check_expired_signing_keys(&value) {
+				warn!("Dropping event {event_id} signed with an expired key");
+				return Err(e);
+			}
+
 			let guard = pub_key_map.read().await;
 			let mut val = match ruma::signatures::verify_event(&guard, &value, &room_version_id) {
 				Err(e) => {
@@ -411,14 +429,11 @@ impl Service {
 				));
 			}
 
-			if !state_res::event_auth::auth_check(
-				&Self::to_room_version(&room_version_id),
-				&incoming_pdu,
-				None::<PduEvent>, // TODO: third party invite
-				|k, s| auth_events.get(&(k.to_string().into(), s.to_owned())),
-			)
-			.map_err(|_e| Error::BadRequest(ErrorKind::forbidden(), "Auth check failed"))?
-			{
+			if !self.auth_check(&room_version_id, &incoming_pdu, |k, s| {
+				auth_events
+					.get(&(k.to_string().into(), s.to_owned()))
+					.cloned()
+			})? {
 				return Err(Error::BadRequest(ErrorKind::forbidden(), "Auth check failed"));
 			}
 
@@ -483,26 +498,39 @@ impl Service {
 		}
 
 		let state_at_incoming_event = state_at_incoming_event.expect("we always set this to some above");
-		let room_version = Self::to_room_version(&room_version_id);
+
+		// Rooms joined with partial state miss most memberships until the resync
+		// completes, the already checked auth events of the event fill the gaps
+		let partial_state = services().rooms.partial_state.is_partial(room_id)?;
+		let partial_state_auth_events: HashMap<(StateEventType, String), Arc<PduEvent>> = if partial_state {
+			incoming_pdu
+				.auth_events
+				.iter()
+				.filter_map(|id| services().rooms.timeline.get_pdu(id).ok().flatten())
+				.filter_map(|pdu| Some(((pdu.kind.to_string().into(), pdu.state_key.clone()?), pdu)))
+				.collect()
+		} else {
+			HashMap::new()
+		};
 
 		debug!("Performing auth check");
 		// 11. Check the auth of the event passes based on the state of the event
-		let check_result = state_res::event_auth::auth_check(
-			&room_version,
-			&incoming_pdu,
-			None::<PduEvent>, // TODO: third party invite
-			|k, s| {
-				services()
-					.rooms
-					.short
-					.get_shortstatekey(&k.to_string().into(), s)
-					.ok()
-					.flatten()
-					.and_then(|shortstatekey| state_at_incoming_event.get(&shortstatekey))
-					.and_then(|event_id| services().rooms.timeline.get_pdu(event_id).ok().flatten())
-			},
-		)
-		.map_err(|_e| Error::BadRequest(ErrorKind::forbidden(), "Auth check failed."))?;
+		let fetch_state = |k: &StateEventType, s: &str| {
+			services()
+				.rooms
+				.short
+				.get_shortstatekey(&k.to_string().into(), s)
+				.ok()
+				.flatten()
+				.and_then(|shortstatekey| state_at_incoming_event.get(&shortstatekey))
+				.and_then(|event_id| services().rooms.timeline.get_pdu(event_id).ok().flatten())
+				.or_else(|| {
+					partial_state_auth_events
+						.get(&(k.clone(), s.to_owned()))
+						.cloned()
+				})
+		};
+		let check_result = self.auth_check(&room_version_id, &incoming_pdu, fetch_state)?;
 
 		if !check_result {
 			return Err(Error::BadRequest(
@@ -522,10 +550,19 @@ impl Service {
 
 		// Soft fail check before doing state res
 		debug!("Performing soft-fail check");
-		let soft_fail = !state_res::event_auth::auth_check(&room_version, &incoming_pdu, None::<PduEvent>, |k, s| {
-			auth_events.get(&(k.clone(), s.to_owned()))
-		})
-		.map_err(|_e| Error::BadRequest(ErrorKind::forbidden(), "Auth check failed."))?;
+		let fetch_state = |k: &StateEventType, s: &str| {
+			auth_events
+				.get(&(k.clone(), s.to_owned()))
+				.or_else(|| partial_state_auth_events.get(&(k.clone(), s.to_owned())))
+				.cloned()
+		};
+		let soft_fail = match self.auth_check(&room_version_id, &incoming_pdu, fetch_state) {
+			Ok(allowed) => !allowed,
+			Err(e) => {
+				debug!("Event is not valid with the current state: {e}");
+				true
+			},
+		};
 
 		// 13. Use state resolution to find new room state
 
@@ -656,6 +693,14 @@ impl Service {
 			)
 			.await?;
 
+		// Checked again against the full state once the resync completes
+		if partial_state {
+			services()
+				.rooms
+				.partial_state
+				.add_accepted_event(room_id, &incoming_pdu.event_id)?;
+		}
+
 		// Event has passed all auth/stateres checks
 		drop(state_lock);
 		debug_info!(
@@ -721,7 +766,7 @@ impl Service {
 			if let Err(e) = &res {
 				error!("Failed to fetch event: {}", e);
 			}
-			res.ok().flatten()
+			res.ok().flatten().map(with_token_as_public_key)
 		});
 
 		let state = match state_resolve {
@@ -885,7 +930,7 @@ impl Service {
 			if let Err(e) = &res {
 				error!("Failed to fetch event: {}", e);
 			}
-			res.ok().flatten()
+			res.ok().flatten().map(with_token_as_public_key)
 		});
 		drop(lock);
 
@@ -1309,13 +1354,7 @@ impl Service {
 			},
 		};
 
-		if acl_event_content.allow.is_empty() {
-			warn!("Ignoring broken ACL event (allow key is empty)");
-			// Ignore broken acl events
-			return Ok(());
-		}
-
-		if acl_event_content.is_allowed(server_name) {
+		if acl_allows(&acl_event_content, server_name) {
 			trace!("server {server_name} is allowed by ACL");
 			Ok(())
 		} else {
@@ -1324,6 +1363,34 @@ impl Service {
 		}
 	}
 
+	/// Checks an event against the auth rules of the room version with the
+	/// state `fetch_state` returns. Third party invites are checked against
+	/// the verified `m.room.third_party_invite` event they refer to, see
+	/// [`verify_third_party_invite`].
+	pub fn auth_check<F>(&self, room_version_id: &RoomVersionId, pdu: &PduEvent, fetch_state: F) -> Result<bool>
+	where
+		F: Fn(&StateEventType, &str) -> Option<Arc<PduEvent>>,
+	{
+		let third_party_invite = verify_third_party_invite(pdu, &fetch_state)?;
+		let fetch_state = |kind: &StateEventType, state_key: &str| {
+			third_party_invite
+				.as_ref()
+				.filter(|invite| {
+					*kind == StateEventType::RoomThirdPartyInvite && invite.state_key.as_deref() == Some(state_key)
+				})
+				.cloned()
+				.or_else(|| fetch_state(kind, state_key))
+		};
+
+		state_res::event_auth::auth_check(
+			&Self::to_room_version(room_version_id),
+			pdu,
+			third_party_invite.clone(),
+			fetch_state,
+		)
+		.map_err(|_e| Error::BadRequest(ErrorKind::forbidden(), "Auth check failed."))
+	}
+
 	fn check_room_id(room_id: &RoomId, pdu: &PduEvent) -> Result<()> {
 		if pdu.room_id != room_id {
 			warn!("Found event from room {} in room {}", pdu.room_id, room_id);
@@ -1346,3 +1413,42 @@ impl Service {
 		RoomVersion::new(room_version_id).expect("room version is supported")
 	}
 }
+
+/// Whether the content of a room's ACL event allows a server. Broken ACL
+/// events without any allowed server are ignored.
+fn acl_allows(acl: &RoomServerAclEventContent, server_name: &ServerName) -> bool {
+	if acl.allow.is_empty() {
+		warn!("Ignoring broken ACL event (allow key is empty)");
+		return true;
+	}
+
+	acl.is_allowed(server_name)
+}
+
+#[cfg(test)]
+mod tests {
+	use ruma::{events::room::server_acl::RoomServerAclEventContent, server_name};
+
+	use super::acl_allows;
+
+	#[test]
+	fn acl_denies_servers_outside_its_allow_list() {
+		let acl = RoomServerAclEventContent::new(
+			false,
+			vec!["*.example.com".to_owned()],
+			vec!["evil.example.com".to_owned()],
+		);
+
+		assert!(acl_allows(&acl, server_name!("matrix.example.com")));
+		assert!(!acl_allows(&acl, server_name!("evil.example.com")));
+		assert!(!acl_allows(&acl, server_name!("example.org")));
+		assert!(!acl_allows(&acl, server_name!("127.0.0.1")));
+	}
+
+	#[test]
+	fn broken_acl_allows_every_server() {
+		let acl = RoomServerAclEventContent::new(false, Vec::new(), vec!["evil.example.com".to_owned()]);
+
+		assert!(acl_allows(&acl, server_name!("evil.example.com")));
+	}
+}
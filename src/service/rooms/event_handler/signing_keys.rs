@@ -5,15 +5,18 @@ use std::{
 
 use futures_util::{stream::FuturesUnordered, StreamExt};
 use ruma::{
-	api::federation::{
-		discovery::{
-			get_remote_server_keys,
-			get_remote_server_keys_batch::{self, v2::QueryCriteria},
-			get_server_keys,
+	api::{
+		client::error::ErrorKind,
+		federation::{
+			discovery::{
+				get_remote_server_keys,
+				get_remote_server_keys_batch::{self, v2::QueryCriteria},
+				get_server_keys, OldVerifyKey, ServerSigningKeys,
+			},
+			membership::create_join_event,
 		},
-		membership::create_join_event,
 	},
-	serde::Base64,
+	serde::{Base64, Raw},
 	CanonicalJsonObject, CanonicalJsonValue, MilliSecondsSinceUnixEpoch, OwnedServerName, OwnedServerSigningKeyId,
 	RoomVersionId, ServerName,
 };
@@ -359,6 +362,14 @@ impl super::Service {
 			return Ok(result);
 		}
 
+		if services().globals.server_is_forbidden(origin) {
+			debug!("Not fetching signing keys of banned server {origin}");
+			return Err(Error::BadRequest(
+				ErrorKind::forbidden(),
+				"Server is banned on this homeserver.",
+			));
+		}
+
 		// i didnt split this out into their own functions because it's relatively small
 		if services().globals.query_trusted_key_servers_first() {
 			info!(
@@ -522,4 +533,219 @@ impl super::Service {
 		warn!("Failed to find public key for server: {origin}");
 		Err(Error::BadServerResponse("Failed to find public key for server"))
 	}
+
+	/// Rejects events carrying a signature made with a retired key of a server,
+	/// including our own, after the key expired. Retired keys may have leaked,
+	/// so they only verify events sent before they were retired.
+	pub fn check_expired_signing_keys(&self, value: &CanonicalJsonObject) -> Result<()> {
+		let Some(CanonicalJsonValue::Integer(origin_server_ts)) = value.get("origin_server_ts") else {
+			return Ok(());
+		};
+		let origin_server_ts = u64::try_from(i64::from(*origin_server_ts)).unwrap_or(0);
+
+		let Some(CanonicalJsonValue::Object(signatures)) = value.get("signatures") else {
+			return Ok(());
+		};
+
+		for (server, key_ids) in signatures {
+			let (Ok(server), CanonicalJsonValue::Object(key_ids)) = (<&ServerName>::try_from(server.as_str()), key_ids)
+			else {
+				continue;
+			};
+
+			let old_keys = services().globals.old_signing_keys_for(server)?;
+			if signed_after_expiry(key_ids.keys().map(String::as_str), &old_keys, origin_server_ts) {
+				return Err(Error::BadRequest(
+					ErrorKind::InvalidParam,
+					"Event is signed with a key that expired before it was sent.",
+				));
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Returns the keys of `origin` signed by itself for serving as a notary.
+	/// Cached keys are used as long as they are valid until
+	/// `minimum_valid_until_ts` and contain all `key_ids`, otherwise the origin
+	/// and then the trusted servers are asked. Outdated keys are returned when
+	/// nothing newer could be found, keys without a valid signature of `origin`
+	/// never are.
+	pub async fn notary_server_keys(
+		&self, origin: &ServerName, key_ids: Vec<String>, minimum_valid_until_ts: MilliSecondsSinceUnixEpoch,
+	) -> Result<Option<ServerSigningKeys>> {
+		let is_fresh = |keys: &ServerSigningKeys| {
+			keys.valid_until_ts >= minimum_valid_until_ts
+				&& key_ids.iter().all(|id| {
+					keys.verify_keys.keys().any(|k| k.as_str() == id)
+						|| keys.old_verify_keys.keys().any(|k| k.as_str() == id)
+				})
+		};
+
+		if let Some(keys) = services().globals.signed_server_keys(origin)? {
+			if origin == services().globals.server_name() || (is_fresh(&keys) && is_self_signed(&keys)) {
+				return Ok(Some(keys));
+			}
+		}
+
+		debug!("Asking {origin} for their signing keys to serve as a notary");
+		if let Some(server_key) = services()
+			.sending
+			.send_federation_request(origin, get_server_keys::v2::Request::new())
+			.await
+			.ok()
+			.and_then(|resp| self_signed_server_keys(origin, &resp.server_key))
+		{
+			services()
+				.globals
+				.add_signing_key(origin, server_key.clone())?;
+
+			if is_fresh(&server_key) {
+				return Ok(Some(server_key));
+			}
+		}
+
+		if !key_ids.is_empty() {
+			if let Err(e) = self.fetch_signing_keys_for_server(origin, key_ids).await {
+				debug!("Failed to fetch signing keys of {origin} for notary request: {e}");
+			}
+		}
+
+		Ok(services()
+			.globals
+			.signed_server_keys(origin)?
+			.filter(is_self_signed))
+	}
+}
+
+/// Whether one of the keys an event sent at `origin_server_ts` was signed with
+/// had expired by then.
+fn signed_after_expiry<'a>(
+	key_ids: impl IntoIterator<Item = &'a str>, old_keys: &BTreeMap<OwnedServerSigningKeyId, OldVerifyKey>,
+	origin_server_ts: u64,
+) -> bool {
+	key_ids.into_iter().any(|key_id| {
+		old_keys.iter().any(|(old_key_id, old_key)| {
+			old_key_id.as_str() == key_id && u64::from(old_key.expired_ts.0) <= origin_server_ts
+		})
+	})
+}
+
+/// Deserializes a key response of `origin`, if it is signed by `origin` with
+/// one of the keys it contains.
+fn self_signed_server_keys(origin: &ServerName, raw: &Raw<ServerSigningKeys>) -> Option<ServerSigningKeys> {
+	let keys = raw
+		.deserialize()
+		.ok()
+		.filter(|keys: &ServerSigningKeys| keys.server_name == *origin)?;
+	let object = serde_json::from_str::<CanonicalJsonObject>(raw.json().get()).ok()?;
+
+	verify_self_signature(&keys, &object).then_some(keys)
+}
+
+/// Whether cached keys are signed by their server with one of the keys they
+/// contain.
+fn is_self_signed(keys: &ServerSigningKeys) -> bool {
+	serde_json::to_value(keys)
+		.and_then(serde_json::from_value::<CanonicalJsonObject>)
+		.is_ok_and(|object| verify_self_signature(keys, &object))
+}
+
+fn verify_self_signature(keys: &ServerSigningKeys, object: &CanonicalJsonObject) -> bool {
+	let server_name = &keys.server_name;
+	let verify_keys = keys
+		.verify_keys
+		.iter()
+		.map(|(key_id, key)| (key_id.to_string(), key.key.clone()))
+		.collect();
+	let public_key_map = BTreeMap::from([(server_name.to_string(), verify_keys)]);
+	if let Err(e) = ruma::signatures::verify_json(&public_key_map, object) {
+		warn!("Signing keys of {server_name} are not signed by {server_name} itself: {e}");
+		return false;
+	}
+
+	true
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::BTreeMap;
+
+	use ruma::{
+		api::federation::discovery::OldVerifyKey,
+		serde::{Base64, Raw},
+		server_name,
+		signatures::Ed25519KeyPair,
+		CanonicalJsonObject, CanonicalJsonValue, MilliSecondsSinceUnixEpoch, OwnedServerSigningKeyId, UInt,
+	};
+	use serde_json::{json, value::to_raw_value};
+
+	use super::{is_self_signed, self_signed_server_keys, signed_after_expiry};
+
+	fn key_response(server_name: &str, keypair: &Ed25519KeyPair) -> CanonicalJsonObject {
+		serde_json::from_value(json!({
+			"server_name": server_name,
+			"valid_until_ts": 1_000_000_000_000_u64,
+			"verify_keys": {
+				format!("ed25519:{}", keypair.version()): {
+					"key": Base64::new(keypair.public_key().to_vec()),
+				},
+			},
+			"old_verify_keys": {},
+		}))
+		.unwrap()
+	}
+
+	fn generate_keypair(version: &str) -> Ed25519KeyPair {
+		Ed25519KeyPair::from_der(&Ed25519KeyPair::generate().unwrap(), version.to_owned()).unwrap()
+	}
+
+	#[test]
+	fn self_signed_keys_are_accepted() {
+		let keypair = generate_keypair("a");
+		let mut object = key_response("example.org", &keypair);
+		ruma::signatures::sign_json("example.org", &keypair, &mut object).unwrap();
+
+		let raw = Raw::from_json(to_raw_value(&object).unwrap());
+		let keys = self_signed_server_keys(server_name!("example.org"), &raw).unwrap();
+		assert!(is_self_signed(&keys), "cached keys stay verifiable");
+		assert!(self_signed_server_keys(server_name!("example.com"), &raw).is_none());
+	}
+
+	#[test]
+	fn keys_not_signed_by_their_server_are_rejected() {
+		let keypair = generate_keypair("a");
+		let unsigned = Raw::from_json(to_raw_value(&key_response("example.org", &keypair)).unwrap());
+		assert!(self_signed_server_keys(server_name!("example.org"), &unsigned).is_none());
+
+		// Signed by another key than the ones it contains
+		let mut object = key_response("example.org", &keypair);
+		ruma::signatures::sign_json("example.org", &generate_keypair("b"), &mut object).unwrap();
+		let raw = Raw::from_json(to_raw_value(&object).unwrap());
+		assert!(self_signed_server_keys(server_name!("example.org"), &raw).is_none());
+
+		// Tampered with after signing
+		let mut object = key_response("example.org", &keypair);
+		ruma::signatures::sign_json("example.org", &keypair, &mut object).unwrap();
+		object.insert(
+			"valid_until_ts".to_owned(),
+			CanonicalJsonValue::Integer(2_000_000_000_000_i64.try_into().unwrap()),
+		);
+		let raw = Raw::from_json(to_raw_value(&object).unwrap());
+		assert!(self_signed_server_keys(server_name!("example.org"), &raw).is_none());
+	}
+
+	#[test]
+	fn retired_keys_only_verify_events_sent_before_they_expired() {
+		let key_id: OwnedServerSigningKeyId = "ed25519:old".try_into().unwrap();
+		let old_keys = BTreeMap::from([(
+			key_id,
+			OldVerifyKey::new(MilliSecondsSinceUnixEpoch(UInt::new(1000).unwrap()), Base64::new(vec![0; 32])),
+		)]);
+
+		assert!(!signed_after_expiry(["ed25519:old"], &old_keys, 999));
+		assert!(signed_after_expiry(["ed25519:old"], &old_keys, 1000));
+		assert!(signed_after_expiry(["ed25519:new", "ed25519:old"], &old_keys, 2000));
+		assert!(!signed_after_expiry(["ed25519:new"], &old_keys, 2000));
+	}
 }
@@ -0,0 +1,271 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use ruma::{
+	api::client::error::ErrorKind,
+	events::{
+		room::{member::MembershipState, third_party_invite::RoomThirdPartyInviteEventContent},
+		StateEventType, TimelineEventType,
+	},
+	serde::Base64,
+	signatures::PublicKeyMap,
+	CanonicalJsonObject, CanonicalJsonValue,
+};
+use serde::Deserialize;
+use serde_json::{json, value::to_raw_value};
+use tracing::debug;
+
+use crate::{Error, PduEvent, Result};
+
+#[derive(Deserialize)]
+struct ExtractThirdPartyInvite {
+	membership: MembershipState,
+	third_party_invite: Option<ThirdPartyInvite>,
+}
+
+#[derive(Deserialize)]
+struct ThirdPartyInvite {
+	signed: CanonicalJsonObject,
+}
+
+/// Verifies the `third_party_invite` of an invite created from a third party
+/// invite against the `m.room.third_party_invite` event it refers to.
+///
+/// The state resolution crate compares the public keys with the invite token
+/// decoded as base64 instead of checking the signatures, so it rejects every
+/// valid invite of this kind. Once verified here, the
+/// `m.room.third_party_invite` event is returned as
+/// [`with_token_as_public_key`] prepares it, to run the remaining auth rules
+/// against. Returns `None` for all other events.
+pub fn verify_third_party_invite<F>(pdu: &PduEvent, fetch_state: F) -> Result<Option<Arc<PduEvent>>>
+where
+	F: Fn(&StateEventType, &str) -> Option<Arc<PduEvent>>,
+{
+	if pdu.kind != TimelineEventType::RoomMember {
+		return Ok(None);
+	}
+
+	let Ok(ExtractThirdPartyInvite {
+		membership: MembershipState::Invite,
+		third_party_invite: Some(third_party_invite),
+	}) = serde_json::from_str(pdu.content.get())
+	else {
+		return Ok(None);
+	};
+
+	let signed = &third_party_invite.signed;
+	let (Some(mxid), Some(token)) = (
+		signed.get("mxid").and_then(CanonicalJsonValue::as_str),
+		signed.get("token").and_then(CanonicalJsonValue::as_str),
+	) else {
+		return Err(Error::BadRequest(ErrorKind::InvalidParam, "Third party invite is not signed."));
+	};
+
+	if pdu.state_key.as_deref() != Some(mxid) {
+		return Err(Error::BadRequest(
+			ErrorKind::forbidden(),
+			"Third party invite was signed for another user.",
+		));
+	}
+
+	let Some(invite_event) = fetch_state(&StateEventType::RoomThirdPartyInvite, token) else {
+		return Err(Error::BadRequest(
+			ErrorKind::forbidden(),
+			"Third party invite refers to an unknown invite token.",
+		));
+	};
+
+	if invite_event.sender != pdu.sender {
+		return Err(Error::BadRequest(
+			ErrorKind::forbidden(),
+			"Third party invite was sent by another user.",
+		));
+	}
+
+	let invite_content: RoomThirdPartyInviteEventContent = serde_json::from_str(invite_event.content.get())
+		.map_err(|_| Error::bad_database("Invalid third party invite event in db."))?;
+
+	let mut public_keys = invite_content
+		.public_keys
+		.unwrap_or_default()
+		.into_iter()
+		.map(|key| key.public_key)
+		.chain(Some(invite_content.public_key));
+
+	if !public_keys.any(|public_key| signed_by(signed, &public_key)) {
+		return Err(Error::BadRequest(
+			ErrorKind::forbidden(),
+			"Third party invite is not signed by the identity server.",
+		));
+	}
+
+	debug!("Verified third party invite of {mxid} with token {token}");
+
+	Ok(Some(with_token_as_public_key(invite_event)))
+}
+
+/// Adds the token of an `m.room.third_party_invite` event to its public keys,
+/// which the state resolution crate compares the token of invites with. Only
+/// invites whose signatures were verified by [`verify_third_party_invite`] are
+/// accepted into a room, so the crate may take them as signed this way when
+/// resolving its state. Other events are returned unchanged.
+#[must_use]
+pub fn with_token_as_public_key(pdu: Arc<PduEvent>) -> Arc<PduEvent> {
+	if pdu.kind != TimelineEventType::RoomThirdPartyInvite {
+		return pdu;
+	}
+
+	let (Some(token), Ok(mut content)) = (
+		pdu.state_key.as_deref(),
+		serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(pdu.content.get()),
+	) else {
+		return pdu;
+	};
+
+	let public_key = json!({ "public_key": token });
+	match content.get_mut("public_keys") {
+		Some(serde_json::Value::Array(public_keys)) => public_keys.push(public_key),
+		_ => {
+			content.insert("public_keys".to_owned(), json!([public_key]));
+		},
+	}
+
+	let mut pdu = (*pdu).clone();
+	pdu.content = to_raw_value(&content).expect("third party invite content is valid JSON");
+
+	Arc::new(pdu)
+}
+
+/// Whether any signature of the `signed` object verifies with the public key
+/// of the identity server.
+fn signed_by(signed: &CanonicalJsonObject, public_key: &Base64) -> bool {
+	let Some(CanonicalJsonValue::Object(signatures)) = signed.get("signatures") else {
+		return false;
+	};
+
+	signatures.iter().any(|(entity, key_ids)| {
+		let CanonicalJsonValue::Object(key_ids) = key_ids else {
+			return false;
+		};
+
+		key_ids.keys().any(|key_id| {
+			let public_key_map: PublicKeyMap =
+				BTreeMap::from([(entity.clone(), BTreeMap::from([(key_id.clone(), public_key.clone())]))]);
+
+			ruma::signatures::verify_json(&public_key_map, signed).is_ok()
+		})
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+
+	use ruma::{
+		event_id,
+		events::room::third_party_invite::RoomThirdPartyInviteEventContent,
+		serde::Base64,
+		signatures::{sign_json, Ed25519KeyPair},
+		CanonicalJsonObject, EventId,
+	};
+	use serde_json::json;
+
+	use super::{verify_third_party_invite, with_token_as_public_key};
+	use crate::PduEvent;
+
+	/// Tokens are compared with public keys as base64 by the state resolution
+	/// crate
+	const TOKEN: &str = "dG9rZW4";
+
+	fn pdu(event_id: &EventId, sender: &str, kind: &str, state_key: &str, content: serde_json::Value) -> PduEvent {
+		let value: CanonicalJsonObject = serde_json::from_value(json!({
+			"room_id": "!room:example.com",
+			"sender": sender,
+			"origin_server_ts": 0,
+			"type": kind,
+			"state_key": state_key,
+			"content": content,
+			"prev_events": [],
+			"auth_events": [],
+			"depth": 1,
+			"hashes": { "sha256": "" },
+			"signatures": {},
+		}))
+		.unwrap();
+
+		PduEvent::from_id_val(event_id, value).unwrap()
+	}
+
+	fn third_party_invite(public_key: &Ed25519KeyPair) -> Arc<PduEvent> {
+		Arc::new(pdu(
+			event_id!("$third_party_invite"),
+			"@inviter:example.com",
+			"m.room.third_party_invite",
+			TOKEN,
+			json!({
+				"display_name": "a...@example.org",
+				"key_validity_url": "https://identity.example.org/_matrix/identity/v2/pubkey/isvalid",
+				"public_key": Base64::new(public_key.public_key().to_vec()),
+			}),
+		))
+	}
+
+	fn invite(sender: &str, keypair: &Ed25519KeyPair) -> PduEvent {
+		let mut signed: CanonicalJsonObject = serde_json::from_value(json!({
+			"mxid": "@invitee:example.com",
+			"token": TOKEN,
+		}))
+		.unwrap();
+		sign_json("identity.example.org", keypair, &mut signed).unwrap();
+
+		pdu(
+			event_id!("$invite"),
+			sender,
+			"m.room.member",
+			"@invitee:example.com",
+			json!({
+				"membership": "invite",
+				"third_party_invite": { "display_name": "a...@example.org", "signed": signed },
+			}),
+		)
+	}
+
+	fn generate_keypair() -> Ed25519KeyPair {
+		Ed25519KeyPair::from_der(&Ed25519KeyPair::generate().unwrap(), "0".to_owned()).unwrap()
+	}
+
+	#[test]
+	fn invites_signed_by_the_identity_server_are_verified() {
+		let keypair = generate_keypair();
+		let invite_event = third_party_invite(&keypair);
+		let fetch_state = |_: &_, state_key: &str| (state_key == TOKEN).then(|| Arc::clone(&invite_event));
+
+		let verified = verify_third_party_invite(&invite("@inviter:example.com", &keypair), fetch_state)
+			.unwrap()
+			.expect("invite is a third party invite");
+		let content: RoomThirdPartyInviteEventContent = serde_json::from_str(verified.content.get()).unwrap();
+		assert!(content
+			.public_keys
+			.unwrap()
+			.iter()
+			.any(|key| key.public_key.encode() == TOKEN));
+
+		// Signed by another key than the identity server's
+		assert!(verify_third_party_invite(&invite("@inviter:example.com", &generate_keypair()), fetch_state).is_err());
+
+		// Sent by another user than the one who created the third party invite
+		assert!(verify_third_party_invite(&invite("@other:example.com", &keypair), fetch_state).is_err());
+	}
+
+	#[test]
+	fn only_third_party_invite_events_get_their_token_as_public_key() {
+		let keypair = generate_keypair();
+		let invite_event = third_party_invite(&keypair);
+		let prepared = with_token_as_public_key(Arc::clone(&invite_event));
+		let content: RoomThirdPartyInviteEventContent = serde_json::from_str(prepared.content.get()).unwrap();
+		assert_eq!(content.public_key, Base64::new(keypair.public_key().to_vec()));
+		assert_eq!(content.public_keys.unwrap().len(), 1);
+
+		let member = Arc::new(invite("@inviter:example.com", &keypair));
+		assert!(Arc::ptr_eq(&with_token_as_public_key(Arc::clone(&member)), &member));
+	}
+}
@@ -5,7 +5,9 @@ pub mod event_handler;
 pub mod lazy_loading;
 pub mod metadata;
 pub mod outlier;
+pub mod partial_state;
 pub mod pdu_metadata;
+pub mod pdu_queue;
 pub mod read_receipt;
 pub mod search;
 pub mod short;
@@ -27,7 +29,9 @@ pub struct Service {
 	pub lazy_loading: lazy_loading::Service,
 	pub metadata: metadata::Service,
 	pub outlier: outlier::Service,
+	pub partial_state: partial_state::Service,
 	pub pdu_metadata: pdu_metadata::Service,
+	pub pdu_queue: pdu_queue::Service,
 	pub read_receipt: read_receipt::Service,
 	pub search: search::Service,
 	pub short: short::Service,
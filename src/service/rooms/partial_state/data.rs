@@ -0,0 +1,90 @@
+use ruma::{EventId, OwnedEventId, OwnedRoomId, RoomId};
+
+use super::PartialState;
+use crate::{utils, Error, KeyValueDatabase, Result};
+
+pub trait Data: Send + Sync {
+	/// Returns how the room was joined if it still has partial state.
+	fn partial_state(&self, room_id: &RoomId) -> Result<Option<PartialState>>;
+
+	/// Marks the room as having partial state.
+	fn set_partial_state(&self, room_id: &RoomId, partial_state: &PartialState) -> Result<()>;
+
+	/// Marks the room as having its full state and forgets the events
+	/// accepted meanwhile.
+	fn remove_partial_state(&self, room_id: &RoomId) -> Result<()>;
+
+	/// Remembers an event accepted while the room had partial state.
+	fn add_partial_state_event(&self, room_id: &RoomId, event_id: &EventId) -> Result<()>;
+
+	/// Returns the events accepted while the room had partial state.
+	fn partial_state_events<'a>(&'a self, room_id: &RoomId) -> Box<dyn Iterator<Item = Result<OwnedEventId>> + 'a>;
+
+	/// Returns all rooms which still have partial state.
+	fn partial_state_rooms<'a>(&'a self) -> Box<dyn Iterator<Item = Result<OwnedRoomId>> + 'a>;
+}
+
+impl Data for KeyValueDatabase {
+	fn partial_state(&self, room_id: &RoomId) -> Result<Option<PartialState>> {
+		self.roomid_partialstate
+			.get(room_id.as_bytes())?
+			.map(|bytes| {
+				serde_json::from_slice(&bytes)
+					.map_err(|_| Error::bad_database("Invalid partial state in roomid_partialstate."))
+			})
+			.transpose()
+	}
+
+	fn set_partial_state(&self, room_id: &RoomId, partial_state: &PartialState) -> Result<()> {
+		self.roomid_partialstate.insert(
+			room_id.as_bytes(),
+			&serde_json::to_vec(partial_state).expect("PartialState can be serialized"),
+		)
+	}
+
+	fn remove_partial_state(&self, room_id: &RoomId) -> Result<()> {
+		let events = self
+			.roomideventid_partialstate
+			.scan_prefix(prefix(room_id))
+			.map(|(key, _)| key)
+			.collect::<Vec<_>>();
+		self.roomideventid_partialstate
+			.remove_batch(&mut events.into_iter())?;
+		self.roomid_partialstate.remove(room_id.as_bytes())
+	}
+
+	fn add_partial_state_event(&self, room_id: &RoomId, event_id: &EventId) -> Result<()> {
+		let mut key = prefix(room_id);
+		key.extend_from_slice(event_id.as_bytes());
+		self.roomideventid_partialstate.insert(&key, &[])
+	}
+
+	fn partial_state_events<'a>(&'a self, room_id: &RoomId) -> Box<dyn Iterator<Item = Result<OwnedEventId>> + 'a> {
+		let prefix = prefix(room_id);
+		Box::new(
+			self.roomideventid_partialstate
+				.scan_prefix(prefix.clone())
+				.map(move |(key, _)| {
+					utils::string_from_bytes(&key[prefix.len()..])
+						.ok()
+						.and_then(|event_id| OwnedEventId::try_from(event_id).ok())
+						.ok_or_else(|| Error::bad_database("Event ID in roomideventid_partialstate is invalid."))
+				}),
+		)
+	}
+
+	fn partial_state_rooms<'a>(&'a self) -> Box<dyn Iterator<Item = Result<OwnedRoomId>> + 'a> {
+		Box::new(self.roomid_partialstate.iter().map(|(key, _)| {
+			utils::string_from_bytes(&key)
+				.ok()
+				.and_then(|room_id| OwnedRoomId::try_from(room_id).ok())
+				.ok_or_else(|| Error::bad_database("Room ID in roomid_partialstate is invalid."))
+		}))
+	}
+}
+
+fn prefix(room_id: &RoomId) -> Vec<u8> {
+	let mut prefix = room_id.as_bytes().to_vec();
+	prefix.push(0xFF);
+	prefix
+}
@@ -0,0 +1,495 @@
+mod data;
+
+use std::{
+	cmp,
+	collections::{BTreeMap, HashMap, HashSet},
+	sync::{Arc, Mutex as StdMutex},
+	time::Duration,
+};
+
+use data::Data;
+use ruma::{
+	api::{
+		client::error::{ErrorKind, RetryAfter},
+		federation::event::get_room_state,
+	},
+	events::StateEventType,
+	CanonicalJsonObject, CanonicalJsonValue, EventId, OwnedEventId, OwnedRoomId, OwnedServerName, RoomId,
+	RoomVersionId,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Notify, RwLock};
+use tracing::{debug, info, warn};
+
+use crate::{pdu::gen_event_id_canonical_json, server_is_ours, services, Error, PduEvent, Result};
+
+/// First delay between attempts to resync the state of a room
+const RESYNC_RETRY_MIN: Duration = Duration::from_secs(30);
+
+/// Longest delay between attempts to resync the state of a room
+const RESYNC_RETRY_MAX: Duration = Duration::from_secs(60 * 60);
+
+/// How long requests needing the full state of a room wait for the resync
+const FULL_STATE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long requests which timed out waiting for the resync are told to wait
+/// before retrying
+const FULL_STATE_RETRY_AFTER: Duration = Duration::from_secs(10);
+
+/// A room joined with `omit_members` (MSC3706), whose membership events are
+/// still missing from our state.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PartialState {
+	/// Our join event the full state is fetched at.
+	pub join_event_id: OwnedEventId,
+	/// Servers in the room according to the `send_join` response, as we
+	/// cannot derive them from the memberships yet.
+	pub servers: Vec<OwnedServerName>,
+}
+
+pub struct Service {
+	pub db: Arc<dyn Data>,
+	pub waiters: StdMutex<HashMap<OwnedRoomId, Arc<Notify>>>,
+}
+
+impl Service {
+	/// Whether the room was joined without its full state and the resync has
+	/// not completed yet.
+	pub fn is_partial(&self, room_id: &RoomId) -> Result<bool> { Ok(self.db.partial_state(room_id)?.is_some()) }
+
+	/// The servers in a partial-state room according to the `send_join`
+	/// response. Empty once the room has its full state.
+	pub fn servers_in_room(&self, room_id: &RoomId) -> Result<Vec<OwnedServerName>> {
+		Ok(self
+			.db
+			.partial_state(room_id)?
+			.map(|partial_state| partial_state.servers)
+			.unwrap_or_default())
+	}
+
+	/// Marks a room we just joined with partial state and starts fetching its
+	/// full state in the background.
+	pub fn mark_partial(&self, room_id: &RoomId, partial_state: &PartialState) -> Result<()> {
+		self.db.set_partial_state(room_id, partial_state)?;
+		self.start_resync(room_id.to_owned());
+
+		Ok(())
+	}
+
+	/// Remembers an event accepted while the room has partial state, to check
+	/// it against the full state once the resync completes.
+	pub fn add_accepted_event(&self, room_id: &RoomId, event_id: &EventId) -> Result<()> {
+		self.db.add_partial_state_event(room_id, event_id)
+	}
+
+	/// Waits until the room has its full state. Returns immediately for rooms
+	/// joined with their full state, and fails with `M_LIMIT_EXCEEDED` if
+	/// the resync takes too long.
+	pub async fn wait_for_full_state(&self, room_id: &RoomId) -> Result<()> {
+		if !self.is_partial(room_id)? {
+			return Ok(());
+		}
+
+		let notify = Arc::clone(
+			self.waiters
+				.lock()
+				.expect("locked")
+				.entry(room_id.to_owned())
+				.or_default(),
+		);
+
+		// Registered before checking again, so a resync completing in between still
+		// wakes us
+		let resynced = notify.notified();
+		let resynced = if self.is_partial(room_id)? {
+			debug!("Waiting for the full state of {room_id}");
+			tokio::time::timeout(FULL_STATE_TIMEOUT, resynced)
+				.await
+				.is_ok()
+		} else {
+			true
+		};
+
+		remove_waiter(&mut self.waiters.lock().expect("locked"), room_id, &notify);
+		if !resynced {
+			return Err(Error::BadRequest(
+				ErrorKind::LimitExceeded {
+					retry_after: Some(RetryAfter::Delay(FULL_STATE_RETRY_AFTER)),
+				},
+				"The full state of this room is still being fetched.",
+			));
+		}
+
+		Ok(())
+	}
+
+	/// Marks the room as having its full state and wakes up its waiters.
+	fn finish(&self, room_id: &RoomId) -> Result<()> {
+		self.db.remove_partial_state(room_id)?;
+		if let Some(notify) = self.waiters.lock().expect("locked").remove(room_id) {
+			notify.notify_waiters();
+		}
+
+		Ok(())
+	}
+
+	/// Resumes the resyncs interrupted by a restart.
+	pub fn start_resyncs(&self) -> Result<()> {
+		for room_id in self.db.partial_state_rooms() {
+			self.start_resync(room_id?);
+		}
+
+		Ok(())
+	}
+
+	/// Spawns a task resyncing the state of the room until it succeeds,
+	/// backing off between attempts.
+	fn start_resync(&self, room_id: OwnedRoomId) {
+		tokio::spawn(async move {
+			let partial_state = &services().rooms.partial_state;
+			let mut delay = RESYNC_RETRY_MIN;
+			while services().server.running() && partial_state.is_partial(&room_id).unwrap_or(false) {
+				if services()
+					.rooms
+					.state_cache
+					.local_users_in_room(&room_id)
+					.next()
+					.is_none()
+				{
+					info!("No local users left in {room_id}, stopping the resync of its state");
+					if let Err(e) = partial_state.finish(&room_id) {
+						warn!("Failed to stop the resync of {room_id}: {e}");
+					}
+					break;
+				}
+
+				match partial_state.resync(&room_id).await {
+					Ok(()) => break,
+					Err(e) => {
+						warn!(
+							"Failed to resync the state of {room_id}, retrying in {} seconds: {e}",
+							delay.as_secs()
+						);
+						tokio::time::sleep(delay).await;
+						delay = cmp::min(delay.saturating_mul(2), RESYNC_RETRY_MAX);
+					},
+				}
+			}
+		});
+	}
+
+	/// Fetches the full state at our join event from the servers in the room
+	/// and replaces the partial state with it.
+	async fn resync(&self, room_id: &RoomId) -> Result<()> {
+		let Some(partial_state) = self.db.partial_state(room_id)? else {
+			return Ok(());
+		};
+
+		let room_version_id = services().rooms.state.get_room_version(room_id)?;
+		for server in partial_state
+			.servers
+			.iter()
+			.filter(|server| !server_is_ours(server) && services().sending.may_federate_with(server, room_id))
+		{
+			debug!("Asking {server} for the full state of {room_id}");
+			let response = match services()
+				.sending
+				.send_federation_request(
+					server,
+					get_room_state::v1::Request {
+						room_id: room_id.to_owned(),
+						event_id: partial_state.join_event_id.clone(),
+					},
+				)
+				.await
+			{
+				Ok(response) => response,
+				Err(e) => {
+					debug!("Failed to fetch the full state of {room_id} from {server}: {e}");
+					continue;
+				},
+			};
+
+			match self
+				.apply_full_state(room_id, &room_version_id, &partial_state, response)
+				.await
+			{
+				Ok(()) => {
+					self.finish(room_id)?;
+					info!("Resynced the full state of {room_id} from {server}");
+					return Ok(());
+				},
+				Err(e) => warn!("Invalid full state of {room_id} from {server}: {e}"),
+			}
+		}
+
+		Err(Error::BadServerResponse("No server in the room returned its full state"))
+	}
+
+	/// Stores the state events of the `/state` response as outliers and sets
+	/// the room state to the full state at our join, keeping the state changes
+	/// which happened since.
+	async fn apply_full_state(
+		&self, room_id: &RoomId, room_version_id: &RoomVersionId, partial_state: &PartialState,
+		response: get_room_state::v1::Response,
+	) -> Result<()> {
+		let parse = |pdus: &[Box<serde_json::value::RawValue>]| {
+			pdus.iter()
+				.filter_map(|pdu| gen_event_id_canonical_json(pdu, room_version_id).ok())
+				.collect::<Vec<_>>()
+		};
+		let state_events = parse(&response.pdus);
+		let auth_events = parse(&response.auth_chain);
+
+		let pub_key_map = RwLock::new(BTreeMap::new());
+		services()
+			.rooms
+			.event_handler
+			.fetch_required_signing_keys(
+				state_events
+					.iter()
+					.chain(&auth_events)
+					.map(|(_, value)| value),
+				&pub_key_map,
+			)
+			.await?;
+
+		let mut state = HashMap::with_capacity(state_events.len());
+		{
+			let pub_key_map = pub_key_map.read().await;
+			for (is_state, (event_id, mut value)) in state_events
+				.into_iter()
+				.map(|event| (true, event))
+				.chain(auth_events.into_iter().map(|event| (false, event)))
+			{
+				if let Err(e) = services()
+					.rooms
+					.event_handler
+					.check_expired_signing_keys(&value)
+				{
+					warn!("Event {event_id} in the state of {room_id} failed verification: {e}");
+					continue;
+				}
+
+				if let Err(e) = ruma::signatures::verify_event(&*pub_key_map, &value, room_version_id) {
+					warn!("Event {event_id} in the state of {room_id} failed verification: {e}");
+					continue;
+				}
+
+				value.insert("event_id".to_owned(), CanonicalJsonValue::String(event_id.as_str().to_owned()));
+				services()
+					.rooms
+					.outlier
+					.add_pdu_outlier(&event_id, &value)?;
+
+				if is_state {
+					insert_state_event(&mut state, &event_id, value)?;
+				}
+			}
+		}
+
+		let create_event_id = services()
+			.rooms
+			.state_accessor
+			.room_state_get_id(room_id, &StateEventType::RoomCreate, "")?
+			.ok_or_else(|| Error::bad_database("Partial-state room has no create event."))?;
+		let create_shortstatekey = services()
+			.rooms
+			.short
+			.get_or_create_shortstatekey(&StateEventType::RoomCreate, "")?;
+		if state.get(&create_shortstatekey) != Some(&create_event_id) {
+			return Err(Error::BadServerResponse("Full state refers to a different create event."));
+		}
+
+		let mutex_state = Arc::clone(
+			services()
+				.globals
+				.roomid_mutex_state
+				.write()
+				.await
+				.entry(room_id.to_owned())
+				.or_default(),
+		);
+		let state_lock = mutex_state.lock().await;
+
+		// Events accepted meanwhile only had their own auth events vouching for the
+		// memberships we missed, so they are checked again with the full state
+		let rejected = self.recheck_accepted_events(room_id, room_version_id, &state)?;
+
+		// Keep what changed since our join, the state at the join itself was partial
+		let partial_at_join = match services()
+			.rooms
+			.state_accessor
+			.pdu_shortstatehash(&partial_state.join_event_id)?
+		{
+			Some(shortstatehash) => {
+				services()
+					.rooms
+					.state_accessor
+					.state_full_ids(shortstatehash)
+					.await?
+			},
+			None => HashMap::new(),
+		};
+		if let Some(current_shortstatehash) = services().rooms.state.get_room_shortstatehash(room_id)? {
+			for (shortstatekey, event_id) in services()
+				.rooms
+				.state_accessor
+				.state_full_ids(current_shortstatehash)
+				.await?
+			{
+				if partial_at_join.get(&shortstatekey) != Some(&event_id) && !rejected.contains(&event_id) {
+					state.insert(shortstatekey, event_id);
+				}
+			}
+		}
+
+		let (shortstatehash, new, removed) = services().rooms.state_compressor.save_state(
+			room_id,
+			Arc::new(
+				state
+					.into_iter()
+					.map(|(shortstatekey, event_id)| {
+						services()
+							.rooms
+							.state_compressor
+							.compress_state_event(shortstatekey, &event_id)
+					})
+					.collect::<Result<_>>()?,
+			),
+		)?;
+
+		services()
+			.rooms
+			.state
+			.force_state(room_id, shortstatehash, new, removed, &state_lock)
+			.await
+	}
+
+	/// Checks the events accepted while the room had partial state in the
+	/// order they were accepted, starting from the full state at our join.
+	/// Events failing the check now are soft failed, their IDs are returned to
+	/// keep them out of the room state.
+	fn recheck_accepted_events(
+		&self, room_id: &RoomId, room_version_id: &RoomVersionId, state_at_join: &HashMap<u64, Arc<EventId>>,
+	) -> Result<HashSet<Arc<EventId>>> {
+		let mut events = Vec::new();
+		for event_id in self.db.partial_state_events(room_id) {
+			let event_id = event_id?;
+			if let (Some(count), Some(pdu)) = (
+				services().rooms.timeline.get_pdu_count(&event_id)?,
+				services().rooms.timeline.get_pdu(&event_id)?,
+			) {
+				events.push((count, pdu));
+			}
+		}
+		events.sort_unstable_by_key(|(count, _)| *count);
+
+		let mut state = state_at_join.clone();
+		let mut rejected = HashSet::new();
+		for (_, pdu) in events {
+			let fetch_state = |kind: &StateEventType, state_key: &str| {
+				services()
+					.rooms
+					.short
+					.get_shortstatekey(&kind.to_string().into(), state_key)
+					.ok()
+					.flatten()
+					.and_then(|shortstatekey| state.get(&shortstatekey))
+					.and_then(|event_id| services().rooms.timeline.get_pdu(event_id).ok().flatten())
+			};
+
+			if !services()
+				.rooms
+				.event_handler
+				.auth_check(room_version_id, &pdu, fetch_state)
+				.unwrap_or(false)
+			{
+				warn!(
+					"Event {} accepted with the partial state of {room_id} fails auth with its full state",
+					pdu.event_id
+				);
+				services()
+					.rooms
+					.pdu_metadata
+					.mark_event_soft_failed(&pdu.event_id)?;
+				rejected.insert(Arc::clone(&pdu.event_id));
+				continue;
+			}
+
+			if let Some(state_key) = &pdu.state_key {
+				let shortstatekey = services()
+					.rooms
+					.short
+					.get_or_create_shortstatekey(&pdu.kind.to_string().into(), state_key)?;
+				state.insert(shortstatekey, Arc::clone(&pdu.event_id));
+			}
+		}
+
+		Ok(rejected)
+	}
+}
+
+/// Forgets a waiter for the full state of a room once it was the last one.
+fn remove_waiter(waiters: &mut HashMap<OwnedRoomId, Arc<Notify>>, room_id: &RoomId, notify: &Arc<Notify>) {
+	// One reference is held by the map, the other one by this waiter
+	if waiters
+		.get(room_id)
+		.is_some_and(|waiting| Arc::ptr_eq(waiting, notify) && Arc::strong_count(notify) == 2)
+	{
+		waiters.remove(room_id);
+	}
+}
+
+/// Adds a state event of the full state to the state map.
+fn insert_state_event(
+	state: &mut HashMap<u64, Arc<EventId>>, event_id: &EventId, value: CanonicalJsonObject,
+) -> Result<()> {
+	let pdu = PduEvent::from_id_val(event_id, value)
+		.map_err(|_| Error::BadServerResponse("Invalid PDU in the full state of a room."))?;
+
+	if let Some(state_key) = &pdu.state_key {
+		let shortstatekey = services()
+			.rooms
+			.short
+			.get_or_create_shortstatekey(&pdu.kind.to_string().into(), state_key)?;
+		state.insert(shortstatekey, pdu.event_id);
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{collections::HashMap, sync::Arc};
+
+	use ruma::room_id;
+	use tokio::sync::Notify;
+
+	use super::remove_waiter;
+
+	#[test]
+	fn waiters_are_removed_with_the_last_one() {
+		let room_id = room_id!("!partial:example.com");
+		let notify = Arc::new(Notify::new());
+		let mut waiters = HashMap::from([(room_id.to_owned(), Arc::clone(&notify))]);
+
+		let other_waiter = Arc::clone(&notify);
+		remove_waiter(&mut waiters, room_id, &notify);
+		assert!(waiters.contains_key(room_id));
+
+		drop(other_waiter);
+		remove_waiter(&mut waiters, room_id, &notify);
+		assert!(!waiters.contains_key(room_id));
+	}
+
+	#[test]
+	fn waiters_of_a_newer_resync_are_kept() {
+		let room_id = room_id!("!partial:example.com");
+		let notify = Arc::new(Notify::new());
+		let mut waiters = HashMap::from([(room_id.to_owned(), Arc::new(Notify::new()))]);
+
+		remove_waiter(&mut waiters, room_id, &notify);
+		assert!(waiters.contains_key(room_id));
+	}
+}
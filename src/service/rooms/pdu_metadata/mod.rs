@@ -104,8 +104,15 @@ impl Service {
 				let events_after: Vec<_> = events_after
 					.into_iter()
 					.rev() // relations are always most recent first
-					.map(|(_, pdu)| pdu.to_message_like_event())
-					.collect();
+					.map(|(_, pdu)| {
+						let mut pdu = pdu.clone();
+						services()
+							.rooms
+							.state_accessor
+							.redact_erased_for_user(sender_user, &mut pdu)?;
+						Ok(pdu.to_message_like_event())
+					})
+					.collect::<Result<_>>()?;
 
 				Ok(get_relating_events::v1::Response {
 					chunk: events_after,
@@ -149,8 +156,15 @@ impl Service {
 
 				let events_before: Vec<_> = events_before
 					.into_iter()
-					.map(|(_, pdu)| pdu.to_message_like_event())
-					.collect();
+					.map(|(_, pdu)| {
+						let mut pdu = pdu.clone();
+						services()
+							.rooms
+							.state_accessor
+							.redact_erased_for_user(sender_user, &mut pdu)?;
+						Ok(pdu.to_message_like_event())
+					})
+					.collect::<Result<_>>()?;
 
 				Ok(get_relating_events::v1::Response {
 					chunk: events_before,
@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+
+use ruma::{OwnedRoomId, OwnedServerName, RoomId, ServerName};
+use serde_json::value::RawValue as RawJsonValue;
+
+use crate::{services, utils, Error, KeyValueDatabase, Result};
+
+pub trait Data: Send + Sync {
+	/// Appends PDUs received from `origin` to the queues of their rooms.
+	fn queue_pdus(&self, origin: &ServerName, pdus: &[(&RoomId, &RawJsonValue)]) -> Result<()>;
+
+	/// Returns the oldest queued PDU of a room with its queue id and origin.
+	#[allow(clippy::type_complexity)]
+	fn next_queued_pdu(&self, room_id: &RoomId) -> Result<Option<(Vec<u8>, OwnedServerName, Box<RawJsonValue>)>>;
+
+	/// Removes a handled PDU from the queue.
+	fn remove_queued_pdu(&self, queue_id: &[u8]) -> Result<()>;
+
+	/// Returns the number of queued PDUs of every room with a non-empty queue.
+	fn queued_rooms(&self) -> Result<HashMap<OwnedRoomId, usize>>;
+}
+
+impl Data for KeyValueDatabase {
+	fn queue_pdus(&self, origin: &ServerName, pdus: &[(&RoomId, &RawJsonValue)]) -> Result<()> {
+		let mut batch = Vec::with_capacity(pdus.len());
+		for (room_id, pdu) in pdus {
+			let mut key = room_id.as_bytes().to_vec();
+			key.push(0xFF);
+			key.extend_from_slice(&services().globals.next_count()?.to_be_bytes());
+
+			let mut value = origin.as_bytes().to_vec();
+			value.push(0xFF);
+			value.extend_from_slice(pdu.get().as_bytes());
+
+			batch.push((key, value));
+		}
+
+		self.roomqueuedpduid_pdu
+			.insert_batch(&mut batch.into_iter())
+	}
+
+	fn next_queued_pdu(&self, room_id: &RoomId) -> Result<Option<(Vec<u8>, OwnedServerName, Box<RawJsonValue>)>> {
+		let mut prefix = room_id.as_bytes().to_vec();
+		prefix.push(0xFF);
+
+		self.roomqueuedpduid_pdu
+			.scan_prefix(prefix)
+			.next()
+			.map(|(key, value)| {
+				let mut parts = value.splitn(2, |&b| b == 0xFF);
+				let origin = parts
+					.next()
+					.and_then(|origin| utils::string_from_bytes(origin).ok())
+					.and_then(|origin| OwnedServerName::try_from(origin).ok())
+					.ok_or_else(|| Error::bad_database("Invalid origin in roomqueuedpduid_pdu."))?;
+				let pdu = parts
+					.next()
+					.and_then(|pdu| utils::string_from_bytes(pdu).ok())
+					.and_then(|pdu| RawJsonValue::from_string(pdu).ok())
+					.ok_or_else(|| Error::bad_database("Invalid PDU in roomqueuedpduid_pdu."))?;
+
+				Ok((key, origin, pdu))
+			})
+			.transpose()
+	}
+
+	fn remove_queued_pdu(&self, queue_id: &[u8]) -> Result<()> { self.roomqueuedpduid_pdu.remove(queue_id) }
+
+	fn queued_rooms(&self) -> Result<HashMap<OwnedRoomId, usize>> {
+		let mut rooms = HashMap::new();
+		for (key, _) in self.roomqueuedpduid_pdu.iter() {
+			let room_id = key
+				.split(|&b| b == 0xFF)
+				.next()
+				.and_then(|room_id| utils::string_from_bytes(room_id).ok())
+				.and_then(|room_id| OwnedRoomId::try_from(room_id).ok())
+				.ok_or_else(|| Error::bad_database("Invalid room id in roomqueuedpduid_pdu."))?;
+
+			let count = rooms.entry(room_id).or_insert(0_usize);
+			*count = count.saturating_add(1);
+		}
+
+		Ok(rooms)
+	}
+}
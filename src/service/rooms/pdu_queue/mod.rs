@@ -0,0 +1,373 @@
+mod data;
+
+use std::{
+	collections::{BTreeMap, HashMap, HashSet},
+	sync::{Arc, Mutex as StdMutex},
+	time::{Duration, Instant},
+};
+
+use data::Data;
+use ruma::{OwnedRoomId, RoomId, ServerName};
+use serde_json::value::RawValue as RawJsonValue;
+use tokio::sync::{RwLock, Semaphore};
+use tracing::{debug, error, warn};
+
+use crate::{debug_warn, rooms::event_handler::parse_incoming_pdu, services, Error, Result};
+
+/// Incoming PDUs of a room which were accepted but not handled yet, along
+/// with what happened to the ones before them.
+#[derive(Clone, Debug, Default)]
+pub struct RoomQueue {
+	/// Number of PDUs waiting to be handled.
+	pub queued: usize,
+	/// Number of PDUs handled since startup.
+	pub handled: u64,
+	/// Number of PDUs which could not be handled since startup.
+	pub failed: u64,
+	/// Number of PDUs refused since startup because the queue was full.
+	pub rejected: u64,
+	/// Time spent handling PDUs since startup.
+	pub busy: Duration,
+	/// Whether a task is currently draining the queue.
+	draining: bool,
+	/// Whether PDUs were queued while the draining task looked for the next
+	/// one, so it has to look again before it stops.
+	woken: bool,
+}
+
+pub struct Service {
+	pub db: Arc<dyn Data>,
+	pub capacity: usize,
+	pub workers: usize,
+	pub permits: Semaphore,
+	pub queues: StdMutex<HashMap<OwnedRoomId, RoomQueue>>,
+}
+
+impl Service {
+	/// Queues the PDUs of a transaction for their rooms. The PDUs of a room
+	/// are refused if its queue would grow past its capacity, the PDUs of the
+	/// other rooms are queued anyway. Returns the rooms whose PDUs were
+	/// refused.
+	pub fn queue_pdus(&self, origin: &ServerName, pdus: &[(&RoomId, &RawJsonValue)]) -> Result<HashSet<OwnedRoomId>> {
+		let mut incoming: HashMap<&RoomId, usize> = HashMap::new();
+		for (room_id, _) in pdus {
+			let count = incoming.entry(room_id).or_insert(0);
+			*count = count.saturating_add(1);
+		}
+
+		let refused = reserve(&mut self.queues.lock().expect("locked"), self.capacity, &incoming);
+		if !refused.is_empty() {
+			debug!(
+				"Refusing the PDUs of {} rooms from {origin}, their queues are full",
+				refused.len()
+			);
+		}
+
+		incoming.retain(|room_id, _| !refused.contains(*room_id));
+		let accepted = pdus
+			.iter()
+			.filter(|(room_id, _)| !refused.contains(*room_id))
+			.copied()
+			.collect::<Vec<_>>();
+
+		if let Err(e) = self.db.queue_pdus(origin, &accepted) {
+			release(&mut self.queues.lock().expect("locked"), &incoming);
+			return Err(e);
+		}
+
+		let start = wake(&mut self.queues.lock().expect("locked"), &incoming);
+		for room_id in start {
+			self.start_draining(room_id);
+		}
+
+		Ok(refused)
+	}
+
+	/// Snapshot of the queues of all rooms which received PDUs since startup.
+	pub fn queues(&self) -> BTreeMap<OwnedRoomId, RoomQueue> {
+		self.queues
+			.lock()
+			.expect("locked")
+			.iter()
+			.map(|(room_id, queue)| (room_id.clone(), queue.clone()))
+			.collect()
+	}
+
+	/// Number of workers currently handling a PDU.
+	pub fn busy_workers(&self) -> usize {
+		self.workers
+			.saturating_sub(self.permits.available_permits())
+	}
+
+	/// Resumes draining the queues persisted before a restart.
+	pub fn start_workers(&self) -> Result<()> {
+		let queued_rooms = self.db.queued_rooms()?;
+		let mut queues = self.queues.lock().expect("locked");
+		for (room_id, queued) in queued_rooms {
+			debug!("Resuming {queued} queued PDUs in {room_id}");
+			queues.insert(
+				room_id.clone(),
+				RoomQueue {
+					queued,
+					draining: true,
+					..RoomQueue::default()
+				},
+			);
+			self.start_draining(room_id);
+		}
+
+		Ok(())
+	}
+
+	/// Spawns a task handling the queued PDUs of a room one by one, in the
+	/// order they were received. Each PDU waits for a free worker, which
+	/// bounds how many rooms are handled at the same time.
+	fn start_draining(&self, room_id: OwnedRoomId) {
+		tokio::spawn(async move {
+			let pdu_queue = &services().rooms.pdu_queue;
+			while services().server.running() {
+				let (queue_id, origin, pdu) = match pdu_queue.db.next_queued_pdu(&room_id) {
+					Ok(Some(next)) => next,
+					Ok(None) => {
+						if stop_draining(
+							pdu_queue
+								.queues
+								.lock()
+								.expect("locked")
+								.entry(room_id.clone())
+								.or_default(),
+						) {
+							break;
+						}
+
+						tokio::task::yield_now().await;
+						continue;
+					},
+					Err(e) => {
+						error!("Failed to read the PDU queue of {room_id}: {e}");
+						let mut queues = pdu_queue.queues.lock().expect("locked");
+						queues.entry(room_id.clone()).or_default().draining = false;
+						break;
+					},
+				};
+
+				let start = Instant::now();
+				let result = {
+					let _permit = pdu_queue
+						.permits
+						.acquire()
+						.await
+						.expect("semaphore is never closed");
+					pdu_queue.handle_pdu(&origin, &room_id, &pdu).await
+				};
+
+				if let Err(e) = &result {
+					debug_warn!("Failed to handle queued PDU from {origin} in {room_id}: {e}");
+				}
+
+				let removed = pdu_queue.db.remove_queued_pdu(&queue_id);
+
+				let mut queues = pdu_queue.queues.lock().expect("locked");
+				let queue = queues.entry(room_id.clone()).or_default();
+				if let Err(e) = removed {
+					error!("Failed to remove a handled PDU from the queue of {room_id}: {e}");
+					queue.draining = false;
+					break;
+				}
+
+				dequeue(queue, &room_id, 1);
+				queue.busy = queue.busy.saturating_add(start.elapsed());
+				if result.is_ok() {
+					queue.handled = queue.handled.saturating_add(1);
+				} else {
+					queue.failed = queue.failed.saturating_add(1);
+				}
+			}
+		});
+	}
+
+	async fn handle_pdu(&self, origin: &ServerName, room_id: &RoomId, pdu: &RawJsonValue) -> Result<()> {
+		let (event_id, value, pdu_room_id) = parse_incoming_pdu(pdu)?;
+		if pdu_room_id != room_id {
+			return Err(Error::bad_database("Queued PDU belongs to another room."));
+		}
+
+		let pub_key_map = RwLock::new(BTreeMap::new());
+		services()
+			.rooms
+			.event_handler
+			.fetch_required_signing_keys([&value], &pub_key_map)
+			.await
+			.unwrap_or_else(|e| {
+				warn!("Could not fetch all signatures for PDU {event_id} from {origin}: {e}");
+			});
+
+		let mutex = Arc::clone(
+			services()
+				.globals
+				.roomid_mutex_federation
+				.write()
+				.await
+				.entry(room_id.to_owned())
+				.or_default(),
+		);
+		let mutex_lock = mutex.lock().await;
+
+		let start_time = Instant::now();
+		services()
+			.rooms
+			.event_handler
+			.handle_incoming_pdu(origin, room_id, &event_id, value, true, &pub_key_map)
+			.await?;
+		drop(mutex_lock);
+
+		debug!(
+			elapsed = ?start_time.elapsed(),
+			"Handled queued PDU {event_id}",
+		);
+
+		Ok(())
+	}
+}
+
+/// Counts incoming PDUs as queued before they are written to the queue, so a
+/// draining task can never handle more PDUs than are counted. Returns the
+/// rooms whose queue would grow past `capacity`, whose PDUs are not counted.
+fn reserve(
+	queues: &mut HashMap<OwnedRoomId, RoomQueue>, capacity: usize, incoming: &HashMap<&RoomId, usize>,
+) -> HashSet<OwnedRoomId> {
+	let mut refused = HashSet::new();
+	for (room_id, count) in incoming {
+		let queue = queues.entry((*room_id).to_owned()).or_default();
+		if queue.queued.saturating_add(*count) > capacity {
+			queue.rejected = queue.rejected.saturating_add(*count as u64);
+			refused.insert((*room_id).to_owned());
+		} else {
+			queue.queued = queue.queued.saturating_add(*count);
+		}
+	}
+
+	refused
+}
+
+/// Stops counting PDUs which could not be written to the queue.
+fn release(queues: &mut HashMap<OwnedRoomId, RoomQueue>, incoming: &HashMap<&RoomId, usize>) {
+	for (room_id, count) in incoming {
+		if let Some(queue) = queues.get_mut(*room_id) {
+			dequeue(queue, room_id, *count);
+		}
+	}
+}
+
+/// Stops counting PDUs which left the queue. They were counted before they
+/// were written to it, so the count going below zero is a bug.
+fn dequeue(queue: &mut RoomQueue, room_id: &RoomId, count: usize) {
+	match queue.queued.checked_sub(count) {
+		Some(queued) => queue.queued = queued,
+		None => {
+			error!("Queued PDU count of {room_id} went below zero, this is a bug");
+			queue.queued = 0;
+		},
+	}
+}
+
+/// Makes sure the queues PDUs were written to are drained. Returns the rooms
+/// which need a task to start draining their queue, the tasks of the others
+/// are woken up.
+fn wake(queues: &mut HashMap<OwnedRoomId, RoomQueue>, incoming: &HashMap<&RoomId, usize>) -> Vec<OwnedRoomId> {
+	let mut start = Vec::new();
+	for room_id in incoming.keys() {
+		let queue = queues.entry((*room_id).to_owned()).or_default();
+		if queue.draining {
+			queue.woken = true;
+		} else {
+			queue.draining = true;
+			start.push((*room_id).to_owned());
+		}
+	}
+
+	start
+}
+
+/// Called when a draining task found no queued PDU. It has to look again if
+/// PDUs were queued since it last looked, otherwise it stops.
+fn stop_draining(queue: &mut RoomQueue) -> bool {
+	if queue.woken {
+		queue.woken = false;
+		return false;
+	}
+
+	queue.draining = false;
+	true
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashMap;
+
+	use ruma::{room_id, OwnedRoomId, RoomId};
+
+	use super::{dequeue, release, reserve, stop_draining, wake, RoomQueue};
+
+	fn counts<'a>(rooms: &[(&'a RoomId, usize)]) -> HashMap<&'a RoomId, usize> { rooms.iter().copied().collect() }
+
+	#[test]
+	fn only_rooms_with_a_full_queue_are_refused() {
+		let full = room_id!("!full:example.com");
+		let other = room_id!("!other:example.com");
+		let mut queues = HashMap::<OwnedRoomId, RoomQueue>::new();
+
+		let incoming = counts(&[(full, 3), (other, 1)]);
+		let refused = reserve(&mut queues, 2, &incoming);
+
+		assert!(refused.contains(full));
+		assert!(!refused.contains(other));
+		assert_eq!(queues[full].rejected, 3);
+		assert_eq!(queues[full].queued, 0);
+		assert_eq!(queues[other].queued, 1);
+	}
+
+	#[test]
+	fn pdus_are_counted_before_they_are_written() {
+		let room = room_id!("!room:example.com");
+		let mut queues = HashMap::<OwnedRoomId, RoomQueue>::new();
+
+		let incoming = counts(&[(room, 2)]);
+		assert!(reserve(&mut queues, 3, &incoming).is_empty());
+		assert!(reserve(&mut queues, 3, &incoming).contains(room));
+
+		// the write failed
+		release(&mut queues, &incoming);
+		assert_eq!(queues[room].queued, 0);
+
+		// a draining task handles a PDU before the transaction wakes it up
+		assert!(reserve(&mut queues, 3, &incoming).is_empty());
+		let queue = queues.get_mut(room).expect("queue exists");
+		dequeue(queue, room, 1);
+		assert_eq!(queue.queued, 1);
+
+		// a bug is logged instead of wrapping around
+		dequeue(queue, room, 2);
+		assert_eq!(queue.queued, 0);
+	}
+
+	#[test]
+	fn draining_starts_once_and_stops_when_empty() {
+		let room = room_id!("!room:example.com");
+		let mut queues = HashMap::<OwnedRoomId, RoomQueue>::new();
+
+		let incoming = counts(&[(room, 1)]);
+		assert_eq!(wake(&mut queues, &incoming), vec![room.to_owned()]);
+		assert!(wake(&mut queues, &incoming).is_empty());
+
+		// PDUs were queued after the task looked for the next one
+		let queue = queues.get_mut(room).expect("queue exists");
+		assert!(!stop_draining(queue));
+		assert!(queue.draining);
+
+		// it only looks again once, even if the PDUs were already handled
+		assert!(stop_draining(queue));
+		assert!(!queue.draining);
+		assert_eq!(wake(&mut queues, &incoming), vec![room.to_owned()]);
+	}
+}
@@ -7,6 +7,7 @@ use std::{
 use data::Data;
 use lru_cache::LruCache;
 use ruma::{
+	canonical_json::redact,
 	events::{
 		room::{
 			avatar::RoomAvatarEventContent,
@@ -16,7 +17,7 @@ use ruma::{
 		},
 		StateEventType,
 	},
-	EventId, OwnedServerName, OwnedUserId, RoomId, ServerName, UserId,
+	CanonicalJsonObject, EventId, OwnedServerName, OwnedUserId, RoomId, ServerName, UserId,
 };
 use serde_json::value::to_raw_value;
 use tokio::sync::MutexGuard;
@@ -207,6 +208,59 @@ impl Service {
 		Ok(visibility)
 	}
 
+	/// Redacts an event of an erased user for a user that was not joined to
+	/// the room when it was sent.
+	pub fn redact_erased_for_user(&self, user_id: &UserId, pdu: &mut PduEvent) -> Result<()> {
+		if pdu.sender == user_id || !services().users.is_erased(&pdu.sender)? {
+			return Ok(());
+		}
+
+		if let Some(shortstatehash) = self.pdu_shortstatehash(&pdu.event_id)? {
+			if self.user_was_joined(shortstatehash, user_id) {
+				return Ok(());
+			}
+		}
+
+		pdu.redact_content(&services().rooms.state.get_room_version(&pdu.room_id)?)
+	}
+
+	/// Redacts the federation form of an event of an erased user for a server
+	/// that had no user joined to the room when it was sent. Servers that
+	/// already received the event get it unchanged, including those whose
+	/// users left the room since.
+	pub fn redact_erased_for_server(
+		&self, origin: &ServerName, room_id: &RoomId, event_id: &EventId, pdu_json: CanonicalJsonObject,
+	) -> Result<CanonicalJsonObject> {
+		let Some(sender) = pdu_json
+			.get("sender")
+			.and_then(|sender| sender.as_str())
+			.and_then(|sender| <&UserId>::try_from(sender).ok())
+		else {
+			return Ok(pdu_json);
+		};
+
+		if !services().users.is_erased(sender)? {
+			return Ok(pdu_json);
+		}
+
+		if let Some(shortstatehash) = self.pdu_shortstatehash(event_id)? {
+			let state_cache = &services().rooms.state_cache;
+			if state_cache
+				.room_members(room_id)
+				.chain(state_cache.room_useroncejoined(room_id))
+				.filter_map(Result::ok)
+				.filter(|member| member.server_name() == origin)
+				.any(|member| self.user_was_joined(shortstatehash, &member))
+			{
+				return Ok(pdu_json);
+			}
+		}
+
+		let room_version_id = services().rooms.state.get_room_version(room_id)?;
+		redact(pdu_json, &room_version_id, None)
+			.map_err(|e| Error::Redaction(services().globals.server_name().to_owned(), e))
+	}
+
 	/// Whether a user is allowed to see an event, based on
 	/// the room's history_visibility at that event's state.
 	#[tracing::instrument(skip(self, user_id, room_id))]
@@ -25,6 +25,9 @@ pub trait Data: Send + Sync {
 		&self, user_id: &UserId, room_id: &RoomId, last_state: Option<Vec<Raw<AnyStrippedStateEvent>>>,
 		invite_via: Option<Vec<OwnedServerName>>,
 	) -> Result<()>;
+	fn mark_as_knocked(
+		&self, user_id: &UserId, room_id: &RoomId, last_state: Option<Vec<Raw<AnyStrippedStateEvent>>>,
+	) -> Result<()>;
 	fn mark_as_left(&self, user_id: &UserId, room_id: &RoomId) -> Result<()>;
 
 	fn update_joined_count(&self, room_id: &RoomId) -> Result<()>;
@@ -69,6 +72,8 @@ pub trait Data: Send + Sync {
 
 	fn get_invite_count(&self, room_id: &RoomId, user_id: &UserId) -> Result<Option<u64>>;
 
+	fn get_knock_count(&self, room_id: &RoomId, user_id: &UserId) -> Result<Option<u64>>;
+
 	fn get_left_count(&self, room_id: &RoomId, user_id: &UserId) -> Result<Option<u64>>;
 
 	/// Returns an iterator over all rooms this user joined.
@@ -79,6 +84,11 @@ pub trait Data: Send + Sync {
 
 	fn invite_state(&self, user_id: &UserId, room_id: &RoomId) -> Result<Option<Vec<Raw<AnyStrippedStateEvent>>>>;
 
+	/// Returns an iterator over all rooms a user knocked on.
+	fn rooms_knocked<'a>(&'a self, user_id: &UserId) -> StrippedStateEventIter<'a>;
+
+	fn knock_state(&self, user_id: &UserId, room_id: &RoomId) -> Result<Option<Vec<Raw<AnyStrippedStateEvent>>>>;
+
 	fn left_state(&self, user_id: &UserId, room_id: &RoomId) -> Result<Option<Vec<Raw<AnyStrippedStateEvent>>>>;
 
 	/// Returns an iterator over all rooms a user left.
@@ -90,6 +100,8 @@ pub trait Data: Send + Sync {
 
 	fn is_invited(&self, user_id: &UserId, room_id: &RoomId) -> Result<bool>;
 
+	fn is_knocked(&self, user_id: &UserId, room_id: &RoomId) -> Result<bool>;
+
 	fn is_left(&self, user_id: &UserId, room_id: &RoomId) -> Result<bool>;
 
 	/// Gets the servers to either accept or decline invites via for a given
@@ -129,6 +141,8 @@ impl Data for KeyValueDatabase {
 		self.roomuserid_invitecount.remove(&roomuser_id)?;
 		self.userroomid_leftstate.remove(&userroom_id)?;
 		self.roomuserid_leftcount.remove(&roomuser_id)?;
+		self.userroomid_knockedstate.remove(&userroom_id)?;
+		self.roomuserid_knockedcount.remove(&roomuser_id)?;
 
 		self.roomid_inviteviaservers.remove(&roomid)?;
 
@@ -157,6 +171,8 @@ impl Data for KeyValueDatabase {
 		self.roomuserid_joined.remove(&roomuser_id)?;
 		self.userroomid_leftstate.remove(&userroom_id)?;
 		self.roomuserid_leftcount.remove(&roomuser_id)?;
+		self.userroomid_knockedstate.remove(&userroom_id)?;
+		self.roomuserid_knockedcount.remove(&roomuser_id)?;
 
 		if let Some(servers) = invite_via {
 			let mut prev_servers = self.servers_invite_via(room_id)?.unwrap_or(Vec::new());
@@ -177,6 +193,33 @@ impl Data for KeyValueDatabase {
 		Ok(())
 	}
 
+	fn mark_as_knocked(
+		&self, user_id: &UserId, room_id: &RoomId, last_state: Option<Vec<Raw<AnyStrippedStateEvent>>>,
+	) -> Result<()> {
+		let mut roomuser_id = room_id.as_bytes().to_vec();
+		roomuser_id.push(0xFF);
+		roomuser_id.extend_from_slice(user_id.as_bytes());
+
+		let mut userroom_id = user_id.as_bytes().to_vec();
+		userroom_id.push(0xFF);
+		userroom_id.extend_from_slice(room_id.as_bytes());
+
+		self.userroomid_knockedstate.insert(
+			&userroom_id,
+			&serde_json::to_vec(&last_state.unwrap_or_default()).expect("state to bytes always works"),
+		)?;
+		self.roomuserid_knockedcount
+			.insert(&roomuser_id, &services().globals.next_count()?.to_be_bytes())?;
+		self.userroomid_joined.remove(&userroom_id)?;
+		self.roomuserid_joined.remove(&roomuser_id)?;
+		self.userroomid_invitestate.remove(&userroom_id)?;
+		self.roomuserid_invitecount.remove(&roomuser_id)?;
+		self.userroomid_leftstate.remove(&userroom_id)?;
+		self.roomuserid_leftcount.remove(&roomuser_id)?;
+
+		Ok(())
+	}
+
 	fn mark_as_left(&self, user_id: &UserId, room_id: &RoomId) -> Result<()> {
 		let roomid = room_id.as_bytes().to_vec();
 
@@ -198,6 +241,8 @@ impl Data for KeyValueDatabase {
 		self.roomuserid_joined.remove(&roomuser_id)?;
 		self.userroomid_invitestate.remove(&userroom_id)?;
 		self.roomuserid_invitecount.remove(&roomuser_id)?;
+		self.userroomid_knockedstate.remove(&userroom_id)?;
+		self.roomuserid_knockedcount.remove(&roomuser_id)?;
 
 		self.roomid_inviteviaservers.remove(&roomid)?;
 
@@ -480,6 +525,18 @@ impl Data for KeyValueDatabase {
 			})
 	}
 
+	#[tracing::instrument(skip(self))]
+	fn get_knock_count(&self, room_id: &RoomId, user_id: &UserId) -> Result<Option<u64>> {
+		let mut key = room_id.as_bytes().to_vec();
+		key.push(0xFF);
+		key.extend_from_slice(user_id.as_bytes());
+
+		self.roomuserid_knockedcount
+			.get(&key)?
+			.map(|bytes| utils::u64_from_bytes(&bytes).map_err(|_| Error::bad_database("Invalid knockedcount in db.")))
+			.transpose()
+	}
+
 	#[tracing::instrument(skip(self))]
 	fn get_left_count(&self, room_id: &RoomId, user_id: &UserId) -> Result<Option<u64>> {
 		let mut key = room_id.as_bytes().to_vec();
@@ -557,6 +614,49 @@ impl Data for KeyValueDatabase {
 			.transpose()
 	}
 
+	/// Returns an iterator over all rooms a user knocked on.
+	#[tracing::instrument(skip(self))]
+	fn rooms_knocked<'a>(&'a self, user_id: &UserId) -> StrippedStateEventIter<'a> {
+		let mut prefix = user_id.as_bytes().to_vec();
+		prefix.push(0xFF);
+
+		Box::new(
+			self.userroomid_knockedstate
+				.scan_prefix(prefix)
+				.map(|(key, state)| {
+					let room_id = RoomId::parse(
+						utils::string_from_bytes(
+							key.rsplit(|&b| b == 0xFF)
+								.next()
+								.expect("rsplit always returns an element"),
+						)
+						.map_err(|_| Error::bad_database("Room ID in userroomid_knockedstate is invalid unicode."))?,
+					)
+					.map_err(|_| Error::bad_database("Room ID in userroomid_knockedstate is invalid."))?;
+
+					let state = serde_json::from_slice(&state)
+						.map_err(|_| Error::bad_database("Invalid state in userroomid_knockedstate."))?;
+
+					Ok((room_id, state))
+				}),
+		)
+	}
+
+	#[tracing::instrument(skip(self))]
+	fn knock_state(&self, user_id: &UserId, room_id: &RoomId) -> Result<Option<Vec<Raw<AnyStrippedStateEvent>>>> {
+		let mut key = user_id.as_bytes().to_vec();
+		key.push(0xFF);
+		key.extend_from_slice(room_id.as_bytes());
+
+		self.userroomid_knockedstate
+			.get(&key)?
+			.map(|state| {
+				serde_json::from_slice(&state)
+					.map_err(|_| Error::bad_database("Invalid state in userroomid_knockedstate."))
+			})
+			.transpose()
+	}
+
 	#[tracing::instrument(skip(self))]
 	fn left_state(&self, user_id: &UserId, room_id: &RoomId) -> Result<Option<Vec<Raw<AnyStrippedStateEvent>>>> {
 		let mut key = user_id.as_bytes().to_vec();
@@ -629,6 +729,15 @@ impl Data for KeyValueDatabase {
 		Ok(self.userroomid_invitestate.get(&userroom_id)?.is_some())
 	}
 
+	#[tracing::instrument(skip(self))]
+	fn is_knocked(&self, user_id: &UserId, room_id: &RoomId) -> Result<bool> {
+		let mut userroom_id = user_id.as_bytes().to_vec();
+		userroom_id.push(0xFF);
+		userroom_id.extend_from_slice(room_id.as_bytes());
+
+		Ok(self.userroomid_knockedstate.get(&userroom_id)?.is_some())
+	}
+
 	#[tracing::instrument(skip(self))]
 	fn is_left(&self, user_id: &UserId, room_id: &RoomId) -> Result<bool> {
 		let mut userroom_id = user_id.as_bytes().to_vec();
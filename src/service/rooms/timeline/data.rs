@@ -1,6 +1,9 @@
 use std::{collections::hash_map, mem::size_of, sync::Arc};
 
-use ruma::{api::client::error::ErrorKind, CanonicalJsonObject, EventId, OwnedUserId, RoomId, UserId};
+use ruma::{
+	api::{client::error::ErrorKind, Direction},
+	CanonicalJsonObject, CanonicalJsonValue, EventId, OwnedUserId, RoomId, UserId,
+};
 use tracing::error;
 
 use super::PduCount;
@@ -63,6 +66,11 @@ pub trait Data: Send + Sync {
 		&'a self, user_id: &UserId, room_id: &RoomId, from: PduCount,
 	) -> Result<Box<dyn Iterator<Item = Result<(PduCount, PduEvent)>> + 'a>>;
 
+	/// Returns the `origin_server_ts` and pdu id of the event in a room
+	/// closest to `ts` in the given direction. An event sent at exactly `ts`
+	/// matches in both directions.
+	fn pdu_id_by_timestamp(&self, room_id: &RoomId, ts: u64, dir: Direction) -> Result<Option<(u64, Vec<u8>)>>;
+
 	fn increment_notification_counts(
 		&self, room_id: &RoomId, notifies: Vec<OwnedUserId>, highlights: Vec<OwnedUserId>,
 	) -> Result<()>;
@@ -204,6 +212,9 @@ impl Data for KeyValueDatabase {
 		self.eventid_pduid.insert(pdu.event_id.as_bytes(), pdu_id)?;
 		self.eventid_outlierpdu.remove(pdu.event_id.as_bytes())?;
 
+		self.roomtimestamp_pduid
+			.insert(&timestamp_key(pdu_id, pdu.origin_server_ts.into()), pdu_id)?;
+
 		Ok(())
 	}
 
@@ -216,18 +227,40 @@ impl Data for KeyValueDatabase {
 		self.eventid_pduid.insert(event_id.as_bytes(), pdu_id)?;
 		self.eventid_outlierpdu.remove(event_id.as_bytes())?;
 
+		if let Some(ts) = origin_server_ts(json) {
+			self.roomtimestamp_pduid
+				.insert(&timestamp_key(pdu_id, ts), pdu_id)?;
+		}
+
 		Ok(())
 	}
 
 	/// Removes a pdu and creates a new one with the same id.
 	fn replace_pdu(&self, pdu_id: &[u8], pdu_json: &CanonicalJsonObject, _pdu: &PduEvent) -> Result<()> {
-		if self.pduid_pdu.get(pdu_id)?.is_some() {
-			self.pduid_pdu.insert(
-				pdu_id,
-				&serde_json::to_vec(pdu_json).expect("CanonicalJsonObject is always a valid"),
-			)?;
-		} else {
+		let Some(old_pdu) = self.pduid_pdu.get(pdu_id)? else {
 			return Err(Error::BadRequest(ErrorKind::NotFound, "PDU does not exist."));
+		};
+
+		self.pduid_pdu.insert(
+			pdu_id,
+			&serde_json::to_vec(pdu_json).expect("CanonicalJsonObject is always a valid"),
+		)?;
+
+		// Keep the timestamp index pointing at the pdu if its timestamp changed
+		let old_ts = serde_json::from_slice::<CanonicalJsonObject>(&old_pdu)
+			.ok()
+			.as_ref()
+			.and_then(origin_server_ts);
+		let new_ts = origin_server_ts(pdu_json);
+		if old_ts != new_ts {
+			if let Some(old_ts) = old_ts {
+				self.roomtimestamp_pduid
+					.remove(&timestamp_key(pdu_id, old_ts))?;
+			}
+			if let Some(new_ts) = new_ts {
+				self.roomtimestamp_pduid
+					.insert(&timestamp_key(pdu_id, new_ts), pdu_id)?;
+			}
 		}
 
 		Ok(())
@@ -284,6 +317,43 @@ impl Data for KeyValueDatabase {
 		))
 	}
 
+	fn pdu_id_by_timestamp(&self, room_id: &RoomId, ts: u64, dir: Direction) -> Result<Option<(u64, Vec<u8>)>> {
+		let prefix = services()
+			.rooms
+			.short
+			.get_shortroomid(room_id)?
+			.ok_or_else(|| Error::bad_database("Looked for bad shortroomid in timeline"))?
+			.to_be_bytes()
+			.to_vec();
+
+		let mut current = prefix.clone();
+		current.extend_from_slice(&ts.to_be_bytes());
+
+		let iter = match dir {
+			Direction::Forward => self.roomtimestamp_pduid.iter_from(&current, false),
+			Direction::Backward => {
+				// Skip past every pdu id sent at `ts`, the longest ones are backfilled
+				current.extend_from_slice(&[0xFF; 2 * size_of::<u64>()]);
+				self.roomtimestamp_pduid.iter_from(&current, true)
+			},
+		}
+		.take_while(move |(k, _)| k.starts_with(&prefix));
+
+		for (key, pdu_id) in iter {
+			if self.pduid_pdu.get(&pdu_id)?.is_none() {
+				// The pdu was removed after it was indexed
+				self.roomtimestamp_pduid.remove(&key)?;
+				continue;
+			}
+
+			let ts = utils::u64_from_bytes(&key[size_of::<u64>()..2 * size_of::<u64>()])
+				.map_err(|_| Error::bad_database("Invalid timestamp in roomtimestamp_pduid."))?;
+			return Ok(Some((ts, pdu_id)));
+		}
+
+		Ok(None)
+	}
+
 	fn increment_notification_counts(
 		&self, room_id: &RoomId, notifies: Vec<OwnedUserId>, highlights: Vec<OwnedUserId>,
 	) -> Result<()> {
@@ -324,6 +394,25 @@ fn pdu_count(pdu_id: &[u8]) -> Result<PduCount> {
 	}
 }
 
+/// Inserts the timestamp between the short room id and the count of a pdu id,
+/// so the events of a room are ordered by their `origin_server_ts`.
+pub(crate) fn timestamp_key(pdu_id: &[u8], ts: u64) -> Vec<u8> {
+	let (shortroomid, count) = pdu_id.split_at(size_of::<u64>());
+
+	let mut key = shortroomid.to_vec();
+	key.extend_from_slice(&ts.to_be_bytes());
+	key.extend_from_slice(count);
+	key
+}
+
+/// The `origin_server_ts` of a pdu, which it is indexed by in
+/// `roomtimestamp_pduid`.
+fn origin_server_ts(json: &CanonicalJsonObject) -> Option<u64> {
+	json.get("origin_server_ts")
+		.and_then(CanonicalJsonValue::as_integer)
+		.and_then(|ts| u64::try_from(ts).ok())
+}
+
 fn count_to_id(room_id: &RoomId, count: PduCount, offset: u64, subtract: bool) -> Result<(Vec<u8>, Vec<u8>)> {
 	let prefix = services()
 		.rooms
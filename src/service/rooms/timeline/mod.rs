@@ -5,11 +5,12 @@ use std::{
 	sync::Arc,
 };
 
+pub(crate) use data::timestamp_key;
 use data::Data;
 use itertools::Itertools;
 use rand::prelude::SliceRandom;
 use ruma::{
-	api::{client::error::ErrorKind, federation},
+	api::{client::error::ErrorKind, federation, Direction},
 	canonical_json::to_canonical_value,
 	events::{
 		push_rules::PushRulesEvent,
@@ -24,9 +25,9 @@ use ruma::{
 	},
 	push::{Action, Ruleset, Tweak},
 	serde::Base64,
-	state_res::{self, Event, RoomVersion},
-	uint, user_id, CanonicalJsonObject, CanonicalJsonValue, EventId, OwnedEventId, OwnedRoomId, OwnedServerName,
-	RoomId, RoomVersionId, ServerName, UserId,
+	state_res::Event,
+	uint, user_id, CanonicalJsonObject, CanonicalJsonValue, EventId, MilliSecondsSinceUnixEpoch, OwnedEventId,
+	OwnedRoomId, OwnedServerName, RoomId, RoomVersionId, ServerName, UserId,
 };
 use serde::Deserialize;
 use serde_json::value::{to_raw_value, RawValue as RawJsonValue};
@@ -434,7 +435,7 @@ impl Service {
 					})?;
 
 					let invite_state = match content.membership {
-						MembershipState::Invite => {
+						MembershipState::Invite | MembershipState::Knock => {
 							let state = services().rooms.state.calculate_invite_state(pdu)?;
 							Some(state)
 						},
@@ -627,8 +628,6 @@ impl Service {
 				}
 			})?;
 
-		let room_version = RoomVersion::new(&room_version_id).expect("room version is supported");
-
 		let auth_events =
 			services()
 				.rooms
@@ -692,16 +691,16 @@ impl Service {
 			signatures: None,
 		};
 
-		let auth_check = state_res::auth_check(
-			&room_version,
-			&pdu,
-			None::<PduEvent>, // TODO: third_party_invite
-			|k, s| auth_events.get(&(k.clone(), s.to_owned())),
-		)
-		.map_err(|e| {
-			error!("Auth check failed: {:?}", e);
-			Error::BadRequest(ErrorKind::forbidden(), "Auth check failed.")
-		})?;
+		let auth_check = services()
+			.rooms
+			.event_handler
+			.auth_check(&room_version_id, &pdu, |k, s| {
+				auth_events.get(&(k.clone(), s.to_owned())).cloned()
+			})
+			.map_err(|e| {
+				error!("Auth check failed: {:?}", e);
+				e
+			})?;
 
 		if !auth_check {
 			return Err(Error::BadRequest(ErrorKind::forbidden(), "Event is not authorized."));
@@ -729,7 +728,7 @@ impl Service {
 
 		match ruma::signatures::hash_and_sign_event(
 			services().globals.server_name().as_str(),
-			services().globals.keypair(),
+			&*services().globals.keypair(),
 			&mut pdu_json,
 			&room_version_id,
 		) {
@@ -776,6 +775,15 @@ impl Service {
 		room_id: &RoomId,
 		state_lock: &MutexGuard<'_, ()>, // Take mutex guard to make sure users get the room state mutex
 	) -> Result<Arc<EventId>> {
+		if self.drops_shadow_banned_event(&pdu_builder, sender, room_id)? {
+			// Pretend the event was sent, it is neither stored nor federated
+			debug!(
+				"Dropping {} event of shadow-banned user {sender} in {room_id}",
+				pdu_builder.event_type
+			);
+			return Ok(EventId::parse_arc(format!("${}", utils::random_string(43))).expect("random event id is valid"));
+		}
+
 		let (pdu, pdu_json) = self.create_hash_and_sign_event(pdu_builder, sender, room_id, state_lock)?;
 		if let Some(admin_room) = service::admin::Service::get_admin_room().await? {
 			if admin_room == room_id {
@@ -944,6 +952,39 @@ impl Service {
 		Ok(Some(pdu_id))
 	}
 
+	/// Whether an event of a shadow-banned sender has to be dropped. Events
+	/// nobody else can see, like those in a room the sender is alone in (e.g.
+	/// while creating a room), and leaving rooms they are actually in are kept
+	/// so the sender does not notice the ban.
+	fn drops_shadow_banned_event(&self, pdu_builder: &PduBuilder, sender: &UserId, room_id: &RoomId) -> Result<bool> {
+		if !services().users.is_shadow_banned(sender)? {
+			return Ok(false);
+		}
+
+		if pdu_builder.event_type == TimelineEventType::RoomMember {
+			// invites, kicks and bans of other users
+			if pdu_builder.state_key.as_deref() != Some(sender.as_str()) {
+				return Ok(true);
+			}
+
+			let content = serde_json::from_str::<RoomMemberEventContent>(pdu_builder.content.get())
+				.map_err(|_| Error::BadRequest(ErrorKind::BadJson, "Invalid member event content."))?;
+			if content.membership == MembershipState::Leave
+				&& (services().rooms.state_cache.is_joined(sender, room_id)?
+					|| services().rooms.state_cache.is_invited(sender, room_id)?)
+			{
+				return Ok(false);
+			}
+		}
+
+		Ok(!services()
+			.rooms
+			.state_cache
+			.room_members(room_id)
+			.filter_map(Result::ok)
+			.all(|member| member == sender))
+	}
+
 	/// Returns an iterator over all PDUs in a room.
 	pub fn all_pdus<'a>(
 		&'a self, user_id: &UserId, room_id: &RoomId,
@@ -970,6 +1011,180 @@ impl Service {
 		self.db.pdus_after(user_id, room_id, from)
 	}
 
+	/// Returns the event in a room closest to `ts` in the given direction
+	/// which is in our local timeline.
+	pub fn pdu_by_timestamp(
+		&self, room_id: &RoomId, ts: MilliSecondsSinceUnixEpoch, dir: Direction,
+	) -> Result<Option<PduEvent>> {
+		self.db
+			.pdu_id_by_timestamp(room_id, ts.get().into(), dir)?
+			.map(|(_, pdu_id)| {
+				self.get_pdu_from_id(&pdu_id)?
+					.ok_or_else(|| Error::bad_database("Invalid pduid in roomtimestamp_pduid."))
+			})
+			.transpose()
+	}
+
+	/// Finds the event in a room closest to `ts` in the given direction. When
+	/// our timeline has a gap around `ts`, the other servers in the room are
+	/// asked and their event is backfilled if it is closer than ours.
+	#[tracing::instrument(skip(self))]
+	pub async fn timestamp_to_event(
+		&self, room_id: &RoomId, ts: MilliSecondsSinceUnixEpoch, dir: Direction,
+	) -> Result<Option<(OwnedEventId, MilliSecondsSinceUnixEpoch)>> {
+		let local = self
+			.pdu_by_timestamp(room_id, ts, dir)?
+			.map(|pdu| (pdu.event_id, MilliSecondsSinceUnixEpoch(pdu.origin_server_ts)));
+
+		if let Some((event_id, _)) = &local {
+			if !self.timestamp_gap(room_id, ts, dir, event_id)? {
+				return Ok(local);
+			}
+		}
+
+		let distance = |found: MilliSecondsSinceUnixEpoch| u64::from(found.get()).abs_diff(ts.get().into());
+		match self.remote_timestamp_to_event(room_id, ts, dir).await {
+			Some(remote)
+				if local
+					.as_ref()
+					.map_or(true, |local| distance(remote.1) < distance(local.1)) =>
+			{
+				Ok(Some(remote))
+			},
+			_ => Ok(local),
+		}
+	}
+
+	/// Whether the events between `ts` and the closest local event to it may
+	/// be missing, because the timeline next to that event has unknown
+	/// `prev_events`.
+	fn timestamp_gap(
+		&self, room_id: &RoomId, ts: MilliSecondsSinceUnixEpoch, dir: Direction, closest: &EventId,
+	) -> Result<bool> {
+		let boundary = match dir {
+			Direction::Forward => self.get_pdu(closest)?,
+			Direction::Backward => self
+				.pdu_by_timestamp(
+					room_id,
+					MilliSecondsSinceUnixEpoch(ts.get().saturating_add(uint!(1))),
+					Direction::Forward,
+				)?
+				.map(Arc::new),
+		};
+
+		let Some(boundary) = boundary else {
+			return Ok(false);
+		};
+
+		for prev_event in &boundary.prev_events {
+			if self.get_pdu_id(prev_event)?.is_none() {
+				return Ok(true);
+			}
+		}
+
+		Ok(false)
+	}
+
+	async fn remote_timestamp_to_event(
+		&self, room_id: &RoomId, ts: MilliSecondsSinceUnixEpoch, dir: Direction,
+	) -> Option<(OwnedEventId, MilliSecondsSinceUnixEpoch)> {
+		let mut servers: Vec<OwnedServerName> = services()
+			.rooms
+			.state_cache
+			.room_servers(room_id)
+			.filter_map(Result::ok)
+			.filter(|server_name| {
+				!server_is_ours(server_name) && services().sending.may_federate_with(server_name, room_id)
+			})
+			.collect();
+
+		servers.shuffle(&mut rand::thread_rng());
+
+		for server in servers.into_iter().take(5) {
+			debug!("Asking {server} for the event closest to {ts:?} in room {room_id}");
+			let response = match services()
+				.sending
+				.send_federation_request(
+					&server,
+					federation::event::get_event_by_timestamp::v1::Request {
+						room_id: room_id.to_owned(),
+						ts,
+						dir,
+					},
+				)
+				.await
+			{
+				Ok(response) => response,
+				Err(e) => {
+					debug!("{server} failed to find the event closest to {ts:?} in room {room_id}: {e}");
+					continue;
+				},
+			};
+
+			if !in_direction(response.origin_server_ts, ts, dir) {
+				debug!("{server} returned {} which is not {dir:?} of {ts:?}", response.event_id);
+				continue;
+			}
+
+			let pdu = match self
+				.fetch_timestamp_event(&server, room_id, &response.event_id)
+				.await
+			{
+				Ok(pdu) => pdu,
+				Err(e) => {
+					warn!("Failed to backfill {} from {server}: {e}", response.event_id);
+					continue;
+				},
+			};
+
+			// The server may have lied about the timestamp of the event
+			let origin_server_ts = MilliSecondsSinceUnixEpoch(pdu.origin_server_ts);
+			if !in_direction(origin_server_ts, ts, dir) {
+				debug!(
+					"{} from {server} was sent at {origin_server_ts:?}, not {dir:?} of {ts:?}",
+					pdu.event_id
+				);
+				continue;
+			}
+
+			return Some((response.event_id, origin_server_ts));
+		}
+
+		None
+	}
+
+	/// Backfills an event another server found by its timestamp, unless we
+	/// already have it in our timeline.
+	async fn fetch_timestamp_event(
+		&self, origin: &ServerName, room_id: &RoomId, event_id: &EventId,
+	) -> Result<PduEvent> {
+		if let Some(pdu) = self.get_non_outlier_pdu(event_id)? {
+			return Ok(pdu);
+		}
+
+		let response = services()
+			.sending
+			.send_federation_request(
+				origin,
+				federation::event::get_event::v1::Request {
+					event_id: event_id.to_owned(),
+				},
+			)
+			.await?;
+
+		let (pdu_event_id, _, pdu_room_id) = parse_incoming_pdu(&response.pdu)?;
+		if pdu_event_id != event_id || pdu_room_id != room_id {
+			return Err(Error::BadServerResponse("Server returned an event of another room or id."));
+		}
+
+		let pub_key_map = RwLock::new(BTreeMap::new());
+		self.backfill_pdu(origin, response.pdu, &pub_key_map)
+			.await?;
+
+		self.get_non_outlier_pdu(event_id)?
+			.ok_or(Error::BadServerResponse("Event was not added to the timeline."))
+	}
+
 	/// Replace a PDU with the redacted form.
 	#[tracing::instrument(skip(self, reason))]
 	pub fn redact_pdu(&self, event_id: &EventId, reason: &PduEvent) -> Result<()> {
@@ -1071,6 +1286,9 @@ impl Service {
 			servers.swap_remove(server_index);
 		}
 
+		// don't backfill from servers banned here or by the room's ACL
+		servers.retain(|server_name| services().sending.may_federate_with(server_name, room_id));
+
 		servers.sort_unstable();
 		servers.dedup();
 		servers.shuffle(&mut rand::thread_rng());
@@ -1197,6 +1415,15 @@ impl Service {
 		Ok(())
 	}
 }
+
+/// Whether an event sent at `found` lies in the direction `dir` of `ts`.
+fn in_direction(found: MilliSecondsSinceUnixEpoch, ts: MilliSecondsSinceUnixEpoch, dir: Direction) -> bool {
+	match dir {
+		Direction::Forward => found >= ts,
+		Direction::Backward => found <= ts,
+	}
+}
+
 #[cfg(test)]
 mod tests {
 	use super::*;
@@ -1208,4 +1435,27 @@ mod tests {
 		assert!(PduCount::Normal(1) > PduCount::Backfilled(1));
 		assert!(PduCount::Backfilled(1) < PduCount::Normal(1));
 	}
+
+	#[test]
+	fn remote_events_must_lie_in_the_requested_direction() {
+		let ts = |ts: u32| MilliSecondsSinceUnixEpoch(ts.into());
+
+		assert!(in_direction(ts(2), ts(2), Direction::Forward));
+		assert!(in_direction(ts(3), ts(2), Direction::Forward));
+		assert!(!in_direction(ts(1), ts(2), Direction::Forward));
+		assert!(in_direction(ts(2), ts(2), Direction::Backward));
+		assert!(in_direction(ts(1), ts(2), Direction::Backward));
+		assert!(!in_direction(ts(3), ts(2), Direction::Backward));
+	}
+
+	#[test]
+	fn timestamp_key_orders_by_timestamp() {
+		let pdu_id = |count: u64| [1_u64.to_be_bytes(), count.to_be_bytes()].concat();
+
+		assert!(timestamp_key(&pdu_id(2), 1) < timestamp_key(&pdu_id(1), 2));
+		assert_eq!(
+			timestamp_key(&pdu_id(3), 4),
+			[1_u64.to_be_bytes(), 4_u64.to_be_bytes(), 3_u64.to_be_bytes()].concat()
+		);
+	}
 }
@@ -1,10 +1,11 @@
 use ruma::{ServerName, UserId};
 
-use super::{Destination, SendingEvent};
+use super::{Destination, DestinationHealth, SendingEvent};
 use crate::{services, utils, Error, KeyValueDatabase, Result};
 
 type OutgoingSendingIter<'a> = Box<dyn Iterator<Item = Result<(Vec<u8>, Destination, SendingEvent)>> + 'a>;
 type SendingEventIter<'a> = Box<dyn Iterator<Item = Result<(Vec<u8>, SendingEvent)>> + 'a>;
+type DestinationHealthIter<'a> = Box<dyn Iterator<Item = Result<(Destination, DestinationHealth)>> + 'a>;
 
 pub trait Data: Send + Sync {
 	fn active_requests(&self) -> OutgoingSendingIter<'_>;
@@ -22,6 +23,10 @@ pub trait Data: Send + Sync {
 	fn mark_as_active(&self, events: &[(SendingEvent, Vec<u8>)]) -> Result<()>;
 	fn set_latest_educount(&self, server_name: &ServerName, educount: u64) -> Result<()>;
 	fn get_latest_educount(&self, server_name: &ServerName) -> Result<u64>;
+	fn set_destination_health(&self, destination: &Destination, health: &DestinationHealth) -> Result<()>;
+	fn destination_health(&self, destination: &Destination) -> Result<Option<DestinationHealth>>;
+	fn destinations_health(&self) -> DestinationHealthIter<'_>;
+	fn remove_destination_health(&self, destination: &Destination) -> Result<()>;
 }
 
 impl Data for KeyValueDatabase {
@@ -132,6 +137,37 @@ impl Data for KeyValueDatabase {
 				utils::u64_from_bytes(&bytes).map_err(|_| Error::bad_database("Invalid u64 in servername_educount."))
 			})
 	}
+
+	fn set_destination_health(&self, destination: &Destination, health: &DestinationHealth) -> Result<()> {
+		self.destination_health.insert(
+			&destination.get_prefix(),
+			&serde_json::to_vec(health).expect("DestinationHealth::to_vec always works"),
+		)
+	}
+
+	fn destination_health(&self, destination: &Destination) -> Result<Option<DestinationHealth>> {
+		self.destination_health
+			.get(&destination.get_prefix())?
+			.map(|bytes| {
+				serde_json::from_slice(&bytes).map_err(|_| Error::bad_database("Invalid health in destination_health."))
+			})
+			.transpose()
+	}
+
+	fn destinations_health(&self) -> DestinationHealthIter<'_> {
+		Box::new(self.destination_health.iter().map(|(key, bytes)| {
+			// Keys are destination prefixes, which parse like a request without event
+			let (destination, _) = parse_servercurrentevent(&key, Vec::new())?;
+			let health = serde_json::from_slice(&bytes)
+				.map_err(|_| Error::bad_database("Invalid health in destination_health."))?;
+
+			Ok((destination, health))
+		}))
+	}
+
+	fn remove_destination_health(&self, destination: &Destination) -> Result<()> {
+		self.destination_health.remove(&destination.get_prefix())
+	}
 }
 
 #[tracing::instrument(skip(key))]
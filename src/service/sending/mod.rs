@@ -4,18 +4,19 @@ pub mod resolve;
 mod send;
 mod sender;
 
-use std::{fmt::Debug, sync::Arc};
+use std::{collections::HashSet, fmt::Debug, sync::Arc};
 
 use data::Data;
 pub use resolve::FedDest;
 use ruma::{
-	api::{appservice::Registration, OutgoingRequest},
+	api::{appservice::Registration, client::error::ErrorKind, OutgoingRequest},
 	OwnedServerName, OwnedUserId, RoomId, ServerName, UserId,
 };
+use serde::{Deserialize, Serialize};
 use tokio::{sync::Mutex, task::JoinHandle};
 use tracing::{error, warn};
 
-use crate::{server_is_ours, services, Config, Error, Result};
+use crate::{debug_warn, server_is_ours, services, Config, Error, Result};
 
 pub struct Service {
 	pub db: Arc<dyn Data>,
@@ -42,6 +43,20 @@ pub enum Destination {
 	Normal(OwnedServerName),
 }
 
+/// Delivery health of a destination. It is persisted, so that the backoff of
+/// failing destinations survives restarts.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct DestinationHealth {
+	/// Number of consecutive failed transactions
+	pub failures: u32,
+	/// Milliseconds since the unix epoch
+	pub last_success: Option<u64>,
+	/// Milliseconds since the unix epoch before which no transaction is
+	/// attempted
+	pub next_retry: u64,
+	pub last_error: Option<String>,
+}
+
 #[allow(clippy::module_name_repetitions)]
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum SendingEvent {
@@ -104,14 +119,20 @@ impl Service {
 		})
 	}
 
+	/// Whether we may exchange the events of a room with a server, which is
+	/// neither banned on this homeserver nor denied by the room's ACL.
+	pub fn may_federate_with(&self, server_name: &ServerName, room_id: &RoomId) -> bool {
+		!services().globals.server_is_forbidden(server_name)
+			&& services()
+				.rooms
+				.event_handler
+				.acl_check(server_name, room_id)
+				.is_ok()
+	}
+
 	#[tracing::instrument(skip(self, room_id, pdu_id))]
 	pub fn send_pdu_room(&self, room_id: &RoomId, pdu_id: &[u8]) -> Result<()> {
-		let servers = services()
-			.rooms
-			.state_cache
-			.room_servers(room_id)
-			.filter_map(Result::ok)
-			.filter(|server_name| !server_is_ours(server_name));
+		let servers = room_servers(room_id)?.into_iter();
 
 		self.send_pdu_servers(servers, pdu_id)
 	}
@@ -120,6 +141,7 @@ impl Service {
 	pub fn send_pdu_servers<I: Iterator<Item = OwnedServerName>>(&self, servers: I, pdu_id: &[u8]) -> Result<()> {
 		let requests = servers
 			.into_iter()
+			.filter(|server| !services().globals.server_is_forbidden(server))
 			.map(|server| (Destination::Normal(server), SendingEvent::Pdu(pdu_id.to_owned())))
 			.collect::<Vec<_>>();
 		let _cork = services().globals.db.cork();
@@ -142,6 +164,10 @@ impl Service {
 
 	#[tracing::instrument(skip(self, server, serialized))]
 	pub fn send_edu_server(&self, server: &ServerName, serialized: Vec<u8>) -> Result<()> {
+		if services().globals.server_is_forbidden(server) {
+			return Ok(());
+		}
+
 		let dest = Destination::Normal(server.to_owned());
 		let event = SendingEvent::Edu(serialized);
 		let _cork = services().globals.db.cork();
@@ -155,12 +181,7 @@ impl Service {
 
 	#[tracing::instrument(skip(self, room_id, serialized))]
 	pub fn send_edu_room(&self, room_id: &RoomId, serialized: Vec<u8>) -> Result<()> {
-		let servers = services()
-			.rooms
-			.state_cache
-			.room_servers(room_id)
-			.filter_map(Result::ok)
-			.filter(|server_name| !server_is_ours(server_name));
+		let servers = room_servers(room_id)?.into_iter();
 
 		self.send_edu_servers(servers, serialized)
 	}
@@ -169,6 +190,7 @@ impl Service {
 	pub fn send_edu_servers<I: Iterator<Item = OwnedServerName>>(&self, servers: I, serialized: Vec<u8>) -> Result<()> {
 		let requests = servers
 			.into_iter()
+			.filter(|server| !services().globals.server_is_forbidden(server))
 			.map(|server| (Destination::Normal(server), SendingEvent::Edu(serialized.clone())))
 			.collect::<Vec<_>>();
 		let _cork = services().globals.db.cork();
@@ -192,12 +214,7 @@ impl Service {
 
 	#[tracing::instrument(skip(self, room_id))]
 	pub fn flush_room(&self, room_id: &RoomId) -> Result<()> {
-		let servers = services()
-			.rooms
-			.state_cache
-			.room_servers(room_id)
-			.filter_map(Result::ok)
-			.filter(|server_name| !server_is_ours(server_name));
+		let servers = room_servers(room_id)?.into_iter();
 
 		self.flush_servers(servers)
 	}
@@ -221,6 +238,14 @@ impl Service {
 	where
 		T: OutgoingRequest + Debug + Send,
 	{
+		if services().globals.server_is_forbidden(dest) {
+			debug_warn!("Refusing to send a federation request to banned server {dest}");
+			return Err(Error::BadRequest(
+				ErrorKind::forbidden(),
+				"Server is banned on this homeserver.",
+			));
+		}
+
 		let client = &services().globals.client.federation;
 		send::send(client, dest, request).await
 	}
@@ -238,6 +263,52 @@ impl Service {
 		appservice::send_request(registration, request).await
 	}
 
+	/// Returns the delivery health of a destination, if anything was ever sent
+	/// to it.
+	pub fn destination_health(&self, dest: &Destination) -> Result<Option<DestinationHealth>> {
+		self.db.destination_health(dest)
+	}
+
+	/// Returns all destinations whose last transaction failed.
+	pub fn unhealthy_destinations(&self) -> Result<Vec<(Destination, DestinationHealth)>> {
+		self.db
+			.destinations_health()
+			.filter(|result| {
+				result
+					.as_ref()
+					.map_or(true, |(_, health)| health.failures > 0)
+			})
+			.collect()
+	}
+
+	/// Resets the backoff of a failing destination and retries its pending
+	/// transaction right away. Returns false if the destination is healthy.
+	pub fn reset_backoff(&self, dest: &Destination) -> Result<bool> {
+		let Some(health) = self.db.destination_health(dest)? else {
+			return Ok(false);
+		};
+
+		if health.failures == 0 {
+			return Ok(false);
+		}
+
+		self.db.set_destination_health(
+			dest,
+			&DestinationHealth {
+				next_retry: 0,
+				..health
+			},
+		)?;
+
+		self.dispatch(Msg {
+			dest: dest.clone(),
+			event: SendingEvent::Flush,
+			queue_id: Vec::<u8>::new(),
+		})?;
+
+		Ok(true)
+	}
+
 	/// Cleanup event data
 	/// Used for instance after we remove an appservice registration
 	#[tracing::instrument(skip(self))]
@@ -282,3 +353,22 @@ impl Destination {
 		prefix
 	}
 }
+
+/// Remote servers in the room we may send to. Rooms with partial state also
+/// include the servers from the `send_join` response, as most memberships are
+/// missing. Servers banned on this homeserver or denied by the room's ACL are
+/// left out.
+fn room_servers(room_id: &RoomId) -> Result<HashSet<OwnedServerName>> {
+	let mut servers: HashSet<_> = services()
+		.rooms
+		.state_cache
+		.room_servers(room_id)
+		.filter_map(Result::ok)
+		.collect();
+	servers.extend(services().rooms.partial_state.servers_in_room(room_id)?);
+	servers.retain(|server_name| {
+		!server_is_ours(server_name) && services().sending.may_federate_with(server_name, room_id)
+	});
+
+	Ok(servers)
+}
@@ -170,7 +170,7 @@ where
 	let mut req_json = serde_json::from_value(req_map.into()).expect("valid JSON is valid BTreeMap");
 	ruma::signatures::sign_json(
 		services().globals.server_name().as_str(),
-		services().globals.keypair(),
+		&*services().globals.keypair(),
 		&mut req_json,
 	)
 	.expect("our request json is what ruma expects");
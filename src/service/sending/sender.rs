@@ -3,7 +3,7 @@ use std::{
 	collections::{BTreeMap, HashMap, HashSet},
 	fmt::Debug,
 	sync::Arc,
-	time::{Duration, Instant},
+	time::Duration,
 };
 
 use base64::{engine::general_purpose, Engine as _};
@@ -20,16 +20,23 @@ use ruma::{
 	events::{push_rules::PushRulesEvent, receipt::ReceiptType, AnySyncEphemeralRoomEvent, GlobalAccountDataEventType},
 	push, uint, MilliSecondsSinceUnixEpoch, OwnedServerName, OwnedUserId, RoomId, ServerName, UInt,
 };
+use tokio::time::{self, MissedTickBehavior};
 use tracing::{debug, error, warn};
 
-use super::{appservice, send, Destination, Msg, SendingEvent, Service};
-use crate::{service::presence::Presence, services, user_is_local, utils::calculate_hash, Error, PduEvent, Result};
+use super::{appservice, send, Destination, DestinationHealth, Msg, SendingEvent, Service};
+use crate::{
+	debug_warn,
+	service::presence::Presence,
+	services, user_is_local,
+	utils::{self, calculate_hash},
+	Error, PduEvent, Result,
+};
 
 #[derive(Debug)]
 enum TransactionStatus {
 	Running,
-	Failed(u32, Instant), // number of times failed, time of last failure
-	Retrying(u32),        // number of times failed
+	Failed,   // waiting for the backoff in the destination's health record to expire
+	Retrying, // retrying after a failure
 }
 
 type SendingError = (Destination, Error);
@@ -41,6 +48,21 @@ type CurTransactionStatus = HashMap<Destination, TransactionStatus>;
 const DEQUEUE_LIMIT: usize = 48;
 const SELECT_EDU_LIMIT: usize = 16;
 
+/// How often failed destinations are checked for an expired backoff
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How often health records of destinations nothing was sent to for a while
+/// are removed
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How long the health record of a healthy destination is kept after the last
+/// successful transaction, in milliseconds
+const HEALTH_RECORD_TTL: u64 = 7 * 24 * 60 * 60 * 1000;
+
+/// How often the last successful transaction of a healthy destination is
+/// written at most, in milliseconds
+const LAST_SUCCESS_WRITE_INTERVAL: u64 = 60 * 60 * 1000;
+
 impl Service {
 	pub async fn start_handler(self: &Arc<Self>) {
 		let self_ = Arc::clone(self);
@@ -59,6 +81,10 @@ impl Service {
 		let receiver = self.receiver.lock().await;
 		let mut futures: SendingFutures<'_> = FuturesUnordered::new();
 		let mut statuses: CurTransactionStatus = CurTransactionStatus::new();
+		let mut retry_interval = time::interval(RETRY_INTERVAL);
+		retry_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+		let mut prune_interval = time::interval(PRUNE_INTERVAL);
+		prune_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
 
 		self.initial_transactions(&futures, &mut statuses);
 		loop {
@@ -71,6 +97,8 @@ impl Service {
 				Some(response) = futures.next() => {
 					self.handle_response(response, &mut futures, &mut statuses);
 				},
+				_ = retry_interval.tick() => self.retry_failed(&futures, &mut statuses),
+				_ = prune_interval.tick() => self.prune_health(&statuses),
 			}
 		}
 	}
@@ -80,19 +108,19 @@ impl Service {
 	) {
 		match response {
 			Ok(dest) => self.handle_response_ok(&dest, futures, statuses),
-			Err((dest, e)) => Self::handle_response_err(dest, futures, statuses, &e),
+			Err((dest, e)) => self.handle_response_err(dest, futures, statuses, &e),
 		};
 	}
 
 	fn handle_response_err(
-		dest: Destination, _futures: &mut SendingFutures<'_>, statuses: &mut CurTransactionStatus, e: &Error,
+		&self, dest: Destination, _futures: &mut SendingFutures<'_>, statuses: &mut CurTransactionStatus, e: &Error,
 	) {
 		debug!(dest = ?dest, "{e:?}");
+		self.record_failure(&dest, e);
 		statuses.entry(dest).and_modify(|e| {
 			*e = match e {
-				TransactionStatus::Running => TransactionStatus::Failed(1, Instant::now()),
-				TransactionStatus::Retrying(n) => TransactionStatus::Failed(*n + 1, Instant::now()),
-				TransactionStatus::Failed(..) => panic!("Request that was not even running failed?!"),
+				TransactionStatus::Running | TransactionStatus::Retrying => TransactionStatus::Failed,
+				TransactionStatus::Failed => panic!("Request that was not even running failed?!"),
 			}
 		});
 	}
@@ -104,6 +132,7 @@ impl Service {
 		self.db
 			.delete_all_active_requests_for(dest)
 			.expect("all active requests deleted");
+		self.record_success(dest);
 
 		// Find events that have been added since starting the last request
 		let new_events = self
@@ -151,6 +180,18 @@ impl Service {
 			}
 		}
 
+		// Destinations that were failing before the restart keep their backoff and
+		// are retried once it expired
+		for (dest, _) in self
+			.db
+			.destinations_health()
+			.filter_map(Result::ok)
+			.filter(|(_, health)| health.failures > 0)
+		{
+			txns.remove(&dest);
+			statuses.insert(dest, TransactionStatus::Failed);
+		}
+
 		for (dest, events) in txns {
 			if self.startup_netburst && !events.is_empty() {
 				statuses.insert(dest.clone(), TransactionStatus::Running);
@@ -159,14 +200,117 @@ impl Service {
 		}
 	}
 
+	/// Retries the pending transactions of failed destinations whose backoff
+	/// expired, sending what was queued for them in the meantime.
+	fn retry_failed(&self, futures: &SendingFutures<'_>, statuses: &mut CurTransactionStatus) {
+		let failed = statuses
+			.iter()
+			.filter(|(_, status)| matches!(status, TransactionStatus::Failed))
+			.map(|(dest, _)| dest.clone())
+			.collect::<Vec<_>>();
+
+		for dest in failed {
+			match self.select_events(&dest, Vec::new(), statuses) {
+				Ok(Some(events)) if !events.is_empty() => futures.push(Box::pin(send_events(dest, events))),
+				Ok(Some(_)) => {
+					// Nothing is waiting to be sent anymore, so the destination is not
+					// failing until the next transaction to it fails
+					statuses.remove(&dest);
+					self.record_idle(&dest);
+				},
+				Ok(None) => {},
+				Err(e) => error!(dest = ?dest, "Failed to retry transaction: {e}"),
+			}
+		}
+	}
+
+	/// Persists a failed transaction in the health record of its destination
+	/// and backs off exponentially.
+	fn record_failure(&self, dest: &Destination, e: &Error) {
+		let mut health = self
+			.db
+			.destination_health(dest)
+			.ok()
+			.flatten()
+			.unwrap_or_default();
+		health.failures = health.failures.saturating_add(1);
+		health.next_retry = utils::millis_since_unix_epoch().saturating_add(backoff(health.failures));
+		health.last_error = Some(e.to_string());
+
+		if let Err(e) = self.db.set_destination_health(dest, &health) {
+			error!(dest = ?dest, "Failed to record failed transaction: {e}");
+		}
+	}
+
+	/// Persists a successful transaction in the health record of its
+	/// destination, ending any backoff.
+	fn record_success(&self, dest: &Destination) {
+		let previous = match self.db.destination_health(dest) {
+			Ok(previous) => previous,
+			Err(e) => {
+				error!(dest = ?dest, "Failed to read destination health: {e}");
+				None
+			},
+		};
+
+		let Some(health) = health_after_success(previous.as_ref(), utils::millis_since_unix_epoch()) else {
+			return;
+		};
+
+		if let Err(e) = self.db.set_destination_health(dest, &health) {
+			error!(dest = ?dest, "Failed to record successful transaction: {e}");
+		}
+	}
+
+	/// Ends the backoff of a failed destination nothing has to be sent to
+	/// anymore.
+	fn record_idle(&self, dest: &Destination) {
+		let health = match self.db.destination_health(dest) {
+			Ok(Some(health)) if health.failures > 0 => health,
+			Ok(_) => return,
+			Err(e) => {
+				error!(dest = ?dest, "Failed to read destination health: {e}");
+				return;
+			},
+		};
+
+		let health = DestinationHealth {
+			failures: 0,
+			next_retry: 0,
+			..health
+		};
+		if let Err(e) = self.db.set_destination_health(dest, &health) {
+			error!(dest = ?dest, "Failed to reset destination health: {e}");
+		}
+	}
+
+	/// Removes the health records of destinations nothing was successfully
+	/// sent to for a while and which are not failing.
+	fn prune_health(&self, statuses: &CurTransactionStatus) {
+		let now = utils::millis_since_unix_epoch();
+		let stale = self
+			.db
+			.destinations_health()
+			.filter_map(Result::ok)
+			.filter(|(dest, health)| !statuses.contains_key(dest) && is_stale(health, now))
+			.map(|(dest, _)| dest)
+			.collect::<Vec<_>>();
+
+		for dest in stale {
+			if let Err(e) = self.db.remove_destination_health(&dest) {
+				error!(dest = ?dest, "Failed to remove destination health: {e}");
+			}
+		}
+	}
+
 	#[tracing::instrument(skip_all)]
 	fn select_events(
 		&self,
 		dest: &Destination,
-		new_events: Vec<(SendingEvent, Vec<u8>)>, // Events we want to send: event and full key
+		mut new_events: Vec<(SendingEvent, Vec<u8>)>, // Events we want to send: event and full key
 		statuses: &mut CurTransactionStatus,
 	) -> Result<Option<Vec<SendingEvent>>> {
-		let (allow, retry) = self.select_events_current(dest.clone(), statuses)?;
+		let (allow, retry) = self.select_events_current(dest, statuses)?;
 
 		// Nothing can be done for this remote, bail out.
 		if !allow {
@@ -183,7 +327,17 @@ impl Service {
 				.filter_map(Result::ok)
 				.for_each(|(_, e)| events.push(e));
 
-			return Ok(Some(events));
+			if !events.is_empty() {
+				return Ok(Some(events));
+			}
+
+			// Nothing was in flight, send what was queued during the backoff instead
+			new_events = self
+				.db
+				.queued_requests(dest)
+				.filter_map(Result::ok)
+				.take(DEQUEUE_LIMIT)
+				.collect();
 		}
 
 		// Compose the next transaction
@@ -207,25 +361,26 @@ impl Service {
 	}
 
 	#[tracing::instrument(skip_all)]
-	fn select_events_current(&self, dest: Destination, statuses: &mut CurTransactionStatus) -> Result<(bool, bool)> {
+	fn select_events_current(&self, dest: &Destination, statuses: &mut CurTransactionStatus) -> Result<(bool, bool)> {
+		let health = match statuses.get(dest) {
+			Some(TransactionStatus::Failed) => self.db.destination_health(dest)?,
+			_ => None,
+		};
+
 		let (mut allow, mut retry) = (true, false);
 		statuses
-			.entry(dest)
+			.entry(dest.clone())
 			.and_modify(|e| match e {
-				TransactionStatus::Failed(tries, time) => {
+				TransactionStatus::Failed => {
 					// Fail if a request has failed recently (exponential backoff)
-					let max_duration = Duration::from_secs(services().globals.config.sender_retry_backoff_limit);
-					let min_duration = Duration::from_secs(services().globals.config.sender_timeout);
-					let min_elapsed_duration = min_duration * (*tries) * (*tries);
-					let min_elapsed_duration = cmp::min(min_elapsed_duration, max_duration);
-					if time.elapsed() < min_elapsed_duration {
+					if health.is_some_and(|health| health.next_retry > utils::millis_since_unix_epoch()) {
 						allow = false;
 					} else {
 						retry = true;
-						*e = TransactionStatus::Retrying(*tries);
+						*e = TransactionStatus::Retrying;
 					}
 				},
-				TransactionStatus::Running | TransactionStatus::Retrying(_) => {
+				TransactionStatus::Running | TransactionStatus::Retrying => {
 					allow = false; // already running
 				},
 			})
@@ -244,6 +399,15 @@ impl Service {
 
 		for room_id in services().rooms.state_cache.server_rooms(server_name) {
 			let room_id = room_id?;
+			if services()
+				.rooms
+				.event_handler
+				.acl_check(server_name, &room_id)
+				.is_err()
+			{
+				continue;
+			}
+
 			// Look for device list updates in this room
 			device_list_changes.extend(
 				services()
@@ -284,6 +448,48 @@ impl Service {
 	}
 }
 
+/// The health record to write after a successful transaction, if it changed
+/// enough to be worth writing.
+fn health_after_success(previous: Option<&DestinationHealth>, now: u64) -> Option<DestinationHealth> {
+	if previous.is_some_and(|previous| {
+		previous.failures == 0
+			&& previous
+				.last_success
+				.is_some_and(|last_success| now.saturating_sub(last_success) < LAST_SUCCESS_WRITE_INTERVAL)
+	}) {
+		return None;
+	}
+
+	Some(DestinationHealth {
+		last_success: Some(now),
+		..DestinationHealth::default()
+	})
+}
+
+/// Whether the health record of a destination can be removed because it is
+/// not failing and nothing was successfully sent to it for a while.
+fn is_stale(health: &DestinationHealth, now: u64) -> bool {
+	health.failures == 0
+		&& health
+			.last_success
+			.map_or(true, |last_success| now.saturating_sub(last_success) >= HEALTH_RECORD_TTL)
+}
+
+/// Exponential backoff after the given number of consecutive failed
+/// transactions, in milliseconds
+fn backoff(failures: u32) -> u64 {
+	let config = &services().globals.config;
+	let failures = u64::from(failures);
+	cmp::min(
+		config
+			.sender_timeout
+			.saturating_mul(failures)
+			.saturating_mul(failures),
+		config.sender_retry_backoff_limit,
+	)
+	.saturating_mul(1000)
+}
+
 /// Look for presence
 fn select_edus_presence(
 	server_name: &ServerName, since: u64, max_edu_count: &mut u64, events: &mut Vec<Vec<u8>>,
@@ -543,6 +749,12 @@ async fn send_events_dest_push(
 async fn send_events_dest_normal(
 	dest: &Destination, server_name: &OwnedServerName, events: Vec<SendingEvent>,
 ) -> SendingResult {
+	// Events queued before the server was banned are dropped
+	if services().globals.server_is_forbidden(server_name) {
+		debug_warn!("Dropping {} events queued for banned server {server_name}", events.len());
+		return Ok(dest.clone());
+	}
+
 	let mut edu_jsons = Vec::new();
 	let mut pdu_jsons = Vec::new();
 
@@ -616,3 +828,62 @@ async fn send_events_dest_normal(
 	})
 	.map_err(|e| (dest.clone(), e))
 }
+
+#[cfg(test)]
+mod tests {
+	use super::{health_after_success, is_stale, DestinationHealth, HEALTH_RECORD_TTL, LAST_SUCCESS_WRITE_INTERVAL};
+
+	const NOW: u64 = 1_000_000_000_000;
+
+	#[test]
+	fn success_is_written_when_the_health_changes() {
+		assert_eq!(
+			health_after_success(None, NOW).and_then(|health| health.last_success),
+			Some(NOW)
+		);
+
+		let failing = DestinationHealth {
+			failures: 3,
+			last_success: Some(NOW - 1),
+			next_retry: NOW + 1,
+			last_error: Some("timeout".to_owned()),
+		};
+		let recovered = health_after_success(Some(&failing), NOW).unwrap();
+		assert_eq!(recovered.failures, 0);
+		assert_eq!(recovered.next_retry, 0);
+		assert_eq!(recovered.last_error, None);
+
+		let healthy = DestinationHealth {
+			last_success: Some(NOW - 1),
+			..DestinationHealth::default()
+		};
+		assert!(health_after_success(Some(&healthy), NOW).is_none());
+
+		let healthy_long_ago = DestinationHealth {
+			last_success: Some(NOW - LAST_SUCCESS_WRITE_INTERVAL),
+			..DestinationHealth::default()
+		};
+		assert!(health_after_success(Some(&healthy_long_ago), NOW).is_some());
+	}
+
+	#[test]
+	fn only_old_healthy_records_are_stale() {
+		let recent = DestinationHealth {
+			last_success: Some(NOW - 1),
+			..DestinationHealth::default()
+		};
+		assert!(!is_stale(&recent, NOW));
+
+		let old = DestinationHealth {
+			last_success: Some(NOW - HEALTH_RECORD_TTL),
+			..DestinationHealth::default()
+		};
+		assert!(is_stale(&old, NOW));
+
+		let failing = DestinationHealth {
+			failures: 1,
+			..old
+		};
+		assert!(!is_stale(&failing, NOW));
+	}
+}
@@ -0,0 +1,52 @@
+use ruma::{OwnedRoomId, OwnedUserId, RoomId, UserId};
+
+use crate::{utils, Error, KeyValueDatabase, Result};
+
+const NOTICES_USER: &[u8] = b"server_notices_user";
+
+pub(crate) trait Data: Send + Sync {
+	/// Returns the server notices room of a user, if one was created.
+	fn notices_room(&self, user_id: &UserId) -> Result<Option<OwnedRoomId>>;
+
+	/// Remembers the server notices room of a user.
+	fn set_notices_room(&self, user_id: &UserId, room_id: &RoomId) -> Result<()>;
+
+	/// Returns the server notices user the server created, if any.
+	fn notices_user(&self) -> Result<Option<OwnedUserId>>;
+
+	/// Remembers that the server created a server notices user.
+	fn set_notices_user(&self, user_id: &UserId) -> Result<()>;
+}
+
+impl Data for KeyValueDatabase {
+	fn notices_room(&self, user_id: &UserId) -> Result<Option<OwnedRoomId>> {
+		self.userid_servernoticesroomid
+			.get(user_id.as_bytes())?
+			.map(|bytes| {
+				utils::string_from_bytes(&bytes)
+					.ok()
+					.and_then(|room_id| OwnedRoomId::try_from(room_id).ok())
+					.ok_or_else(|| Error::bad_database("Room ID in userid_servernoticesroomid is invalid."))
+			})
+			.transpose()
+	}
+
+	fn set_notices_room(&self, user_id: &UserId, room_id: &RoomId) -> Result<()> {
+		self.userid_servernoticesroomid
+			.insert(user_id.as_bytes(), room_id.as_bytes())
+	}
+
+	fn notices_user(&self) -> Result<Option<OwnedUserId>> {
+		self.global
+			.get(NOTICES_USER)?
+			.map(|bytes| {
+				utils::string_from_bytes(&bytes)
+					.ok()
+					.and_then(|user_id| OwnedUserId::try_from(user_id).ok())
+					.ok_or_else(|| Error::bad_database("Server notices user in global is invalid."))
+			})
+			.transpose()
+	}
+
+	fn set_notices_user(&self, user_id: &UserId) -> Result<()> { self.global.insert(NOTICES_USER, user_id.as_bytes()) }
+}
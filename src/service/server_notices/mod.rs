@@ -0,0 +1,196 @@
+mod data;
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use conduit::{Error, Result};
+use data::Data;
+use ruma::{
+	events::{
+		room::{
+			member::{MembershipState, RoomMemberEventContent},
+			message::RoomMessageEventContent,
+			power_levels::RoomPowerLevelsEventContent,
+		},
+		tag::{TagEvent, TagEventContent, TagInfo, TagName},
+		RoomAccountDataEventType, TimelineEventType,
+	},
+	EventId, OwnedRoomId, OwnedUserId, RoomId, UserId,
+};
+use serde_json::value::to_raw_value;
+use tokio::sync::Mutex;
+use tracing::{error, info};
+
+use crate::{admin::create_system_room, pdu::PduBuilder, services};
+
+/// Display name and room name of the server notices user and rooms
+const SERVER_NOTICES_NAME: &str = "Server Notices";
+
+pub struct Service {
+	pub(super) db: Arc<dyn Data>,
+	/// Serializes the creation of notices rooms, so concurrent notices to a
+	/// user end up in the same room
+	pub(super) create_lock: Mutex<()>,
+}
+
+impl Service {
+	/// The system user sending server notices.
+	#[must_use]
+	pub fn notices_user(&self) -> OwnedUserId {
+		UserId::parse_with_server_name(
+			services().globals.config.server_notices_localpart.as_str(),
+			services().globals.server_name(),
+		)
+		.expect("server_notices_localpart was validated on startup")
+	}
+
+	/// Whether the user is the server notices user, which is reserved even
+	/// before the first notice creates it.
+	#[must_use]
+	pub fn is_notices_user(&self, user_id: &UserId) -> bool { user_id == self.notices_user() }
+
+	/// Sends a server notice to a local user, creating their notices room and
+	/// inviting them to it if they have none or left it.
+	pub async fn send_notice(&self, user_id: &UserId, content: &RoomMessageEventContent) -> Result<Arc<EventId>> {
+		let notices_user = self.notices_user();
+		let room_id = self.notices_room(user_id, &notices_user).await?;
+
+		let mutex_state = Arc::clone(
+			services()
+				.globals
+				.roomid_mutex_state
+				.write()
+				.await
+				.entry(room_id.clone())
+				.or_default(),
+		);
+		let state_lock = mutex_state.lock().await;
+
+		services()
+			.rooms
+			.timeline
+			.build_and_append_pdu(
+				PduBuilder {
+					event_type: TimelineEventType::RoomMessage,
+					content: to_raw_value(content).expect("event is valid, we just created it"),
+					unsigned: None,
+					state_key: None,
+					redacts: None,
+				},
+				&notices_user,
+				&room_id,
+				&state_lock,
+			)
+			.await
+	}
+
+	/// Returns the notices room of the user, creating a new one if the user
+	/// has none or is neither joined nor invited anymore.
+	async fn notices_room(&self, user_id: &UserId, notices_user: &UserId) -> Result<OwnedRoomId> {
+		let _create_lock = self.create_lock.lock().await;
+
+		if let Some(room_id) = self.db.notices_room(user_id)? {
+			let state_cache = &services().rooms.state_cache;
+			if state_cache.is_joined(notices_user, &room_id)?
+				&& (state_cache.is_joined(user_id, &room_id)? || state_cache.is_invited(user_id, &room_id)?)
+			{
+				return Ok(room_id);
+			}
+		}
+
+		if !services().users.exists(notices_user)? {
+			services().users.create(notices_user, None)?;
+			self.db.set_notices_user(notices_user)?;
+			services()
+				.users
+				.set_displayname(notices_user, Some(SERVER_NOTICES_NAME.to_owned()))
+				.await?;
+		} else if self.db.notices_user()?.as_deref() != Some(notices_user) {
+			// Never send notices as a user that registered with the localpart
+			error!("\"server_notices_localpart\" is the localpart of the existing user {notices_user}");
+			return Err(Error::bad_config(
+				"\"server_notices_localpart\" belongs to an existing user, refusing to send server notices.",
+			));
+		}
+
+		let room_id = RoomId::new(services().globals.server_name());
+		services().rooms.short.get_or_create_shortroomid(&room_id)?;
+
+		let mutex_state = Arc::clone(
+			services()
+				.globals
+				.roomid_mutex_state
+				.write()
+				.await
+				.entry(room_id.clone())
+				.or_default(),
+		);
+		let state_lock = mutex_state.lock().await;
+
+		// Users can read and leave their notices room, but not send to it or invite
+		// others
+		let mut users = BTreeMap::new();
+		users.insert(notices_user.to_owned(), 100.into());
+		let power_levels = RoomPowerLevelsEventContent {
+			users,
+			events_default: 100.into(),
+			invite: 100.into(),
+			..Default::default()
+		};
+
+		create_system_room(
+			&room_id,
+			notices_user,
+			power_levels,
+			SERVER_NOTICES_NAME.to_owned(),
+			&state_lock,
+		)
+		.await?;
+
+		services()
+			.rooms
+			.timeline
+			.build_and_append_pdu(
+				PduBuilder {
+					event_type: TimelineEventType::RoomMember,
+					content: to_raw_value(&RoomMemberEventContent {
+						membership: MembershipState::Invite,
+						displayname: None,
+						avatar_url: None,
+						is_direct: None,
+						third_party_invite: None,
+						blurhash: None,
+						reason: None,
+						join_authorized_via_users_server: None,
+					})
+					.expect("event is valid, we just created it"),
+					unsigned: None,
+					state_key: Some(user_id.to_string()),
+					redacts: None,
+				},
+				notices_user,
+				&room_id,
+				&state_lock,
+			)
+			.await?;
+
+		// Clients single out rooms tagged as server notices
+		let mut tags = BTreeMap::new();
+		tags.insert(TagName::ServerNotice, TagInfo::new());
+		services().account_data.update(
+			Some(&room_id),
+			user_id,
+			RoomAccountDataEventType::Tag,
+			&serde_json::to_value(TagEvent {
+				content: TagEventContent {
+					tags,
+				},
+			})
+			.expect("to json value always works"),
+		)?;
+
+		self.db.set_notices_room(user_id, &room_id)?;
+		info!("Created server notices room {room_id} for {user_id}");
+
+		Ok(room_id)
+	}
+}
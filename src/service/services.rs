@@ -6,12 +6,12 @@ use std::{
 use conduit::{debug_info, Result, Server};
 use database::KeyValueDatabase;
 use lru_cache::LruCache;
-use tokio::sync::{broadcast, Mutex, RwLock};
+use tokio::sync::{broadcast, Mutex, RwLock, Semaphore};
 use tracing::{debug, info, trace};
 
 use crate::{
-	account_data, admin, appservice, globals, key_backups, media, presence, pusher, rooms, sending, transaction_ids,
-	uiaa, users,
+	account_data, admin, appservice, globals, key_backups, media, presence, pusher, registration_tokens, rooms,
+	sending, server_notices, threepid, transaction_ids, uiaa, users,
 };
 
 pub struct Services {
@@ -20,6 +20,8 @@ pub struct Services {
 	pub rooms: rooms::Service,
 	pub transaction_ids: transaction_ids::Service,
 	pub uiaa: uiaa::Service,
+	pub registration_tokens: registration_tokens::Service,
+	pub threepid: threepid::Service,
 	pub users: users::Service,
 	pub account_data: account_data::Service,
 	pub presence: Arc<presence::Service>,
@@ -28,6 +30,7 @@ pub struct Services {
 	pub key_backups: key_backups::Service,
 	pub media: media::Service,
 	pub sending: Arc<sending::Service>,
+	pub server_notices: server_notices::Service,
 	pub server: Arc<Server>,
 	pub db: Arc<KeyValueDatabase>,
 }
@@ -61,9 +64,20 @@ impl Services {
 				outlier: rooms::outlier::Service {
 					db: db.clone(),
 				},
+				partial_state: rooms::partial_state::Service {
+					db: db.clone(),
+					waiters: StdMutex::new(HashMap::new()),
+				},
 				pdu_metadata: rooms::pdu_metadata::Service {
 					db: db.clone(),
 				},
+				pdu_queue: rooms::pdu_queue::Service {
+					db: db.clone(),
+					capacity: config.incoming_pdu_queue_capacity,
+					workers: config.incoming_pdu_workers,
+					permits: Semaphore::new(config.incoming_pdu_workers),
+					queues: StdMutex::new(HashMap::new()),
+				},
 				read_receipt: rooms::read_receipt::Service {
 					db: db.clone(),
 				},
@@ -124,9 +138,18 @@ impl Services {
 			uiaa: uiaa::Service {
 				db: db.clone(),
 			},
+			registration_tokens: registration_tokens::Service {
+				db: db.clone(),
+				usage_lock: StdMutex::new(()),
+			},
+			threepid: threepid::Service::build(db.clone(), config)?,
 			users: users::Service {
 				db: db.clone(),
 				connections: StdMutex::new(BTreeMap::new()),
+				last_seen: StdMutex::new(HashMap::new()),
+				auth_providers: users::auth::build(config),
+				login_token_mutex: StdMutex::new(()),
+				refresh_token_mutex: StdMutex::new(()),
 			},
 			account_data: account_data::Service {
 				db: db.clone(),
@@ -141,6 +164,10 @@ impl Services {
 				url_preview_mutex: RwLock::new(HashMap::new()),
 			},
 			sending: sending::Service::build(db.clone(), config),
+			server_notices: server_notices::Service {
+				db: db.clone(),
+				create_lock: Mutex::new(()),
+			},
 			globals: globals::Service::load(db.clone(), config)?,
 			server,
 			db,
@@ -282,6 +309,9 @@ bad_signature_ratelimiter: {bad_signature_ratelimiter}
 
 		self.admin.start_handler().await;
 		self.sending.start_handler().await;
+		self.rooms.partial_state.start_resyncs()?;
+		self.rooms.pdu_queue.start_workers()?;
+		self.users.start_impersonation_cleanup();
 		if self.globals.config.allow_local_presence {
 			self.presence.start_handler().await;
 		}
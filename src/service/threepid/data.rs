@@ -0,0 +1,171 @@
+use ruma::{
+	thirdparty::{Medium, ThirdPartyIdentifier},
+	OwnedUserId, UserId,
+};
+
+use super::ValidationSession;
+use crate::{utils, Error, KeyValueDatabase, Result};
+
+pub(crate) trait Data: Send + Sync {
+	/// Returns a validation session by its id.
+	fn session(&self, sid: &str) -> Result<Option<ValidationSession>>;
+
+	/// Returns the validation session a client created for an address.
+	fn session_by_client_secret(
+		&self, client_secret: &str, medium: &Medium, address: &str,
+	) -> Result<Option<ValidationSession>>;
+
+	/// Creates or updates a validation session.
+	fn set_session(&self, session: &ValidationSession) -> Result<()>;
+
+	fn remove_session(&self, session: &ValidationSession) -> Result<()>;
+
+	/// Remembers the validation session a UIAA session completed the email
+	/// identity stage with.
+	fn set_uiaa_session(&self, uiaa_session: &str, sid: &str) -> Result<()>;
+
+	fn uiaa_session(&self, uiaa_session: &str) -> Result<Option<String>>;
+
+	fn remove_uiaa_session(&self, uiaa_session: &str) -> Result<()>;
+
+	/// Binds a third party identifier to a user.
+	fn add_threepid(&self, user_id: &UserId, threepid: &ThirdPartyIdentifier) -> Result<()>;
+
+	fn remove_threepid(&self, user_id: &UserId, medium: &Medium, address: &str) -> Result<()>;
+
+	/// Returns an iterator over the third party identifiers bound to a user.
+	fn threepids<'a>(&'a self, user_id: &UserId) -> Box<dyn Iterator<Item = Result<ThirdPartyIdentifier>> + 'a>;
+
+	/// Returns the user a third party identifier is bound to.
+	fn find_user(&self, medium: &Medium, address: &str) -> Result<Option<OwnedUserId>>;
+}
+
+impl Data for KeyValueDatabase {
+	fn session(&self, sid: &str) -> Result<Option<ValidationSession>> {
+		self.threepidsid_session
+			.get(sid.as_bytes())?
+			.map(|bytes| {
+				serde_json::from_slice(&bytes)
+					.map_err(|_| Error::bad_database("Invalid 3PID validation session in db."))
+			})
+			.transpose()
+	}
+
+	fn session_by_client_secret(
+		&self, client_secret: &str, medium: &Medium, address: &str,
+	) -> Result<Option<ValidationSession>> {
+		let Some(sid) = self
+			.clientsecretthreepid_sid
+			.get(&client_secret_key(client_secret, medium, address))?
+		else {
+			return Ok(None);
+		};
+
+		let sid = utils::string_from_bytes(&sid)
+			.map_err(|_| Error::bad_database("Session id in clientsecretthreepid_sid is invalid."))?;
+
+		self.session(&sid)
+	}
+
+	fn set_session(&self, session: &ValidationSession) -> Result<()> {
+		self.threepidsid_session.insert(
+			session.sid.as_bytes(),
+			&serde_json::to_vec(session).expect("ValidationSession::to_vec always works"),
+		)?;
+		self.clientsecretthreepid_sid.insert(
+			&client_secret_key(&session.client_secret, &session.medium, &session.address),
+			session.sid.as_bytes(),
+		)
+	}
+
+	fn remove_session(&self, session: &ValidationSession) -> Result<()> {
+		self.threepidsid_session.remove(session.sid.as_bytes())?;
+		self.clientsecretthreepid_sid.remove(&client_secret_key(
+			&session.client_secret,
+			&session.medium,
+			&session.address,
+		))
+	}
+
+	fn set_uiaa_session(&self, uiaa_session: &str, sid: &str) -> Result<()> {
+		self.uiaasessionid_threepidsid
+			.insert(uiaa_session.as_bytes(), sid.as_bytes())
+	}
+
+	fn uiaa_session(&self, uiaa_session: &str) -> Result<Option<String>> {
+		self.uiaasessionid_threepidsid
+			.get(uiaa_session.as_bytes())?
+			.map(|bytes| {
+				utils::string_from_bytes(&bytes)
+					.map_err(|_| Error::bad_database("Session id in uiaasessionid_threepidsid is invalid."))
+			})
+			.transpose()
+	}
+
+	fn remove_uiaa_session(&self, uiaa_session: &str) -> Result<()> {
+		self.uiaasessionid_threepidsid
+			.remove(uiaa_session.as_bytes())
+	}
+
+	fn add_threepid(&self, user_id: &UserId, threepid: &ThirdPartyIdentifier) -> Result<()> {
+		let mut key = user_id.as_bytes().to_vec();
+		key.push(0xFF);
+		key.extend_from_slice(&threepid_key(&threepid.medium, &threepid.address));
+
+		self.useridthreepid_info.insert(
+			&key,
+			&serde_json::to_vec(threepid).expect("ThirdPartyIdentifier::to_vec always works"),
+		)?;
+		self.threepid_userid
+			.insert(&threepid_key(&threepid.medium, &threepid.address), user_id.as_bytes())
+	}
+
+	fn remove_threepid(&self, user_id: &UserId, medium: &Medium, address: &str) -> Result<()> {
+		let mut key = user_id.as_bytes().to_vec();
+		key.push(0xFF);
+		key.extend_from_slice(&threepid_key(medium, address));
+
+		self.useridthreepid_info.remove(&key)?;
+		self.threepid_userid.remove(&threepid_key(medium, address))
+	}
+
+	fn threepids<'a>(&'a self, user_id: &UserId) -> Box<dyn Iterator<Item = Result<ThirdPartyIdentifier>> + 'a> {
+		let mut prefix = user_id.as_bytes().to_vec();
+		prefix.push(0xFF);
+
+		Box::new(
+			self.useridthreepid_info
+				.scan_prefix(prefix)
+				.map(|(_, bytes)| {
+					serde_json::from_slice(&bytes)
+						.map_err(|_| Error::bad_database("Invalid 3PID in useridthreepid_info."))
+				}),
+		)
+	}
+
+	fn find_user(&self, medium: &Medium, address: &str) -> Result<Option<OwnedUserId>> {
+		self.threepid_userid
+			.get(&threepid_key(medium, address))?
+			.map(|bytes| {
+				utils::string_from_bytes(&bytes)
+					.map_err(|_| Error::bad_database("User ID in threepid_userid is invalid unicode."))?
+					.try_into()
+					.map_err(|_| Error::bad_database("User ID in threepid_userid is invalid."))
+			})
+			.transpose()
+	}
+}
+
+fn threepid_key(medium: &Medium, address: &str) -> Vec<u8> {
+	let mut key = medium.as_str().as_bytes().to_vec();
+	key.push(0xFF);
+	key.extend_from_slice(address.as_bytes());
+	key
+}
+
+fn client_secret_key(client_secret: &str, medium: &Medium, address: &str) -> Vec<u8> {
+	let mut key = client_secret.as_bytes().to_vec();
+	key.push(0xFF);
+	key.extend_from_slice(&threepid_key(medium, address));
+	key
+}
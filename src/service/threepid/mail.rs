@@ -0,0 +1,107 @@
+#[cfg(feature = "email")]
+use std::fs;
+
+use conduit::{Config, Error, Result};
+#[cfg(feature = "email")]
+use lettre::{
+	message::{header::ContentType, Mailbox},
+	transport::smtp::authentication::Credentials,
+	Address, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+#[cfg(feature = "email")]
+use ruma::api::client::error::ErrorKind;
+#[cfg(feature = "email")]
+use tracing::error;
+
+/// Sends emails through the configured SMTP relay.
+#[cfg(feature = "email")]
+pub(super) struct Mailer {
+	transport: AsyncSmtpTransport<Tokio1Executor>,
+	from: Mailbox,
+}
+
+/// Emails cannot be sent without the "email" feature, so there is no mailer.
+#[cfg(not(feature = "email"))]
+pub(super) enum Mailer {}
+
+#[cfg(feature = "email")]
+impl Mailer {
+	pub(super) fn build(config: &Config) -> Result<Self> {
+		let url = config
+			.smtp
+			.url
+			.as_deref()
+			.expect("checked by config::check");
+		let mut transport = AsyncSmtpTransport::<Tokio1Executor>::from_url(url)
+			.map_err(|e| Error::bad_config(&format!("Invalid \"smtp.url\": {e}")))?;
+
+		if let Some(username) = &config.smtp.username {
+			let password = match &config.smtp.password_file {
+				Some(path) => fs::read_to_string(path)?.trim_end().to_owned(),
+				None => String::new(),
+			};
+			transport = transport.credentials(Credentials::new(username.clone(), password));
+		}
+
+		let from = config
+			.smtp
+			.from
+			.as_deref()
+			.expect("checked by config::check")
+			.parse()
+			.map_err(|e| Error::bad_config(&format!("Invalid \"smtp.from\": {e}")))?;
+
+		Ok(Self {
+			transport: transport.build(),
+			from,
+		})
+	}
+
+	/// Email addresses are compared case-insensitively.
+	pub(super) fn normalize_address(&self, email: &str) -> Result<String> {
+		let email = email.trim().to_lowercase();
+		if email.parse::<Address>().is_err() {
+			return Err(Error::BadRequest(ErrorKind::InvalidParam, "Email address is invalid."));
+		}
+
+		Ok(email)
+	}
+
+	pub(super) async fn send(&self, to: &str, subject: String, body: String) -> Result<()> {
+		let to = to
+			.parse::<Address>()
+			.map(|address| Mailbox::new(None, address))
+			.map_err(|_| Error::BadRequest(ErrorKind::InvalidParam, "Email address is invalid."))?;
+
+		let message = Message::builder()
+			.from(self.from.clone())
+			.to(to)
+			.subject(subject)
+			.header(ContentType::TEXT_PLAIN)
+			.body(body)
+			.map_err(|e| {
+				error!("Failed to build validation email: {e}");
+				Error::BadServerResponse("Failed to send validation email.")
+			})?;
+
+		self.transport.send(message).await.map_err(|e| {
+			error!("Failed to send validation email through the SMTP relay: {e}");
+			Error::BadServerResponse("Failed to send validation email.")
+		})?;
+
+		Ok(())
+	}
+}
+
+#[cfg(not(feature = "email"))]
+impl Mailer {
+	pub(super) fn build(_config: &Config) -> Result<Self> {
+		Err(Error::bad_config(
+			"SMTP is enabled but conduwuit was built without the \"email\" feature.",
+		))
+	}
+
+	pub(super) fn normalize_address(&self, _email: &str) -> Result<String> { match *self {} }
+
+	pub(super) async fn send(&self, _to: &str, _subject: String, _body: String) -> Result<()> { match *self {} }
+}
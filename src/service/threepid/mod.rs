@@ -0,0 +1,714 @@
+mod data;
+mod mail;
+
+use std::{
+	collections::{BTreeMap, HashMap},
+	net::IpAddr,
+	sync::{Arc, Mutex},
+	time::{Duration, Instant},
+};
+
+#[cfg(feature = "email")]
+use base64::{engine::general_purpose, Engine as _};
+use conduit::{utils, Config, Error, Result};
+use data::Data;
+use mail::Mailer;
+use ruma::{
+	api::client::error::{ErrorKind, RetryAfter},
+	events::{
+		room::third_party_invite::{PublicKey, RoomThirdPartyInviteEventContent},
+		StateEventType,
+	},
+	thirdparty::{Medium, ThirdPartyIdentifier, ThirdPartyIdentifierInit},
+	MilliSecondsSinceUnixEpoch, OwnedUserId, RoomId, UInt, UserId,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::json;
+#[cfg(feature = "email")]
+use sha2::{Digest, Sha256};
+use tracing::{debug, info};
+use url::Url;
+
+use crate::services;
+
+/// Length of generated validation session ids
+pub const SESSION_ID_LENGTH: usize = 32;
+
+/// Length of validation tokens sent by email
+pub const VALIDATION_TOKEN_LENGTH: usize = 32;
+
+/// Path of the endpoint validation links and `submit_url` point to
+pub const SUBMIT_TOKEN_PATH: &str = "/_conduwuit/client/email/submit_token";
+
+/// Minimum time between two validation emails to the same address, or
+/// requested by the same client
+const EMAIL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Minimum time between two third party invite exchanges the identity server
+/// asks for on behalf of the same user, or from the same address
+const ONBIND_INTERVAL: Duration = Duration::from_secs(10);
+
+/// What a validation email was requested for, which decides whether the
+/// address has to be bound to an account already.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Purpose {
+	Register,
+	AddThreepid,
+	PasswordReset,
+}
+
+/// An attempt to prove ownership of a third party identifier, identified by
+/// the `sid` returned from `requestToken`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ValidationSession {
+	pub sid: String,
+	pub client_secret: String,
+	pub medium: Medium,
+	pub address: String,
+	pub token: String,
+	pub send_attempt: u64,
+	pub next_link: Option<String>,
+	/// Milliseconds since the unix epoch after which the token is invalid
+	pub expires_at: u64,
+	/// Set once the token was submitted
+	pub validated_at: Option<u64>,
+	/// The UIAA session which completed a stage with this session, which no
+	/// other UIAA session can use it for anymore
+	#[serde(default)]
+	pub uiaa_session: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct HashDetails {
+	algorithms: Vec<String>,
+	lookup_pepper: String,
+}
+
+#[derive(Deserialize)]
+struct LookupResponse {
+	#[serde(default)]
+	mappings: BTreeMap<String, OwnedUserId>,
+}
+
+#[derive(Deserialize)]
+struct StoreInviteResponse {
+	token: String,
+	display_name: String,
+	public_keys: Vec<PublicKey>,
+}
+
+pub struct Service {
+	pub(super) db: Arc<dyn Data>,
+	mailer: Option<Mailer>,
+	/// When the last validation email was sent to an address or for a client
+	/// IP address
+	emails_sent: Mutex<HashMap<String, Instant>>,
+	/// When the identity server last asked to exchange the third party invites
+	/// of a user, or from an IP address
+	onbinds: Mutex<HashMap<String, Instant>>,
+	/// Serializes claiming validation sessions and binding their addresses
+	bind_lock: Mutex<()>,
+}
+
+impl Service {
+	pub fn build(db: Arc<dyn Data>, config: &Config) -> Result<Self> {
+		let mailer = if config.smtp.enable {
+			Some(Mailer::build(config)?)
+		} else {
+			None
+		};
+
+		Ok(Self {
+			db,
+			mailer,
+			emails_sent: Mutex::new(HashMap::new()),
+			onbinds: Mutex::new(HashMap::new()),
+			bind_lock: Mutex::new(()),
+		})
+	}
+
+	/// Whether email addresses can be validated.
+	pub fn email_enabled(&self) -> bool { self.mailer.is_some() }
+
+	/// Sends a validation token to an email address and returns the id of the
+	/// validation session. Repeated requests with the same client secret,
+	/// address and send attempt return the existing session without sending
+	/// another email. Emails to the same address or for the same client are
+	/// rate limited.
+	pub async fn request_email_token(
+		&self, purpose: Purpose, client_secret: &str, email: &str, send_attempt: UInt, next_link: Option<&str>,
+		client_ip: Option<IpAddr>,
+	) -> Result<String> {
+		let Some(mailer) = &self.mailer else {
+			return Err(Error::BadRequest(
+				ErrorKind::ThreepidDenied,
+				"Email addresses are not supported on this server.",
+			));
+		};
+
+		let address = mailer.normalize_address(email)?;
+		let bound = self.find_user(&Medium::Email, &address)?.is_some();
+		match purpose {
+			Purpose::Register | Purpose::AddThreepid if bound => {
+				return Err(Error::BadRequest(ErrorKind::ThreepidInUse, "Email address is already in use."));
+			},
+			Purpose::PasswordReset if !bound => {
+				return Err(Error::BadRequest(
+					ErrorKind::ThreepidNotFound,
+					"Email address is not bound to an account.",
+				));
+			},
+			_ => {},
+		}
+
+		let send_attempt = u64::from(send_attempt);
+		let existing = self
+			.db
+			.session_by_client_secret(client_secret, &Medium::Email, &address)?;
+		if let Some(session) = &existing {
+			if send_attempt <= session.send_attempt {
+				return Ok(session.sid.clone());
+			}
+		}
+
+		// A new send attempt keeps the session and the UIAA session which used it
+		let (sid, uiaa_session) = existing.map_or_else(
+			|| (utils::random_string(SESSION_ID_LENGTH), None),
+			|session| (session.sid, session.uiaa_session),
+		);
+		let session = ValidationSession {
+			sid,
+			client_secret: client_secret.to_owned(),
+			medium: Medium::Email,
+			address,
+			token: utils::random_string(VALIDATION_TOKEN_LENGTH),
+			send_attempt,
+			next_link: next_link.map(ToOwned::to_owned),
+			expires_at: utils::millis_since_unix_epoch()
+				.saturating_add(services().globals.config.smtp.validation_token_ttl),
+			validated_at: None,
+			uiaa_session,
+		};
+
+		self.limit_email_rate(&session.address, client_ip)?;
+
+		mailer
+			.send(&session.address, subject(purpose), email_body(purpose, &session))
+			.await?;
+
+		self.db.set_session(&session)?;
+		info!("Sent {purpose:?} validation email for session {}", session.sid);
+
+		Ok(session.sid)
+	}
+
+	/// Validates a session with the token sent to its address. Returns the
+	/// `next_link` the client asked to be redirected to.
+	pub fn submit_token(&self, sid: &str, client_secret: &str, token: &str) -> Result<Option<String>> {
+		let Some(mut session) = self
+			.db
+			.session(sid)?
+			.filter(|session| session.client_secret == client_secret)
+		else {
+			return Err(Error::BadRequest(ErrorKind::ThreepidAuthFailed, "Unknown validation session."));
+		};
+
+		if session.expires_at < utils::millis_since_unix_epoch() {
+			return Err(Error::BadRequest(
+				ErrorKind::ThreepidAuthFailed,
+				"Validation token has expired, please request a new one.",
+			));
+		}
+
+		if session.token != token {
+			return Err(Error::BadRequest(ErrorKind::ThreepidAuthFailed, "Invalid validation token."));
+		}
+
+		if session.validated_at.is_none() {
+			session.validated_at = Some(utils::millis_since_unix_epoch());
+			self.db.set_session(&session)?;
+		}
+
+		Ok(session.next_link)
+	}
+
+	/// Returns a session if its token was submitted before it expired.
+	pub fn validated_session(&self, sid: &str, client_secret: &str) -> Result<Option<ValidationSession>> {
+		Ok(self.db.session(sid)?.filter(|session| {
+			session.client_secret == client_secret
+				&& session
+					.validated_at
+					.is_some_and(|validated_at| validated_at <= session.expires_at)
+		}))
+	}
+
+	/// Lets a UIAA session complete the `m.login.email.identity` stage with a
+	/// validated session. A validation session can only ever be used by one
+	/// UIAA session, returns false if it is not validated or was already used
+	/// by another one.
+	pub fn use_in_uiaa_session(&self, uiaa_session: &str, sid: &str, client_secret: &str) -> Result<bool> {
+		let _lock = self.bind_lock.lock().expect("locked");
+		let Some(mut session) = self.validated_session(sid, client_secret)? else {
+			return Ok(false);
+		};
+
+		if !claim(&mut session, uiaa_session) {
+			return Ok(false);
+		}
+
+		self.db.set_session(&session)?;
+		self.db.set_uiaa_session(uiaa_session, sid)?;
+
+		Ok(true)
+	}
+
+	/// Returns the validation session a finished UIAA session used. Both
+	/// sessions are deleted so the validation cannot be used again.
+	pub fn take_uiaa_session(&self, uiaa_session: &str) -> Result<Option<ValidationSession>> {
+		let Some(sid) = self.db.uiaa_session(uiaa_session)? else {
+			return Ok(None);
+		};
+
+		self.db.remove_uiaa_session(uiaa_session)?;
+		let session = self.db.session(&sid)?;
+		if let Some(session) = &session {
+			self.db.remove_session(session)?;
+		}
+
+		Ok(session)
+	}
+
+	/// Binds a validated session's third party identifier to an account and
+	/// deletes the session so it cannot be used again.
+	pub fn add(&self, user_id: &UserId, session: &ValidationSession) -> Result<()> {
+		let _lock = self.bind_lock.lock().expect("locked");
+		if self.find_user(&session.medium, &session.address)?.is_some() {
+			return Err(Error::BadRequest(
+				ErrorKind::ThreepidInUse,
+				"Third party identifier is already in use.",
+			));
+		}
+
+		let threepid = ThirdPartyIdentifier::from(ThirdPartyIdentifierInit {
+			address: session.address.clone(),
+			medium: session.medium.clone(),
+			validated_at: MilliSecondsSinceUnixEpoch(session.validated_at.and_then(UInt::new).unwrap_or_default()),
+			added_at: MilliSecondsSinceUnixEpoch::now(),
+		});
+
+		self.db.add_threepid(user_id, &threepid)?;
+		self.db.remove_session(session)
+	}
+
+	/// Unbinds a third party identifier, returns false if the user did not
+	/// have it.
+	pub fn remove(&self, user_id: &UserId, medium: &Medium, address: &str) -> Result<bool> {
+		if self.find_user(medium, address)?.as_deref() != Some(user_id) {
+			return Ok(false);
+		}
+
+		self.db.remove_threepid(user_id, medium, address)?;
+
+		Ok(true)
+	}
+
+	/// Unbinds all third party identifiers of a user.
+	pub fn remove_all(&self, user_id: &UserId) -> Result<()> {
+		for threepid in self.threepids(user_id) {
+			let threepid = threepid?;
+			self.db
+				.remove_threepid(user_id, &threepid.medium, &threepid.address)?;
+		}
+
+		Ok(())
+	}
+
+	/// Returns the third party identifiers bound to a user.
+	pub fn threepids<'a>(&'a self, user_id: &UserId) -> impl Iterator<Item = Result<ThirdPartyIdentifier>> + 'a {
+		self.db.threepids(user_id)
+	}
+
+	/// Returns the user a third party identifier is bound to.
+	pub fn find_user(&self, medium: &Medium, address: &str) -> Result<Option<OwnedUserId>> {
+		self.db.find_user(medium, address)
+	}
+
+	/// Returns the configured identity server if it is the one a client named
+	/// by host name in a third party invite.
+	pub fn identity_server(&self, id_server: &str) -> Result<Url> {
+		let identity_server = services()
+			.globals
+			.config
+			.identity_server
+			.as_ref()
+			.filter(|_| cfg!(feature = "email"));
+		let Some(identity_server) = identity_server else {
+			return Err(Error::BadRequest(
+				ErrorKind::ThreepidDenied,
+				"Third party invites are not supported on this server.",
+			));
+		};
+
+		let host = identity_server.host_str().unwrap_or_default();
+		let authority = match identity_server.port() {
+			Some(port) => format!("{host}:{port}"),
+			None => host.to_owned(),
+		};
+		if !authority.eq_ignore_ascii_case(id_server) {
+			return Err(Error::BadRequest(
+				ErrorKind::forbidden(),
+				"Identity server is not trusted by this server.",
+			));
+		}
+
+		Ok(identity_server.clone())
+	}
+
+	/// Looks up the user a third party identifier is bound to on the identity
+	/// server, using the hashed lookup of the identity service API v2.
+	pub async fn identity_lookup(
+		&self, id_server: &str, id_access_token: &str, medium: &Medium, address: &str,
+	) -> Result<Option<OwnedUserId>> {
+		let base = self.identity_server(id_server)?;
+		let client = &services().globals.client.default;
+
+		let hash_details: HashDetails = identity_request(
+			client
+				.get(identity_url(&base, "/_matrix/identity/v2/hash_details"))
+				.bearer_auth(id_access_token),
+		)
+		.await?;
+
+		let (algorithm, lookup) = if hash_details.algorithms.iter().any(|a| a == "sha256") {
+			("sha256", sha256_lookup(address, medium, &hash_details.lookup_pepper))
+		} else if hash_details.algorithms.iter().any(|a| a == "none") {
+			("none", format!("{address} {medium}"))
+		} else {
+			return Err(Error::BadServerResponse("Identity server supports no known lookup algorithm."));
+		};
+
+		let response: LookupResponse = identity_request(
+			client
+				.post(identity_url(&base, "/_matrix/identity/v2/lookup"))
+				.bearer_auth(id_access_token)
+				.json(&json!({
+					"addresses": [&lookup],
+					"algorithm": algorithm,
+					"pepper": hash_details.lookup_pepper,
+				})),
+		)
+		.await?;
+
+		Ok(response.mappings.get(&lookup).cloned())
+	}
+
+	/// Asks the identity server to notify an unbound third party identifier of
+	/// an invite. Returns the invite token and the content of the
+	/// `m.room.third_party_invite` event to send with it as state key.
+	pub async fn store_invite(
+		&self, id_server: &str, id_access_token: &str, medium: &Medium, address: &str, room_id: &RoomId,
+		sender: &UserId,
+	) -> Result<(String, RoomThirdPartyInviteEventContent)> {
+		let base = self.identity_server(id_server)?;
+
+		// Optional details for the identity server to show in its notification
+		let state_content = |event_type: &StateEventType, field: &str| {
+			services()
+				.rooms
+				.state_accessor
+				.room_state_get(room_id, event_type, "")
+				.ok()
+				.flatten()
+				.and_then(|event| serde_json::from_str::<serde_json::Value>(event.content.get()).ok())
+				.and_then(|content| content.get(field).cloned())
+		};
+		let body = json!({
+			"medium": medium,
+			"address": address,
+			"room_id": room_id,
+			"sender": sender,
+			"room_alias": state_content(&StateEventType::RoomCanonicalAlias, "alias"),
+			"room_avatar_url": state_content(&StateEventType::RoomAvatar, "url"),
+			"room_join_rules": state_content(&StateEventType::RoomJoinRules, "join_rule"),
+			"room_name": state_content(&StateEventType::RoomName, "name"),
+			"sender_display_name": services().users.displayname(sender)?,
+			"sender_avatar_url": services().users.avatar_url(sender)?,
+		});
+
+		let response: StoreInviteResponse = identity_request(
+			services()
+				.globals
+				.client
+				.default
+				.post(identity_url(&base, "/_matrix/identity/v2/store-invite"))
+				.bearer_auth(id_access_token)
+				.json(&body),
+		)
+		.await?;
+
+		let Some(first_key) = response.public_keys.first() else {
+			return Err(Error::BadServerResponse(
+				"Identity server returned no public keys for the invite.",
+			));
+		};
+
+		info!("Stored third party invite to {room_id} by {sender} on {id_server}");
+
+		Ok((
+			response.token,
+			RoomThirdPartyInviteEventContent {
+				display_name: response.display_name,
+				key_validity_url: first_key.key_validity_url.clone().unwrap_or_default(),
+				public_key: first_key.public_key.clone(),
+				public_keys: Some(response.public_keys),
+			},
+		))
+	}
+
+	fn limit_email_rate(&self, address: &str, client_ip: Option<IpAddr>) -> Result<()> {
+		let keys = [Some(address.to_owned()), client_ip.map(|ip| ip.to_string())];
+		if let Some(retry_after) = limit_rate(
+			&mut self.emails_sent.lock().expect("locked"),
+			EMAIL_INTERVAL,
+			keys,
+			Instant::now(),
+		) {
+			return Err(Error::BadRequest(
+				ErrorKind::LimitExceeded {
+					retry_after: Some(RetryAfter::Delay(retry_after)),
+				},
+				"Too many validation emails requested, try again later.",
+			));
+		}
+
+		Ok(())
+	}
+
+	/// Rate limits the identity server asking to exchange the third party
+	/// invites of a user, per user and per client IP address.
+	pub fn limit_onbind_rate(&self, user_id: &UserId, client_ip: Option<IpAddr>) -> Result<()> {
+		let keys = [Some(user_id.to_string()), client_ip.map(|ip| ip.to_string())];
+		if let Some(retry_after) =
+			limit_rate(&mut self.onbinds.lock().expect("locked"), ONBIND_INTERVAL, keys, Instant::now())
+		{
+			return Err(Error::BadRequest(
+				ErrorKind::LimitExceeded {
+					retry_after: Some(RetryAfter::Delay(retry_after)),
+				},
+				"Too many third party invite exchanges requested, try again later.",
+			));
+		}
+
+		Ok(())
+	}
+}
+
+/// Claims a validation session for a UIAA session, returns false if another
+/// UIAA session already did.
+fn claim(session: &mut ValidationSession, uiaa_session: &str) -> bool {
+	match &session.uiaa_session {
+		Some(used_by) => used_by == uiaa_session,
+		None => {
+			session.uiaa_session = Some(uiaa_session.to_owned());
+			true
+		},
+	}
+}
+
+/// Records an action for each of the keys, unless one of them is still waiting
+/// for `interval` to pass since its last action. Returns how long to wait in
+/// that case.
+fn limit_rate(
+	last_actions: &mut HashMap<String, Instant>, interval: Duration, keys: [Option<String>; 2], now: Instant,
+) -> Option<Duration> {
+	last_actions.retain(|_, acted_at| retry_after(interval, *acted_at, now).is_some());
+
+	if let Some(retry_after) = keys
+		.iter()
+		.flatten()
+		.filter_map(|key| last_actions.get(key))
+		.filter_map(|acted_at| retry_after(interval, *acted_at, now))
+		.max()
+	{
+		return Some(retry_after);
+	}
+
+	for key in keys.into_iter().flatten() {
+		last_actions.insert(key, now);
+	}
+
+	None
+}
+
+/// How long to wait until the action may be repeated, if it was done at
+/// `sent_at` before.
+fn retry_after(interval: Duration, sent_at: Instant, now: Instant) -> Option<Duration> {
+	interval
+		.checked_sub(now.saturating_duration_since(sent_at))
+		.filter(|remaining| !remaining.is_zero())
+}
+
+fn identity_url(base: &Url, path: &str) -> Url { base.join(path).expect("path is a valid relative URL") }
+
+/// The lookup hash of the identity service API v2 for an address.
+#[cfg(feature = "email")]
+fn sha256_lookup(address: &str, medium: &Medium, pepper: &str) -> String {
+	let lookup = format!("{address} {medium} {pepper}");
+	general_purpose::URL_SAFE_NO_PAD.encode(Sha256::digest(lookup.as_bytes()))
+}
+
+/// Identity servers are refused without the "email" feature, so nothing is
+/// ever looked up.
+#[cfg(not(feature = "email"))]
+fn sha256_lookup(_address: &str, _medium: &Medium, _pepper: &str) -> String {
+	unreachable!("identity servers require the \"email\" feature")
+}
+
+/// Sends a request to the identity server and deserializes the response.
+async fn identity_request<T: DeserializeOwned>(request: reqwest::RequestBuilder) -> Result<T> {
+	let response = request.send().await.map_err(|e| {
+		debug!("Identity server request failed: {e}");
+		Error::BadServerResponse("Failed to reach the identity server.")
+	})?;
+
+	if !response.status().is_success() {
+		debug!("Identity server responded with {}", response.status());
+		return Err(Error::BadServerResponse("Identity server rejected the request."));
+	}
+
+	response.json().await.map_err(|e| {
+		debug!("Invalid identity server response: {e}");
+		Error::BadServerResponse("Invalid response from the identity server.")
+	})
+}
+
+fn subject(purpose: Purpose) -> String {
+	let server_name = services().globals.server_name();
+	match purpose {
+		Purpose::Register => format!("Validate your email address to register on {server_name}"),
+		Purpose::AddThreepid => format!("Validate your email address on {server_name}"),
+		Purpose::PasswordReset => format!("Reset your password on {server_name}"),
+	}
+}
+
+fn email_body(purpose: Purpose, session: &ValidationSession) -> String {
+	let server_name = services().globals.server_name();
+	let action = match purpose {
+		Purpose::Register => "register an account with this email address",
+		Purpose::AddThreepid => "add this email address to your account",
+		Purpose::PasswordReset => "reset the password of your account",
+	};
+
+	format!(
+		"Someone asked to {action} on {server_name}. If this was you, open the following link to \
+		 continue:\n\n{}\n\nIf you did not ask for this, you can ignore this email.\n",
+		validation_link(session)
+	)
+}
+
+fn validation_link(session: &ValidationSession) -> Url {
+	let mut url = submit_url();
+	url.query_pairs_mut()
+		.append_pair("sid", &session.sid)
+		.append_pair("client_secret", &session.client_secret)
+		.append_pair("token", &session.token);
+
+	url
+}
+
+/// URL clients submit validation tokens to, on the client base URL from the
+/// well-known config.
+#[must_use]
+pub fn submit_url() -> Url {
+	let base = services()
+		.globals
+		.config
+		.well_known
+		.client
+		.clone()
+		.unwrap_or_else(|| {
+			Url::parse(&format!("https://{}", services().globals.server_name())).expect("server name is a valid host")
+		});
+
+	base.join(SUBMIT_TOKEN_PATH)
+		.expect("path is a valid relative URL")
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{
+		collections::HashMap,
+		time::{Duration, Instant},
+	};
+
+	use ruma::thirdparty::Medium;
+
+	use super::{claim, limit_rate, retry_after, ValidationSession, EMAIL_INTERVAL, ONBIND_INTERVAL};
+
+	fn session() -> ValidationSession {
+		ValidationSession {
+			sid: "sid".to_owned(),
+			client_secret: "secret".to_owned(),
+			medium: Medium::Email,
+			address: "alice@example.com".to_owned(),
+			token: "token".to_owned(),
+			send_attempt: 1,
+			next_link: None,
+			expires_at: 0,
+			validated_at: Some(0),
+			uiaa_session: None,
+		}
+	}
+
+	#[test]
+	fn validation_session_is_claimed_once() {
+		let mut session = session();
+		assert!(claim(&mut session, "first"));
+		assert!(claim(&mut session, "first"), "the same UIAA session can retry");
+		assert!(!claim(&mut session, "second"));
+		assert_eq!(session.uiaa_session.as_deref(), Some("first"));
+	}
+
+	#[test]
+	fn emails_are_rate_limited() {
+		let sent_at = Instant::now();
+		assert_eq!(retry_after(EMAIL_INTERVAL, sent_at, sent_at), Some(EMAIL_INTERVAL));
+		assert_eq!(
+			retry_after(EMAIL_INTERVAL, sent_at, sent_at + Duration::from_secs(15)),
+			Some(EMAIL_INTERVAL - Duration::from_secs(15))
+		);
+		assert_eq!(retry_after(EMAIL_INTERVAL, sent_at, sent_at + EMAIL_INTERVAL), None);
+		assert_eq!(
+			retry_after(EMAIL_INTERVAL, sent_at + Duration::from_secs(1), sent_at),
+			Some(EMAIL_INTERVAL)
+		);
+	}
+
+	#[test]
+	fn actions_are_limited_per_key() {
+		let now = Instant::now();
+		let mut last_actions = HashMap::new();
+		let user = || Some("@user:example.com".to_owned());
+		let ip = || Some("192.0.2.1".to_owned());
+
+		assert_eq!(limit_rate(&mut last_actions, ONBIND_INTERVAL, [user(), ip()], now), None);
+		assert_eq!(
+			limit_rate(
+				&mut last_actions,
+				ONBIND_INTERVAL,
+				[Some("@other:example.com".to_owned()), ip()],
+				now
+			),
+			Some(ONBIND_INTERVAL)
+		);
+		assert_eq!(
+			limit_rate(&mut last_actions, ONBIND_INTERVAL, [user(), None], now + Duration::from_secs(4)),
+			Some(Duration::from_secs(6))
+		);
+		assert_eq!(
+			limit_rate(&mut last_actions, ONBIND_INTERVAL, [user(), ip()], now + ONBIND_INTERVAL),
+			None
+		);
+		assert_eq!(last_actions.len(), 2);
+	}
+}
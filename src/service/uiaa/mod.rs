@@ -2,15 +2,19 @@ mod data;
 
 use std::sync::Arc;
 
-use conduit::{utils, utils::hash, Error, Result};
+use conduit::{utils, Error, Result};
 use data::Data;
 use ruma::{
 	api::client::{
 		error::ErrorKind,
-		uiaa::{AuthData, AuthType, Password, UiaaInfo, UserIdentifier},
+		uiaa::{AuthData, AuthType, EmailIdentity, Password, UiaaInfo, UserIdentifier},
 	},
 	CanonicalJsonValue, DeviceId, UserId,
 };
+use serde_json::{
+	json,
+	value::{to_raw_value, RawValue as RawJsonValue},
+};
 use tracing::error;
 
 use crate::services;
@@ -41,7 +45,7 @@ impl Service {
 		)
 	}
 
-	pub fn try_auth(
+	pub async fn try_auth(
 		&self, user_id: &UserId, device_id: &DeviceId, auth: &AuthData, uiaainfo: &UiaaInfo,
 	) -> Result<(bool, UiaaInfo)> {
 		let mut uiaainfo = auth.session().map_or_else(
@@ -81,22 +85,22 @@ impl Service {
 					.map_err(|_| Error::BadRequest(ErrorKind::InvalidParam, "User ID is invalid."))?;
 
 				// Check if password is correct
-				if let Some(hash) = services().users.password_hash(&user_id)? {
-					let hash_matches = hash::verify_password(password, &hash).is_ok();
-					if !hash_matches {
-						uiaainfo.auth_error = Some(ruma::api::client::error::StandardErrorBody {
-							kind: ErrorKind::forbidden(),
-							message: "Invalid username or password.".to_owned(),
-						});
-						return Ok((false, uiaainfo));
-					}
+				if !services().users.check_password(&user_id, password).await? {
+					uiaainfo.auth_error = Some(ruma::api::client::error::StandardErrorBody {
+						kind: ErrorKind::forbidden(),
+						message: "Invalid username or password.".to_owned(),
+					});
+					return Ok((false, uiaainfo));
 				}
 
 				// Password was correct! Let's add it to `completed`
 				uiaainfo.completed.push(AuthType::Password);
 			},
 			AuthData::RegistrationToken(t) => {
-				if Some(t.token.trim()) == services().globals.config.registration_token.as_deref() {
+				if services()
+					.registration_tokens
+					.use_in_session(t.token.trim(), uiaainfo.session.as_ref().expect("session is always set"))?
+				{
 					uiaainfo.completed.push(AuthType::RegistrationToken);
 				} else {
 					uiaainfo.auth_error = Some(ruma::api::client::error::StandardErrorBody {
@@ -106,6 +110,27 @@ impl Service {
 					return Ok((false, uiaainfo));
 				}
 			},
+			AuthData::EmailIdentity(EmailIdentity {
+				thirdparty_id_creds,
+				..
+			}) => {
+				if services().threepid.use_in_uiaa_session(
+					uiaainfo.session.as_ref().expect("session is always set"),
+					thirdparty_id_creds.sid.as_str(),
+					thirdparty_id_creds.client_secret.as_str(),
+				)? {
+					uiaainfo.completed.push(AuthType::EmailIdentity);
+				} else {
+					uiaainfo.auth_error = Some(ruma::api::client::error::StandardErrorBody {
+						kind: ErrorKind::ThreepidAuthFailed,
+						message: "Email address has not been validated yet.".to_owned(),
+					});
+					return Ok((false, uiaainfo));
+				}
+			},
+			AuthData::Terms(_) => {
+				uiaainfo.completed.push(AuthType::Terms);
+			},
 			AuthData::Dummy(_) => {
 				uiaainfo.completed.push(AuthType::Dummy);
 			},
@@ -144,6 +169,37 @@ impl Service {
 		Ok((true, uiaainfo))
 	}
 
+	/// Parameters of the `m.login.terms` stage describing the current terms of
+	/// service, if users have to accept any.
+	#[must_use]
+	pub fn terms_params(&self) -> Option<Box<RawJsonValue>> {
+		let terms = &services().globals.config.terms;
+		let (Some(version), Some(url)) = (&terms.version, &terms.url) else {
+			return None;
+		};
+
+		let mut policy = serde_json::Map::new();
+		policy.insert("version".to_owned(), version.clone().into());
+		policy.insert(
+			terms.language.clone(),
+			json!({
+				"name": terms.name,
+				"url": url,
+			}),
+		);
+
+		Some(
+			to_raw_value(&json!({
+				"m.login.terms": {
+					"policies": {
+						"terms_of_service": policy,
+					},
+				},
+			}))
+			.expect("terms params are valid json"),
+		)
+	}
+
 	#[must_use]
 	pub fn get_uiaa_request(
 		&self, user_id: &UserId, device_id: &DeviceId, session: &str,
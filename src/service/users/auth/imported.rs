@@ -0,0 +1,67 @@
+#[cfg(feature = "bcrypt")]
+use async_trait::async_trait;
+#[cfg(feature = "bcrypt")]
+use ruma::UserId;
+#[cfg(feature = "bcrypt")]
+use tracing::info;
+
+#[cfg(feature = "bcrypt")]
+use super::{PasswordCheck, PasswordProvider};
+#[cfg(feature = "bcrypt")]
+use crate::{services, Result};
+
+/// Checks passwords against bcrypt hashes imported from another homeserver
+/// such as Synapse, and re-hashes them with Argon2 on success.
+#[cfg(feature = "bcrypt")]
+pub(super) struct Provider {
+	/// Synapse's `password_config.pepper`, appended to the password before
+	/// hashing
+	pub(super) pepper: Option<String>,
+}
+
+#[cfg(feature = "bcrypt")]
+#[async_trait]
+impl PasswordProvider for Provider {
+	fn name(&self) -> &'static str { "imported bcrypt" }
+
+	async fn check_password(&self, user_id: &UserId, password: &str) -> Result<PasswordCheck> {
+		let Some(hash) = services().users.password_hash(user_id)? else {
+			return Ok(PasswordCheck::Unknown);
+		};
+
+		if !is_bcrypt_hash(&hash) {
+			return Ok(PasswordCheck::Unknown);
+		}
+
+		let peppered = format!("{password}{}", self.pepper.as_deref().unwrap_or_default());
+		if !bcrypt::verify(peppered, &hash).unwrap_or(false) {
+			return Ok(PasswordCheck::Rejected);
+		}
+
+		services().users.set_password(user_id, Some(password))?;
+		info!("Re-hashed the imported bcrypt password of {user_id} with Argon2");
+
+		Ok(PasswordCheck::Accepted(None))
+	}
+}
+
+/// Whether a stored password hash is a bcrypt hash this provider can check
+#[must_use]
+pub fn is_bcrypt_hash(hash: &str) -> bool {
+	["$2a$", "$2b$", "$2x$", "$2y$"]
+		.iter()
+		.any(|prefix| hash.starts_with(prefix))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::is_bcrypt_hash;
+
+	#[test]
+	fn detects_bcrypt_hashes() {
+		assert!(is_bcrypt_hash("$2b$12$DExmQ4z1zLC7sBtvTWI6/.xVpa5UVh2nW6dnp7ZfbpFH6pQyXg3Pq"));
+		assert!(is_bcrypt_hash("$2y$10$abcdefghijklmnopqrstuu"));
+		assert!(!is_bcrypt_hash("$argon2id$v=19$m=19456,t=2,p=1$c2FsdA$aGFzaA"));
+		assert!(!is_bcrypt_hash(""));
+	}
+}
@@ -0,0 +1,151 @@
+use async_trait::async_trait;
+use ldap3::{dn_escape, ldap_escape, Ldap, LdapConnAsync, LdapConnSettings, LdapError, Scope, SearchEntry};
+use ruma::UserId;
+use tracing::debug;
+
+use super::{ExternalUser, PasswordCheck, PasswordProvider};
+use crate::{config::LdapConfig, Error, Result};
+
+/// LDAP result code returned for a bind with a wrong password or unknown DN
+const INVALID_CREDENTIALS: u32 = 49;
+
+/// Authenticates users against an LDAP directory, either by binding as them
+/// directly or by searching for their entry first.
+pub(super) struct Provider {
+	pub(super) config: LdapConfig,
+}
+
+#[async_trait]
+impl PasswordProvider for Provider {
+	fn name(&self) -> &'static str { "LDAP" }
+
+	async fn check_password(&self, user_id: &UserId, password: &str) -> Result<PasswordCheck> {
+		// users unknown to the directory or with a wrong directory password may still
+		// have a local password
+		Ok(authenticate(&self.config, user_id.localpart(), password)
+			.await?
+			.map_or(PasswordCheck::Unknown, |user| PasswordCheck::Accepted(Some(user))))
+	}
+}
+
+/// Authenticates a localpart and password against the directory.
+///
+/// Returns `None` if the user could not be found or the password is wrong.
+async fn authenticate(config: &LdapConfig, localpart: &str, password: &str) -> Result<Option<ExternalUser>> {
+	// an empty password would turn the bind into an unauthenticated bind, which
+	// most directories accept without checking anything
+	if password.is_empty() {
+		return Ok(None);
+	}
+
+	let uri = config
+		.uri
+		.as_ref()
+		.ok_or_else(|| Error::bad_config("LDAP authentication is enabled but \"ldap.uri\" is not set."))?;
+
+	let settings = LdapConnSettings::new().set_starttls(config.starttls);
+	let (conn, mut ldap) = LdapConnAsync::with_settings(settings, uri.as_str())
+		.await
+		.map_err(ldap_error)?;
+	ldap3::drive!(conn);
+
+	let direct_dn = config
+		.bind_dn
+		.as_ref()
+		.filter(|bind_dn| bind_dn.contains("{username}"))
+		.map(|bind_dn| bind_dn.replace("{username}", &dn_escape(localpart)));
+
+	let dn = if let Some(dn) = direct_dn {
+		if !bind(&mut ldap, &dn, password).await? {
+			return Ok(None);
+		}
+
+		dn
+	} else {
+		if let Some(bind_dn) = &config.bind_dn {
+			let bind_password = match &config.bind_password_file {
+				Some(path) => tokio::fs::read_to_string(path).await?.trim_end().to_owned(),
+				None => String::new(),
+			};
+
+			if !bind(&mut ldap, bind_dn, &bind_password).await? {
+				return Err(Error::Err("LDAP service account credentials were rejected".to_owned()));
+			}
+		}
+
+		let filter = format!("(&{}({}={}))", config.filter, config.uid_attribute, ldap_escape(localpart));
+		let (entries, _) = ldap
+			.search(&config.base_dn, Scope::Subtree, &filter, vec!["1.1"])
+			.await
+			.and_then(|result| result.success())
+			.map_err(ldap_error)?;
+
+		if entries.len() != 1 {
+			debug!(
+				"LDAP search for {localpart} returned {} entries, expected exactly one",
+				entries.len()
+			);
+			return Ok(None);
+		}
+
+		let dn = entries
+			.into_iter()
+			.next()
+			.map(|entry| SearchEntry::construct(entry).dn)
+			.expect("checked that there is exactly one entry");
+
+		if !bind(&mut ldap, &dn, password).await? {
+			return Ok(None);
+		}
+
+		dn
+	};
+
+	let (entries, _) = ldap
+		.search(&dn, Scope::Base, "(objectClass=*)", vec![config.name_attribute.as_str()])
+		.await
+		.and_then(|result| result.success())
+		.map_err(ldap_error)?;
+
+	let displayname = entries
+		.into_iter()
+		.next()
+		.map(SearchEntry::construct)
+		.and_then(|mut entry| entry.attrs.remove(&config.name_attribute))
+		.and_then(|values| values.into_iter().next());
+
+	let is_admin = if let Some(admin_filter) = &config.admin_filter {
+		let filter = admin_filter.replace("{username}", &ldap_escape(localpart));
+		let base_dn = config.admin_base_dn.as_ref().unwrap_or(&config.base_dn);
+		let (entries, _) = ldap
+			.search(base_dn, Scope::Subtree, &filter, vec!["1.1"])
+			.await
+			.and_then(|result| result.success())
+			.map_err(ldap_error)?;
+
+		Some(!entries.is_empty())
+	} else {
+		None
+	};
+
+	ldap.unbind().await.map_err(ldap_error)?;
+
+	Ok(Some(ExternalUser {
+		displayname,
+		is_admin,
+		create: config.create_users,
+	}))
+}
+
+/// Binds as `dn`, returning false if the credentials were rejected.
+async fn bind(ldap: &mut Ldap, dn: &str, password: &str) -> Result<bool> {
+	let result = ldap.simple_bind(dn, password).await.map_err(ldap_error)?;
+	if result.rc == INVALID_CREDENTIALS {
+		return Ok(false);
+	}
+
+	result.success().map_err(ldap_error)?;
+	Ok(true)
+}
+
+fn ldap_error(e: LdapError) -> Error { Error::Err(format!("LDAP error: {e}")) }
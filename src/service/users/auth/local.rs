@@ -0,0 +1,32 @@
+use async_trait::async_trait;
+use conduit::utils::hash;
+use ruma::UserId;
+
+use super::{PasswordCheck, PasswordProvider};
+use crate::{services, Result};
+
+/// Checks passwords against the Argon2 hashes stored in the database.
+pub(super) struct Provider;
+
+#[async_trait]
+impl PasswordProvider for Provider {
+	fn name(&self) -> &'static str { "local" }
+
+	async fn check_password(&self, user_id: &UserId, password: &str) -> Result<PasswordCheck> {
+		let Some(hash) = services().users.password_hash(user_id)? else {
+			return Ok(PasswordCheck::Unknown);
+		};
+
+		// deactivated accounts have an empty hash, other hash formats are handled by
+		// the imported hash provider
+		if !hash.starts_with("$argon2") {
+			return Ok(PasswordCheck::Unknown);
+		}
+
+		if hash::verify_password(password, &hash).is_ok() {
+			Ok(PasswordCheck::Accepted(None))
+		} else {
+			Ok(PasswordCheck::Rejected)
+		}
+	}
+}
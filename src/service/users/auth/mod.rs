@@ -0,0 +1,71 @@
+mod imported;
+#[cfg(feature = "ldap")]
+mod ldap;
+mod local;
+mod rest;
+
+use async_trait::async_trait;
+pub use imported::is_bcrypt_hash;
+use ruma::UserId;
+
+use crate::{Config, Result};
+
+/// A source of truth for user passwords, consulted by password login and the
+/// UIAA password stage.
+#[async_trait]
+pub trait PasswordProvider: Send + Sync {
+	/// Name of the provider used in logs and admin room notices
+	fn name(&self) -> &'static str;
+
+	/// Checks the password of a local user.
+	async fn check_password(&self, user_id: &UserId, password: &str) -> Result<PasswordCheck>;
+}
+
+pub enum PasswordCheck {
+	/// The password is correct. External providers return the user as known to
+	/// them so the local account can be created or updated.
+	Accepted(Option<ExternalUser>),
+	/// The password is wrong, no further providers are consulted
+	Rejected,
+	/// The provider does not know about the user, the next provider is
+	/// consulted
+	Unknown,
+}
+
+pub struct ExternalUser {
+	pub displayname: Option<String>,
+	/// `None` if the provider does not manage admin privileges
+	pub is_admin: Option<bool>,
+	/// Whether a local account should be created if there is none yet
+	pub create: bool,
+}
+
+/// Builds the provider chain in the order providers are consulted: external
+/// directories first, then the local Argon2 hashes, then imported hashes if
+/// built with the "bcrypt" feature.
+#[must_use]
+pub fn build(config: &Config) -> Vec<Box<dyn PasswordProvider>> {
+	let mut providers: Vec<Box<dyn PasswordProvider>> = Vec::new();
+
+	#[cfg(feature = "ldap")]
+	if config.ldap.enable {
+		providers.push(Box::new(ldap::Provider {
+			config: config.ldap.clone(),
+		}));
+	}
+
+	if let Some(endpoint) = &config.rest_auth.endpoint {
+		providers.push(Box::new(rest::Provider {
+			endpoint: endpoint.clone(),
+			create_users: config.rest_auth.create_users,
+		}));
+	}
+
+	providers.push(Box::new(local::Provider));
+	#[cfg(feature = "bcrypt")]
+	providers.push(Box::new(imported::Provider {
+		pepper: config.imported_password_pepper.clone(),
+	}));
+
+	providers
+}
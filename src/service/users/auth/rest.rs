@@ -0,0 +1,91 @@
+use async_trait::async_trait;
+use ruma::UserId;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use super::{ExternalUser, PasswordCheck, PasswordProvider};
+use crate::{services, Error, Result};
+
+/// Checks passwords with an external HTTP service, using the protocol of the
+/// Synapse REST password provider and ma1sd.
+pub(super) struct Provider {
+	pub(super) endpoint: Url,
+	pub(super) create_users: bool,
+}
+
+#[derive(Serialize)]
+struct CheckCredentials<'a> {
+	user: Credentials<'a>,
+}
+
+#[derive(Serialize)]
+struct Credentials<'a> {
+	id: &'a UserId,
+	password: &'a str,
+}
+
+#[derive(Deserialize)]
+struct CheckCredentialsResponse {
+	auth: AuthResponse,
+}
+
+#[derive(Deserialize)]
+struct AuthResponse {
+	success: bool,
+	#[serde(default)]
+	profile: Option<Profile>,
+}
+
+#[derive(Deserialize)]
+struct Profile {
+	display_name: Option<String>,
+}
+
+#[async_trait]
+impl PasswordProvider for Provider {
+	fn name(&self) -> &'static str { "REST auth" }
+
+	async fn check_password(&self, user_id: &UserId, password: &str) -> Result<PasswordCheck> {
+		let url = self
+			.endpoint
+			.join("_matrix-internal/identity/v1/check_credentials")
+			.map_err(|e| Error::Err(format!("Invalid REST auth endpoint: {e}")))?;
+
+		let body = serde_json::to_vec(&CheckCredentials {
+			user: Credentials {
+				id: user_id,
+				password,
+			},
+		})
+		.expect("credentials serialize to json");
+
+		let response = services()
+			.globals
+			.client
+			.default
+			.post(url)
+			.header(http::header::CONTENT_TYPE, "application/json")
+			.body(body)
+			.send()
+			.await?
+			.error_for_status()?
+			.bytes()
+			.await?;
+
+		let response: CheckCredentialsResponse = serde_json::from_slice(&response)
+			.map_err(|e| Error::Err(format!("Invalid response from the REST auth endpoint: {e}")))?;
+
+		if !response.auth.success {
+			return Ok(PasswordCheck::Unknown);
+		}
+
+		Ok(PasswordCheck::Accepted(Some(ExternalUser {
+			displayname: response
+				.auth
+				.profile
+				.and_then(|profile| profile.display_name),
+			is_admin: None,
+			create: self.create_users,
+		})))
+	}
+}
@@ -1,4 +1,4 @@
-use std::{collections::BTreeMap, mem::size_of};
+use std::{collections::BTreeMap, mem::size_of, net::IpAddr};
 
 use ruma::{
 	api::client::{device::Device, error::ErrorKind, filter::FilterDefinition},
@@ -19,6 +19,46 @@ pub trait Data: Send + Sync {
 	/// Check if account is deactivated
 	fn is_deactivated(&self, user_id: &UserId) -> Result<bool>;
 
+	/// Suspends or unsuspends an account. Suspended accounts are read-only.
+	fn set_suspended(&self, user_id: &UserId, suspended: bool) -> Result<()>;
+
+	/// Returns since when an account is suspended, in milliseconds since the
+	/// unix epoch.
+	fn suspended_since(&self, user_id: &UserId) -> Result<Option<u64>>;
+
+	/// Locks or unlocks an account. Locked accounts cannot make any requests.
+	fn set_locked(&self, user_id: &UserId, locked: bool) -> Result<()>;
+
+	/// Returns since when an account is locked, in milliseconds since the unix
+	/// epoch.
+	fn locked_since(&self, user_id: &UserId) -> Result<Option<u64>>;
+
+	/// Shadow-bans or un-shadow-bans an account.
+	fn set_shadow_banned(&self, user_id: &UserId, shadow_banned: bool) -> Result<()>;
+
+	/// Returns since when an account is shadow-banned, in milliseconds since
+	/// the unix epoch.
+	fn shadow_banned_since(&self, user_id: &UserId) -> Result<Option<u64>>;
+
+	/// Marks an account as erased, which cannot be undone.
+	fn set_erased(&self, user_id: &UserId) -> Result<()>;
+
+	/// Returns since when an account is erased, in milliseconds since the unix
+	/// epoch.
+	fn erased_since(&self, user_id: &UserId) -> Result<Option<u64>>;
+
+	/// Records the version of the terms of service a user accepted.
+	fn set_accepted_terms_version(&self, user_id: &UserId, version: &str) -> Result<()>;
+
+	/// Returns the version of the terms of service a user accepted last.
+	fn accepted_terms_version(&self, user_id: &UserId) -> Result<Option<String>>;
+
+	/// Sets the token authenticating a user on the consent page.
+	fn set_consent_token(&self, user_id: &UserId, token: &str) -> Result<()>;
+
+	/// Returns the token authenticating a user on the consent page.
+	fn consent_token(&self, user_id: &UserId) -> Result<Option<String>>;
+
 	/// Returns the number of users registered on this server.
 	fn count(&self) -> Result<usize>;
 
@@ -40,6 +80,10 @@ pub trait Data: Send + Sync {
 	/// Hash and set the user's password to the Argon2 hash
 	fn set_password(&self, user_id: &UserId, password: Option<&str>) -> Result<()>;
 
+	/// Stores an already hashed password as is, e.g. one imported from
+	/// another homeserver
+	fn set_password_hash(&self, user_id: &UserId, hash: &str) -> Result<()>;
+
 	/// Returns the displayname of a user on this homeserver.
 	fn displayname(&self, user_id: &UserId) -> Result<Option<String>>;
 
@@ -62,6 +106,7 @@ pub trait Data: Send + Sync {
 	/// Adds a new device to a user.
 	fn create_device(
 		&self, user_id: &UserId, device_id: &DeviceId, token: &str, initial_device_display_name: Option<String>,
+		client_ip: Option<IpAddr>, user_agent: Option<&str>,
 	) -> Result<()>;
 
 	/// Removes a device from a user.
@@ -73,6 +118,36 @@ pub trait Data: Send + Sync {
 	/// Replaces the access token of one device.
 	fn set_token(&self, user_id: &UserId, device_id: &DeviceId, token: &str) -> Result<()>;
 
+	/// Sets the expiry timestamp in milliseconds of an access token.
+	fn set_token_expires_at(&self, token: &str, expires_at: u64) -> Result<()>;
+
+	/// Returns the expiry timestamp of an access token, if it expires at all.
+	fn token_expires_at(&self, token: &str) -> Result<Option<u64>>;
+
+	/// Returns all access tokens which expired before `now`.
+	fn expired_tokens<'a>(&'a self, now: u64) -> Box<dyn Iterator<Item = Result<String>> + 'a>;
+
+	/// Rotates the refresh token of one device. The previous refresh token is
+	/// remembered so that reuse of a rotated token can be detected.
+	fn set_refresh_token(&self, user_id: &UserId, device_id: &DeviceId, refresh_token: &str) -> Result<()>;
+
+	/// Revokes the current and previous refresh token of one device.
+	fn remove_refresh_tokens(&self, user_id: &UserId, device_id: &DeviceId) -> Result<()>;
+
+	/// Find out which device a refresh token belongs to, and whether it is the
+	/// device's current refresh token (`true`) or an already rotated one
+	/// (`false`).
+	fn find_from_refresh_token(&self, refresh_token: &str) -> Result<Option<(OwnedUserId, OwnedDeviceId, bool)>>;
+
+	/// Creates a short-lived login token, which can be used to log in using the
+	/// `m.login.token` mechanism. Returns the expiry timestamp in milliseconds.
+	fn create_login_token(&self, user_id: &UserId, token: &str, ttl: u64) -> Result<u64>;
+
+	/// Find out which user a login token belongs to. The token is consumed
+	/// regardless of whether it is still valid. Callers must make sure this is
+	/// not called concurrently for the same token.
+	fn find_from_login_token(&self, token: &str) -> Result<Option<OwnedUserId>>;
+
 	fn add_one_time_key(
 		&self, user_id: &UserId, device_id: &DeviceId, one_time_key_key: &DeviceKeyId,
 		one_time_key_value: &Raw<OneTimeKey>,
@@ -137,6 +212,16 @@ pub trait Data: Send + Sync {
 	/// Get device metadata.
 	fn get_device_metadata(&self, user_id: &UserId, device_id: &DeviceId) -> Result<Option<Device>>;
 
+	/// Records the IP address, user agent and time a device was last seen
+	/// at, without announcing a device list update.
+	fn set_device_last_seen(
+		&self, user_id: &UserId, device_id: &DeviceId, client_ip: Option<IpAddr>, user_agent: Option<&str>,
+		last_seen_ts: MilliSecondsSinceUnixEpoch,
+	) -> Result<()>;
+
+	/// Returns the user agent a device was last seen with.
+	fn device_user_agent(&self, user_id: &UserId, device_id: &DeviceId) -> Result<Option<String>>;
+
 	fn get_devicelist_version(&self, user_id: &UserId) -> Result<Option<u64>>;
 
 	fn all_devices_metadata<'a>(&'a self, user_id: &UserId) -> Box<dyn Iterator<Item = Result<Device>> + 'a>;
@@ -160,6 +245,107 @@ impl Data for KeyValueDatabase {
 			.is_empty())
 	}
 
+	fn set_suspended(&self, user_id: &UserId, suspended: bool) -> Result<()> {
+		if suspended {
+			self.userid_suspendedsince
+				.insert(user_id.as_bytes(), &utils::millis_since_unix_epoch().to_be_bytes())
+		} else {
+			self.userid_suspendedsince.remove(user_id.as_bytes())
+		}
+	}
+
+	fn suspended_since(&self, user_id: &UserId) -> Result<Option<u64>> {
+		self.userid_suspendedsince
+			.get(user_id.as_bytes())?
+			.map(|bytes| {
+				utils::u64_from_bytes(&bytes)
+					.map_err(|_| Error::bad_database("Invalid timestamp in userid_suspendedsince."))
+			})
+			.transpose()
+	}
+
+	fn set_locked(&self, user_id: &UserId, locked: bool) -> Result<()> {
+		if locked {
+			self.userid_lockedsince
+				.insert(user_id.as_bytes(), &utils::millis_since_unix_epoch().to_be_bytes())
+		} else {
+			self.userid_lockedsince.remove(user_id.as_bytes())
+		}
+	}
+
+	fn locked_since(&self, user_id: &UserId) -> Result<Option<u64>> {
+		self.userid_lockedsince
+			.get(user_id.as_bytes())?
+			.map(|bytes| {
+				utils::u64_from_bytes(&bytes)
+					.map_err(|_| Error::bad_database("Invalid timestamp in userid_lockedsince."))
+			})
+			.transpose()
+	}
+
+	fn set_shadow_banned(&self, user_id: &UserId, shadow_banned: bool) -> Result<()> {
+		if shadow_banned {
+			self.userid_shadowbannedsince
+				.insert(user_id.as_bytes(), &utils::millis_since_unix_epoch().to_be_bytes())
+		} else {
+			self.userid_shadowbannedsince.remove(user_id.as_bytes())
+		}
+	}
+
+	fn shadow_banned_since(&self, user_id: &UserId) -> Result<Option<u64>> {
+		self.userid_shadowbannedsince
+			.get(user_id.as_bytes())?
+			.map(|bytes| {
+				utils::u64_from_bytes(&bytes)
+					.map_err(|_| Error::bad_database("Invalid timestamp in userid_shadowbannedsince."))
+			})
+			.transpose()
+	}
+
+	fn set_erased(&self, user_id: &UserId) -> Result<()> {
+		self.erased_users
+			.insert(user_id.as_bytes(), &utils::millis_since_unix_epoch().to_be_bytes())
+	}
+
+	fn erased_since(&self, user_id: &UserId) -> Result<Option<u64>> {
+		self.erased_users
+			.get(user_id.as_bytes())?
+			.map(|bytes| {
+				utils::u64_from_bytes(&bytes).map_err(|_| Error::bad_database("Invalid timestamp in erased_users."))
+			})
+			.transpose()
+	}
+
+	fn set_accepted_terms_version(&self, user_id: &UserId, version: &str) -> Result<()> {
+		self.userid_termsversion
+			.insert(user_id.as_bytes(), version.as_bytes())
+	}
+
+	fn accepted_terms_version(&self, user_id: &UserId) -> Result<Option<String>> {
+		self.userid_termsversion
+			.get(user_id.as_bytes())?
+			.map(|bytes| {
+				utils::string_from_bytes(&bytes)
+					.map_err(|_| Error::bad_database("Terms version in userid_termsversion is invalid."))
+			})
+			.transpose()
+	}
+
+	fn set_consent_token(&self, user_id: &UserId, token: &str) -> Result<()> {
+		self.userid_consenttoken
+			.insert(user_id.as_bytes(), token.as_bytes())
+	}
+
+	fn consent_token(&self, user_id: &UserId) -> Result<Option<String>> {
+		self.userid_consenttoken
+			.get(user_id.as_bytes())?
+			.map(|bytes| {
+				utils::string_from_bytes(&bytes)
+					.map_err(|_| Error::bad_database("Consent token in userid_consenttoken is invalid."))
+			})
+			.transpose()
+	}
+
 	/// Returns the number of users registered on this server.
 	fn count(&self) -> Result<usize> { Ok(self.userid_password.iter().count()) }
 
@@ -242,6 +428,11 @@ impl Data for KeyValueDatabase {
 		}
 	}
 
+	fn set_password_hash(&self, user_id: &UserId, hash: &str) -> Result<()> {
+		self.userid_password
+			.insert(user_id.as_bytes(), hash.as_bytes())
+	}
+
 	/// Returns the displayname of a user on this homeserver.
 	fn displayname(&self, user_id: &UserId) -> Result<Option<String>> {
 		self.userid_displayname
@@ -322,6 +513,7 @@ impl Data for KeyValueDatabase {
 	/// Adds a new device to a user.
 	fn create_device(
 		&self, user_id: &UserId, device_id: &DeviceId, token: &str, initial_device_display_name: Option<String>,
+		client_ip: Option<IpAddr>, user_agent: Option<&str>,
 	) -> Result<()> {
 		// This method should never be called for nonexistent users. We shouldn't assert
 		// though...
@@ -342,12 +534,17 @@ impl Data for KeyValueDatabase {
 			&serde_json::to_vec(&Device {
 				device_id: device_id.into(),
 				display_name: initial_device_display_name,
-				last_seen_ip: None, // TODO
+				last_seen_ip: client_ip.map(|ip| ip.to_string()),
 				last_seen_ts: Some(MilliSecondsSinceUnixEpoch::now()),
 			})
 			.expect("Device::to_string never fails."),
 		)?;
 
+		if let Some(user_agent) = user_agent {
+			self.userdeviceid_useragent
+				.insert(&userdeviceid, user_agent.as_bytes())?;
+		}
+
 		self.set_token(user_id, device_id, token)?;
 
 		Ok(())
@@ -359,12 +556,17 @@ impl Data for KeyValueDatabase {
 		userdeviceid.push(0xFF);
 		userdeviceid.extend_from_slice(device_id.as_bytes());
 
+		self.userdeviceid_useragent.remove(&userdeviceid)?;
+
 		// Remove tokens
 		if let Some(old_token) = self.userdeviceid_token.get(&userdeviceid)? {
 			self.userdeviceid_token.remove(&userdeviceid)?;
 			self.token_userdeviceid.remove(&old_token)?;
+			self.token_expiresat.remove(&old_token)?;
 		}
 
+		self.remove_refresh_tokens(user_id, device_id)?;
+
 		// Remove todevice events
 		let mut prefix = userdeviceid.clone();
 		prefix.push(0xFF);
@@ -424,6 +626,7 @@ impl Data for KeyValueDatabase {
 		// Remove old token
 		if let Some(old_token) = self.userdeviceid_token.get(&userdeviceid)? {
 			self.token_userdeviceid.remove(&old_token)?;
+			self.token_expiresat.remove(&old_token)?;
 			// It will be removed from userdeviceid_token by the insert later
 		}
 
@@ -436,6 +639,140 @@ impl Data for KeyValueDatabase {
 		Ok(())
 	}
 
+	fn set_token_expires_at(&self, token: &str, expires_at: u64) -> Result<()> {
+		self.token_expiresat
+			.insert(token.as_bytes(), &expires_at.to_be_bytes())
+	}
+
+	fn token_expires_at(&self, token: &str) -> Result<Option<u64>> {
+		self.token_expiresat
+			.get(token.as_bytes())?
+			.map(|bytes| {
+				utils::u64_from_bytes(&bytes).map_err(|_| Error::bad_database("Invalid expires_at in token_expiresat."))
+			})
+			.transpose()
+	}
+
+	fn expired_tokens<'a>(&'a self, now: u64) -> Box<dyn Iterator<Item = Result<String>> + 'a> {
+		Box::new(
+			self.token_expiresat
+				.iter()
+				.filter_map(move |(token, expires_at)| {
+					match utils::u64_from_bytes(&expires_at) {
+						Ok(expires_at) if expires_at >= now => return None,
+						Ok(_) => {},
+						Err(_) => return Some(Err(Error::bad_database("Invalid expires_at in token_expiresat."))),
+					}
+
+					Some(
+						utils::string_from_bytes(&token)
+							.map_err(|_| Error::bad_database("Token in token_expiresat is invalid unicode.")),
+					)
+				}),
+		)
+	}
+
+	fn set_refresh_token(&self, user_id: &UserId, device_id: &DeviceId, refresh_token: &str) -> Result<()> {
+		let mut userdeviceid = user_id.as_bytes().to_vec();
+		userdeviceid.push(0xFF);
+		userdeviceid.extend_from_slice(device_id.as_bytes());
+
+		let old_refresh_tokens = self.userdeviceid_refreshtoken.get(&userdeviceid)?;
+		let (refresh_tokens, forgotten) = rotate_refresh_tokens(refresh_token, old_refresh_tokens.as_deref());
+		if let Some(forgotten) = forgotten {
+			self.refreshtoken_userdeviceid.remove(forgotten)?;
+		}
+
+		self.userdeviceid_refreshtoken
+			.insert(&userdeviceid, &refresh_tokens)?;
+		self.refreshtoken_userdeviceid
+			.insert(refresh_token.as_bytes(), &userdeviceid)?;
+
+		Ok(())
+	}
+
+	fn remove_refresh_tokens(&self, user_id: &UserId, device_id: &DeviceId) -> Result<()> {
+		let mut userdeviceid = user_id.as_bytes().to_vec();
+		userdeviceid.push(0xFF);
+		userdeviceid.extend_from_slice(device_id.as_bytes());
+
+		if let Some(refresh_tokens) = self.userdeviceid_refreshtoken.get(&userdeviceid)? {
+			self.userdeviceid_refreshtoken.remove(&userdeviceid)?;
+			for refresh_token in refresh_tokens.split(|&b| b == 0xFF) {
+				self.refreshtoken_userdeviceid.remove(refresh_token)?;
+			}
+		}
+
+		Ok(())
+	}
+
+	fn find_from_refresh_token(&self, refresh_token: &str) -> Result<Option<(OwnedUserId, OwnedDeviceId, bool)>> {
+		let Some(userdeviceid) = self
+			.refreshtoken_userdeviceid
+			.get(refresh_token.as_bytes())?
+		else {
+			return Ok(None);
+		};
+
+		let mut parts = userdeviceid.split(|&b| b == 0xFF);
+		let user_id = utils::user_id_from_bytes(
+			parts
+				.next()
+				.ok_or_else(|| Error::bad_database("User ID in refreshtoken_userdeviceid is invalid."))?,
+		)?;
+		let device_id: OwnedDeviceId = utils::string_from_bytes(
+			parts
+				.next()
+				.ok_or_else(|| Error::bad_database("Device ID in refreshtoken_userdeviceid is invalid."))?,
+		)
+		.map_err(|_| Error::bad_database("Device ID in refreshtoken_userdeviceid is invalid."))?
+		.into();
+
+		let is_current = self
+			.userdeviceid_refreshtoken
+			.get(&userdeviceid)?
+			.is_some_and(|refresh_tokens| is_current_refresh_token(&refresh_tokens, refresh_token));
+
+		Ok(Some((user_id, device_id, is_current)))
+	}
+
+	fn create_login_token(&self, user_id: &UserId, token: &str, ttl: u64) -> Result<u64> {
+		let now = utils::millis_since_unix_epoch();
+		let expires_at = now.saturating_add(ttl);
+
+		// login tokens are short-lived, so this tree stays small enough to purge the
+		// expired ones whenever a new one is created
+		let expired = self
+			.logintoken_expiresatuserid
+			.iter()
+			.filter(|(_, value)| parse_login_token(value).map_or(true, |(expires_at, _)| expires_at < now))
+			.map(|(token, _)| token)
+			.collect::<Vec<_>>();
+		self.logintoken_expiresatuserid
+			.remove_batch(&mut expired.into_iter())?;
+
+		self.logintoken_expiresatuserid
+			.insert(token.as_bytes(), &login_token_value(expires_at, user_id))?;
+
+		Ok(expires_at)
+	}
+
+	fn find_from_login_token(&self, token: &str) -> Result<Option<OwnedUserId>> {
+		let Some(value) = self.logintoken_expiresatuserid.get(token.as_bytes())? else {
+			return Ok(None);
+		};
+
+		// login tokens are single-use
+		self.logintoken_expiresatuserid.remove(token.as_bytes())?;
+
+		let (expires_at, user_id) = parse_login_token(&value)?;
+		if expires_at < utils::millis_since_unix_epoch() {
+			return Ok(None);
+		}
+
+		Ok(Some(user_id))
+	}
+
 	fn add_one_time_key(
 		&self, user_id: &UserId, device_id: &DeviceId, one_time_key_key: &DeviceKeyId,
 		one_time_key_value: &Raw<OneTimeKey>,
@@ -949,6 +1286,51 @@ impl Data for KeyValueDatabase {
 			})
 	}
 
+	fn set_device_last_seen(
+		&self, user_id: &UserId, device_id: &DeviceId, client_ip: Option<IpAddr>, user_agent: Option<&str>,
+		last_seen_ts: MilliSecondsSinceUnixEpoch,
+	) -> Result<()> {
+		let mut userdeviceid = user_id.as_bytes().to_vec();
+		userdeviceid.push(0xFF);
+		userdeviceid.extend_from_slice(device_id.as_bytes());
+
+		// The device might have been deleted by a concurrent request
+		let Some(mut device) = self.get_device_metadata(user_id, device_id)? else {
+			return Ok(());
+		};
+
+		if let Some(client_ip) = client_ip {
+			device.last_seen_ip = Some(client_ip.to_string());
+		}
+		device.last_seen_ts = Some(last_seen_ts);
+
+		self.userdeviceid_metadata.insert(
+			&userdeviceid,
+			&serde_json::to_vec(&device).expect("Device::to_string always works"),
+		)?;
+
+		if let Some(user_agent) = user_agent {
+			self.userdeviceid_useragent
+				.insert(&userdeviceid, user_agent.as_bytes())?;
+		}
+
+		Ok(())
+	}
+
+	fn device_user_agent(&self, user_id: &UserId, device_id: &DeviceId) -> Result<Option<String>> {
+		let mut userdeviceid = user_id.as_bytes().to_vec();
+		userdeviceid.push(0xFF);
+		userdeviceid.extend_from_slice(device_id.as_bytes());
+
+		self.userdeviceid_useragent
+			.get(&userdeviceid)?
+			.map(|bytes| {
+				utils::string_from_bytes(&bytes)
+					.map_err(|_| Error::bad_database("User agent in userdeviceid_useragent is invalid unicode."))
+			})
+			.transpose()
+	}
+
 	fn get_devicelist_version(&self, user_id: &UserId) -> Result<Option<u64>> {
 		self.userid_devicelistversion
 			.get(user_id.as_bytes())?
@@ -1020,3 +1402,93 @@ fn get_username_with_valid_password(username: &[u8], password: &[u8]) -> Option<
 		}
 	}
 }
+
+/// Value of a login token: when it expires followed by the user it belongs to.
+fn login_token_value(expires_at: u64, user_id: &UserId) -> Vec<u8> {
+	let mut value = expires_at.to_be_bytes().to_vec();
+	value.extend_from_slice(user_id.as_bytes());
+	value
+}
+
+fn parse_login_token(value: &[u8]) -> Result<(u64, OwnedUserId)> {
+	if value.len() < size_of::<u64>() {
+		return Err(Error::bad_database("Invalid value in logintoken_expiresatuserid."));
+	}
+
+	let (expires_at_bytes, user_bytes) = value.split_at(size_of::<u64>());
+	let expires_at = utils::u64_from_bytes(expires_at_bytes)
+		.map_err(|_| Error::bad_database("Invalid expires_at in logintoken_expiresatuserid."))?;
+
+	Ok((expires_at, utils::user_id_from_bytes(user_bytes)?))
+}
+
+/// Puts a new refresh token in front of the refresh tokens of a device. The
+/// current refresh token becomes the previous one, which is kept around to
+/// detect reuse. Returns the new value and the token before that, which is
+/// forgotten.
+fn rotate_refresh_tokens<'a>(refresh_token: &str, old_refresh_tokens: Option<&'a [u8]>) -> (Vec<u8>, Option<&'a [u8]>) {
+	let mut refresh_tokens = refresh_token.as_bytes().to_vec();
+	let mut parts = old_refresh_tokens
+		.into_iter()
+		.flat_map(|old| old.split(|&b| b == 0xFF));
+
+	if let Some(current) = parts.next() {
+		refresh_tokens.push(0xFF);
+		refresh_tokens.extend_from_slice(current);
+	}
+
+	(refresh_tokens, parts.next())
+}
+
+fn is_current_refresh_token(refresh_tokens: &[u8], refresh_token: &str) -> bool {
+	refresh_tokens
+		.split(|&b| b == 0xFF)
+		.next()
+		.is_some_and(|current| current == refresh_token.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+	use ruma::user_id;
+
+	use super::{is_current_refresh_token, login_token_value, parse_login_token, rotate_refresh_tokens};
+
+	#[test]
+	fn refresh_tokens_rotate() {
+		let (first, forgotten) = rotate_refresh_tokens("first", None);
+		assert!(forgotten.is_none());
+		assert!(is_current_refresh_token(&first, "first"));
+
+		let (second, forgotten) = rotate_refresh_tokens("second", Some(&first));
+		assert!(forgotten.is_none());
+		assert!(is_current_refresh_token(&second, "second"));
+		assert!(!is_current_refresh_token(&second, "first"));
+
+		let (third, forgotten) = rotate_refresh_tokens("third", Some(&second));
+		assert_eq!(forgotten, Some(b"first".as_slice()));
+		assert_eq!(third, b"third\xFFsecond".as_slice());
+	}
+
+	#[test]
+	fn refresh_token_prefix_is_not_current() {
+		let (tokens, _) = rotate_refresh_tokens("token", None);
+		assert!(!is_current_refresh_token(&tokens, "tok"));
+		assert!(!is_current_refresh_token(&tokens, ""));
+	}
+
+	#[test]
+	fn login_token_value_roundtrip() {
+		let user_id = user_id!("@alice:example.com");
+		let value = login_token_value(1_700_000_000_000, user_id);
+
+		let (expires_at, parsed) = parse_login_token(&value).unwrap();
+		assert_eq!(expires_at, 1_700_000_000_000);
+		assert_eq!(parsed, user_id);
+	}
+
+	#[test]
+	fn login_token_value_rejects_truncated() {
+		assert!(parse_login_token(&[0, 1, 2]).is_err());
+		assert!(parse_login_token(&login_token_value(1, user_id!("@alice:example.com"))[..7]).is_err());
+	}
+}
@@ -1,10 +1,14 @@
+pub mod auth;
 mod data;
 use std::{
-	collections::{BTreeMap, BTreeSet},
+	collections::{BTreeMap, BTreeSet, HashMap},
 	mem,
+	net::IpAddr,
 	sync::{Arc, Mutex},
+	time::Duration,
 };
 
+use auth::{ExternalUser, PasswordCheck, PasswordProvider};
 use data::Data;
 use ruma::{
 	api::client::{
@@ -17,13 +21,16 @@ use ruma::{
 		},
 	},
 	encryption::{CrossSigningKey, DeviceKeys, OneTimeKey},
-	events::AnyToDeviceEvent,
+	events::{room::message::RoomMessageEventContent, AnyToDeviceEvent, GlobalAccountDataEventType},
+	push,
 	serde::Raw,
-	DeviceId, DeviceKeyAlgorithm, DeviceKeyId, OwnedDeviceId, OwnedDeviceKeyId, OwnedMxcUri, OwnedRoomId, OwnedUserId,
-	RoomAliasId, UInt, UserId,
+	DeviceId, DeviceKeyAlgorithm, DeviceKeyId, MilliSecondsSinceUnixEpoch, OwnedDeviceId, OwnedDeviceKeyId,
+	OwnedMxcUri, OwnedRoomId, OwnedUserId, RoomAliasId, UInt, UserId,
 };
+use tracing::{debug, error, info, warn};
+use url::Url;
 
-use crate::{services, Error, Result};
+use crate::{services, user_is_local, utils, Error, Result};
 
 pub struct SlidingSyncCache {
 	lists: BTreeMap<String, SyncRequestList>,
@@ -34,9 +41,52 @@ pub struct SlidingSyncCache {
 
 type DbConnections = Mutex<BTreeMap<(OwnedUserId, OwnedDeviceId, String), Arc<Mutex<SlidingSyncCache>>>>;
 
+/// The last seen info of a device as last written to the database
+pub struct LastSeen {
+	client_ip: Option<IpAddr>,
+	user_agent: Option<String>,
+	/// Milliseconds since the unix epoch
+	written_at: u64,
+}
+
+type DevicesLastSeen = Mutex<HashMap<(OwnedUserId, OwnedDeviceId), LastSeen>>;
+
+/// How often the last seen time of a device is written at most, unless its IP
+/// address or user agent changed
+const LAST_SEEN_WRITE_INTERVAL: u64 = 5 * 60 * 1000;
+
+/// Prefix of the ids of devices created for admins to impersonate a user,
+/// which are removed once their access token expired
+pub const IMPERSONATION_DEVICE_PREFIX: &str = "IMPERSONATION_";
+
+/// Length of the random part of impersonation device ids
+const IMPERSONATION_DEVICE_ID_LENGTH: usize = 10;
+
+/// Length of impersonation access tokens
+const IMPERSONATION_TOKEN_LENGTH: usize = 32;
+
+/// Lifetime of impersonation access tokens if no other is given, in
+/// milliseconds
+pub const IMPERSONATION_TOKEN_TTL: u64 = 60 * 60 * 1000;
+
+/// How often expired impersonation sessions are removed
+const IMPERSONATION_CLEANUP_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Path of the page on which users accept the terms of service
+pub const CONSENT_PATH: &str = "/_conduwuit/consent";
+
+/// Length of the tokens authenticating users on the consent page
+const CONSENT_TOKEN_LENGTH: usize = 32;
+
 pub struct Service {
 	pub db: Arc<dyn Data>,
 	pub connections: DbConnections,
+	pub last_seen: DevicesLastSeen,
+	pub auth_providers: Vec<Box<dyn PasswordProvider>>,
+	/// Makes consuming a login token atomic, so it can only be used once
+	pub login_token_mutex: Mutex<()>,
+	/// Makes rotating a refresh token atomic, so it can only be used once
+	pub refresh_token_mutex: Mutex<()>,
 }
 
 impl Service {
@@ -232,6 +282,115 @@ impl Service {
 	/// Check if account is deactivated
 	pub fn is_deactivated(&self, user_id: &UserId) -> Result<bool> { self.db.is_deactivated(user_id) }
 
+	/// Suspends or unsuspends an account (MSC3823). Suspended users can still
+	/// log in, sync and read, but cannot send events, join rooms or upload.
+	pub fn set_suspended(&self, user_id: &UserId, suspended: bool) -> Result<()> {
+		self.db.set_suspended(user_id, suspended)
+	}
+
+	/// Returns since when an account is suspended.
+	pub fn suspended_since(&self, user_id: &UserId) -> Result<Option<u64>> { self.db.suspended_since(user_id) }
+
+	/// Check if account is suspended
+	pub fn is_suspended(&self, user_id: &UserId) -> Result<bool> { Ok(self.suspended_since(user_id)?.is_some()) }
+
+	/// Locks or unlocks an account (MSC3939). Every request of a locked user
+	/// fails until the account is unlocked, but their devices stay logged in.
+	pub fn set_locked(&self, user_id: &UserId, locked: bool) -> Result<()> { self.db.set_locked(user_id, locked) }
+
+	/// Returns since when an account is locked.
+	pub fn locked_since(&self, user_id: &UserId) -> Result<Option<u64>> { self.db.locked_since(user_id) }
+
+	/// Check if account is locked
+	pub fn is_locked(&self, user_id: &UserId) -> Result<bool> { Ok(self.locked_since(user_id)?.is_some()) }
+
+	/// Shadow-bans or un-shadow-bans an account. Requests of shadow-banned
+	/// users appear to succeed, but their events, invites, joins and profile
+	/// changes are silently dropped.
+	pub fn set_shadow_banned(&self, user_id: &UserId, shadow_banned: bool) -> Result<()> {
+		self.db.set_shadow_banned(user_id, shadow_banned)
+	}
+
+	/// Returns since when an account is shadow-banned.
+	pub fn shadow_banned_since(&self, user_id: &UserId) -> Result<Option<u64>> { self.db.shadow_banned_since(user_id) }
+
+	/// Check if a local account is shadow-banned
+	pub fn is_shadow_banned(&self, user_id: &UserId) -> Result<bool> {
+		Ok(user_is_local(user_id) && self.shadow_banned_since(user_id)?.is_some())
+	}
+
+	/// Returns since when an account is erased.
+	pub fn erased_since(&self, user_id: &UserId) -> Result<Option<u64>> { self.db.erased_since(user_id) }
+
+	/// Check if an account was erased when it was deactivated
+	pub fn is_erased(&self, user_id: &UserId) -> Result<bool> {
+		Ok(user_is_local(user_id) && self.erased_since(user_id)?.is_some())
+	}
+
+	/// Records that a user accepted the current terms of service.
+	pub fn accept_terms(&self, user_id: &UserId) -> Result<()> {
+		match &services().globals.config.terms.version {
+			Some(version) => self.db.set_accepted_terms_version(user_id, version),
+			None => Ok(()),
+		}
+	}
+
+	/// Returns the version of the terms of service a user accepted last.
+	pub fn accepted_terms_version(&self, user_id: &UserId) -> Result<Option<String>> {
+		self.db.accepted_terms_version(user_id)
+	}
+
+	/// Check if a local user has yet to accept the current terms of service
+	pub fn needs_consent(&self, user_id: &UserId) -> Result<bool> {
+		let terms = &services().globals.config.terms;
+		if !terms.enabled() || !user_is_local(user_id) {
+			return Ok(false);
+		}
+
+		Ok(self.db.accepted_terms_version(user_id)? != terms.version)
+	}
+
+	/// Returns the consent page on which a user accepts the current terms of
+	/// service, authenticated by a token in the link.
+	pub fn consent_url(&self, user_id: &UserId) -> Result<Url> {
+		let token = match self.db.consent_token(user_id)? {
+			Some(token) => token,
+			None => {
+				let token = utils::random_string(CONSENT_TOKEN_LENGTH);
+				self.db.set_consent_token(user_id, &token)?;
+				token
+			},
+		};
+
+		let base = services()
+			.globals
+			.config
+			.well_known
+			.client
+			.clone()
+			.unwrap_or_else(|| {
+				Url::parse(&format!("https://{}", services().globals.server_name()))
+					.expect("server name is a valid host")
+			});
+
+		let mut url = base
+			.join(CONSENT_PATH)
+			.expect("path is a valid relative URL");
+		url.query_pairs_mut()
+			.append_pair("u", user_id.as_str())
+			.append_pair("t", &token);
+
+		Ok(url)
+	}
+
+	/// Checks the token authenticating a user on the consent page.
+	pub fn check_consent_token(&self, user_id: &UserId, token: &str) -> Result<bool> {
+		Ok(self
+			.db
+			.consent_token(user_id)?
+			.is_some_and(|consent_token| consent_token == token))
+	}
+
 	/// Check if a user is an admin
 	pub fn is_admin(&self, user_id: &UserId) -> Result<bool> {
 		let admin_room_alias_id = RoomAliasId::parse(format!("#admins:{}", services().globals.server_name()))
@@ -254,6 +413,104 @@ impl Service {
 		Ok(())
 	}
 
+	/// Checks the password of a local user against the auth provider chain.
+	///
+	/// Providers are consulted in order until one accepts or rejects the
+	/// password. Accounts of users accepted by an external provider are
+	/// created on their first login if the provider allows it.
+	pub async fn check_password(&self, user_id: &UserId, password: &str) -> Result<bool> {
+		if !user_is_local(user_id) {
+			return Ok(false);
+		}
+
+		for provider in &self.auth_providers {
+			match provider.check_password(user_id, password).await {
+				Ok(PasswordCheck::Accepted(external)) => {
+					debug!("{user_id} authenticated by the {} password provider", provider.name());
+					return match external {
+						Some(external) => {
+							self.provision_external_user(user_id, provider.name(), external)
+								.await
+						},
+						None => Ok(true),
+					};
+				},
+				Ok(PasswordCheck::Rejected) => return Ok(false),
+				Ok(PasswordCheck::Unknown) => {},
+				Err(e) => error!(
+					"The {} password provider failed to authenticate {user_id}: {e}",
+					provider.name()
+				),
+			}
+		}
+
+		Ok(false)
+	}
+
+	/// Creates or updates the local account of a user authenticated by an
+	/// external provider.
+	async fn provision_external_user(&self, user_id: &UserId, provider: &str, external: ExternalUser) -> Result<bool> {
+		if !self.exists(user_id)? {
+			if !external.create {
+				info!("{user_id} authenticated via {provider} but has no local account and user creation is disabled");
+				return Ok(false);
+			}
+
+			// external accounts get an unusable local password as an empty one marks
+			// the account as deactivated
+			self.create(user_id, Some(&utils::random_string(32)))?;
+			self.set_displayname(
+				user_id,
+				Some(
+					external
+						.displayname
+						.unwrap_or_else(|| user_id.localpart().to_owned()),
+				),
+			)
+			.await?;
+
+			services().account_data.update(
+				None,
+				user_id,
+				GlobalAccountDataEventType::PushRules.to_string().into(),
+				&serde_json::to_value(ruma::events::push_rules::PushRulesEvent {
+					content: ruma::events::push_rules::PushRulesEventContent {
+						global: push::Ruleset::server_default(user_id),
+					},
+				})
+				.expect("to json always works"),
+			)?;
+
+			info!("New user \"{user_id}\" created on first {provider} login.");
+			services()
+				.admin
+				.send_message(RoomMessageEventContent::notice_plain(format!(
+					"New user \"{user_id}\" created on first {provider} login."
+				)))
+				.await;
+		} else if self.is_deactivated(user_id)? {
+			return Ok(false);
+		}
+
+		if let Some(is_admin) = external.is_admin {
+			if is_admin && !self.is_admin(user_id)? {
+				let displayname = self
+					.displayname(user_id)?
+					.unwrap_or_else(|| user_id.localpart().to_owned());
+				services()
+					.admin
+					.make_user_admin(user_id, displayname)
+					.await?;
+				info!("Granted {user_id} admin privileges from {provider}");
+			} else if !is_admin && self.is_admin(user_id)? {
+				services().admin.revoke_user_admin(user_id).await?;
+				info!("Revoked admin privileges of {user_id} from {provider}");
+			}
+		}
+
+		Ok(true)
+	}
+
 	/// Returns the number of users registered on this server.
 	pub fn count(&self) -> Result<usize> { self.db.count() }
 
@@ -279,6 +536,56 @@ impl Service {
 		self.db.set_password(user_id, password)
 	}
 
+	/// Checks a new password against the configured password policy.
+	///
+	/// Returns an `M_WEAK_PASSWORD` error describing the first rule the
+	/// password violates.
+	pub fn check_password_policy(&self, user_id: &UserId, password: &str) -> Result<()> {
+		let config = &services().globals.config;
+		let weak = |message: &'static str| -> Result<()> { Err(Error::BadRequest(ErrorKind::WeakPassword, message)) };
+
+		if password.chars().count() < config.password_min_length {
+			return weak("Password is shorter than the minimum length required by the password policy.");
+		}
+
+		if config.password_require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+			return weak("Password must contain at least one digit.");
+		}
+
+		if config.password_require_lowercase && !password.chars().any(char::is_lowercase) {
+			return weak("Password must contain at least one lowercase letter.");
+		}
+
+		if config.password_require_uppercase && !password.chars().any(char::is_uppercase) {
+			return weak("Password must contain at least one uppercase letter.");
+		}
+
+		if config.password_require_symbol && password.chars().all(char::is_alphanumeric) {
+			return weak("Password must contain at least one symbol.");
+		}
+
+		let password = password.to_lowercase();
+		if config.password_forbid_localpart && password.contains(&user_id.localpart().to_lowercase()) {
+			return weak("Password must not contain the username.");
+		}
+
+		if config
+			.password_denylist
+			.iter()
+			.any(|denied| denied.to_lowercase() == password)
+		{
+			return weak("Password is too common.");
+		}
+
+		Ok(())
+	}
+
+	/// Stores an already hashed password, e.g. a bcrypt hash imported from
+	/// another homeserver. It is re-hashed with Argon2 on the next login.
+	pub fn set_password_hash(&self, user_id: &UserId, hash: &str) -> Result<()> {
+		self.db.set_password_hash(user_id, hash)
+	}
+
 	/// Returns the displayname of a user on this homeserver.
 	pub fn displayname(&self, user_id: &UserId) -> Result<Option<String>> { self.db.displayname(user_id) }
 
@@ -307,13 +614,18 @@ impl Service {
 	/// Adds a new device to a user.
 	pub fn create_device(
 		&self, user_id: &UserId, device_id: &DeviceId, token: &str, initial_device_display_name: Option<String>,
+		client_ip: Option<IpAddr>, user_agent: Option<&str>,
 	) -> Result<()> {
 		self.db
-			.create_device(user_id, device_id, token, initial_device_display_name)
+			.create_device(user_id, device_id, token, initial_device_display_name, client_ip, user_agent)
 	}
 
 	/// Removes a device from a user.
 	pub fn remove_device(&self, user_id: &UserId, device_id: &DeviceId) -> Result<()> {
+		self.last_seen
+			.lock()
+			.expect("locked")
+			.remove(&(user_id.to_owned(), device_id.to_owned()));
 		self.db.remove_device(user_id, device_id)
 	}
 
@@ -327,6 +639,151 @@ impl Service {
 		self.db.set_token(user_id, device_id, token)
 	}
 
+	/// Applies the configured access token lifetime to the access token of a
+	/// device which just logged in or registered and, if the client supports
+	/// them, gives it a new refresh token. Refresh tokens of a previous login
+	/// on the same device are revoked.
+	///
+	/// Returns the lifetime of the access token and the refresh token, which
+	/// are both `None` if the access token does not expire.
+	pub fn set_token_expiry(
+		&self, user_id: &UserId, device_id: &DeviceId, token: &str, refresh_token: Option<&str>,
+	) -> Result<(Option<Duration>, Option<String>)> {
+		self.db.remove_refresh_tokens(user_id, device_id)?;
+
+		if refresh_token.is_none()
+			&& !services()
+				.globals
+				.config
+				.expire_access_tokens_without_refresh
+		{
+			return Ok((None, None));
+		}
+
+		let ttl = self.expire_token(token)?;
+		if let Some(refresh_token) = refresh_token {
+			self.db
+				.set_refresh_token(user_id, device_id, refresh_token)?;
+		}
+
+		Ok((Some(ttl), refresh_token.map(ToOwned::to_owned)))
+	}
+
+	/// Applies the configured access token lifetime to an access token,
+	/// returning the lifetime.
+	pub fn expire_token(&self, token: &str) -> Result<Duration> {
+		let ttl = services().globals.config.access_token_ttl;
+		self.db
+			.set_token_expires_at(token, utils::millis_since_unix_epoch().saturating_add(ttl))?;
+
+		Ok(Duration::from_millis(ttl))
+	}
+
+	/// Creates a temporary device for an admin to act as a user, with an
+	/// access token expiring after `ttl` milliseconds. Returns the device id,
+	/// the access token and when it expires.
+	pub fn create_impersonation_device(&self, user_id: &UserId, ttl: u64) -> Result<(OwnedDeviceId, String, u64)> {
+		let device_id: OwnedDeviceId = format!(
+			"{IMPERSONATION_DEVICE_PREFIX}{}",
+			utils::random_string(IMPERSONATION_DEVICE_ID_LENGTH)
+		)
+		.into();
+		let token = utils::random_string(IMPERSONATION_TOKEN_LENGTH);
+
+		self.create_device(
+			user_id,
+			&device_id,
+			&token,
+			Some("Admin impersonation session".to_owned()),
+			None,
+			None,
+		)?;
+
+		let expires_at = utils::millis_since_unix_epoch().saturating_add(ttl);
+		self.db.set_token_expires_at(&token, expires_at)?;
+
+		Ok((device_id, token, expires_at))
+	}
+
+	/// Spawns a task periodically removing impersonation sessions whose access
+	/// token expired, so they do not stay around until their token is used.
+	pub fn start_impersonation_cleanup(&self) {
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(IMPERSONATION_CLEANUP_INTERVAL);
+			while services().server.running() {
+				interval.tick().await;
+				if let Err(e) = services().users.remove_expired_impersonation_devices() {
+					warn!("Failed to remove expired impersonation sessions: {e}");
+				}
+			}
+		});
+	}
+
+	/// Removes all impersonation sessions whose access token expired.
+	pub fn remove_expired_impersonation_devices(&self) -> Result<()> {
+		let expired = self
+			.db
+			.expired_tokens(utils::millis_since_unix_epoch())
+			.collect::<Result<Vec<_>>>()?;
+
+		for token in expired {
+			let Some((user_id, device_id)) = self.db.find_from_token(&token)? else {
+				continue;
+			};
+
+			if is_impersonation_device(&device_id) {
+				debug!("Removing expired impersonation session {device_id} of {user_id}");
+				self.remove_device(&user_id, device_id.as_str().into())?;
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Check if an access token has expired. Tokens without an expiry never
+	/// expire.
+	pub fn is_token_expired(&self, token: &str) -> Result<bool> {
+		Ok(self
+			.db
+			.token_expires_at(token)?
+			.is_some_and(|expires_at| expires_at < utils::millis_since_unix_epoch()))
+	}
+
+	/// Find out which device a refresh token belongs to, and whether it is the
+	/// device's current refresh token or one that was already rotated. A
+	/// current refresh token is consumed by rotating in `new_refresh_token`,
+	/// so every refresh token can only be used once.
+	pub fn rotate_refresh_token(
+		&self, refresh_token: &str, new_refresh_token: &str,
+	) -> Result<Option<(OwnedUserId, OwnedDeviceId, bool)>> {
+		let _lock = self.refresh_token_mutex.lock().expect("locked");
+		let Some((user_id, device_id, is_current)) = self.db.find_from_refresh_token(refresh_token)? else {
+			return Ok(None);
+		};
+
+		if is_current {
+			self.db
+				.set_refresh_token(&user_id, &device_id, new_refresh_token)?;
+		}
+
+		Ok(Some((user_id, device_id, is_current)))
+	}
+
+	/// Creates a short-lived login token for the `m.login.token` mechanism.
+	/// Any flow issuing login tokens (`login/get_token`, SSO) goes through
+	/// here. Returns the number of milliseconds until the token expires.
+	pub fn create_login_token(&self, user_id: &UserId, token: &str) -> Result<u64> {
+		let ttl = services().globals.config.login_token_ttl;
+		self.db.create_login_token(user_id, token, ttl)?;
+		Ok(ttl)
+	}
+
+	/// Find out which user a login token belongs to, consuming the token.
+	pub fn find_from_login_token(&self, token: &str) -> Result<Option<OwnedUserId>> {
+		let _lock = self.login_token_mutex.lock().expect("locked");
+		self.db.find_from_login_token(token)
+	}
+
 	pub fn add_one_time_key(
 		&self, user_id: &UserId, device_id: &DeviceId, one_time_key_key: &DeviceKeyId,
 		one_time_key_value: &Raw<OneTimeKey>,
@@ -439,6 +896,47 @@ impl Service {
 		self.db.get_device_metadata(user_id, device_id)
 	}
 
+	/// Records that a device made a request. Writes are throttled to one per
+	/// `LAST_SEEN_WRITE_INTERVAL` unless the IP address or user agent changed.
+	pub fn update_device_last_seen(
+		&self, user_id: &UserId, device_id: &DeviceId, client_ip: Option<IpAddr>, user_agent: Option<&str>,
+	) -> Result<()> {
+		let now = utils::millis_since_unix_epoch();
+		let key = (user_id.to_owned(), device_id.to_owned());
+
+		let mut last_seen = self.last_seen.lock().expect("locked");
+		if last_seen.get(&key).is_some_and(|last| {
+			last.client_ip == client_ip
+				&& last.user_agent.as_deref() == user_agent
+				&& now.saturating_sub(last.written_at) < LAST_SEEN_WRITE_INTERVAL
+		}) {
+			return Ok(());
+		}
+
+		last_seen.insert(
+			key,
+			LastSeen {
+				client_ip,
+				user_agent: user_agent.map(ToOwned::to_owned),
+				written_at: now,
+			},
+		);
+		drop(last_seen);
+
+		self.db.set_device_last_seen(
+			user_id,
+			device_id,
+			client_ip,
+			user_agent,
+			MilliSecondsSinceUnixEpoch(UInt::new(now).unwrap_or_default()),
+		)
+	}
+
+	/// Returns the user agent a device was last seen with.
+	pub fn device_user_agent(&self, user_id: &UserId, device_id: &DeviceId) -> Result<Option<String>> {
+		self.db.device_user_agent(user_id, device_id)
+	}
+
 	pub fn get_devicelist_version(&self, user_id: &UserId) -> Result<Option<u64>> {
 		self.db.get_devicelist_version(user_id)
 	}
@@ -460,8 +958,29 @@ impl Service {
 		// account is deactivated.
 		self.db.set_password(user_id, None)?;
 
-		// TODO: Unhook 3PID
-		Ok(())
+		// Free up the user's email addresses for other accounts
+		services().threepid.remove_all(user_id)
+	}
+
+	/// Erases a deactivated account: clears its profile, deletes the media it
+	/// uploaded and marks it so its past events are served redacted to users
+	/// and servers that were not in the room when they were sent. Servers
+	/// that already have the events are not asked to redact them.
+	///
+	/// The user should have left all rooms before, so their membership events
+	/// do not keep the old profile.
+	pub async fn erase_account(&self, user_id: &UserId) -> Result<()> {
+		self.set_displayname(user_id, None).await?;
+		self.set_avatar_url(user_id, None).await?;
+		self.set_blurhash(user_id, None).await?;
+
+		for mxc in services().media.get_all_user_mxcs(user_id) {
+			if let Err(e) = services().media.delete(mxc.clone()).await {
+				warn!("Failed to delete {mxc} of erased user {user_id}: {e}");
+			}
+		}
+
+		self.db.set_erased(user_id)
 	}
 
 	/// Creates a new sync filter. Returns the filter id.
@@ -474,6 +993,23 @@ impl Service {
 	}
 }
 
+/// Whether a device was created for an admin to impersonate its user.
+#[must_use]
+pub fn is_impersonation_device(device_id: &str) -> bool { device_id.starts_with(IMPERSONATION_DEVICE_PREFIX) }
+
+/// Rejects device ids chosen by clients on login or registration which are
+/// reserved for impersonation sessions.
+pub fn check_client_device_id(device_id: Option<&DeviceId>) -> Result<()> {
+	if device_id.is_some_and(|device_id| is_impersonation_device(device_id.as_str())) {
+		return Err(Error::BadRequest(
+			ErrorKind::InvalidParam,
+			"Device IDs starting with IMPERSONATION_ are reserved.",
+		));
+	}
+
+	Ok(())
+}
+
 /// Ensure that a user only sees signatures from themselves and the target user
 pub fn clean_signatures<F: Fn(&UserId) -> bool>(
 	cross_signing_key: &mut serde_json::Value, sender_user: Option<&UserId>, user_id: &UserId, allowed_signatures: F,
@@ -496,3 +1032,20 @@ pub fn clean_signatures<F: Fn(&UserId) -> bool>(
 
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use ruma::device_id;
+
+	use super::{check_client_device_id, is_impersonation_device};
+
+	#[test]
+	fn impersonation_device_ids_are_reserved() {
+		assert!(is_impersonation_device("IMPERSONATION_ABCDEFGHIJ"));
+		assert!(!is_impersonation_device("ABCDEFGHIJ"));
+
+		assert!(check_client_device_id(None).is_ok());
+		assert!(check_client_device_id(Some(device_id!("ABCDEFGHIJ"))).is_ok());
+		assert!(check_client_device_id(Some(device_id!("IMPERSONATION_ABCDEFGHIJ"))).is_err());
+	}
+}